@@ -0,0 +1,39 @@
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // アイコンなどのリソースを実行ファイルに埋め込む
+    embed_resource::compile("res/speech.rc", embed_resource::NONE);
+    set_build_date();
+}
+
+/// ビルド日 (UTC) を `BUILD_DATE` 環境変数としてクレートに渡す
+///
+/// 再現可能なビルドのため、`SOURCE_DATE_EPOCH` が設定されていればその日時を使う。
+fn set_build_date() {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        });
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    println!("cargo:rustc-env=BUILD_DATE={year:04}-{month:02}-{day:02}");
+}
+
+/// 1970-01-01 からの日数を年月日に変換する
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,41 @@
+//! 起動中の speech にテキストを送って読み上げさせる例
+//!
+//! ```text
+//! cargo run --example send_text -- speak こんにちは
+//! cargo run --example send_text -- load 読み込むテキスト
+//! cargo run --example send_text -- stop
+//! ```
+
+use anyhow::{bail, ensure, Context, Result};
+use windows::{
+    core::w,
+    Win32::{
+        Foundation::LPARAM,
+        System::DataExchange::COPYDATASTRUCT,
+        UI::WindowsAndMessaging::{FindWindowW, SendMessageW, WM_COPYDATA},
+    },
+};
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let kind = match args.next().as_deref() {
+        Some("speak") => 1,
+        Some("load") => 2,
+        Some("stop") => 3,
+        _ => bail!("usage: send_text (speak | load | stop) [text]"),
+    };
+    let text = args.collect::<Vec<_>>().join(" ");
+    let data = text.encode_utf16().collect::<Vec<_>>();
+
+    let hwnd = unsafe { FindWindowW(w!("speech_window_cls42"), None) }
+        .context("speech is not running.")?;
+    let copy_data = COPYDATASTRUCT {
+        dwData: kind,
+        cbData: (data.len() * 2) as _,
+        lpData: data.as_ptr() as _,
+    };
+    let ret = unsafe { SendMessageW(hwnd, WM_COPYDATA, None, LPARAM(&copy_data as *const _ as _)) };
+    ensure!(ret.0 == 1, "speech rejected the request.");
+    println!("ok");
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use crate::clipboard;
+use crate::dialog::{self, create_control};
+use crate::dpi::LogicalRect;
+use crate::strings::{self, tr, Msg};
+use anyhow::Result;
+use windows::{
+    core::{w, HSTRING, PCWSTR},
+    Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+        UI::{
+            Input::KeyboardAndMouse::SetFocus,
+            WindowsAndMessaging::{
+                DefWindowProcW, BS_DEFPUSHBUTTON, BS_PUSHBUTTON, ES_AUTOVSCROLL, ES_MULTILINE,
+                ES_READONLY, IDCANCEL, IDOK, WINDOW_STYLE, WM_CLOSE, WM_COMMAND, WM_CREATE,
+                WS_BORDER, WS_TABSTOP, WS_VSCROLL,
+            },
+        },
+    },
+};
+
+/// バージョン情報ダイアログのクラス名
+const CLASS_NAME: PCWSTR = w!("speech_about_cls42");
+/// コピーボタンの ID
+const ID_COPY: u16 = 100;
+/// ダイアログのクライアント領域の大きさ (96 DPI 基準)
+const CLIENT_SIZE: (i32, i32) = (360, 260);
+/// バージョンと説明のラベルの配置
+const INFO_RECT: LogicalRect = LogicalRect::new(12, 12, 336, 64);
+/// ライセンス一覧の配置
+const LICENSE_RECT: LogicalRect = LogicalRect::new(12, 84, 336, 120);
+/// コピーボタンの配置
+const COPY_RECT: LogicalRect = LogicalRect::new(168, 218, 84, 28);
+/// OK ボタンの配置
+const OK_RECT: LogicalRect = LogicalRect::new(264, 218, 84, 28);
+/// 同梱しているサードパーティ製クレートとそのライセンス
+const LICENSES: &str = "anyhow - MIT OR Apache-2.0\r\n\
+    Copyright (c) David Tolnay\r\n\
+    https://github.com/dtolnay/anyhow\r\n\
+    \r\n\
+    windows, windows-core, windows-targets - MIT OR Apache-2.0\r\n\
+    Copyright (c) Microsoft Corporation\r\n\
+    https://github.com/microsoft/windows-rs";
+
+/// バグ報告用のバージョン文字列
+fn version_text() -> String {
+    format!(
+        "{} {} ({})",
+        tr(Msg::AppName),
+        env!("CARGO_PKG_VERSION"),
+        env!("BUILD_DATE")
+    )
+}
+
+/// バージョン情報ダイアログをモーダルで表示する
+///
+/// ダイアログが閉じられるまで戻らない。
+pub fn show(owner: HWND) -> Result<()> {
+    dialog::show_modal(
+        owner,
+        CLASS_NAME,
+        Some(wnd_proc),
+        Msg::AboutTitle,
+        CLIENT_SIZE,
+    )
+}
+
+fn create(hwnd: HWND) -> Result<()> {
+    let info = format!(
+        "{} {}\r\n{}\r\n\r\n{}",
+        tr(Msg::AppName),
+        env!("CARGO_PKG_VERSION"),
+        strings::trf(Msg::AboutBuildDate, &[env!("BUILD_DATE")]),
+        tr(Msg::AboutDescription),
+    );
+    create_control(
+        hwnd,
+        w!("STATIC"),
+        &HSTRING::from(info),
+        WINDOW_STYLE::default(),
+        INFO_RECT,
+        0,
+    )?;
+    let licenses = format!("{}\r\n\r\n{LICENSES}", tr(Msg::AboutLicenses));
+    create_control(
+        hwnd,
+        w!("EDIT"),
+        &HSTRING::from(licenses),
+        WINDOW_STYLE((ES_MULTILINE | ES_AUTOVSCROLL | ES_READONLY) as _)
+            | WS_BORDER
+            | WS_VSCROLL
+            | WS_TABSTOP,
+        LICENSE_RECT,
+        0,
+    )?;
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ButtonCopy),
+        WINDOW_STYLE(BS_PUSHBUTTON as _) | WS_TABSTOP,
+        COPY_RECT,
+        ID_COPY,
+    )?;
+    let ok = create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ButtonOk),
+        WINDOW_STYLE(BS_DEFPUSHBUTTON as _) | WS_TABSTOP,
+        OK_RECT,
+        IDOK.0 as _,
+    )?;
+    _ = unsafe { SetFocus(ok) };
+    Ok(())
+}
+
+fn command(hwnd: HWND, wparam: WPARAM) -> Result<()> {
+    let id = crate::loword(wparam.0 as _);
+    if id == ID_COPY {
+        clipboard::set_text(hwnd, &version_text())?;
+    } else if id == IDOK.0 as u16 || id == IDCANCEL.0 as u16 {
+        dialog::close(hwnd)?;
+    }
+    Ok(())
+}
+
+/// ダイアログのウィンドウプロシージャ
+unsafe extern "system" fn wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_CREATE => {
+            if create(hwnd).is_err() {
+                return LRESULT(-1);
+            }
+        }
+        WM_COMMAND => {
+            if let Err(e) = command(hwnd, wparam) {
+                crate::report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+        WM_CLOSE => {
+            dialog::close(hwnd).ok();
+        }
+        _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+    LRESULT::default()
+}
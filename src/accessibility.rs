@@ -0,0 +1,86 @@
+use anyhow::Result;
+use std::cell::OnceCell;
+use windows::{
+    core::{GUID, HSTRING},
+    Win32::{
+        Foundation::HWND,
+        System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER},
+        UI::{
+            Accessibility::{
+                CAccPropServices, IAccPropServices, NotifyWinEvent, PROPID_ACC_NAME,
+                PROPID_ACC_VALUE,
+            },
+            WindowsAndMessaging::{CHILDID_SELF, EVENT_OBJECT_VALUECHANGE, OBJID_CLIENT},
+        },
+    },
+};
+
+thread_local! {
+    /// スクリーンリーダー向けのプロパティを上書きするためのサービス
+    static SERVICES: OnceCell<IAccPropServices> = OnceCell::new();
+}
+
+/// スクリーンリーダーが読み上げるコントロールの名前を設定する
+pub fn set_name(hwnd: HWND, name: &str) -> Result<()> {
+    set_prop(hwnd, PROPID_ACC_NAME, name)
+}
+
+/// スクリーンリーダーが読み上げるコントロールの値を設定し、変更を通知する
+pub fn set_value(hwnd: HWND, value: &str) -> Result<()> {
+    set_prop(hwnd, PROPID_ACC_VALUE, value)?;
+    unsafe {
+        NotifyWinEvent(
+            EVENT_OBJECT_VALUECHANGE,
+            hwnd,
+            OBJID_CLIENT.0,
+            CHILDID_SELF as _,
+        )
+    };
+    Ok(())
+}
+
+/// 設定したプロパティを削除する (コントロールを破棄する前に呼び出す)
+pub fn clear(hwnd: HWND) {
+    _ = with_services(|services| unsafe {
+        services.ClearHwndProps(
+            hwnd,
+            OBJID_CLIENT.0 as _,
+            CHILDID_SELF,
+            &[PROPID_ACC_NAME, PROPID_ACC_VALUE],
+        )?;
+        Ok(())
+    });
+}
+
+fn set_prop(hwnd: HWND, prop: GUID, text: &str) -> Result<()> {
+    with_services(|services| unsafe {
+        services.SetHwndPropStr(
+            hwnd,
+            OBJID_CLIENT.0 as _,
+            CHILDID_SELF,
+            prop,
+            &HSTRING::from(text),
+        )?;
+        Ok(())
+    })
+}
+
+/// COM の初期化後に呼び出すこと
+fn with_services<T>(f: impl FnOnce(&IAccPropServices) -> Result<T>) -> Result<T> {
+    SERVICES.with(|cell| {
+        let services = match cell.get() {
+            Some(services) => services,
+            None => {
+                let services = unsafe {
+                    CoCreateInstance::<_, IAccPropServices>(
+                        &CAccPropServices,
+                        None,
+                        CLSCTX_INPROC_SERVER,
+                    )?
+                };
+                cell.get_or_init(|| services)
+            }
+        };
+        f(services)
+    })
+}
@@ -0,0 +1,172 @@
+use anyhow::{bail, ensure, Context, Result};
+use speech::synthesis::{MAX_RATE, MIN_RATE};
+use std::ffi::OsString;
+use std::io::{self, IsTerminal, Read};
+use std::path::PathBuf;
+
+/// ウィンドウを表示せずに実行するオプション
+pub const OPTIONS: [&str; 6] = ["--text", "--in", "--out", "--voice", "--rate", "--help"];
+
+/// 読み上げるテキストの入力元
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Text(String),
+    File(PathBuf),
+    /// パイプやリダイレクトで渡された標準入力
+    Stdin,
+}
+
+/// ヘッドレスモードのオプション
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub input: Input,
+    pub out: PathBuf,
+    pub voice: Option<String>,
+    pub rate: f64,
+}
+
+/// 標準入力が端末ではなくパイプやファイルにつながっているかどうか
+pub fn stdin_is_piped() -> bool {
+    !io::stdin().is_terminal()
+}
+
+/// オプションを解釈する。`--help` の場合は None を返す
+///
+/// `--text` と `--in` がなく、標準入力がパイプの場合は標準入力から読み込む。
+pub fn parse(args: impl Iterator<Item = OsString>, stdin_piped: bool) -> Result<Option<Options>> {
+    let mut args = args.map(|arg| arg.to_string_lossy().into_owned());
+    let mut text = None;
+    let mut input = None;
+    let mut out = None;
+    let mut voice = None;
+    let mut rate = 1.0;
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("{arg} requires a value."))
+        };
+        match arg.as_str() {
+            "--help" => return Ok(None),
+            "--text" => text = Some(value()?),
+            "--in" => input = Some(PathBuf::from(value()?)),
+            "--out" => out = Some(PathBuf::from(value()?)),
+            "--voice" => voice = Some(value()?),
+            "--rate" => {
+                let v = value()?;
+                rate = v.parse().with_context(|| format!("invalid rate: {v}"))?;
+                ensure!(
+                    (MIN_RATE..=MAX_RATE).contains(&rate),
+                    "rate must be between {MIN_RATE} and {MAX_RATE}."
+                );
+            }
+            _ => bail!("unknown option: {arg} (see --help)"),
+        }
+    }
+    let input = match (text, input) {
+        (Some(text), None) => Input::Text(text),
+        (None, Some(path)) => Input::File(path),
+        (Some(_), Some(_)) => bail!("--text and --in cannot be used together."),
+        (None, None) if stdin_piped => Input::Stdin,
+        (None, None) => bail!("--text, --in or text piped to standard input is required."),
+    };
+    let out = out.context("--out is required.")?;
+    Ok(Some(Options {
+        input,
+        out,
+        voice,
+        rate,
+    }))
+}
+
+/// 標準入力をすべて読み込む
+pub fn read_stdin() -> Result<String> {
+    let mut bytes = vec![];
+    io::stdin()
+        .read_to_end(&mut bytes)
+        .context("failed to read standard input.")?;
+    decode_stdin(&bytes)
+}
+
+/// 標準入力のバイト列を UTF-8 として解釈する
+///
+/// BOM は取り除き、改行は LF にそろえる。空白だけの場合はエラーにする。
+pub fn decode_stdin(bytes: &[u8]) -> Result<String> {
+    let bytes = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]).unwrap_or(bytes);
+    let text = String::from_utf8_lossy(bytes).replace("\r\n", "\n");
+    ensure!(!text.trim().is_empty(), "standard input is empty.");
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = OsString> + '_ {
+        args.iter().map(OsString::from)
+    }
+
+    #[test]
+    fn parse_text_options() {
+        let options = parse(
+            args(&[
+                "--text", "hello", "--out", "a.wav", "--voice", "Haruka", "--rate", "1.5",
+            ]),
+            false,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                input: Input::Text("hello".into()),
+                out: "a.wav".into(),
+                voice: Some("Haruka".into()),
+                rate: 1.5,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_help() {
+        assert_eq!(
+            parse(args(&["--out", "a.wav", "--help"]), false).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_stdin_only_when_piped() {
+        let options = parse(args(&["--out", "a.wav"]), true).unwrap().unwrap();
+        assert_eq!(options.input, Input::Stdin);
+        assert!(parse(args(&["--out", "a.wav"]), false).is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse(args(&["--text", "a"]), false).is_err());
+        assert!(parse(
+            args(&["--text", "a", "--in", "b.txt", "--out", "a.wav"]),
+            false
+        )
+        .is_err());
+        assert!(parse(
+            args(&["--text", "a", "--out", "a.wav", "--rate", "3"]),
+            false
+        )
+        .is_err());
+        assert!(parse(args(&["--text", "a", "--out"]), false).is_err());
+        assert!(parse(args(&["--text", "a", "--out", "a.wav", "--loud"]), false).is_err());
+    }
+
+    #[test]
+    fn decode_stdin_strips_bom_and_crlf() {
+        let text = decode_stdin("\u{feff}一行目\r\n二行目\n".as_bytes()).unwrap();
+        assert_eq!(text, "一行目\n二行目\n");
+    }
+
+    #[test]
+    fn decode_stdin_rejects_empty() {
+        assert!(decode_stdin(b"").is_err());
+        assert!(decode_stdin(b"\r\n \n").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use crate::settings;
+use anyhow::Result;
+use speech::text_file;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// エディットコントロールのテキストを自動保存するファイルのファイル名
+const FILE_NAME: &str = "lasttext.txt";
+
+fn path() -> Result<PathBuf> {
+    Ok(settings::config_dir()?.join(FILE_NAME))
+}
+
+/// エディットコントロールのテキストを UTF-8 で保存する。空の場合はファイルを削除する
+pub fn save(text: &[u16]) -> Result<()> {
+    write(&path()?, text)
+}
+
+/// 自動保存したテキストを読み込む。保存していなければ None を返す
+pub fn load() -> Result<Option<Vec<u16>>> {
+    read(&path()?)
+}
+
+/// 自動保存したテキストを削除する (自動保存を無効にしたとき)
+pub fn delete() -> Result<()> {
+    remove(&path()?)
+}
+
+fn write(path: &Path, text: &[u16]) -> Result<()> {
+    if text.is_empty() {
+        return remove(path);
+    }
+    fs::create_dir_all(path.parent().unwrap_or(path))?;
+    // 保存の途中で終了しても前回のテキストが壊れないように、別名で書いてから置き換える
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, String::from_utf16_lossy(text))?;
+    fs::rename(temp, path)?;
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<Option<Vec<u16>>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(text_file::normalize_newlines(&text_file::decode(
+            &bytes,
+        )?))),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn remove(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_restore() {
+        let dir = std::env::temp_dir().join(format!("speech-autosave-test-{}", std::process::id()));
+        let path = dir.join(FILE_NAME);
+        let text = "今日は\r\n晴れ".encode_utf16().collect::<Vec<_>>();
+        write(&path, &text).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "今日は\r\n晴れ");
+        assert_eq!(read(&path).unwrap(), Some(text));
+        // 空にしたら次の起動で復元しない
+        write(&path, &[]).unwrap();
+        assert_eq!(read(&path).unwrap(), None);
+        fs::remove_dir_all(dir).ok();
+    }
+}
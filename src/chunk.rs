@@ -0,0 +1,349 @@
+use crate::utf16;
+
+/// 長いテキストを合成しやすい長さに区切る
+///
+/// 一度に合成するテキストが長すぎると再生が始まるまで時間がかかり、音声によっては失敗するので、
+/// 最大 max_len 単位 (UTF-16) ずつに分ける。区切る位置は次の順に優先する。
+///
+/// 1. 文末 (。！？ や改行、空白が続くピリオドなど。後ろの閉じかっこは前の文に含める)
+/// 2. 空白の後 (単語の途中では区切らない)
+/// 3. 読点の後
+/// 4. どれもなければ文字の境界 (サロゲートペアの途中では区切らない)
+///
+/// 区切ったテキストをつなげると元のテキストに戻る。
+pub fn split(text: &[u16], max_len: usize) -> Vec<&[u16]> {
+    split_growing(text, max_len, max_len)
+}
+
+/// [split] と同じように区切るが、最初のチャンクを first_len 以下に短くし、続くチャンクは倍ずつ max_len まで長くする
+///
+/// 最初のチャンクはすぐに合成できるので再生が早く始まり、その再生中に次の長いチャンクを合成できる。
+pub fn split_growing(text: &[u16], first_len: usize, max_len: usize) -> Vec<&[u16]> {
+    let max_len = max_len.max(2);
+    let mut limit = first_len.clamp(2, max_len);
+    let mut chunks = vec![];
+    let mut rest = text;
+    while rest.len() > limit {
+        let end = split_point(rest, limit);
+        chunks.push(&rest[..end]);
+        rest = &rest[end..];
+        limit = (limit * 2).min(max_len);
+    }
+    if !rest.is_empty() {
+        chunks.push(rest);
+    }
+    chunks
+}
+
+/// rest[..end] で区切る位置を探す (rest は max_len より長い)
+fn split_point(rest: &[u16], max_len: usize) -> usize {
+    let candidates = || (1..=max_len).rev();
+    candidates()
+        .find(|&end| is_sentence_end(rest, end))
+        .or_else(|| candidates().find(|&end| is_whitespace(rest[end - 1])))
+        .or_else(|| candidates().find(|&end| is_comma(rest[end - 1])))
+        .or_else(|| candidates().find(|&end| utf16::floor_char_boundary(rest, end) == end))
+        .unwrap_or(max_len)
+}
+
+/// rest[..end] が文末で終わるかどうか
+fn is_sentence_end(rest: &[u16], end: usize) -> bool {
+    let next = rest[end];
+    // 「です。」のような閉じかっこや、続けて書いた終止符は前の文に含める
+    if is_closing(next) || is_terminator(next) {
+        return false;
+    }
+    let mut last = end - 1;
+    while last > 0 && is_closing(rest[last]) {
+        last -= 1;
+    }
+    match rest[last] {
+        // 小数点や省略のピリオドと区別するため、後ろに空白が続く場合だけ文末とみなす
+        c if c == '.' as u16 => is_whitespace(next),
+        c => c == '\n' as u16 || is_terminator(c),
+    }
+}
+
+/// テキストを文ごとに区切る ([split] と同じ文末の判定を使う)
+///
+/// 区切ったテキストをつなげると元のテキストに戻る。
+pub fn sentences(text: &[u16]) -> Vec<&[u16]> {
+    let mut sentences = vec![];
+    let mut start = 0;
+    for end in 1..text.len() {
+        if is_sentence_end(&text[start..], end - start) {
+            sentences.push(&text[start..end]);
+            start = end;
+        }
+    }
+    if start < text.len() {
+        sentences.push(&text[start..]);
+    }
+    sentences
+}
+
+/// テキストを空行で段落ごとに区切る。空行などの続く空白は前の段落に含める
+///
+/// 区切ったテキストをつなげると元のテキストに戻る。
+pub fn paragraphs(text: &[u16]) -> Vec<&[u16]> {
+    let mut paragraphs = vec![];
+    let mut start = 0;
+    let mut pos = 0;
+    while pos < text.len() {
+        if !is_whitespace(text[pos]) {
+            pos += 1;
+            continue;
+        }
+        let end = text[pos..]
+            .iter()
+            .position(|&c| !is_whitespace(c))
+            .map_or(text.len(), |len| pos + len);
+        if end < text.len() && line_breaks(&text[pos..end]) >= 2 {
+            paragraphs.push(&text[start..end]);
+            start = end;
+        }
+        pos = end;
+    }
+    if start < text.len() {
+        paragraphs.push(&text[start..]);
+    }
+    paragraphs
+}
+
+/// テキストが空行で終わるかどうか (段落の終わりか)
+pub fn ends_paragraph(text: &[u16]) -> bool {
+    let trailing = text.iter().rev().take_while(|&&c| is_whitespace(c)).count();
+    line_breaks(&text[text.len() - trailing..]) >= 2
+}
+
+/// 改行の数 (CR LF は一つと数える)
+fn line_breaks(text: &[u16]) -> usize {
+    let cr = '\r' as u16;
+    let lf = '\n' as u16;
+    (0..text.len())
+        .filter(|&i| text[i] == lf || (text[i] == cr && text.get(i + 1) != Some(&lf)))
+        .count()
+}
+
+/// pos を含む文の先頭の位置 (pos より前に文末がなければ 0)
+///
+/// 続きから読み上げるときに、文の途中から始めないように戻す。
+pub fn sentence_start(text: &[u16], pos: usize) -> usize {
+    (1..=pos.min(text.len().saturating_sub(1)))
+        .rev()
+        .find(|&end| is_sentence_end(text, end))
+        .unwrap_or(0)
+}
+
+/// pos を含む文の範囲 (先頭の位置, 末尾の位置)
+///
+/// [sentence_start] と同じ文末の判定を使い、文末に続く空白や改行も文に含める。
+pub fn sentence_at(text: &[u16], pos: usize) -> (usize, usize) {
+    let start = sentence_start(text, pos);
+    let end = (start + 1..text.len())
+        .find(|&end| is_sentence_end(text, end))
+        .unwrap_or(text.len());
+    (start, end)
+}
+
+/// 終止符 (ピリオドは別に扱う)
+fn is_terminator(c: u16) -> bool {
+    ['。', '．', '！', '？', '!', '?'].contains(&char_of(c))
+}
+
+/// 閉じかっこや閉じ引用符
+fn is_closing(c: u16) -> bool {
+    [
+        '」', '』', '）', '】', '〉', '》', ')', ']', '"', '\'', '”', '’',
+    ]
+    .contains(&char_of(c))
+}
+
+/// 読点
+fn is_comma(c: u16) -> bool {
+    ['、', '，', ',', '；', ';', '：', ':'].contains(&char_of(c))
+}
+
+fn is_whitespace(c: u16) -> bool {
+    char_of(c).is_whitespace()
+}
+
+/// BMP の文字に変換する (サロゲートは U+FFFD になる)
+fn char_of(c: u16) -> char {
+    char::from_u32(c as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    fn split_str(s: &str, max_len: usize) -> Vec<String> {
+        let text = wide(s);
+        let chunks = split(&text, max_len);
+        assert_eq!(chunks.concat(), text, "chunks must join back to the text");
+        for chunk in &chunks {
+            assert!(chunk.len() <= max_len, "{chunk:?} is too long");
+        }
+        chunks
+            .into_iter()
+            .map(|c| String::from_utf16(c).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn grow_chunks_after_a_short_first_one() {
+        let text = wide("今日は晴れです。明日は雨です。明後日は曇りです。");
+        let chunks = split_growing(&text, 8, 16);
+        assert_eq!(chunks.concat(), text);
+        assert_eq!(
+            chunks
+                .into_iter()
+                .map(|c| String::from_utf16(c).unwrap())
+                .collect::<Vec<_>>(),
+            ["今日は晴れです。", "明日は雨です。明後日は曇りです。"]
+        );
+        let text = wide(&"a ".repeat(100));
+        let lens: Vec<_> = split_growing(&text, 10, 50)
+            .iter()
+            .map(|c| c.len())
+            .collect();
+        assert_eq!(lens, [10, 20, 40, 50, 50, 30]);
+    }
+
+    #[test]
+    fn split_into_sentences() {
+        let text = wide("今日は「晴れ」です。明日は？\nHe said 3.5 words. Bye");
+        let sentences: Vec<_> = sentences(&text)
+            .into_iter()
+            .map(|s| String::from_utf16(s).unwrap())
+            .collect();
+        assert_eq!(
+            sentences,
+            [
+                "今日は「晴れ」です。",
+                "明日は？",
+                "\n",
+                "He said 3.5 words.",
+                " Bye"
+            ]
+        );
+        assert!(super::sentences(&[]).is_empty());
+    }
+
+    #[test]
+    fn split_into_paragraphs() {
+        let text = wide("一段落目。\r\n続き。\r\n\r\n二段落目。\n \n\n三段落目\r\r");
+        let paragraphs = paragraphs(&text);
+        assert_eq!(paragraphs.concat(), text);
+        let paragraphs: Vec<_> = paragraphs
+            .into_iter()
+            .map(|p| String::from_utf16(p).unwrap())
+            .collect();
+        assert_eq!(
+            paragraphs,
+            [
+                "一段落目。\r\n続き。\r\n\r\n",
+                "二段落目。\n \n\n",
+                "三段落目\r\r"
+            ]
+        );
+        assert!(ends_paragraph(&wide("一段落目。\r\n\r\n")));
+        assert!(ends_paragraph(&wide("三段落目\r\r")));
+        assert!(!ends_paragraph(&wide("続き。\r\n")));
+        assert!(!ends_paragraph(&[]));
+        assert!(super::paragraphs(&[]).is_empty());
+    }
+
+    #[test]
+    fn short_text_is_one_chunk() {
+        assert_eq!(split_str("こんにちは。", 10), ["こんにちは。"]);
+        assert!(split(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn split_japanese_at_sentence_end() {
+        assert_eq!(
+            split_str("今日は晴れです。明日は雨です。明後日は曇りです。", 16),
+            ["今日は晴れです。明日は雨です。", "明後日は曇りです。"]
+        );
+        // 閉じかっこは前の文に含める
+        assert_eq!(
+            split_str("彼は「行きます。」と言った。それから出かけた。", 12),
+            ["彼は「行きます。」", "と言った。", "それから出かけた。"]
+        );
+        assert_eq!(
+            split_str("一行目\n二行目\n三行目", 8),
+            ["一行目\n二行目\n", "三行目"]
+        );
+    }
+
+    #[test]
+    fn split_english_at_sentence_end() {
+        assert_eq!(
+            split_str("It costs 3.14 dollars. Is that right? Yes!", 30),
+            ["It costs 3.14 dollars.", " Is that right? Yes!"]
+        );
+        assert_eq!(
+            split_str("He said \"Stop.\" Then he left.", 20),
+            ["He said \"Stop.\"", " Then he left."]
+        );
+    }
+
+    #[test]
+    fn never_split_inside_a_word() {
+        assert_eq!(
+            split_str("the quick brown fox jumps over the lazy dog", 12),
+            ["the quick ", "brown fox ", "jumps over ", "the lazy dog"]
+        );
+    }
+
+    #[test]
+    fn fall_back_to_commas_then_characters() {
+        assert_eq!(
+            split_str("あいうえお、かきくけこさしすせそ", 8),
+            ["あいうえお、", "かきくけこさしす", "せそ"]
+        );
+        assert_eq!(split_str("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn snap_back_to_sentence_start() {
+        let text = wide("一文目です。二文目は「長い」です。Third one. End");
+        // 文の途中からは文の先頭に戻る
+        assert_eq!(sentence_start(&text, 3), 0);
+        assert_eq!(sentence_start(&text, 10), 6);
+        assert_eq!(sentence_start(&text, 16), 6);
+        // 文の先頭はそのまま
+        assert_eq!(sentence_start(&text, 17), 17);
+        assert_eq!(sentence_start(&text, 25), 17);
+        // ピリオドは空白が続く場合だけ文末とみなす
+        assert_eq!(sentence_start(&text, 30), 27);
+        // 範囲外の位置は最後の文の先頭にする
+        assert_eq!(sentence_start(&text, 100), 27);
+        assert_eq!(sentence_start(&[], 5), 0);
+    }
+
+    #[test]
+    fn sentence_under_the_caret() {
+        let text = wide("一文目です。二文目は「長い」です。End");
+        assert_eq!(sentence_at(&text, 0), (0, 6));
+        assert_eq!(sentence_at(&text, 8), (6, 17));
+        assert_eq!(sentence_at(&text, 17), (17, 20));
+        // 末尾のキャレットは最後の文にする
+        assert_eq!(sentence_at(&text, 20), (17, 20));
+        assert_eq!(sentence_at(&[], 0), (0, 0));
+    }
+
+    #[test]
+    fn never_split_surrogate_pairs() {
+        // 😀 は UTF-16 で 2 単位
+        let chunks = split_str("ab😀😀😀", 3);
+        assert_eq!(chunks, ["ab", "😀", "😀", "😀"]);
+        let chunks = split_str("😀😀😀", 3);
+        assert_eq!(chunks, ["😀", "😀", "😀"]);
+    }
+}
@@ -0,0 +1,57 @@
+use crate::args::{self, Input, Options, OPTIONS};
+use crate::settings;
+use crate::strings::{tr, Msg};
+use anyhow::{Context, Result};
+use speech::synthesis::{self, SynthOptions};
+use speech::{text_file, wav};
+use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+
+/// コマンドラインにヘッドレスモードのオプションが含まれているかどうか
+pub fn is_requested() -> bool {
+    std::env::args_os()
+        .skip(1)
+        .any(|arg| OPTIONS.iter().any(|option| arg == *option))
+}
+
+/// ウィンドウを作らずにテキストを WAV ファイルに保存し、終了コードを返す
+///
+/// GUI アプリケーションとしてビルドしているので、出力が見えるように親プロセスのコンソールに接続する。
+pub fn run() -> i32 {
+    _ = unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
+    let result =
+        args::parse(std::env::args_os().skip(1), args::stdin_is_piped()).and_then(|options| {
+            match options {
+                Some(options) => save(&options),
+                None => {
+                    println!("{}", tr(Msg::CliHelp));
+                    Ok(())
+                }
+            }
+        });
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            1
+        }
+    }
+}
+
+/// GUI の保存と同じ処理で WAV ファイルを作成する
+fn save(options: &Options) -> Result<()> {
+    settings::activate_rules()?;
+    let text = match &options.input {
+        Input::Text(text) => text.encode_utf16().collect(),
+        Input::File(path) => text_file::read_text_file(path)
+            .with_context(|| format!("failed to read {}.", path.display()))?,
+        Input::Stdin => args::read_stdin()?.encode_utf16().collect(),
+    };
+    let synth_options = SynthOptions {
+        voice_id: options.voice.clone(),
+        rate: options.rate,
+        ..Default::default()
+    };
+    let bytes = synthesis::synthesize_wav(&text, &synth_options)?;
+    wav::write(&bytes, &options.out)
+        .with_context(|| format!("failed to write {}.", options.out.display()))
+}
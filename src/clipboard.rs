@@ -0,0 +1,99 @@
+use anyhow::{ensure, Result};
+use speech::utf16;
+use std::mem;
+use std::ptr;
+use std::slice;
+use windows::Win32::{
+    Foundation::{HANDLE, HGLOBAL, HWND},
+    System::{
+        DataExchange::{
+            CloseClipboard, EmptyClipboard, GetClipboardData, IsClipboardFormatAvailable,
+            OpenClipboard, SetClipboardData,
+        },
+        Memory::{GlobalAlloc, GlobalFree, GlobalLock, GlobalSize, GlobalUnlock, GMEM_MOVEABLE},
+        Ole::CF_UNICODETEXT,
+    },
+};
+
+/// クリップボードに文字列をコピーする
+pub fn set_text(hwnd: HWND, text: &str) -> Result<()> {
+    let text = text.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+    unsafe { OpenClipboard(hwnd)? };
+    let ret = copy(&text);
+    _ = unsafe { CloseClipboard() };
+    ret
+}
+
+/// クリップボードの文字列を取得する。文字列がない場合は None を返す
+pub fn get_text(hwnd: HWND) -> Result<Option<Vec<u16>>> {
+    if unsafe { IsClipboardFormatAvailable(CF_UNICODETEXT.0 as _) }.is_err() {
+        return Ok(None);
+    }
+    unsafe { OpenClipboard(hwnd)? };
+    let ret = paste();
+    _ = unsafe { CloseClipboard() };
+    ret.map(Some)
+}
+
+/// 長すぎるテキストを max_len 単位 (UTF-16) までに切り詰め、切り詰めたかどうかを返す
+///
+/// サロゲートペアの途中では切らない。
+pub fn truncate(text: &mut Vec<u16>, max_len: usize) -> bool {
+    if text.len() <= max_len {
+        return false;
+    }
+    text.truncate(utf16::floor_char_boundary(text, max_len));
+    true
+}
+
+fn paste() -> Result<Vec<u16>> {
+    let data = unsafe { GetClipboardData(CF_UNICODETEXT.0 as _)? };
+    let mem = HGLOBAL(data.0);
+    let src = unsafe { GlobalLock(mem) } as *const u16;
+    ensure!(!src.is_null(), "failed to lock memory.");
+    let len = unsafe { GlobalSize(mem) } / mem::size_of::<u16>();
+    let text = unsafe { slice::from_raw_parts(src, len) };
+    let text = text.iter().take_while(|c| **c != 0).copied().collect();
+    _ = unsafe { GlobalUnlock(mem) };
+    Ok(text)
+}
+
+fn copy(text: &[u16]) -> Result<()> {
+    unsafe { EmptyClipboard()? };
+    let mem = unsafe { GlobalAlloc(GMEM_MOVEABLE, mem::size_of_val(text))? };
+    let dst = unsafe { GlobalLock(mem) } as *mut u16;
+    if dst.is_null() {
+        _ = unsafe { GlobalFree(mem) };
+    }
+    ensure!(!dst.is_null(), "failed to lock memory.");
+    unsafe { ptr::copy_nonoverlapping(text.as_ptr(), dst, text.len()) };
+    _ = unsafe { GlobalUnlock(mem) };
+    // 成功した場合、メモリの所有権はシステムに移る
+    if let Err(e) = unsafe { SetClipboardData(CF_UNICODETEXT.0 as _, HANDLE(mem.0)) } {
+        _ = unsafe { GlobalFree(mem) };
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_long_text() {
+        let wide = |s: &str| s.encode_utf16().collect::<Vec<_>>();
+        let mut text = wide("abc");
+        assert!(!truncate(&mut text, 3));
+        assert_eq!(text, wide("abc"));
+        assert!(truncate(&mut text, 2));
+        assert_eq!(text, wide("ab"));
+        // サロゲートペアの途中では切らない
+        let mut text = wide("a😀b");
+        assert!(truncate(&mut text, 2));
+        assert_eq!(text, wide("a"));
+        let mut text = wide("a😀b");
+        assert!(truncate(&mut text, 3));
+        assert_eq!(text, wide("a😀"));
+    }
+}
@@ -0,0 +1,57 @@
+//! スレッドの COM アパートメント
+//!
+//! UI スレッドはコモンダイアログやスクリーンリーダー向けのプロパティのために STA で初期化する。
+//! 自分で作るスレッドから WinRT を呼ぶ場合は、先に [MtaGuard] で MTA に参加させる。
+
+use anyhow::Result;
+use windows::Win32::System::Com::{
+    CoGetApartmentType, CoInitializeEx, CoUninitialize, APTTYPE, APTTYPEQUALIFIER, APTTYPE_MAINSTA,
+    APTTYPE_STA, COINIT_MULTITHREADED,
+};
+
+/// スレッドを MTA で初期化し、drop で初期化を解除するガード
+///
+/// 暗黙の MTA に頼ると、先に COM を初期化したコードによって RPC_E_CHANGED_MODE や
+/// アクティベーションの失敗が起こることがあるので、スレッドの最初に作っておく。
+pub struct MtaGuard(());
+
+impl MtaGuard {
+    pub fn new() -> Result<Self> {
+        unsafe { CoInitializeEx(None, COINIT_MULTITHREADED).ok()? };
+        Ok(Self(()))
+    }
+}
+
+impl Drop for MtaGuard {
+    fn drop(&mut self) {
+        unsafe { CoUninitialize() };
+    }
+}
+
+/// 呼び出したスレッドが STA かどうか
+pub fn is_sta() -> bool {
+    let mut apartment = APTTYPE::default();
+    let mut qualifier = APTTYPEQUALIFIER::default();
+    unsafe { CoGetApartmentType(&mut apartment, &mut qualifier) }.is_ok()
+        && [APTTYPE_STA, APTTYPE_MAINSTA].contains(&apartment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthesis::{self, SynthOptions};
+    use crate::wav;
+
+    #[test]
+    fn synthesize_on_worker_thread() {
+        std::thread::spawn(|| {
+            let _guard = MtaGuard::new().unwrap();
+            assert!(!is_sta());
+            let text = "テスト".encode_utf16().collect::<Vec<_>>();
+            let bytes = synthesis::synthesize_wav(&text, &SynthOptions::default()).unwrap();
+            assert!(wav::parse_header(&bytes).unwrap().data_len > 0);
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+use crate::logging;
+use crate::strings::{self, trf, Msg};
+use anyhow::{anyhow, Result};
+use speech::unwind;
+use std::backtrace::Backtrace;
+use std::fs;
+use std::panic::{self, PanicHookInfo};
+use std::path::PathBuf;
+use std::thread;
+use windows::{
+    core::HSTRING,
+    Win32::{
+        System::SystemInformation::GetLocalTime,
+        UI::WindowsAndMessaging::{MessageBoxW, MB_ICONERROR, MB_OK},
+    },
+};
+
+/// パニックしたときに詳細をファイルに残すようにする
+///
+/// ウィンドウアプリケーションではパニックしても何も表示されずに消えてしまうので、
+/// UI スレッドでのパニックはメッセージボックスで知らせてから終了する。
+/// ほかのスレッドでのパニックは [unwind::catch] で受け止めて、普段のエラー表示に回す。
+pub fn install() {
+    panic::set_hook(Box::new(|info| {
+        let location = info
+            .location()
+            .map_or_else(String::new, |location| format!(" at {location}"));
+        let message = format!("{}{location}", unwind::message(info.payload()));
+        logging::error("panic", &anyhow!("{message}"));
+        let path = write_report(info).unwrap_or_default();
+        if thread::current().name() == Some("main") {
+            let text = trf(Msg::Crashed, &[&path.to_string_lossy()]);
+            unsafe {
+                MessageBoxW(
+                    None,
+                    &HSTRING::from(text),
+                    &strings::wide(Msg::AppName),
+                    MB_OK | MB_ICONERROR,
+                )
+            };
+            std::process::abort();
+        }
+    }));
+}
+
+/// パニックの内容とバックトレースをログフォルダーに書き出す
+fn write_report(info: &PanicHookInfo) -> Result<PathBuf> {
+    let dir = logging::log_dir()?;
+    fs::create_dir_all(&dir)?;
+    let time = unsafe { GetLocalTime() };
+    let path = dir.join(format!(
+        "crash-{:04}{:02}{:02}-{:02}{:02}{:02}.txt",
+        time.wYear, time.wMonth, time.wDay, time.wHour, time.wMinute, time.wSecond
+    ));
+    let report = format!(
+        "speech {}\r\n{info}\r\n\r\n{}",
+        env!("CARGO_PKG_VERSION"),
+        Backtrace::force_capture()
+    );
+    fs::write(&path, report)?;
+    Ok(path)
+}
@@ -0,0 +1,183 @@
+use crate::dpi::{self, LogicalRect};
+use crate::strings::{self, Msg};
+use anyhow::Result;
+use std::cell::RefCell;
+use std::sync::{Mutex, PoisonError};
+use windows::{
+    core::{HSTRING, PCWSTR},
+    Win32::{
+        Foundation::{HWND, LPARAM, RECT, WPARAM},
+        Graphics::Gdi::{DeleteObject, GetSysColorBrush, COLOR_BTNFACE, HFONT},
+        UI::{
+            HiDpi::AdjustWindowRectExForDpi,
+            Input::KeyboardAndMouse::EnableWindow,
+            WindowsAndMessaging::{
+                CreateWindowExW, DestroyWindow, DispatchMessageW, GetMessageW, GetWindow,
+                GetWindowRect, IsDialogMessageW, IsWindow, PostQuitMessage, RegisterClassW,
+                SendMessageW, SetForegroundWindow, ShowWindow, TranslateMessage, GW_OWNER, HMENU,
+                MSG, SW_SHOW, WINDOW_EX_STYLE, WINDOW_STYLE, WM_SETFONT, WNDCLASSW, WNDPROC,
+                WS_CAPTION, WS_CHILD, WS_EX_DLGMODALFRAME, WS_POPUP, WS_SYSMENU, WS_VISIBLE,
+            },
+        },
+    },
+};
+
+/// 登録済みのダイアログのウィンドウクラスの名前
+static REGISTERED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+thread_local! {
+    /// 表示中のダイアログと、その子コントロールに設定したフォント
+    static FONTS: RefCell<Vec<(HWND, HFONT)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// ダイアログのウィンドウクラスを登録する (登録済みなら何もしない)
+fn register_class(class: PCWSTR, wnd_proc: WNDPROC) {
+    let name = unsafe { class.to_string() }.unwrap_or_default();
+    let mut registered = REGISTERED.lock().unwrap_or_else(PoisonError::into_inner);
+    if registered.contains(&name) {
+        return;
+    }
+    let wnd_class = WNDCLASSW {
+        lpfnWndProc: wnd_proc,
+        lpszClassName: class,
+        hbrBackground: unsafe { GetSysColorBrush(COLOR_BTNFACE) },
+        ..Default::default()
+    };
+    unsafe { RegisterClassW(&wnd_class) };
+    registered.push(name);
+}
+
+/// オーナーウィンドウの中央にダイアログをモーダルで表示する
+///
+/// ウィンドウクラスは最初に表示するときに wnd_proc で登録する。ダイアログが閉じられるまで戻らない。
+pub fn show_modal(
+    owner: HWND,
+    class: PCWSTR,
+    wnd_proc: WNDPROC,
+    title: Msg,
+    client_size: (i32, i32),
+) -> Result<()> {
+    register_class(class, wnd_proc);
+    let dpi = dpi::dpi_for_window(owner);
+    let style = WS_POPUP | WS_CAPTION | WS_SYSMENU;
+    let (width, height) = client_size;
+    let mut rc = RECT {
+        right: dpi::scale(width, dpi),
+        bottom: dpi::scale(height, dpi),
+        ..Default::default()
+    };
+    unsafe { AdjustWindowRectExForDpi(&mut rc, style, false, WS_EX_DLGMODALFRAME, dpi)? };
+    let (width, height) = (rc.right - rc.left, rc.bottom - rc.top);
+
+    // オーナーウィンドウの中央に配置する
+    let mut owner_rc = RECT::default();
+    unsafe { GetWindowRect(owner, &mut owner_rc)? };
+    let x = owner_rc.left + (owner_rc.right - owner_rc.left - width) / 2;
+    let y = owner_rc.top + (owner_rc.bottom - owner_rc.top - height) / 2;
+
+    let hwnd = unsafe {
+        CreateWindowExW(
+            WS_EX_DLGMODALFRAME,
+            class,
+            &strings::wide(title),
+            style,
+            x,
+            y,
+            width,
+            height,
+            owner,
+            None,
+            None,
+            None,
+        )?
+    };
+
+    _ = unsafe { EnableWindow(owner, false) };
+    _ = unsafe { ShowWindow(hwnd, SW_SHOW) };
+
+    // ダイアログが閉じられるまでメッセージループを回す
+    let mut msg = MSG::default();
+    while unsafe { IsWindow(hwnd) }.as_bool() {
+        if !unsafe { GetMessageW(&mut msg, None, 0, 0) }.as_bool() {
+            // WM_QUIT は外側のメッセージループに任せる (ダイアログはまだ残っているので、フォントも残す)
+            unsafe { PostQuitMessage(msg.wParam.0 as _) };
+            return Ok(());
+        }
+        // Esc キーで IDCANCEL が送られる
+        if unsafe { IsDialogMessageW(hwnd, &msg) }.as_bool() {
+            continue;
+        }
+        unsafe {
+            _ = TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+    }
+    delete_font(hwnd);
+    Ok(())
+}
+
+/// hwnd のダイアログの子コントロールに設定するフォント
+///
+/// 最初の子コントロールを生成するときに、ダイアログの DPI に合わせて作る。
+fn font(hwnd: HWND) -> Result<HFONT> {
+    FONTS.with_borrow_mut(|fonts| {
+        if let Some(&(_, font)) = fonts.iter().find(|&&(dialog, _)| dialog == hwnd) {
+            return Ok(font);
+        }
+        let font = dpi::create_message_font(dpi::dpi_for_window(hwnd))?;
+        fonts.push((hwnd, font));
+        Ok(font)
+    })
+}
+
+/// 閉じたダイアログのフォントを破棄する
+fn delete_font(hwnd: HWND) {
+    let found = FONTS.with_borrow_mut(|fonts| {
+        let index = fonts.iter().position(|&(dialog, _)| dialog == hwnd)?;
+        Some(fonts.remove(index).1)
+    });
+    if let Some(font) = found.filter(|font| !font.is_invalid()) {
+        _ = unsafe { DeleteObject(font) };
+    }
+}
+
+/// ダイアログに子コントロールを生成し、ダイアログのフォントを設定する
+pub fn create_control(
+    hwnd: HWND,
+    class: PCWSTR,
+    text: &HSTRING,
+    style: WINDOW_STYLE,
+    rect: LogicalRect,
+    id: u16,
+) -> Result<HWND> {
+    let font = font(hwnd)?;
+    let (x, y, width, height) = rect.scale(dpi::dpi_for_window(hwnd));
+    let control = unsafe {
+        CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            class,
+            text,
+            WS_CHILD | WS_VISIBLE | style,
+            x,
+            y,
+            width,
+            height,
+            hwnd,
+            HMENU(id as _),
+            None,
+            None,
+        )?
+    };
+    unsafe { SendMessageW(control, WM_SETFONT, WPARAM(font.0 as _), LPARAM(1)) };
+    Ok(control)
+}
+
+/// オーナーウィンドウを有効に戻してからダイアログを閉じる
+pub fn close(hwnd: HWND) -> Result<()> {
+    if let Ok(owner) = unsafe { GetWindow(hwnd, GW_OWNER) } {
+        _ = unsafe { EnableWindow(owner, true) };
+        _ = unsafe { SetForegroundWindow(owner) };
+    }
+    unsafe { DestroyWindow(hwnd)? };
+    Ok(())
+}
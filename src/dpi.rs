@@ -0,0 +1,103 @@
+use anyhow::{ensure, Result};
+use std::mem;
+use windows::Win32::{
+    Foundation::HWND,
+    Graphics::Gdi::{CreateFontIndirectW, DEFAULT_CHARSET, HFONT, LOGFONTW},
+    UI::{
+        HiDpi::{GetDpiForWindow, SystemParametersInfoForDpi},
+        WindowsAndMessaging::{NONCLIENTMETRICSW, SPI_GETNONCLIENTMETRICS},
+    },
+};
+
+/// 座標の基準となる DPI
+pub const BASE_DPI: u32 = 96;
+
+/// 96 DPI を基準とした論理座標の矩形
+#[derive(Clone, Copy)]
+pub struct LogicalRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl LogicalRect {
+    pub const fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// 指定した DPI の物理座標 (x, y, width, height) に変換する
+    pub fn scale(&self, dpi: u32) -> (i32, i32, i32, i32) {
+        (
+            scale(self.x, dpi),
+            scale(self.y, dpi),
+            scale(self.width, dpi),
+            scale(self.height, dpi),
+        )
+    }
+}
+
+/// 96 DPI 基準の値を指定した DPI の値に変換する
+pub fn scale(value: i32, dpi: u32) -> i32 {
+    ((value as i64 * dpi as i64 + BASE_DPI as i64 / 2) / BASE_DPI as i64) as i32
+}
+
+/// ウィンドウが表示されているモニターの DPI を取得する
+pub fn dpi_for_window(hwnd: HWND) -> u32 {
+    match unsafe { GetDpiForWindow(hwnd) } {
+        0 => BASE_DPI,
+        dpi => dpi,
+    }
+}
+
+/// 指定した DPI に合わせたメッセージ用フォントを生成する
+pub fn create_message_font(dpi: u32) -> Result<HFONT> {
+    let mut metrics = NONCLIENTMETRICSW {
+        cbSize: mem::size_of::<NONCLIENTMETRICSW>() as _,
+        ..Default::default()
+    };
+    unsafe {
+        SystemParametersInfoForDpi(
+            SPI_GETNONCLIENTMETRICS.0,
+            metrics.cbSize,
+            Some(&mut metrics as *mut _ as _),
+            0,
+            dpi,
+        )?
+    };
+    let font = unsafe { CreateFontIndirectW(&metrics.lfMessageFont) };
+    ensure!(!font.is_invalid(), "failed to create font.");
+    Ok(font)
+}
+
+/// 書体名と大きさ (1/10 ポイント単位) と太さから、指定した DPI での LOGFONTW を作る
+pub fn logfont(face: &str, size: i32, weight: i32, dpi: u32) -> LOGFONTW {
+    let mut logfont = LOGFONTW {
+        // 負の値は文字の高さ (内部レディングを含まない) を表す
+        lfHeight: -((size as i64 * dpi as i64 + 360) / 720) as i32,
+        lfWeight: weight,
+        lfCharSet: DEFAULT_CHARSET,
+        ..Default::default()
+    };
+    // 末尾の NUL のために最後の 1 文字分は空けておく
+    let len = logfont.lfFaceName.len() - 1;
+    for (dst, src) in logfont.lfFaceName[..len]
+        .iter_mut()
+        .zip(face.encode_utf16())
+    {
+        *dst = src;
+    }
+    logfont
+}
+
+/// LOGFONTW からフォントを生成する
+pub fn create_font(logfont: &LOGFONTW) -> Result<HFONT> {
+    let font = unsafe { CreateFontIndirectW(logfont) };
+    ensure!(!font.is_invalid(), "failed to create font.");
+    Ok(font)
+}
@@ -0,0 +1,151 @@
+use std::{fmt, io};
+use windows::core::HRESULT;
+
+/// 利用者に対処を案内できる失敗
+///
+/// よくある HRESULT をここで見分けておき、表示する側で説明を選ぶ ([anyhow::Error] に入れて返す)。
+#[derive(Debug)]
+pub enum SpeechError {
+    /// 音声が選択されておらず、既定の音声も使えない
+    NoVoiceSelected,
+    /// 選択した音声がアンインストールされたなどで使えない
+    VoiceUnavailable,
+    /// 原因を見分けられなかった合成の失敗
+    SynthesisFailed(HRESULT),
+    /// オンライン音声の合成にネットワークに接続できない
+    NetworkRequired,
+    /// 再生するオーディオデバイスがない
+    AudioDeviceMissing,
+    /// ファイルに書き込めない
+    FileWrite(io::Error),
+    /// ダイアログで取り消した
+    DialogCancelled,
+}
+
+/// HRESULT_FROM_WIN32(ERROR_CANCELLED)
+const E_CANCELLED: u32 = 0x800704C7;
+/// HRESULT_FROM_WIN32(ERROR_NOT_FOUND)
+const E_NOT_FOUND: u32 = 0x80070490;
+/// SPERR_NOT_FOUND
+const SPERR_NOT_FOUND: u32 = 0x8004503A;
+/// WININET_E_TIMEOUT
+const WININET_E_TIMEOUT: u32 = 0x80072EE2;
+/// WININET_E_NAME_NOT_RESOLVED
+const WININET_E_NAME_NOT_RESOLVED: u32 = 0x80072EE7;
+/// WININET_E_CANNOT_CONNECT
+const WININET_E_CANNOT_CONNECT: u32 = 0x80072EFD;
+/// WININET_E_CONNECTION_RESET
+const WININET_E_CONNECTION_RESET: u32 = 0x80072EFF;
+/// MF_E_NO_AUDIO_PLAYBACK_DEVICE
+const MF_E_NO_AUDIO_PLAYBACK_DEVICE: u32 = 0xC00D36FA;
+/// AUDCLNT_E_DEVICE_INVALIDATED
+const AUDCLNT_E_DEVICE_INVALIDATED: u32 = 0x88890004;
+/// AUDCLNT_E_ENDPOINT_CREATE_FAILED
+const AUDCLNT_E_ENDPOINT_CREATE_FAILED: u32 = 0x8889000F;
+
+impl SpeechError {
+    /// 見分けられる HRESULT の場合だけ、対応する失敗を返す
+    pub fn known(code: HRESULT) -> Option<Self> {
+        Some(match code.0 as u32 {
+            E_CANCELLED => Self::DialogCancelled,
+            E_NOT_FOUND | SPERR_NOT_FOUND => Self::VoiceUnavailable,
+            WININET_E_TIMEOUT
+            | WININET_E_NAME_NOT_RESOLVED
+            | WININET_E_CANNOT_CONNECT
+            | WININET_E_CONNECTION_RESET => Self::NetworkRequired,
+            MF_E_NO_AUDIO_PLAYBACK_DEVICE
+            | AUDCLNT_E_DEVICE_INVALIDATED
+            | AUDCLNT_E_ENDPOINT_CREATE_FAILED => Self::AudioDeviceMissing,
+            _ => return None,
+        })
+    }
+
+    /// HRESULT から失敗を選ぶ。見分けられなければ [SpeechError::SynthesisFailed] にする
+    pub fn from_hresult(code: HRESULT) -> Self {
+        Self::known(code).unwrap_or(Self::SynthesisFailed(code))
+    }
+
+    /// エラーの原因をたどって最初に見つかった [SpeechError]
+    pub fn find(err: &anyhow::Error) -> Option<&Self> {
+        err.chain().find_map(|cause| cause.downcast_ref())
+    }
+}
+
+impl From<windows::core::Error> for SpeechError {
+    fn from(e: windows::core::Error) -> Self {
+        Self::from_hresult(e.code())
+    }
+}
+
+impl From<io::Error> for SpeechError {
+    fn from(e: io::Error) -> Self {
+        Self::FileWrite(e)
+    }
+}
+
+impl fmt::Display for SpeechError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoVoiceSelected => f.write_str("no voice is selected."),
+            Self::VoiceUnavailable => f.write_str("the voice is unavailable."),
+            Self::SynthesisFailed(code) => write!(f, "synthesis failed (0x{:08X}).", code.0 as u32),
+            Self::NetworkRequired => f.write_str("the voice requires a network connection."),
+            Self::AudioDeviceMissing => f.write_str("no audio playback device."),
+            Self::FileWrite(e) => write!(f, "failed to write the file: {e}"),
+            Self::DialogCancelled => f.write_str("the dialog was cancelled."),
+        }
+    }
+}
+
+impl std::error::Error for SpeechError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::FileWrite(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known(code: u32) -> Option<SpeechError> {
+        SpeechError::known(HRESULT(code as i32))
+    }
+
+    #[test]
+    fn map_common_hresults() {
+        assert!(matches!(
+            known(0x800704C7),
+            Some(SpeechError::DialogCancelled)
+        ));
+        for code in [0x80070490, 0x8004503A] {
+            assert!(matches!(known(code), Some(SpeechError::VoiceUnavailable)));
+        }
+        for code in [0x80072EE2, 0x80072EE7, 0x80072EFD, 0x80072EFF] {
+            assert!(matches!(known(code), Some(SpeechError::NetworkRequired)));
+        }
+        for code in [0xC00D36FA, 0x88890004, 0x8889000F] {
+            assert!(matches!(known(code), Some(SpeechError::AudioDeviceMissing)));
+        }
+    }
+
+    #[test]
+    fn keep_unknown_hresults() {
+        assert!(known(0x80004005).is_none());
+        let e = SpeechError::from_hresult(HRESULT(0x80045508_u32 as i32));
+        assert!(matches!(e, SpeechError::SynthesisFailed(code) if code.0 as u32 == 0x80045508));
+        assert_eq!(e.to_string(), "synthesis failed (0x80045508).");
+    }
+
+    #[test]
+    fn find_in_context() {
+        let err = anyhow::Error::new(SpeechError::NetworkRequired).context("failed to speak.");
+        assert!(matches!(
+            SpeechError::find(&err),
+            Some(SpeechError::NetworkRequired)
+        ));
+        assert!(SpeechError::find(&anyhow::anyhow!("other")).is_none());
+    }
+}
@@ -0,0 +1,175 @@
+use anyhow::{ensure, Result};
+use speech::search::Query;
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::sync::OnceLock;
+use windows::{
+    core::PWSTR,
+    Win32::{
+        Foundation::{HWND, LPARAM},
+        UI::{
+            Controls::Dialogs::{
+                FindTextW, ReplaceTextW, FINDMSGSTRINGW, FINDREPLACEW, FR_DIALOGTERM, FR_DOWN,
+                FR_FINDNEXT, FR_HIDEWHOLEWORD, FR_MATCHCASE, FR_REPLACE, FR_REPLACEALL,
+            },
+            Input::KeyboardAndMouse::SetFocus,
+            WindowsAndMessaging::{DestroyWindow, IsDialogMessageW, RegisterWindowMessageW, MSG},
+        },
+    },
+};
+
+/// 検索する文字列と置換後の文字列のバッファの長さ (終端の NUL を含む)
+const BUFFER_LEN: usize = 256;
+/// 検索・置換ダイアログから通知されるメッセージ
+static FIND_MESSAGE: OnceLock<u32> = OnceLock::new();
+
+thread_local! {
+    /// 表示中のダイアログが置換ダイアログかどうか
+    static REPLACING: Cell<bool> = const { Cell::new(false) };
+    /// ダイアログに渡す構造体。ダイアログが書き換えるので、閉じた後も次の検索のために残しておく
+    static REQUEST: RefCell<Option<Box<Request>>> = const { RefCell::new(None) };
+}
+
+/// [FINDREPLACEW] と、そこから指すバッファ (ダイアログが閉じるまで移動しないように Box に入れる)
+struct Request {
+    fr: FINDREPLACEW,
+    find_what: [u16; BUFFER_LEN],
+    replace_with: [u16; BUFFER_LEN],
+}
+
+/// ダイアログで押されたボタン
+pub enum Command {
+    FindNext,
+    Replace,
+    ReplaceAll,
+    Closed,
+}
+
+/// 最後にダイアログで指定した検索の条件
+pub struct Search {
+    pub pattern: Vec<u16>,
+    pub replacement: Vec<u16>,
+    pub match_case: bool,
+    /// 下方向に探す (置換ダイアログでは常に下方向)
+    pub forward: bool,
+}
+
+impl Search {
+    pub fn query(&self) -> Query<'_> {
+        Query {
+            pattern: &self.pattern,
+            match_case: self.match_case,
+        }
+    }
+}
+
+/// 検索・置換ダイアログから通知されるメッセージを取得する
+pub fn message() -> u32 {
+    *FIND_MESSAGE.get_or_init(|| unsafe { RegisterWindowMessageW(FINDMSGSTRINGW) })
+}
+
+/// 検索ダイアログ (replace が true なら置換ダイアログ) を表示し、表示しているダイアログを返す
+///
+/// dialog は表示中のダイアログ (閉じていれば無効なハンドル)、initial は検索する文字列の初期値。
+/// 同じ種類のダイアログを表示中ならそちらにフォーカスを移す。
+pub fn show(owner: HWND, dialog: HWND, replace: bool, initial: Option<&[u16]>) -> Result<HWND> {
+    if !dialog.is_invalid() {
+        if REPLACING.get() == replace {
+            unsafe { SetFocus(dialog)? };
+            return Ok(dialog);
+        }
+        // 種類の違うダイアログは閉じてから開き直す (閉じると FR_DIALOGTERM が通知される)
+        close(dialog);
+    }
+    let dialog = REQUEST.with_borrow_mut(|request| {
+        let request = request.get_or_insert_with(|| {
+            Box::new(Request {
+                fr: FINDREPLACEW {
+                    lStructSize: mem::size_of::<FINDREPLACEW>() as _,
+                    Flags: FR_DOWN,
+                    ..Default::default()
+                },
+                find_what: [0; BUFFER_LEN],
+                replace_with: [0; BUFFER_LEN],
+            })
+        });
+        if let Some(initial) = initial.filter(|text| text.len() < BUFFER_LEN) {
+            request.find_what.fill(0);
+            request.find_what[..initial.len()].copy_from_slice(initial);
+        }
+        let find_what = PWSTR(request.find_what.as_mut_ptr());
+        let replace_with = PWSTR(request.replace_with.as_mut_ptr());
+        let fr = &mut request.fr;
+        fr.hwndOwner = owner;
+        // 前回の方向と大文字小文字の区別だけを引き継ぐ
+        fr.Flags = (fr.Flags & (FR_DOWN | FR_MATCHCASE)) | FR_HIDEWHOLEWORD;
+        fr.lpstrFindWhat = find_what;
+        fr.lpstrReplaceWith = replace_with;
+        fr.wFindWhatLen = BUFFER_LEN as _;
+        fr.wReplaceWithLen = BUFFER_LEN as _;
+        unsafe {
+            if replace {
+                ReplaceTextW(fr)
+            } else {
+                FindTextW(fr)
+            }
+        }
+    });
+    ensure!(!dialog.is_invalid(), "failed to open the find dialog.");
+    REPLACING.set(replace);
+    Ok(dialog)
+}
+
+/// 表示中のダイアログを閉じる
+pub fn close(dialog: HWND) {
+    if !dialog.is_invalid() {
+        _ = unsafe { DestroyWindow(dialog) };
+    }
+}
+
+/// ダイアログ宛てのキー入力 (Tab や Enter) をダイアログに処理させる。処理した場合は true を返す
+pub fn is_dialog_message(dialog: HWND, msg: &MSG) -> bool {
+    !dialog.is_invalid() && unsafe { IsDialogMessageW(dialog, msg) }.as_bool()
+}
+
+/// [message] の lparam から、押されたボタンを取得する
+///
+/// [Command::Closed] が返されたら、ダイアログはもう破棄されている。
+pub fn command(lparam: LPARAM) -> Option<Command> {
+    // lparam は [show] で渡した FINDREPLACEW を指している
+    let flags = unsafe { (*(lparam.0 as *const FINDREPLACEW)).Flags };
+    if flags.contains(FR_DIALOGTERM) {
+        Some(Command::Closed)
+    } else if flags.contains(FR_FINDNEXT) {
+        Some(Command::FindNext)
+    } else if flags.contains(FR_REPLACE) {
+        Some(Command::Replace)
+    } else if flags.contains(FR_REPLACEALL) {
+        Some(Command::ReplaceAll)
+    } else {
+        None
+    }
+}
+
+/// 最後にダイアログで指定した検索の条件。まだ検索していないか、検索する文字列が空なら None
+pub fn last_search() -> Option<Search> {
+    REQUEST.with_borrow(|request| {
+        let request = request.as_ref()?;
+        let pattern = until_nul(&request.find_what);
+        if pattern.is_empty() {
+            return None;
+        }
+        let flags = request.fr.Flags;
+        Some(Search {
+            pattern: pattern.to_vec(),
+            replacement: until_nul(&request.replace_with).to_vec(),
+            match_case: flags.contains(FR_MATCHCASE),
+            forward: REPLACING.get() || flags.contains(FR_DOWN),
+        })
+    })
+}
+
+fn until_nul(buffer: &[u16]) -> &[u16] {
+    let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+    &buffer[..len]
+}
@@ -0,0 +1,425 @@
+use crate::logging;
+use crate::ui_message::UiMessage;
+use crate::wav_output::WavOutput;
+use anyhow::{Context, Result};
+use speech::synthesis::{self, CancelHandle, SynthOptions, Voiced};
+use speech::{com, text_file};
+use std::collections::HashMap;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use windows::{
+    core::HSTRING,
+    Media::SpeechSynthesis::SpeechSynthesizer,
+    Win32::{
+        Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0},
+        Storage::FileSystem::{
+            CreateFileW, ReadDirectoryChangesW, FILE_ACTION_ADDED, FILE_ACTION_MODIFIED,
+            FILE_ACTION_RENAMED_NEW_NAME, FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OVERLAPPED,
+            FILE_LIST_DIRECTORY, FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE,
+            FILE_NOTIFY_CHANGE_SIZE, FILE_NOTIFY_INFORMATION, FILE_SHARE_DELETE, FILE_SHARE_READ,
+            FILE_SHARE_WRITE, OPEN_EXISTING,
+        },
+        System::{
+            Threading::{CreateEventW, ResetEvent, WaitForSingleObject},
+            IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED},
+        },
+    },
+};
+
+/// 変更の通知を待つ間に終了要求と書き込み中のファイルを確かめる間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// ファイルの大きさがこの時間変わらなければ、書き込みが終わったとみなす
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+/// 変換を試みる回数 (失敗したら一度だけやり直す)
+const MAX_ATTEMPTS: u32 = 2;
+/// 変更の通知を受け取るバッファの大きさ (u32 単位)
+const BUFFER_LEN: usize = 16 * 1024;
+/// 監視を終了するときに、スレッドが終わるのを待つ最大の時間 (通知を待つ間隔より長くする)
+const JOIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 監視スレッドと、そのスレッドに終了を求めるための状態
+static WATCHER: Mutex<Option<(JoinHandle<()>, Arc<Control>)>> = Mutex::new(None);
+
+/// 監視スレッドに終了を求めるための状態
+///
+/// 待ちきれずに残したスレッドが次の監視と混ざらないように、監視ごとに作る。
+#[derive(Default)]
+struct Control {
+    /// 監視を終了しようとしているかどうか
+    stopping: AtomicBool,
+    /// 変換中のファイルの合成を取り消すハンドル
+    converting: Mutex<Option<CancelHandle>>,
+}
+
+impl Control {
+    fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::Relaxed)
+    }
+
+    /// 終了を求め、変換中のファイルがあればその合成を取り消す
+    fn stop(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+        if let Some(converting) = self.converting.lock().unwrap().as_ref() {
+            converting.cancel();
+        }
+    }
+}
+
+/// 一つのテキストファイルを WAV に変換した結果
+pub struct Converted {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub result: Result<()>,
+}
+
+/// 書き込みが終わるのを待っているファイル
+struct Entry {
+    len: Option<u64>,
+    /// 最後に大きさが変わったか、変更が通知された時刻
+    changed: Instant,
+    attempts: u32,
+}
+
+/// 変更が通知され、書き込みが終わるのを待っているファイルの一覧
+#[derive(Default)]
+struct Pending {
+    files: HashMap<PathBuf, Entry>,
+}
+
+impl Pending {
+    /// 変更が通知されたファイルを追加する。すでにあれば待ち時間を数え直す
+    fn touch(&mut self, path: PathBuf, now: Instant) {
+        let entry = self.files.entry(path).or_insert(Entry {
+            len: None,
+            changed: now,
+            attempts: 0,
+        });
+        entry.changed = now;
+    }
+
+    /// 大きさが [SETTLE_TIME] 変わっていないファイルを一覧から取り出す
+    ///
+    /// len_of はファイルの大きさを返す。開けないファイルは大きさが変わったものとして待ち続ける。
+    fn take_ready(
+        &mut self,
+        now: Instant,
+        len_of: impl Fn(&Path) -> Option<u64>,
+    ) -> Vec<(PathBuf, u32)> {
+        let mut ready = vec![];
+        self.files.retain(|path, entry| {
+            let len = len_of(path);
+            if len.is_none() || len != entry.len {
+                entry.len = len;
+                entry.changed = now;
+                return true;
+            }
+            if now.duration_since(entry.changed) < SETTLE_TIME {
+                return true;
+            }
+            ready.push((path.clone(), entry.attempts));
+            false
+        });
+        ready
+    }
+
+    /// 変換に失敗したファイルを、少し待ってからやり直すように戻す
+    fn retry(&mut self, path: PathBuf, attempts: u32, now: Instant) {
+        self.files.insert(
+            path,
+            Entry {
+                len: None,
+                changed: now,
+                attempts,
+            },
+        );
+    }
+}
+
+/// 監視して変換するテキストファイルかどうか
+fn is_text_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("txt"))
+}
+
+/// テキストファイルと同じフォルダーに作る WAV ファイルのパス
+fn target_path(source: &Path) -> PathBuf {
+    source.with_extension("wav")
+}
+
+/// テキストファイルを読み込み、合成して WAV ファイルに書き込む
+///
+/// ほかの保存と同じファイルになるように、書き込む前に [WavOutput] の後処理を施す。
+/// 合成が終わるまで待つ間も [stop] で取り消せるように、合成中はハンドルを control に置いておく。
+fn convert(
+    source: &Path,
+    target: &Path,
+    synth: &SpeechSynthesizer,
+    control: &Control,
+) -> Result<()> {
+    let text = text_file::read_text_file(source)
+        .with_context(|| format!("failed to read {}.", source.display()))?;
+    let output = WavOutput::new(&text, &[Voiced::whole(&text, synth)])?;
+    let (sender, receiver) = mpsc::channel();
+    let converting = synthesis::start_wav(synth, &text, move |bytes| _ = sender.send(bytes));
+    *control.converting.lock().unwrap() = Some(converting.clone());
+    // ハンドルを置く前に終了を求められていたら、ここで取り消す
+    if control.is_stopping() {
+        converting.cancel();
+    }
+    let bytes = receiver.recv().context("no synthesis result.");
+    control.converting.lock().unwrap().take();
+    output
+        .write(bytes??, target)
+        .with_context(|| format!("failed to write {}.", target.display()))?;
+    Ok(())
+}
+
+/// フォルダーの監視を始める。追加または変更された `*.txt` を、書き込みが終わったら WAV に変換する
+///
+/// 変換には始めたときの音声と読み上げ速度を使い、結果は [UiMessage::FolderConverted] で知らせる。
+/// 監視中のフォルダーがあれば、その監視を終えてから始める。
+pub fn start(hwnd_handle: isize, dir: &Path, options: SynthOptions) -> Result<()> {
+    stop();
+    let dir_handle = unsafe {
+        CreateFileW(
+            &HSTRING::from(dir),
+            FILE_LIST_DIRECTORY.0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+            None,
+            OPEN_EXISTING,
+            FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OVERLAPPED,
+            None,
+        )?
+    };
+    let control = Arc::new(Control::default());
+    // HANDLE はスレッドに送れないので、値として渡す
+    let raw = dir_handle.0 as isize;
+    let dir = dir.to_path_buf();
+    let watcher = {
+        let control = control.clone();
+        thread::spawn(move || {
+            let dir_handle = HANDLE(raw as _);
+            if let Err(e) = watch(dir_handle, &dir, hwnd_handle, &options, &control) {
+                logging::error("failed to watch the folder", &e);
+            }
+            _ = unsafe { CloseHandle(dir_handle) };
+        })
+    };
+    *WATCHER.lock().unwrap() = Some((watcher, control));
+    Ok(())
+}
+
+/// 終了を求められるまで変更を待ち、書き込みが終わったファイルを変換する
+fn watch(
+    dir_handle: HANDLE,
+    dir: &Path,
+    hwnd_handle: isize,
+    options: &SynthOptions,
+    control: &Control,
+) -> Result<()> {
+    let _com = com::MtaGuard::new()?;
+    let synth = synthesis::create_synthesizer(options)?;
+    let event = unsafe { CreateEventW(None, true, false, None)? };
+    let mut overlapped = OVERLAPPED {
+        hEvent: event,
+        ..Default::default()
+    };
+    let mut buffer = vec![0u32; BUFFER_LEN];
+    let mut pending = Pending::default();
+    let result = (|| -> Result<()> {
+        loop {
+            unsafe {
+                ResetEvent(event)?;
+                ReadDirectoryChangesW(
+                    dir_handle,
+                    buffer.as_mut_ptr() as _,
+                    (buffer.len() * 4) as u32,
+                    false,
+                    FILE_NOTIFY_CHANGE_FILE_NAME
+                        | FILE_NOTIFY_CHANGE_LAST_WRITE
+                        | FILE_NOTIFY_CHANGE_SIZE,
+                    None,
+                    Some(&mut overlapped as *mut _),
+                    None,
+                )?
+            };
+            // 通知が届くまで、書き込みが終わったファイルを変換しながら待つ
+            loop {
+                if control.is_stopping() {
+                    return Ok(());
+                }
+                let wait = unsafe { WaitForSingleObject(event, POLL_INTERVAL.as_millis() as u32) };
+                if wait == WAIT_OBJECT_0 {
+                    break;
+                }
+                convert_ready(&mut pending, hwnd_handle, &synth, control);
+            }
+            let mut read = 0;
+            unsafe { GetOverlappedResult(dir_handle, &overlapped, &mut read, false)? };
+            // バッファがあふれた場合は 0 になり、その間の変更は届かない
+            for name in changed_names(&buffer, read as usize) {
+                let path = dir.join(name);
+                if is_text_file(&path) {
+                    pending.touch(path, Instant::now());
+                }
+            }
+        }
+    })();
+    unsafe {
+        // 読み取りを取り消し、バッファが使われなくなるまで待つ
+        if CancelIoEx(dir_handle, Some(&overlapped as *const _)).is_ok() {
+            let mut read = 0;
+            _ = GetOverlappedResult(dir_handle, &overlapped, &mut read, true);
+        }
+        _ = CloseHandle(event);
+    }
+    result
+}
+
+/// 書き込みが終わったファイルを変換する。失敗したら一度だけやり直し、それでも失敗したら飛ばす
+fn convert_ready(
+    pending: &mut Pending,
+    hwnd_handle: isize,
+    synth: &SpeechSynthesizer,
+    control: &Control,
+) {
+    let ready = pending.take_ready(Instant::now(), |path| {
+        fs::metadata(path).ok().map(|metadata| metadata.len())
+    });
+    for (source, attempts) in ready {
+        if control.is_stopping() {
+            return;
+        }
+        let target = target_path(&source);
+        let result = convert(&source, &target, synth, control);
+        match &result {
+            // 終了するために取り消した変換は、失敗として知らせない
+            Err(e) if e.is::<synthesis::Cancelled>() => return,
+            Ok(()) => logging::info(format_args!(
+                "converted {} to {}",
+                source.display(),
+                target.display()
+            )),
+            Err(e) if attempts + 1 < MAX_ATTEMPTS => {
+                logging::error(
+                    &format!("failed to convert {}, retrying", source.display()),
+                    e,
+                );
+                pending.retry(source, attempts + 1, Instant::now());
+                continue;
+            }
+            Err(e) => logging::error(&format!("skipped {}", source.display()), e),
+        }
+        let converted = Converted {
+            source,
+            target,
+            result,
+        };
+        UiMessage::FolderConverted(converted).post(hwnd_handle);
+    }
+}
+
+/// ReadDirectoryChangesW が返した通知から、追加、変更、名前の変更の後のファイル名を取り出す
+fn changed_names(buffer: &[u32], len: usize) -> Vec<String> {
+    let mut names = vec![];
+    let mut offset = 0;
+    while len >= offset + mem::size_of::<FILE_NOTIFY_INFORMATION>() {
+        let info = unsafe {
+            &*((buffer.as_ptr() as *const u8).add(offset) as *const FILE_NOTIFY_INFORMATION)
+        };
+        if matches!(
+            info.Action,
+            FILE_ACTION_ADDED | FILE_ACTION_MODIFIED | FILE_ACTION_RENAMED_NEW_NAME
+        ) {
+            let name = unsafe {
+                std::slice::from_raw_parts(info.FileName.as_ptr(), info.FileNameLength as usize / 2)
+            };
+            names.push(String::from_utf16_lossy(name));
+        }
+        if info.NextEntryOffset == 0 {
+            break;
+        }
+        offset += info.NextEntryOffset as usize;
+    }
+    names
+}
+
+/// 監視しているかどうか
+pub fn is_watching() -> bool {
+    WATCHER.lock().unwrap().is_some()
+}
+
+/// 監視を終了し、スレッドが終わるまで待つ (変換中のファイルがあれば、その合成を取り消す)
+///
+/// UI スレッドから呼ぶので、[JOIN_TIMEOUT] を過ぎても終わらないスレッドは待たずに残す。
+pub fn stop() {
+    let Some((watcher, control)) = WATCHER.lock().unwrap().take() else {
+        return;
+    };
+    control.stop();
+    let deadline = Instant::now() + JOIN_TIMEOUT;
+    while !watcher.is_finished() {
+        if Instant::now() >= deadline {
+            logging::info("the folder watcher did not stop in time");
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    _ = watcher.join();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn convert_only_text_files() {
+        assert!(is_text_file(Path::new(r"C:\in\report0401.txt")));
+        assert!(is_text_file(Path::new("REPORT.TXT")));
+        assert!(!is_text_file(Path::new("report0401.wav")));
+        assert!(!is_text_file(Path::new("txt")));
+        assert_eq!(
+            target_path(Path::new(r"C:\in\report0401.txt")),
+            Path::new(r"C:\in\report0401.wav")
+        );
+    }
+
+    #[test]
+    fn wait_until_the_file_stops_growing() {
+        let start = Instant::now();
+        let len = Cell::new(Some(10));
+        let len_of = |_: &Path| len.get();
+        let mut pending = Pending::default();
+        pending.touch("a.txt".into(), start);
+        // 最初に大きさを確かめてから数え始める
+        assert!(pending.take_ready(start, len_of).is_empty());
+        assert!(pending
+            .take_ready(start + SETTLE_TIME / 2, len_of)
+            .is_empty());
+        // 伸びたら数え直す
+        len.set(Some(20));
+        assert!(pending.take_ready(start + SETTLE_TIME, len_of).is_empty());
+        assert!(pending
+            .take_ready(start + SETTLE_TIME * 2 - Duration::from_millis(1), len_of)
+            .is_empty());
+        assert_eq!(
+            pending.take_ready(start + SETTLE_TIME * 2, len_of),
+            vec![(PathBuf::from("a.txt"), 0)]
+        );
+        assert!(pending.files.is_empty());
+
+        // やり直すファイルは、試した回数を覚えておく
+        pending.retry("a.txt".into(), 1, start);
+        assert!(pending.take_ready(start, len_of).is_empty());
+        assert_eq!(
+            pending.take_ready(start + SETTLE_TIME, len_of),
+            vec![(PathBuf::from("a.txt"), 1)]
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use crate::settings;
+use anyhow::Result;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// 読み上げの履歴を保存するファイルのファイル名
+const FILE_NAME: &str = "history.txt";
+/// 覚えておく履歴の最大の数
+pub const MAX_ENTRIES: usize = 20;
+
+thread_local! {
+    /// 読み上げたテキストの履歴 (新しい順)
+    static HISTORY: RefCell<Vec<Entry>> = const { RefCell::new(vec![]) };
+}
+
+/// 読み上げたテキストと、そのときの音声と読み上げ速度
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub text: String,
+    pub voice_id: String,
+    pub rate: f64,
+}
+
+/// 履歴の先頭に追加する。同じテキストがあれば先頭に移し、古いものから [MAX_ENTRIES] を超えた分を捨てる
+pub fn push(entry: Entry) {
+    HISTORY.with_borrow_mut(|entries| add(entries, entry));
+}
+
+/// 新しい順の履歴
+pub fn entries() -> Vec<Entry> {
+    HISTORY.with_borrow(Clone::clone)
+}
+
+/// index 番目 (0 が最新) の履歴
+pub fn get(index: usize) -> Option<Entry> {
+    HISTORY.with_borrow(|entries| entries.get(index).cloned())
+}
+
+/// 履歴をすべて消す
+pub fn clear() {
+    HISTORY.with_borrow_mut(Vec::clear);
+}
+
+fn add(entries: &mut Vec<Entry>, entry: Entry) {
+    entries.retain(|e| e.text != entry.text);
+    entries.insert(0, entry);
+    entries.truncate(MAX_ENTRIES);
+}
+
+fn path() -> Result<PathBuf> {
+    Ok(settings::config_dir()?.join(FILE_NAME))
+}
+
+/// 履歴をファイルに保存する。空の場合はファイルを削除する
+pub fn save() -> Result<()> {
+    write(&path()?, &entries())
+}
+
+/// 保存した履歴を読み込む
+pub fn load() -> Result<()> {
+    let entries = read(&path()?)?;
+    HISTORY.with_borrow_mut(|history| *history = entries);
+    Ok(())
+}
+
+/// 保存した履歴を削除する (履歴を保存しない設定にしたとき)
+pub fn delete() -> Result<()> {
+    remove(&path()?)
+}
+
+/// 一行に一つ、`読み上げ速度<TAB>音声の ID<TAB>テキスト` の形式で書く
+fn serialize(entries: &[Entry]) -> String {
+    let mut text = String::new();
+    for entry in entries {
+        _ = writeln!(
+            text,
+            "{}\t{}\t{}",
+            entry.rate,
+            escape(&entry.voice_id),
+            escape(&entry.text)
+        );
+    }
+    text
+}
+
+/// [serialize] で書いたテキストを読む。読めない行は飛ばす
+fn parse(text: &str) -> Vec<Entry> {
+    let entries = text.lines().filter_map(|line| {
+        let mut fields = line.splitn(3, '\t');
+        Some(Entry {
+            rate: fields.next()?.parse().ok()?,
+            voice_id: unescape(fields.next()?),
+            text: unescape(fields.next()?),
+        })
+    });
+    entries.take(MAX_ENTRIES).collect()
+}
+
+/// 改行とタブを一行に収まるように `\n` などに置き換える
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+fn write(path: &Path, entries: &[Entry]) -> Result<()> {
+    if entries.is_empty() {
+        return remove(path);
+    }
+    fs::create_dir_all(path.parent().unwrap_or(path))?;
+    // 保存の途中で終了しても前回の履歴が壊れないように、別名で書いてから置き換える
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, serialize(entries))?;
+    fs::rename(temp, path)?;
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<Vec<Entry>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(parse(&text)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn remove(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(text: &str, rate: f64) -> Entry {
+        Entry {
+            text: text.into(),
+            voice_id: "HKEY\\Voices\\Haruka".into(),
+            rate,
+        }
+    }
+
+    #[test]
+    fn keep_recent_unique_entries() {
+        let mut entries = vec![];
+        for i in 0..MAX_ENTRIES + 5 {
+            add(&mut entries, entry(&i.to_string(), 1.0));
+        }
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0].text, (MAX_ENTRIES + 4).to_string());
+        // 同じテキストは先頭に移し、新しい音声と速度で覚え直す
+        add(&mut entries, entry("10", 1.5));
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0], entry("10", 1.5));
+        assert_eq!(entries.iter().filter(|e| e.text == "10").count(), 1);
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let dir = std::env::temp_dir().join(format!("speech-history-test-{}", std::process::id()));
+        let path = dir.join(FILE_NAME);
+        let entries = vec![
+            entry("おはよう\r\n\tございます \\n", 1.2),
+            entry("はい", 0.8),
+        ];
+        write(&path, &entries).unwrap();
+        assert_eq!(read(&path).unwrap(), entries);
+        // 空にしたらファイルを消す
+        write(&path, &[]).unwrap();
+        assert!(!path.exists());
+        assert_eq!(read(&path).unwrap(), vec![]);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[test]
+    fn skip_broken_lines() {
+        let entries = parse("1.5\tvoice\ttext\nbroken\nfast\tvoice\ttext\n1\tvoice\t\n");
+        assert_eq!(
+            entries,
+            [
+                Entry {
+                    text: "text".into(),
+                    voice_id: "voice".into(),
+                    rate: 1.5,
+                },
+                Entry {
+                    text: "".into(),
+                    voice_id: "voice".into(),
+                    rate: 1.0,
+                },
+            ]
+        );
+    }
+}
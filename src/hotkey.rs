@@ -0,0 +1,238 @@
+use crate::strings::Msg;
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::str::FromStr;
+use windows::Win32::{
+    Foundation::HWND,
+    UI::{
+        Controls::{HOTKEYF_ALT, HOTKEYF_CONTROL, HOTKEYF_SHIFT},
+        Input::KeyboardAndMouse::{
+            RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL,
+            MOD_NOREPEAT, MOD_SHIFT, MOD_WIN, VIRTUAL_KEY, VK_BACK, VK_DELETE, VK_DOWN, VK_END,
+            VK_ESCAPE, VK_F1, VK_HOME, VK_INSERT, VK_LEFT, VK_NEXT, VK_PAUSE, VK_PRIOR, VK_RETURN,
+            VK_RIGHT, VK_SPACE, VK_TAB, VK_UP,
+        },
+        WindowsAndMessaging::{ACCEL, ACCEL_VIRT_FLAGS, FALT, FCONTROL, FSHIFT, FVIRTKEY},
+    },
+};
+
+/// クリップボード読み上げのホットキーの ID
+pub const ID_SPEAK_CLIPBOARD: i32 = 1;
+
+/// 修飾キーの名前 (設定ファイルでの表記)
+const MODIFIERS: [(&str, HOT_KEY_MODIFIERS); 4] = [
+    ("Ctrl", MOD_CONTROL),
+    ("Alt", MOD_ALT),
+    ("Shift", MOD_SHIFT),
+    ("Win", MOD_WIN),
+];
+
+/// 修飾キーとアクセラレータ・ホットキーコントロールのフラグの対応
+const MODIFIER_FLAGS: [(HOT_KEY_MODIFIERS, ACCEL_VIRT_FLAGS, u32); 3] = [
+    (MOD_CONTROL, FCONTROL, HOTKEYF_CONTROL),
+    (MOD_ALT, FALT, HOTKEYF_ALT),
+    (MOD_SHIFT, FSHIFT, HOTKEYF_SHIFT),
+];
+
+/// 英数字とファンクションキー以外のキーの名前
+const KEY_NAMES: [(&str, VIRTUAL_KEY); 16] = [
+    ("Space", VK_SPACE),
+    ("Enter", VK_RETURN),
+    ("Esc", VK_ESCAPE),
+    ("Tab", VK_TAB),
+    ("Backspace", VK_BACK),
+    ("Delete", VK_DELETE),
+    ("Insert", VK_INSERT),
+    ("Home", VK_HOME),
+    ("End", VK_END),
+    ("PageUp", VK_PRIOR),
+    ("PageDown", VK_NEXT),
+    ("Left", VK_LEFT),
+    ("Up", VK_UP),
+    ("Right", VK_RIGHT),
+    ("Down", VK_DOWN),
+    ("Pause", VK_PAUSE),
+];
+
+/// ショートカットキーを設定できる操作
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    /// クリップボードを読み上げる (ほかのアプリケーションを使っているときも有効)
+    SpeakClipboard,
+    /// 再生する
+    Play,
+    /// 一時停止・再開する
+    Pause,
+    /// 停止する
+    Stop,
+}
+
+impl Action {
+    /// すべての操作 (設定ダイアログに表示する順)
+    pub const ALL: [Self; 4] = [Self::SpeakClipboard, Self::Play, Self::Pause, Self::Stop];
+
+    /// 既定のショートカットキー
+    pub fn default_hotkey(self) -> Hotkey {
+        let (modifiers, key) = match self {
+            Self::SpeakClipboard => (HOT_KEY_MODIFIERS(MOD_CONTROL.0 | MOD_ALT.0), VK_SPACE),
+            Self::Play => (MOD_CONTROL, VK_RETURN),
+            Self::Pause => (MOD_CONTROL, VIRTUAL_KEY(b'P' as _)),
+            Self::Stop => (HOT_KEY_MODIFIERS(0), VK_ESCAPE),
+        };
+        Hotkey {
+            modifiers,
+            key: key.0,
+        }
+    }
+
+    /// 設定ファイルでのキー
+    pub fn setting_key(self) -> &'static str {
+        match self {
+            Self::SpeakClipboard => "clipboard_hotkey",
+            Self::Play => "play_hotkey",
+            Self::Pause => "pause_hotkey",
+            Self::Stop => "stop_hotkey",
+        }
+    }
+
+    /// 設定ダイアログに表示する名前
+    pub fn label(self) -> Msg {
+        match self {
+            Self::SpeakClipboard => Msg::HotkeySpeakClipboard,
+            Self::Play => Msg::HotkeyPlay,
+            Self::Pause => Msg::HotkeyPause,
+            Self::Stop => Msg::HotkeyStop,
+        }
+    }
+
+    /// RegisterHotKey で登録するときの ID (None ならアクセラレータテーブルで処理する)
+    pub fn hotkey_id(self) -> Option<i32> {
+        match self {
+            Self::SpeakClipboard => Some(ID_SPEAK_CLIPBOARD),
+            _ => None,
+        }
+    }
+}
+
+/// 修飾キーと仮想キーコードの組み合わせ (`Ctrl+Alt+Space` 形式で保存する)
+#[derive(Clone, Copy, PartialEq)]
+pub struct Hotkey {
+    modifiers: HOT_KEY_MODIFIERS,
+    key: u16,
+}
+
+impl Hotkey {
+    /// アクセラレータテーブルの項目に変換する (Win キーは使えないので無視する)
+    pub fn to_accel(self, cmd: u16) -> ACCEL {
+        let fvirt = MODIFIER_FLAGS
+            .iter()
+            .filter(|(modifier, _, _)| self.modifiers.0 & modifier.0 != 0)
+            .fold(FVIRTKEY, |acc, (_, flag, _)| acc | *flag);
+        ACCEL {
+            fVirt: fvirt,
+            key: self.key,
+            cmd,
+        }
+    }
+
+    /// ホットキーコントロールの値 (下位バイトが仮想キーコード、上位バイトが修飾キー) から変換する
+    ///
+    /// キーが入力されていない場合は None を返す。
+    pub fn from_control_value(value: usize) -> Option<Self> {
+        let key = (value & 0xff) as u16;
+        let flags = ((value >> 8) & 0xff) as u32;
+        if key == 0 {
+            return None;
+        }
+        let modifiers = MODIFIER_FLAGS
+            .iter()
+            .filter(|(_, _, flag)| flags & flag != 0)
+            .fold(HOT_KEY_MODIFIERS(0), |acc, (modifier, _, _)| {
+                acc | *modifier
+            });
+        Some(Self { modifiers, key })
+    }
+
+    /// ホットキーコントロールに設定する値
+    pub fn to_control_value(self) -> usize {
+        let flags = MODIFIER_FLAGS
+            .iter()
+            .filter(|(modifier, _, _)| self.modifiers.0 & modifier.0 != 0)
+            .fold(0, |acc, (_, _, flag)| acc | flag);
+        self.key as usize | (flags as usize) << 8
+    }
+}
+
+impl FromStr for Hotkey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split('+').map(str::trim).collect::<Vec<_>>();
+        let key = parse_key(parts.pop().context("no key.")?)?;
+        let modifiers = parts.iter().try_fold(HOT_KEY_MODIFIERS(0), |acc, name| {
+            MODIFIERS
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, m)| acc | *m)
+                .with_context(|| format!("unknown modifier: {name}"))
+        })?;
+        Ok(Self { modifiers, key })
+    }
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, modifier) in MODIFIERS {
+            if self.modifiers.0 & modifier.0 != 0 {
+                write!(f, "{name}+")?;
+            }
+        }
+        if let Some((name, _)) = KEY_NAMES.iter().find(|(_, vk)| vk.0 == self.key) {
+            return f.write_str(name);
+        }
+        match self.key {
+            key if (VK_F1.0..VK_F1.0 + 24).contains(&key) => write!(f, "F{}", key - VK_F1.0 + 1),
+            // 小文字の範囲はテンキーの仮想キーコードなので英大文字と数字だけを名前で表す
+            key if key < 0x80
+                && ((key as u8).is_ascii_uppercase() || (key as u8).is_ascii_digit()) =>
+            {
+                write!(f, "{}", key as u8 as char)
+            }
+            // 名前のないキーは仮想キーコードで表す
+            key => write!(f, "#{key}"),
+        }
+    }
+}
+
+/// キーの名前を仮想キーコードに変換する
+fn parse_key(name: &str) -> Result<u16> {
+    if let Some((_, vk)) = KEY_NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+        return Ok(vk.0);
+    }
+    if let Some(code) = name.strip_prefix('#').and_then(|n| n.parse().ok()) {
+        return Ok(code);
+    }
+    if let Some(n) = name
+        .strip_prefix(['F', 'f'])
+        .and_then(|n| n.parse::<u16>().ok())
+    {
+        if (1..=24).contains(&n) {
+            return Ok(VK_F1.0 + n - 1);
+        }
+    }
+    match name.as_bytes() {
+        [c] if c.is_ascii_alphanumeric() => Ok(c.to_ascii_uppercase() as u16),
+        _ => bail!("unknown key: {name}"),
+    }
+}
+
+/// ホットキーを登録する。ほかのアプリケーションが使っている場合は失敗する
+pub fn register(hwnd: HWND, id: i32, hotkey: Hotkey) -> Result<()> {
+    unsafe { RegisterHotKey(hwnd, id, hotkey.modifiers | MOD_NOREPEAT, hotkey.key as _) }
+        .with_context(|| format!("failed to register {hotkey}."))
+}
+
+/// ホットキーの登録を解除する
+pub fn unregister(hwnd: HWND, id: i32) {
+    _ = unsafe { UnregisterHotKey(hwnd, id) };
+}
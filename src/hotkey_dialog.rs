@@ -0,0 +1,196 @@
+use crate::dialog::{self, create_control};
+use crate::dpi::LogicalRect;
+use crate::hotkey::{Action, Hotkey};
+use crate::settings;
+use crate::strings::{self, Msg};
+use anyhow::Result;
+use windows::{
+    core::{w, HSTRING, PCWSTR},
+    Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+        UI::{
+            Controls::{HKM_GETHOTKEY, HKM_SETHOTKEY, HOTKEY_CLASSW},
+            Input::KeyboardAndMouse::SetFocus,
+            WindowsAndMessaging::{
+                DefWindowProcW, GetDlgItem, GetWindow, SendMessageW, BS_DEFPUSHBUTTON,
+                BS_PUSHBUTTON, GW_OWNER, IDCANCEL, IDOK, WINDOW_STYLE, WM_CLOSE, WM_COMMAND,
+                WM_CREATE, WS_BORDER, WS_TABSTOP,
+            },
+        },
+    },
+};
+
+/// ショートカットキー設定ダイアログのクラス名
+const CLASS_NAME: PCWSTR = w!("speech_hotkey_cls42");
+/// 既定に戻すボタンの ID
+const ID_RESET: u16 = 100;
+/// ホットキーコントロールの ID (操作の順に割り当てる)
+const ID_HOTKEY_BASE: u16 = 200;
+/// ダイアログのクライアント領域の大きさ (96 DPI 基準)
+const CLIENT_SIZE: (i32, i32) = (360, 190);
+/// 1 行の高さ
+const ROW_HEIGHT: i32 = 32;
+/// 操作名のラベルの配置 (1 行目)
+const LABEL_RECT: LogicalRect = LogicalRect::new(12, 16, 170, 20);
+/// ホットキーコントロールの配置 (1 行目)
+const HOTKEY_RECT: LogicalRect = LogicalRect::new(190, 12, 158, 24);
+/// 既定に戻すボタンの配置
+const RESET_RECT: LogicalRect = LogicalRect::new(12, 150, 120, 28);
+/// OK ボタンの配置
+const OK_RECT: LogicalRect = LogicalRect::new(172, 150, 84, 28);
+/// キャンセルボタンの配置
+const CANCEL_RECT: LogicalRect = LogicalRect::new(264, 150, 84, 28);
+
+/// ショートカットキー設定ダイアログをモーダルで表示する
+///
+/// ダイアログが閉じられるまで戻らない。
+pub fn show(owner: HWND) -> Result<()> {
+    dialog::show_modal(
+        owner,
+        CLASS_NAME,
+        Some(wnd_proc),
+        Msg::HotkeyTitle,
+        CLIENT_SIZE,
+    )
+}
+
+/// 1 行目の配置を i 行目にずらす
+fn row(rect: LogicalRect, i: usize) -> LogicalRect {
+    LogicalRect {
+        y: rect.y + ROW_HEIGHT * i as i32,
+        ..rect
+    }
+}
+
+fn create(hwnd: HWND) -> Result<()> {
+    let hotkeys = Action::ALL.map(|action| settings::get().hotkey(action));
+    for (i, action) in Action::ALL.into_iter().enumerate() {
+        create_control(
+            hwnd,
+            w!("STATIC"),
+            &strings::wide(action.label()),
+            WINDOW_STYLE::default(),
+            row(LABEL_RECT, i),
+            0,
+        )?;
+        let control = create_control(
+            hwnd,
+            HOTKEY_CLASSW,
+            &HSTRING::new(),
+            WS_BORDER | WS_TABSTOP,
+            row(HOTKEY_RECT, i),
+            ID_HOTKEY_BASE + i as u16,
+        )?;
+        set_hotkey(control, hotkeys[i]);
+    }
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ButtonReset),
+        WINDOW_STYLE(BS_PUSHBUTTON as _) | WS_TABSTOP,
+        RESET_RECT,
+        ID_RESET,
+    )?;
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ButtonOk),
+        WINDOW_STYLE(BS_DEFPUSHBUTTON as _) | WS_TABSTOP,
+        OK_RECT,
+        IDOK.0 as _,
+    )?;
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ButtonCancel),
+        WINDOW_STYLE(BS_PUSHBUTTON as _) | WS_TABSTOP,
+        CANCEL_RECT,
+        IDCANCEL.0 as _,
+    )?;
+    _ = unsafe { SetFocus(GetDlgItem(hwnd, ID_HOTKEY_BASE as _)?) };
+    Ok(())
+}
+
+fn set_hotkey(control: HWND, hotkey: Hotkey) {
+    unsafe {
+        SendMessageW(
+            control,
+            HKM_SETHOTKEY,
+            WPARAM(hotkey.to_control_value()),
+            None,
+        )
+    };
+}
+
+/// ホットキーコントロールに入力された組み合わせ。空の場合は既定のキーにする
+fn get_hotkeys(hwnd: HWND) -> Result<Vec<(Action, Hotkey)>> {
+    Action::ALL
+        .into_iter()
+        .enumerate()
+        .map(|(i, action)| {
+            let control = unsafe { GetDlgItem(hwnd, (ID_HOTKEY_BASE + i as u16) as _)? };
+            let value = unsafe { SendMessageW(control, HKM_GETHOTKEY, None, None) }.0 as usize;
+            let hotkey =
+                Hotkey::from_control_value(value).unwrap_or_else(|| action.default_hotkey());
+            Ok((action, hotkey))
+        })
+        .collect()
+}
+
+fn reset(hwnd: HWND) -> Result<()> {
+    for (i, action) in Action::ALL.into_iter().enumerate() {
+        let control = unsafe { GetDlgItem(hwnd, (ID_HOTKEY_BASE + i as u16) as _)? };
+        set_hotkey(control, action.default_hotkey());
+    }
+    Ok(())
+}
+
+/// 入力されたショートカットキーをメインウィンドウに適用する
+///
+/// ほかのアプリケーションが使っているキーを登録できなかった場合は、ダイアログを開いたままにする。
+fn apply(hwnd: HWND) -> Result<()> {
+    let hotkeys = get_hotkeys(hwnd)?;
+    let owner = unsafe { GetWindow(hwnd, GW_OWNER)? };
+    crate::apply_hotkeys(owner, &hotkeys)?;
+    dialog::close(hwnd)
+}
+
+fn command(hwnd: HWND, wparam: WPARAM) -> Result<()> {
+    let id = crate::loword(wparam.0 as _);
+    if id == ID_RESET {
+        reset(hwnd)?;
+    } else if id == IDOK.0 as u16 {
+        if let Err(e) = apply(hwnd) {
+            crate::report_error(hwnd, Msg::ErrorHotkey, &e);
+        }
+    } else if id == IDCANCEL.0 as u16 {
+        dialog::close(hwnd)?;
+    }
+    Ok(())
+}
+
+/// ダイアログのウィンドウプロシージャ
+unsafe extern "system" fn wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_CREATE => {
+            if create(hwnd).is_err() {
+                return LRESULT(-1);
+            }
+        }
+        WM_COMMAND => {
+            if let Err(e) = command(hwnd, wparam) {
+                crate::report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+        WM_CLOSE => {
+            dialog::close(hwnd).ok();
+        }
+        _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+    LRESULT::default()
+}
@@ -0,0 +1,86 @@
+use anyhow::Result;
+use windows::{
+    core::PCWSTR,
+    Win32::{
+        System::LibraryLoader::GetModuleHandleW,
+        UI::{
+            HiDpi::GetSystemMetricsForDpi,
+            WindowsAndMessaging::{
+                LoadImageW, HICON, IMAGE_ICON, LR_DEFAULTCOLOR, LR_SHARED, SM_CXICON, SM_CXSMICON,
+                SM_CYICON, SM_CYSMICON, SYSTEM_METRICS_INDEX,
+            },
+        },
+    },
+};
+
+/// アプリケーションアイコンのリソース ID (res/speech.rc と合わせること)
+pub const IDI_APP: u16 = 1;
+/// ツールバーの再生アイコンのリソース ID
+pub const IDI_PLAY: u16 = 101;
+/// ツールバーの一時停止アイコンのリソース ID
+pub const IDI_PAUSE: u16 = 102;
+/// ツールバーの停止アイコンのリソース ID
+pub const IDI_STOP: u16 = 103;
+/// ツールバーの開くアイコンのリソース ID
+pub const IDI_OPEN: u16 = 104;
+/// ツールバーの保存アイコンのリソース ID
+pub const IDI_SAVE: u16 = 105;
+/// ツールバーの設定アイコンのリソース ID
+pub const IDI_SETTINGS: u16 = 106;
+/// ツールバーの前の文アイコンのリソース ID
+pub const IDI_PREVIOUS: u16 = 107;
+/// ツールバーの次の文アイコンのリソース ID
+pub const IDI_NEXT: u16 = 108;
+
+/// リソース ID を文字列ポインタとして渡すためのヘルパー関数 (MAKEINTRESOURCE)
+pub fn make_int_resource(id: u16) -> PCWSTR {
+    PCWSTR(id as usize as _)
+}
+
+fn load(cx: SYSTEM_METRICS_INDEX, cy: SYSTEM_METRICS_INDEX, dpi: u32) -> Result<HICON> {
+    let (width, height) = unsafe {
+        (
+            GetSystemMetricsForDpi(cx, dpi),
+            GetSystemMetricsForDpi(cy, dpi),
+        )
+    };
+    // システム標準の大きさで読み込むので LR_SHARED で共有してよい
+    let icon = unsafe {
+        LoadImageW(
+            GetModuleHandleW(None)?,
+            make_int_resource(IDI_APP),
+            IMAGE_ICON,
+            width,
+            height,
+            LR_DEFAULTCOLOR | LR_SHARED,
+        )?
+    };
+    Ok(HICON(icon.0))
+}
+
+/// アイコンリソースを指定した大きさで読み込む
+///
+/// 返したアイコンは共有されないので、不要になったら DestroyIcon で破棄すること。
+pub fn load_sized(id: u16, size: i32) -> Result<HICON> {
+    let icon = unsafe {
+        LoadImageW(
+            GetModuleHandleW(None)?,
+            make_int_resource(id),
+            IMAGE_ICON,
+            size,
+            size,
+            LR_DEFAULTCOLOR,
+        )?
+    };
+    Ok(HICON(icon.0))
+}
+
+/// 指定した DPI のタイトルバーやタスクトレイ用の小さいアイコンを読み込む
+pub fn load_small(dpi: u32) -> Result<HICON> {
+    load(SM_CXSMICON, SM_CYSMICON, dpi)
+}
+
+/// 指定した DPI のタスクバーや Alt+Tab 用の大きいアイコンを読み込む
+pub fn load_large(dpi: u32) -> Result<HICON> {
+    load(SM_CXICON, SM_CYICON, dpi)
+}
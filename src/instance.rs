@@ -0,0 +1,260 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::slice;
+use windows::{
+    core::{w, PCWSTR},
+    Win32::{
+        Foundation::{CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, LPARAM},
+        System::{DataExchange::COPYDATASTRUCT, Threading::CreateMutexW},
+        UI::WindowsAndMessaging::{
+            AllowSetForegroundWindow, FindWindowW, GetWindowThreadProcessId, SendMessageW,
+            WM_COPYDATA,
+        },
+    },
+};
+
+/// 起動済みのインスタンスを見つけるためのミューテックスの名前
+const MUTEX_NAME: PCWSTR = w!("speech_single_instance_mutex42");
+/// WM_COPYDATA で起動済みのインスタンスに送ったデータであることを表す値 (ほかのプログラム向けの操作と重ならない値にする)
+const DATA_MAGIC: usize = 0x5350_0000;
+/// WM_COPYDATA で引数がないことを表す種類
+const DATA_NONE: usize = DATA_MAGIC | 0x10;
+/// WM_COPYDATA でファイルパスを送ることを表す種類
+const DATA_FILE: usize = DATA_MAGIC | 0x11;
+/// WM_COPYDATA でテキストを送ることを表す種類
+const DATA_TEXT: usize = DATA_MAGIC | 0x12;
+/// WM_COPYDATA で開いた後に再生することを表すフラグ
+const DATA_PLAY: usize = 0x100;
+/// WM_COPYDATA で WAV ファイルに書き出すことを表すフラグ (先頭の要素が書き出し先)
+const DATA_EXPORT: usize = 0x200;
+/// WM_COPYDATA でクリップボードのテキストを読み上げることを表すフラグ
+const DATA_CLIPBOARD: usize = 0x400;
+/// WM_COPYDATA で受け取るテキストの最大の長さ (UTF-16 単位)
+const DATA_MAX_LEN: usize = 16 * 1024 * 1024;
+/// WM_COPYDATA で複数のパスを区切る文字
+const DATA_SEPARATOR: char = '\0';
+/// 開いた後に再生するコマンドラインオプション
+const PLAY_OPTION: &str = "--play";
+/// クリップボードのテキストを読み込んで読み上げるコマンドラインオプション
+const SPEAK_CLIPBOARD_OPTION: &str = "--speak-clipboard";
+/// ファイルを WAV に書き出すフォルダーを指定するコマンドラインオプション
+const EXPORT_DIR_OPTION: &str = "--export-dir";
+
+/// コマンドライン引数で渡されたファイルまたはテキスト
+pub enum Argument {
+    /// 開くファイル (二つ目以降は再生待ちに追加する)
+    Files(Vec<PathBuf>),
+    Text(String),
+}
+
+/// 解釈したコマンドライン
+pub struct CommandLine {
+    /// 開くファイルまたはテキスト
+    pub argument: Option<Argument>,
+    /// 開いた後に再生するかどうか (`--play`)
+    pub play: bool,
+    /// ファイルを開かずに WAV に書き出すフォルダー (`--export-dir`)
+    pub export_dir: Option<PathBuf>,
+    /// 開くものがなければ、クリップボードのテキストを読み込んで読み上げる (`--speak-clipboard`)
+    pub speak_clipboard: bool,
+}
+
+impl CommandLine {
+    /// コマンドライン引数を解釈する
+    ///
+    /// 引数がすべてファイルまたはファイル名らしいもの (拡張子があり空白を含まない) ならファイル、
+    /// それ以外はテキストとして扱う。存在しないファイルは開くときにエラーになる。
+    pub fn from_args() -> Self {
+        let mut play = false;
+        let mut speak_clipboard = false;
+        let mut export_dir = None;
+        let mut args = vec![];
+        let mut iter = std::env::args_os().skip(1);
+        while let Some(arg) = iter.next() {
+            if arg == PLAY_OPTION {
+                play = true;
+            } else if arg == SPEAK_CLIPBOARD_OPTION {
+                speak_clipboard = true;
+            } else if arg == EXPORT_DIR_OPTION {
+                export_dir = iter.next().map(PathBuf::from);
+            } else {
+                args.push(arg);
+            }
+        }
+        let argument = if args.is_empty() {
+            None
+        } else if args.iter().all(|arg| looks_like_file(Path::new(arg))) {
+            Some(Argument::Files(
+                args.into_iter().map(PathBuf::from).collect(),
+            ))
+        } else {
+            Some(Argument::Text(
+                args.iter()
+                    .map(|arg| arg.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ))
+        };
+        Self {
+            argument,
+            play,
+            export_dir,
+            speak_clipboard,
+        }
+    }
+
+    /// WM_COPYDATA で受け取ったデータから復元する (このアプリが送ったデータでなければ None)
+    ///
+    /// # Safety
+    /// `lparam` は WM_COPYDATA で渡された [COPYDATASTRUCT] を指していること。
+    pub unsafe fn from_copy_data(lparam: LPARAM) -> Option<Self> {
+        let data = &*(lparam.0 as *const COPYDATASTRUCT);
+        let len = data.cbData as usize;
+        // 長さの 2 単位より短いデータや、奇数バイトのデータは読まない
+        if data.lpData.is_null() || len < 4 || len % 2 != 0 || len / 2 > DATA_MAX_LEN + 2 {
+            return None;
+        }
+        Self::decode(
+            data.dwData,
+            slice::from_raw_parts(data.lpData as *const u16, len / 2),
+        )
+    }
+
+    /// 先頭の 2 単位にテキストの長さが入ったデータから復元する
+    fn decode(kind: usize, data: &[u16]) -> Option<Self> {
+        let flags = DATA_PLAY | DATA_EXPORT | DATA_CLIPBOARD;
+        let [low, high, wide @ ..] = data else {
+            return None;
+        };
+        let len = *low as usize | ((*high as usize) << 16);
+        if len != wide.len() || len > DATA_MAX_LEN {
+            return None;
+        }
+        let export = kind & DATA_EXPORT != 0;
+        let mut command_line = Self {
+            argument: None,
+            play: kind & DATA_PLAY != 0,
+            export_dir: None,
+            speak_clipboard: kind & DATA_CLIPBOARD != 0,
+        };
+        let text = String::from_utf16_lossy(wide);
+        command_line.argument = match kind & !flags {
+            DATA_FILE => {
+                let mut paths = text.split(DATA_SEPARATOR).map(PathBuf::from);
+                if export {
+                    command_line.export_dir = paths.next();
+                }
+                Some(Argument::Files(paths.collect()))
+            }
+            DATA_TEXT => Some(Argument::Text(text)),
+            DATA_NONE if len == 0 && !export => None,
+            _ => return None,
+        };
+        Some(command_line)
+    }
+
+    /// 送るデータの種類と、先頭にテキストの長さを付けたデータ
+    fn to_copy_data(&self) -> (usize, Vec<u16>) {
+        let (mut kind, data) = match &self.argument {
+            Some(Argument::Files(paths)) => {
+                let paths = self
+                    .export_dir
+                    .iter()
+                    .chain(paths)
+                    .map(|path| path.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(&DATA_SEPARATOR.to_string());
+                (DATA_FILE, paths.encode_utf16().collect())
+            }
+            Some(Argument::Text(text)) => (DATA_TEXT, text.encode_utf16().collect()),
+            None => (DATA_NONE, vec![]),
+        };
+        if self.play {
+            kind |= DATA_PLAY;
+        }
+        if self.export_dir.is_some() {
+            kind |= DATA_EXPORT;
+        }
+        if self.speak_clipboard {
+            kind |= DATA_CLIPBOARD;
+        }
+        let len = data.len().min(DATA_MAX_LEN);
+        let prefix = [len as u16, (len >> 16) as u16];
+        (
+            kind,
+            prefix
+                .into_iter()
+                .chain(data.into_iter().take(len))
+                .collect(),
+        )
+    }
+}
+
+/// ファイルとして開く引数かどうか
+fn looks_like_file(path: &Path) -> bool {
+    path.is_file()
+        || (path.extension().is_some() && !path.to_string_lossy().contains(char::is_whitespace))
+}
+
+/// 起動済みのインスタンスがあれば引数を転送して前面に出す
+///
+/// 転送した場合は true を返すので、呼び出し側はそのまま終了すること。
+pub fn forward_to_existing(class_name: PCWSTR, command_line: &CommandLine) -> Result<bool> {
+    let mutex = unsafe { CreateMutexW(None, false, MUTEX_NAME)? };
+    if unsafe { GetLastError() } != ERROR_ALREADY_EXISTS {
+        // ミューテックスはプロセスの終了まで保持する
+        return Ok(false);
+    }
+    _ = unsafe { CloseHandle(mutex) };
+    // 先に起動したインスタンスがまだウィンドウを生成していない場合は、そのまま起動する
+    let Ok(hwnd) = (unsafe { FindWindowW(class_name, None) }) else {
+        return Ok(false);
+    };
+    let mut pid = 0;
+    unsafe { GetWindowThreadProcessId(hwnd, Some(&mut pid)) };
+    // 受け取った側が自分でウィンドウを前面に出せるようにする
+    _ = unsafe { AllowSetForegroundWindow(pid) };
+    let (kind, data) = command_line.to_copy_data();
+    let copy_data = COPYDATASTRUCT {
+        dwData: kind,
+        cbData: (data.len() * 2) as _,
+        lpData: data.as_ptr() as _,
+    };
+    unsafe { SendMessageW(hwnd, WM_COPYDATA, None, LPARAM(&copy_data as *const _ as _)) };
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_data_round_trip() {
+        let command_line = CommandLine {
+            argument: Some(Argument::Text("こんにちは".into())),
+            play: true,
+            export_dir: None,
+            speak_clipboard: false,
+        };
+        let (kind, data) = command_line.to_copy_data();
+        let decoded = CommandLine::decode(kind, &data).unwrap();
+        assert!(decoded.play);
+        assert!(matches!(decoded.argument, Some(Argument::Text(text)) if text == "こんにちは"));
+    }
+
+    #[test]
+    fn reject_unexpected_copy_data() {
+        let (kind, data) = CommandLine {
+            argument: Some(Argument::Text("abc".into())),
+            play: false,
+            export_dir: None,
+            speak_clipboard: false,
+        }
+        .to_copy_data();
+        // 種類が違う、長さが合わない、長さがない
+        assert!(CommandLine::decode(0x12, &data).is_none());
+        assert!(CommandLine::decode(kind, &data[..4]).is_none());
+        assert!(CommandLine::decode(kind, &[3]).is_none());
+        assert!(CommandLine::decode(DATA_NONE, &[0, 0]).is_some());
+    }
+}
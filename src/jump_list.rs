@@ -0,0 +1,105 @@
+use crate::settings;
+use crate::strings::{tr, Msg};
+use anyhow::Result;
+use std::path::Path;
+use windows::{
+    core::{Interface, HSTRING, PROPVARIANT},
+    Win32::{
+        Storage::EnhancedStorage::PKEY_Title,
+        System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER},
+        UI::Shell::{
+            Common::{IObjectArray, IObjectCollection},
+            DestinationList, EnumerableObjectCollection, ICustomDestinationList, IShellLinkW,
+            PropertiesSystem::IPropertyStore,
+            ShellLink,
+        },
+    },
+};
+
+/// 最近開いたファイルの最大数 (ジャンプリストとファイルメニューに表示する)
+pub const MAX_RECENT_FILES: usize = 10;
+
+/// 最近開いたファイルの先頭に追加し、ジャンプリストを作り直す
+pub fn add_recent_file(path: &Path) -> Result<()> {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    settings::update(|s| {
+        s.recent_files.retain(|p| p != &path);
+        s.recent_files.insert(0, path);
+        s.recent_files.truncate(MAX_RECENT_FILES);
+    })?;
+    update()
+}
+
+/// 最近開いたファイルからジャンプリストを作り直す
+///
+/// 存在しなくなったファイルと、ユーザーがジャンプリストから削除したファイルは取り除く。
+pub fn update() -> Result<()> {
+    let list: ICustomDestinationList =
+        unsafe { CoCreateInstance(&DestinationList, None, CLSCTX_INPROC_SERVER)? };
+    let mut min_slots = 0;
+    let removed: IObjectArray = unsafe { list.BeginList(&mut min_slots)? };
+    let removed = removed_arguments(&removed)?;
+    let files = {
+        let mut settings = settings::get();
+        settings
+            .recent_files
+            .retain(|path| path.exists() && !removed.contains(&quote(path)));
+        let files = settings.recent_files.clone();
+        settings.save()?;
+        files
+    };
+
+    let items: IObjectCollection =
+        unsafe { CoCreateInstance(&EnumerableObjectCollection, None, CLSCTX_INPROC_SERVER)? };
+    let exe = HSTRING::from(std::env::current_exe()?.as_os_str());
+    for path in &files {
+        unsafe { items.AddObject(&create_link(&exe, path)?)? };
+    }
+    unsafe {
+        list.AppendCategory(
+            &HSTRING::from(tr(Msg::JumpListRecent)),
+            &items.cast::<IObjectArray>()?,
+        )?
+    };
+    unsafe { list.CommitList()? };
+    Ok(())
+}
+
+/// ファイルパスを引数にしてこの exe を起動するショートカットを作る
+fn create_link(exe: &HSTRING, path: &Path) -> Result<IShellLinkW> {
+    let link: IShellLinkW = unsafe { CoCreateInstance(&ShellLink, None, CLSCTX_INPROC_SERVER)? };
+    unsafe {
+        link.SetPath(exe)?;
+        link.SetArguments(&HSTRING::from(quote(path)))?;
+        link.SetIconLocation(exe, 0)?;
+        link.SetDescription(&HSTRING::from(path.as_os_str()))?;
+    }
+    // ジャンプリストにはタイトルとしてファイル名を表示する
+    let title = path
+        .file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy();
+    let store: IPropertyStore = link.cast()?;
+    unsafe { store.SetValue(&PKEY_Title, &PROPVARIANT::from(title.as_ref()))? };
+    unsafe { store.Commit()? };
+    Ok(link)
+}
+
+/// ユーザーがジャンプリストから削除した項目の引数を取り出す
+fn removed_arguments(removed: &IObjectArray) -> Result<Vec<String>> {
+    let count = unsafe { removed.GetCount()? };
+    (0..count)
+        .map(|i| {
+            let link: IShellLinkW = unsafe { removed.GetAt(i)? };
+            let mut buf = [0; 1024];
+            unsafe { link.GetArguments(&mut buf)? };
+            let len = buf.iter().position(|c| *c == 0).unwrap_or(buf.len());
+            Ok(String::from_utf16_lossy(&buf[..len]))
+        })
+        .collect()
+}
+
+/// コマンドライン引数として渡せるようにパスを引用符で囲む
+fn quote(path: &Path) -> String {
+    format!("\"{}\"", path.display())
+}
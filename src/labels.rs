@@ -0,0 +1,178 @@
+use crate::dpi;
+use anyhow::Result;
+use windows::Win32::{
+    Foundation::{HWND, POINT, RECT},
+    Graphics::Gdi::{
+        BeginPaint, BitBlt, CreateCompatibleBitmap, CreateCompatibleDC, DeleteDC, DeleteObject,
+        DrawTextW, EndPaint, FillRect, InvalidateRect, ScreenToClient, SelectObject, SetBkMode,
+        SetViewportOrgEx, DRAW_TEXT_FORMAT, DT_BOTTOM, DT_CENTER, DT_LEFT, DT_NOPREFIX, DT_RIGHT,
+        DT_SINGLELINE, DT_VCENTER, HBRUSH, HDC, HFONT, PAINTSTRUCT, SRCCOPY, TRANSPARENT,
+    },
+    UI::WindowsAndMessaging::{GetClientRect, GetDlgItem, GetWindowRect, IsWindowVisible},
+};
+
+/// ラベルとコントロールの間隔 (96 DPI 基準)
+const GAP: i32 = 6;
+
+/// 基準となるコントロールから見たラベルの位置
+#[derive(Clone, Copy)]
+pub enum Side {
+    /// 左側に右寄せで描画する
+    Left,
+    /// 右側に左寄せで描画する
+    Right,
+    /// 上側に中央寄せで描画する
+    Above,
+}
+
+/// 子コントロールの隣に描画するラベル
+pub struct Label {
+    /// 基準とするコントロールの ID
+    pub control: u16,
+    pub side: Side,
+    /// ラベルを描画するウィンドウから表示する文字列を求める (None の場合は描画しない)
+    pub text: fn(HWND) -> Option<String>,
+}
+
+/// ラベルを描画する
+///
+/// ドラッグ中に頻繁に再描画してもちらつかないよう、メモリ DC に描画してから転送する。
+pub fn paint(hwnd: HWND, labels: &[Label], font: HFONT, background: HBRUSH) -> Result<()> {
+    let mut ps = PAINTSTRUCT::default();
+    let hdc = unsafe { BeginPaint(hwnd, &mut ps) };
+    let ret = draw_buffered(hwnd, hdc, ps.rcPaint, labels, font, background);
+    // 描画に失敗しても EndPaint を呼ばないと WM_PAINT が送られ続ける
+    unsafe { EndPaint(hwnd, &ps).ok()? };
+    ret
+}
+
+/// ラベルの領域だけを再描画させる
+pub fn invalidate(hwnd: HWND, label: &Label) -> Result<()> {
+    if let Some((rect, _)) = label_rect(hwnd, label)? {
+        _ = unsafe { InvalidateRect(hwnd, Some(&rect), false) };
+    }
+    Ok(())
+}
+
+fn draw_buffered(
+    hwnd: HWND,
+    hdc: HDC,
+    rc: RECT,
+    labels: &[Label],
+    font: HFONT,
+    background: HBRUSH,
+) -> Result<()> {
+    let (width, height) = (rc.right - rc.left, rc.bottom - rc.top);
+    if width <= 0 || height <= 0 {
+        return Ok(());
+    }
+    let mem_dc = unsafe { CreateCompatibleDC(hdc) };
+    let bitmap = unsafe { CreateCompatibleBitmap(hdc, width, height) };
+    let old_bitmap = unsafe { SelectObject(mem_dc, bitmap) };
+    // 再描画する範囲の左上がビットマップの原点になるようにずらす
+    _ = unsafe { SetViewportOrgEx(mem_dc, -rc.left, -rc.top, None) };
+    let ret = draw(hwnd, mem_dc, &rc, labels, font, background).and_then(|_| unsafe {
+        BitBlt(
+            hdc, rc.left, rc.top, width, height, mem_dc, rc.left, rc.top, SRCCOPY,
+        )?;
+        Ok(())
+    });
+    unsafe { SelectObject(mem_dc, old_bitmap) };
+    _ = unsafe { DeleteObject(bitmap) };
+    _ = unsafe { DeleteDC(mem_dc) };
+    ret
+}
+
+fn draw(
+    hwnd: HWND,
+    hdc: HDC,
+    rc: &RECT,
+    labels: &[Label],
+    font: HFONT,
+    background: HBRUSH,
+) -> Result<()> {
+    unsafe { FillRect(hdc, rc, background) };
+    unsafe { SetBkMode(hdc, TRANSPARENT) };
+    let old_font = unsafe { SelectObject(hdc, font) };
+    let ret = labels.iter().try_for_each(|label| -> Result<()> {
+        let (Some((mut rect, format)), Some(text)) = (label_rect(hwnd, label)?, (label.text)(hwnd))
+        else {
+            return Ok(());
+        };
+        let mut text = text.encode_utf16().collect::<Vec<_>>();
+        unsafe {
+            DrawTextW(
+                hdc,
+                &mut text,
+                &mut rect,
+                format | DT_SINGLELINE | DT_NOPREFIX,
+            )
+        };
+        Ok(())
+    });
+    unsafe { SelectObject(hdc, old_font) };
+    ret
+}
+
+/// コントロールの実際の位置からラベルを描画する領域を求める
+///
+/// コントロールが非表示の場合は None を返す。
+fn label_rect(hwnd: HWND, label: &Label) -> Result<Option<(RECT, DRAW_TEXT_FORMAT)>> {
+    let control = unsafe { GetDlgItem(hwnd, label.control as _)? };
+    if !unsafe { IsWindowVisible(control) }.as_bool() {
+        return Ok(None);
+    }
+    let mut client = RECT::default();
+    unsafe { GetClientRect(hwnd, &mut client)? };
+    let rc = control_rect(hwnd, control)?;
+    let gap = dpi::scale(GAP, dpi::dpi_for_window(hwnd));
+    let label_rect = match label.side {
+        Side::Left => (
+            RECT {
+                left: client.left,
+                right: rc.left - gap,
+                ..rc
+            },
+            DT_RIGHT | DT_VCENTER,
+        ),
+        Side::Right => (
+            RECT {
+                left: rc.right + gap,
+                right: client.right,
+                ..rc
+            },
+            DT_LEFT | DT_VCENTER,
+        ),
+        Side::Above => (
+            RECT {
+                top: client.top,
+                bottom: rc.top,
+                ..rc
+            },
+            DT_CENTER | DT_BOTTOM,
+        ),
+    };
+    Ok(Some(label_rect))
+}
+
+/// 子コントロールの位置を親ウィンドウのクライアント座標で求める
+fn control_rect(hwnd: HWND, control: HWND) -> Result<RECT> {
+    let mut rc = RECT::default();
+    unsafe { GetWindowRect(control, &mut rc)? };
+    let mut top_left = POINT {
+        x: rc.left,
+        y: rc.top,
+    };
+    let mut bottom_right = POINT {
+        x: rc.right,
+        y: rc.bottom,
+    };
+    unsafe { ScreenToClient(hwnd, &mut top_left).ok()? };
+    unsafe { ScreenToClient(hwnd, &mut bottom_right).ok()? };
+    Ok(RECT {
+        left: top_left.x,
+        top: top_left.y,
+        right: bottom_right.x,
+        bottom: bottom_right.y,
+    })
+}
@@ -0,0 +1,324 @@
+//! 文字の種類からテキストの言語を推定する
+//!
+//! 辞書は使わず、かな・漢字・ハングル・ラテン文字・キリル文字の数を比べるだけの簡単な判定。
+//! 漢字だけのテキストは中国語とは区別せず日本語とみなす。
+
+/// 言語を切り替えずに読む区間の大きさ。これより小さい区間 ("AI" や "PC" など) は前後の言語で読む
+///
+/// 大きさは [detect] と同じ重みで数えるので、漢字やかなは二文字、ラテン文字は四文字からになる。
+pub const MIN_SEGMENT_WEIGHT: usize = 4;
+
+/// 文字の種類から推定できる言語
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Language {
+    Japanese,
+    English,
+    Korean,
+    Russian,
+}
+
+impl Language {
+    /// 言語タグ (BCP 47) の主言語の部分
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::Japanese => "ja",
+            Language::English => "en",
+            Language::Korean => "ko",
+            Language::Russian => "ru",
+        }
+    }
+
+    /// 音声の言語タグ (`ja-JP` など) がこの言語かどうか
+    pub fn matches(self, tag: &str) -> bool {
+        primary_language(tag) == self.code()
+    }
+
+    /// 一文字あたりの重み。かなや漢字、ハングルは一文字でラテン文字の数文字分を表すので重くする
+    fn weight(self) -> usize {
+        match self {
+            Language::Japanese | Language::Korean => 2,
+            Language::English | Language::Russian => 1,
+        }
+    }
+}
+
+/// 言語タグの主言語の部分を小文字にしたもの (`ja-JP` なら `ja`)
+pub fn primary_language(tag: &str) -> String {
+    let primary = tag.split(['-', '_']).next().unwrap_or_default();
+    primary.to_ascii_lowercase()
+}
+
+/// 文字の種類から決まる言語。数字や記号、空白などどの言語でも使う文字は None
+pub fn language_of(c: char) -> Option<Language> {
+    match c {
+        // ひらがな、カタカナ、CJK 統合漢字、半角カタカナ
+        '\u{3040}'..='\u{30FF}'
+        | '\u{31F0}'..='\u{31FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FF66}'..='\u{FF9D}'
+        | '\u{20000}'..='\u{2FFFF}' => Some(Language::Japanese),
+        // ハングル字母とハングル音節
+        '\u{1100}'..='\u{11FF}' | '\u{3130}'..='\u{318F}' | '\u{AC00}'..='\u{D7AF}' => {
+            Some(Language::Korean)
+        }
+        '\u{0400}'..='\u{04FF}' => Some(Language::Russian),
+        c if c.is_ascii_alphabetic() => Some(Language::English),
+        // アクセント付きのラテン文字と全角の英字もラテン文字として数える
+        '\u{00C0}'..='\u{024F}' | '\u{FF21}'..='\u{FF3A}' | '\u{FF41}'..='\u{FF5A}' => {
+            Some(Language::English)
+        }
+        _ => None,
+    }
+}
+
+/// テキストで最も多く使われている文字の種類から言語を推定する。文字の種類が決まる文字がなければ None
+pub fn detect(text: &[u16]) -> Option<Language> {
+    const LANGUAGES: [Language; 4] = [
+        Language::Japanese,
+        Language::English,
+        Language::Korean,
+        Language::Russian,
+    ];
+    let mut counts = [0usize; LANGUAGES.len()];
+    for c in char::decode_utf16(text.iter().copied()).flatten() {
+        if let Some(language) = language_of(c) {
+            let index = LANGUAGES.iter().position(|&l| l == language).unwrap_or(0);
+            counts[index] += language.weight();
+        }
+    }
+    // 同じ数なら先に並べた言語にする
+    let (index, &count) = counts
+        .iter()
+        .enumerate()
+        .rev()
+        .max_by_key(|&(_, count)| count)?;
+    (count > 0).then_some(LANGUAGES[index])
+}
+
+/// 同じ言語で読む区間 (UTF-16 単位)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub start: usize,
+    pub end: usize,
+    pub language: Language,
+}
+
+/// テキストを同じ言語で読む区間に分ける。言語の決まる文字がなければ空を返す
+///
+/// 数字や記号、空白など言語の決まらない文字は前の区間に含める (先頭なら最初の区間)。
+/// 区間は隙間なく並び、最初の区間は 0 から、最後の区間は text.len() までになる。
+/// 大きさが min_weight より小さい区間は、前後の区間のうち大きいほうの言語で読む。
+pub fn segments(text: &[u16], min_weight: usize) -> Vec<Segment> {
+    // 言語の決まる文字が続く区間と、その大きさ
+    let mut runs: Vec<(Segment, usize)> = vec![];
+    let mut pos = 0;
+    for c in char::decode_utf16(text.iter().copied()).map(Result::ok) {
+        // 対になっていないサロゲートは一単位の記号として扱う
+        let len = c.map_or(1, char::len_utf16);
+        let language = c.and_then(language_of);
+        match (runs.last_mut(), language) {
+            (Some((last, weight)), Some(language)) if last.language == language => {
+                last.end = pos + len;
+                *weight += language.weight();
+            }
+            (Some((last, _)), None) => last.end = pos + len,
+            (_, Some(language)) => {
+                let segment = Segment {
+                    start: pos,
+                    end: pos + len,
+                    language,
+                };
+                runs.push((segment, language.weight()));
+            }
+            (None, None) => (),
+        }
+        pos += len;
+    }
+    if let Some((first, _)) = runs.first_mut() {
+        first.start = 0;
+    }
+    // 小さい区間から順に、隣の大きいほうの区間に含めていく
+    while runs.len() > 1 {
+        let smallest = runs
+            .iter()
+            .enumerate()
+            .filter(|(_, &(_, weight))| weight < min_weight)
+            .min_by_key(|(_, &(_, weight))| weight)
+            .map(|(i, _)| i);
+        let Some(i) = smallest else {
+            break;
+        };
+        let weight_at = |i: Option<usize>| i.and_then(|i| runs.get(i)).map(|&(_, weight)| weight);
+        let (before, after) = (i.checked_sub(1), Some(i + 1));
+        let into = match (weight_at(before), weight_at(after)) {
+            (Some(b), Some(a)) if a > b => i + 1,
+            (Some(_), _) => i - 1,
+            _ => i + 1,
+        };
+        let language = runs[into].0.language;
+        runs[i].0.language = language;
+        runs = coalesce(runs);
+    }
+    runs.into_iter().map(|(segment, _)| segment).collect()
+}
+
+/// 隣り合う同じ言語の区間をつなげる
+fn coalesce(runs: Vec<(Segment, usize)>) -> Vec<(Segment, usize)> {
+    let mut merged: Vec<(Segment, usize)> = Vec::with_capacity(runs.len());
+    for (segment, weight) in runs {
+        match merged.last_mut() {
+            Some((last, total)) if last.language == segment.language => {
+                last.end = segment.end;
+                *total += weight;
+            }
+            _ => merged.push((segment, weight)),
+        }
+    }
+    merged
+}
+
+/// 言語に合う音声を選ぶ。voices は音声ごとの (言語タグ, ID)
+///
+/// preferred (その言語で最後に選んだ音声の ID) が合う音声の中にあればそれを、なければ最初に見つかった音声を選ぶ。
+pub fn choose_voice<T: AsRef<str>>(
+    language: Language,
+    voices: &[(T, T)],
+    preferred: Option<&str>,
+) -> Option<usize> {
+    let matching = || {
+        voices
+            .iter()
+            .enumerate()
+            .filter(|(_, (tag, _))| language.matches(tag.as_ref()))
+    };
+    matching()
+        .find(|(_, (_, id))| Some(id.as_ref()) == preferred)
+        .or_else(|| matching().next())
+        .map(|(index, _)| index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_str(s: &str) -> Option<Language> {
+        detect(&s.encode_utf16().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn detect_single_language() {
+        assert_eq!(
+            detect_str("今日はいい天気ですね。"),
+            Some(Language::Japanese)
+        );
+        assert_eq!(detect_str("東京都"), Some(Language::Japanese));
+        assert_eq!(
+            detect_str("It's a fine day, isn't it?"),
+            Some(Language::English)
+        );
+        assert_eq!(detect_str("안녕하세요"), Some(Language::Korean));
+        assert_eq!(detect_str("Доброе утро"), Some(Language::Russian));
+    }
+
+    #[test]
+    fn detect_mixed_text_by_majority() {
+        // 日本語の文に英単語が混ざっていても日本語
+        assert_eq!(
+            detect_str("新しい MacBook Pro を買いました。"),
+            Some(Language::Japanese)
+        );
+        // 英語の文に日本語の単語が混ざっていても英語
+        assert_eq!(
+            detect_str("We had sushi and tempura in 東京 yesterday."),
+            Some(Language::English)
+        );
+    }
+
+    #[test]
+    fn nothing_to_detect() {
+        assert_eq!(detect_str(""), None);
+        assert_eq!(detect_str("  123-456 !? "), None);
+    }
+
+    /// 区間ごとのテキストと言語
+    fn segments_str(s: &str) -> Vec<(String, Language)> {
+        let text = s.encode_utf16().collect::<Vec<_>>();
+        segments(&text, MIN_SEGMENT_WEIGHT)
+            .into_iter()
+            .map(|segment| {
+                let part = String::from_utf16_lossy(&text[segment.start..segment.end]);
+                (part, segment.language)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_into_language_segments() {
+        use Language::*;
+        assert_eq!(
+            segments_str("新しい MacBook Pro を買いました。"),
+            [
+                ("新しい ".into(), Japanese),
+                ("MacBook Pro ".into(), English),
+                ("を買いました。".into(), Japanese),
+            ]
+        );
+        // 先頭と末尾の記号や空白も、どこかの区間に含める
+        assert_eq!(
+            segments_str("「Hello」と言った"),
+            [("「Hello」".into(), English), ("と言った".into(), Japanese),]
+        );
+        assert_eq!(segments_str("hello"), [("hello".into(), English)]);
+        assert!(segments_str("").is_empty());
+        assert!(segments_str(" 123 ").is_empty());
+    }
+
+    #[test]
+    fn keep_short_runs_in_the_surrounding_language() {
+        use Language::*;
+        assert_eq!(
+            segments_str("AI と PC の話です。"),
+            [("AI と PC の話です。".into(), Japanese)]
+        );
+        assert_eq!(
+            segments_str("新しい PC を買った"),
+            [("新しい PC を買った".into(), Japanese)]
+        );
+        // 一文字の漢字は英語の中では短いが、二文字なら切り替える
+        assert_eq!(
+            segments_str("Tokyo (東) is big"),
+            [("Tokyo (東) is big".into(), English)]
+        );
+        assert_eq!(
+            segments_str("We went to 東京 yesterday."),
+            [
+                ("We went to ".into(), English),
+                ("東京 ".into(), Japanese),
+                ("yesterday.".into(), English),
+            ]
+        );
+    }
+
+    #[test]
+    fn choose_the_preferred_voice_for_the_language() {
+        let voices = [
+            ("en-US", "David"),
+            ("ja-JP", "Haruka"),
+            ("ja-JP", "Ichiro"),
+            ("en-GB", "Hazel"),
+        ];
+        assert_eq!(choose_voice(Language::Japanese, &voices, None), Some(1));
+        assert_eq!(
+            choose_voice(Language::Japanese, &voices, Some("Ichiro")),
+            Some(2)
+        );
+        // 別の言語の音声は選ばない
+        assert_eq!(
+            choose_voice(Language::English, &voices, Some("Ichiro")),
+            Some(0)
+        );
+        assert_eq!(choose_voice(Language::Korean, &voices, None), None);
+    }
+}
@@ -0,0 +1,255 @@
+//! 読み方の辞書 (語句をそのままの文字列で置き換える)
+//!
+//! 製品名や人名など、音声が読み間違える語句を読み方に置き換えてから合成する。
+//! 正規表現の置換ルール ([crate::rules]) と違い、UTF-16 のまま先頭から探し、同じ位置では長い語句を優先する。
+
+use crate::offsets::{OffsetMap, Rewriter};
+use crate::utf16;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{PoisonError, RwLock};
+
+/// 辞書を保存するファイルのファイル名
+pub const FILE_NAME: &str = "lexicon.tsv";
+/// 合成する前に適用する辞書
+static ACTIVE: RwLock<Lexicon> = RwLock::new(Lexicon(Vec::new()));
+/// 辞書を変更した回数 (合成結果を使い回すかどうかの判定に使う)
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// 語句とその読み方
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// 置き換える語句
+    pub pattern: String,
+    /// 読み方
+    pub replacement: String,
+    /// 単語の途中には一致させない (空白で区切らない日本語などの文字の境界では区別しない)
+    pub whole_word: bool,
+}
+
+/// UTF-16 にした語句の並び (長い語句が先)
+#[derive(Default)]
+pub struct Lexicon(Vec<(Vec<u16>, Vec<u16>, bool)>);
+
+impl Lexicon {
+    /// 空の語句を除いて、長い語句から順に並べる
+    pub fn new(entries: &[Entry]) -> Self {
+        let mut words = entries
+            .iter()
+            .filter(|entry| !entry.pattern.is_empty())
+            .map(|entry| {
+                (
+                    entry.pattern.encode_utf16().collect::<Vec<_>>(),
+                    entry.replacement.encode_utf16().collect(),
+                    entry.whole_word,
+                )
+            })
+            .collect::<Vec<_>>();
+        // 同じ長さなら先に登録した語句を優先する
+        words.sort_by_key(|(pattern, _, _)| std::cmp::Reverse(pattern.len()));
+        Self(words)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// テキストの先頭から、それぞれの位置で一致する最も長い語句を読み方に置き換える
+    ///
+    /// 置き換えた読み方には、ほかの語句を適用しない。
+    pub fn apply(&self, text: &[u16]) -> Vec<u16> {
+        self.apply_mapped(text).0
+    }
+
+    /// [Self::apply] と同じように置き換え、置き換えた後の位置を元のテキストの位置に戻す対応も返す
+    pub fn apply_mapped(&self, text: &[u16]) -> (Vec<u16>, OffsetMap) {
+        let mut result = Rewriter::default();
+        let mut pos = 0;
+        while pos < text.len() {
+            let found = self.0.iter().find(|(pattern, _, whole_word)| {
+                text[pos..].starts_with(pattern)
+                    && (!whole_word || is_word_boundary(text, pos, pos + pattern.len()))
+            });
+            if let Some((pattern, replacement, _)) = found {
+                result.replace(pattern.len(), replacement);
+                pos += pattern.len();
+                continue;
+            }
+            // サロゲートペアの途中から一致させないように、文字ごとに進める
+            let len = char_len_at(text, pos);
+            result.keep(&text[pos..pos + len]);
+            pos += len;
+        }
+        result.finish()
+    }
+}
+
+/// pos の位置にある文字の UTF-16 単位の長さ
+fn char_len_at(text: &[u16], pos: usize) -> usize {
+    if utf16::is_high_surrogate(text[pos])
+        && text
+            .get(pos + 1)
+            .is_some_and(|&c| utf16::is_low_surrogate(c))
+    {
+        2
+    } else {
+        1
+    }
+}
+
+/// pos の位置から始まる文字
+fn char_at(text: &[u16], pos: usize) -> Option<char> {
+    char::decode_utf16(text.get(pos..)?.iter().copied())
+        .next()?
+        .ok()
+}
+
+/// pos の位置で終わる文字
+fn char_before(text: &[u16], pos: usize) -> Option<char> {
+    let start = utf16::floor_char_boundary(text, pos.checked_sub(1)?);
+    char_at(text, start)
+}
+
+/// 空白で単語を区切る文字か (日本語と中国語の文字は単語を区切らないので含めない)
+fn is_word_char(c: char) -> bool {
+    let spaceless = matches!(
+        c,
+        '\u{3040}'..='\u{30FF}'
+            | '\u{3400}'..='\u{4DBF}'
+            | '\u{4E00}'..='\u{9FFF}'
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{FF66}'..='\u{FF9F}'
+            | '\u{20000}'..='\u{3FFFF}'
+    );
+    (c.is_alphanumeric() || c == '_') && !spaceless
+}
+
+/// text[start..end] の前後が単語の途中でないか
+///
+/// 語句の端の文字か隣の文字のどちらかが単語を区切らない文字なら、文字列として一致すればよい。
+fn is_word_boundary(text: &[u16], start: usize, end: usize) -> bool {
+    let joined = |a: Option<char>, b: Option<char>| {
+        a.zip(b)
+            .is_some_and(|(a, b)| is_word_char(a) && is_word_char(b))
+    };
+    !joined(char_before(text, start), char_at(text, start))
+        && !joined(char_before(text, end), char_at(text, end))
+}
+
+/// 合成する前に適用する辞書を差し替える
+pub fn set_active(lexicon: Lexicon) {
+    *ACTIVE.write().unwrap_or_else(PoisonError::into_inner) = lexicon;
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 辞書を変更した回数
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// 合成する前に適用する辞書でテキストを置き換え、元のテキストの位置に戻す対応と一緒に返す
+pub fn apply_active(text: &[u16]) -> (Vec<u16>, OffsetMap) {
+    let lexicon = ACTIVE.read().unwrap_or_else(PoisonError::into_inner);
+    if lexicon.is_empty() {
+        return (text.to_vec(), OffsetMap::default());
+    }
+    lexicon.apply_mapped(text)
+}
+
+/// 辞書のファイルを読み込む。一行に「単語単位 (1 か 0)、語句、読み方」をタブで区切って並べる
+pub fn parse(text: &str) -> Vec<Entry> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let whole_word = fields.next()? == "1";
+            let pattern = fields.next().filter(|p| !p.is_empty())?;
+            Some(Entry {
+                pattern: pattern.to_string(),
+                replacement: fields.next().unwrap_or_default().to_string(),
+                whole_word,
+            })
+        })
+        .collect()
+}
+
+/// 辞書をファイルに保存する形式にする (タブと改行は空白にする)
+pub fn serialize(entries: &[Entry]) -> String {
+    entries
+        .iter()
+        .map(|entry| {
+            let pattern = entry.pattern.replace(['\t', '\r', '\n'], " ");
+            let replacement = entry.replacement.replace(['\t', '\r', '\n'], " ");
+            format!("{}\t{pattern}\t{replacement}\r\n", entry.whole_word as u8)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(pattern: &str, replacement: &str, whole_word: bool) -> Entry {
+        Entry {
+            pattern: pattern.into(),
+            replacement: replacement.into(),
+            whole_word,
+        }
+    }
+
+    fn apply(entries: &[Entry], text: &str) -> String {
+        let text = text.encode_utf16().collect::<Vec<_>>();
+        String::from_utf16(&Lexicon::new(entries).apply(&text)).unwrap()
+    }
+
+    #[test]
+    fn prefer_the_longest_overlapping_entry() {
+        let entries = [
+            entry("Speech", "スピーチ", false),
+            entry("SpeechKit", "スピーチキット", false),
+            entry("Kit", "キット", false),
+        ];
+        assert_eq!(
+            apply(&entries, "SpeechKit と Speech Kit"),
+            "スピーチキット と スピーチ キット"
+        );
+        // 置き換えた読み方にはほかの語句を適用しない
+        let entries = [entry("a", "b", false), entry("b", "c", false)];
+        assert_eq!(apply(&entries, "ab"), "bc");
+    }
+
+    #[test]
+    fn replace_surrogate_pairs() {
+        let entries = [
+            entry("𠮷野家", "よしのや", false),
+            entry("😀", "笑顔", false),
+        ];
+        assert_eq!(apply(&entries, "𠮷野家で😀"), "よしのやで笑顔");
+        // サロゲートペアの下位だけの語句は、ペアの途中に一致させない
+        let text = "😀".encode_utf16().collect::<Vec<_>>();
+        let lexicon = Lexicon(vec![(vec![text[1]], vec![0x78], false)]);
+        assert_eq!(lexicon.apply(&text), text);
+    }
+
+    #[test]
+    fn whole_word_only() {
+        let entries = [entry("AI", "エーアイ", true)];
+        assert_eq!(
+            apply(&entries, "AI と FAIR と AI_x"),
+            "エーアイ と FAIR と AI_x"
+        );
+        // 空白で区切らない日本語の中では、文字列として一致すればよい
+        let entries = [entry("山田", "やまだ", true), entry("AI", "エーアイ", true)];
+        assert_eq!(
+            apply(&entries, "山田太郎さんはAIを使う"),
+            "やまだ太郎さんはエーアイを使う"
+        );
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let entries = vec![entry("SQL", "シークェル", true), entry("a\tb", "c", false)];
+        let parsed = parse(&serialize(&entries));
+        assert_eq!(parsed[0], entries[0]);
+        assert_eq!(parsed[1], entry("a b", "c", false));
+        assert_eq!(parse("1\tx\n\n0\t\ty\n"), vec![entry("x", "", true)]);
+    }
+}
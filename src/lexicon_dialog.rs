@@ -0,0 +1,281 @@
+use crate::dialog::{self, create_control};
+use crate::dpi::LogicalRect;
+use crate::settings;
+use crate::strings::{self, tr, Msg};
+use anyhow::{ensure, Result};
+use speech::lexicon::Entry;
+use std::cell::RefCell;
+use windows::{
+    core::{w, HSTRING, PCWSTR},
+    Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+        UI::{
+            Controls::BST_CHECKED,
+            Input::KeyboardAndMouse::SetFocus,
+            WindowsAndMessaging::{
+                DefWindowProcW, GetDlgItem, GetWindowTextLengthW, GetWindowTextW, SendMessageW,
+                SetWindowTextW, BM_GETCHECK, BM_SETCHECK, BS_AUTOCHECKBOX, BS_DEFPUSHBUTTON,
+                BS_PUSHBUTTON, ES_AUTOHSCROLL, IDCANCEL, IDOK, LBN_SELCHANGE, LBS_NOINTEGRALHEIGHT,
+                LBS_NOTIFY, LB_ADDSTRING, LB_ERR, LB_GETCURSEL, LB_RESETCONTENT, LB_SETCURSEL,
+                WINDOW_STYLE, WM_CLOSE, WM_COMMAND, WM_CREATE, WS_BORDER, WS_TABSTOP, WS_VSCROLL,
+            },
+        },
+    },
+};
+
+/// 読み方の辞書のダイアログのクラス名
+const CLASS_NAME: PCWSTR = w!("speech_lexicon_cls42");
+/// 語句の一覧のリストボックスの ID
+const ID_LIST: u16 = 100;
+/// 削除ボタンの ID
+const ID_DELETE: u16 = 101;
+/// 語句のエディットの ID
+const ID_PATTERN: u16 = 102;
+/// 読み方のエディットの ID
+const ID_REPLACEMENT: u16 = 103;
+/// 単語単位のチェックボックスの ID
+const ID_WHOLE_WORD: u16 = 104;
+/// 追加ボタンの ID
+const ID_ADD: u16 = 105;
+/// 変更ボタンの ID
+const ID_UPDATE: u16 = 106;
+/// ダイアログのクライアント領域の大きさ (96 DPI 基準)
+const CLIENT_SIZE: (i32, i32) = (480, 300);
+/// 語句の一覧の配置
+const LIST_RECT: LogicalRect = LogicalRect::new(12, 12, 364, 150);
+/// 削除ボタンの配置
+const DELETE_RECT: LogicalRect = LogicalRect::new(384, 12, 84, 28);
+/// ラベルの配置 (語句、読み方)
+const LABEL_RECTS: [LogicalRect; 2] = [
+    LogicalRect::new(12, 176, 76, 20),
+    LogicalRect::new(12, 206, 76, 20),
+];
+/// 語句のエディットの配置
+const PATTERN_RECT: LogicalRect = LogicalRect::new(92, 172, 284, 24);
+/// 読み方のエディットの配置
+const REPLACEMENT_RECT: LogicalRect = LogicalRect::new(92, 202, 284, 24);
+/// 単語単位のチェックボックスの配置
+const WHOLE_WORD_RECT: LogicalRect = LogicalRect::new(92, 232, 284, 20);
+/// 追加ボタンの配置
+const ADD_RECT: LogicalRect = LogicalRect::new(384, 170, 84, 28);
+/// 変更ボタンの配置
+const UPDATE_RECT: LogicalRect = LogicalRect::new(384, 200, 84, 28);
+/// OK ボタンの配置
+const OK_RECT: LogicalRect = LogicalRect::new(292, 264, 84, 28);
+/// キャンセルボタンの配置
+const CANCEL_RECT: LogicalRect = LogicalRect::new(384, 264, 84, 28);
+
+thread_local! {
+    /// 編集中の辞書 (OK を押すまで保存しない)
+    static ENTRIES: RefCell<Vec<Entry>> = const { RefCell::new(Vec::new()) };
+}
+
+/// 読み方の辞書を編集するダイアログをモーダルで表示する
+///
+/// OK で閉じたら辞書を保存し、以降の読み上げと保存に適用する。
+pub fn show(owner: HWND) -> Result<()> {
+    ENTRIES.set(settings::load_lexicon()?);
+    dialog::show_modal(
+        owner,
+        CLASS_NAME,
+        Some(wnd_proc),
+        Msg::LexiconTitle,
+        CLIENT_SIZE,
+    )?;
+    ENTRIES.take();
+    Ok(())
+}
+
+fn create_button(hwnd: HWND, msg: Msg, rect: LogicalRect, id: u16) -> Result<HWND> {
+    let style = WINDOW_STYLE(BS_PUSHBUTTON as _) | WS_TABSTOP;
+    create_control(hwnd, w!("BUTTON"), &strings::wide(msg), style, rect, id)
+}
+
+fn create_edit(hwnd: HWND, rect: LogicalRect, id: u16) -> Result<HWND> {
+    let style = WINDOW_STYLE(ES_AUTOHSCROLL as _) | WS_BORDER | WS_TABSTOP;
+    create_control(hwnd, w!("EDIT"), &HSTRING::new(), style, rect, id)
+}
+
+fn create(hwnd: HWND) -> Result<()> {
+    let list_style = WINDOW_STYLE((LBS_NOTIFY | LBS_NOINTEGRALHEIGHT) as _)
+        | WS_BORDER
+        | WS_VSCROLL
+        | WS_TABSTOP;
+    let list = create_control(
+        hwnd,
+        w!("LISTBOX"),
+        &HSTRING::new(),
+        list_style,
+        LIST_RECT,
+        ID_LIST,
+    )?;
+    create_button(hwnd, Msg::ButtonDelete, DELETE_RECT, ID_DELETE)?;
+    let labels = [Msg::LabelLexiconPattern, Msg::LabelLexiconReplacement];
+    for (msg, rect) in labels.into_iter().zip(LABEL_RECTS) {
+        create_control(
+            hwnd,
+            w!("STATIC"),
+            &strings::wide(msg),
+            WINDOW_STYLE::default(),
+            rect,
+            0,
+        )?;
+    }
+    create_edit(hwnd, PATTERN_RECT, ID_PATTERN)?;
+    create_edit(hwnd, REPLACEMENT_RECT, ID_REPLACEMENT)?;
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::LexiconWholeWord),
+        WINDOW_STYLE(BS_AUTOCHECKBOX as _) | WS_TABSTOP,
+        WHOLE_WORD_RECT,
+        ID_WHOLE_WORD,
+    )?;
+    create_button(hwnd, Msg::ButtonAdd, ADD_RECT, ID_ADD)?;
+    create_button(hwnd, Msg::ButtonUpdate, UPDATE_RECT, ID_UPDATE)?;
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ButtonOk),
+        WINDOW_STYLE(BS_DEFPUSHBUTTON as _) | WS_TABSTOP,
+        OK_RECT,
+        IDOK.0 as _,
+    )?;
+    create_button(hwnd, Msg::ButtonCancel, CANCEL_RECT, IDCANCEL.0 as _)?;
+    refresh(hwnd, Some(0))?;
+    _ = unsafe { SetFocus(list) };
+    Ok(())
+}
+
+/// コントロールのテキストを取得する
+fn control_text(hwnd: HWND, id: u16) -> Result<String> {
+    let control = unsafe { GetDlgItem(hwnd, id as _)? };
+    let mut buf = vec![0; unsafe { GetWindowTextLengthW(control) } as usize + 1];
+    let len = unsafe { GetWindowTextW(control, &mut buf) } as usize;
+    Ok(String::from_utf16_lossy(&buf[..len]))
+}
+
+fn set_control_text(hwnd: HWND, id: u16, text: &str) -> Result<()> {
+    let control = unsafe { GetDlgItem(hwnd, id as _)? };
+    unsafe { SetWindowTextW(control, &HSTRING::from(text))? };
+    Ok(())
+}
+
+/// 一覧で選択している語句の位置
+fn selection(hwnd: HWND) -> Result<Option<usize>> {
+    let list = unsafe { GetDlgItem(hwnd, ID_LIST as _)? };
+    let index = unsafe { SendMessageW(list, LB_GETCURSEL, None, None) }.0;
+    Ok((index != LB_ERR as isize).then_some(index as usize))
+}
+
+/// 一覧に表示する語句の文字列
+fn list_item(entry: &Entry) -> String {
+    let whole_word = if entry.whole_word { "[W] " } else { "" };
+    format!("{whole_word}{}  →  {}", entry.pattern, entry.replacement)
+}
+
+/// 語句の一覧を表示し直して、指定した位置の語句を選択する
+fn refresh(hwnd: HWND, select: Option<usize>) -> Result<()> {
+    let list = unsafe { GetDlgItem(hwnd, ID_LIST as _)? };
+    unsafe { SendMessageW(list, LB_RESETCONTENT, None, None) };
+    ENTRIES.with_borrow(|entries| {
+        for entry in entries {
+            let item = HSTRING::from(list_item(entry));
+            unsafe { SendMessageW(list, LB_ADDSTRING, None, LPARAM(item.as_ptr() as _)) };
+        }
+    });
+    if let Some(index) = select.filter(|&i| i < ENTRIES.with_borrow(Vec::len)) {
+        unsafe { SendMessageW(list, LB_SETCURSEL, WPARAM(index), None) };
+        selection_changed(hwnd)?;
+    }
+    Ok(())
+}
+
+/// 選択した語句をエディットに表示する
+fn selection_changed(hwnd: HWND) -> Result<()> {
+    let Some(entry) =
+        selection(hwnd)?.and_then(|i| ENTRIES.with_borrow(|entries| entries.get(i).cloned()))
+    else {
+        return Ok(());
+    };
+    set_control_text(hwnd, ID_PATTERN, &entry.pattern)?;
+    set_control_text(hwnd, ID_REPLACEMENT, &entry.replacement)?;
+    let whole_word = unsafe { GetDlgItem(hwnd, ID_WHOLE_WORD as _)? };
+    let check = if entry.whole_word { BST_CHECKED.0 } else { 0 };
+    unsafe { SendMessageW(whole_word, BM_SETCHECK, WPARAM(check as _), None) };
+    Ok(())
+}
+
+/// エディットに入力した語句を読み取る
+fn edited_entry(hwnd: HWND) -> Result<Entry> {
+    let whole_word = unsafe { GetDlgItem(hwnd, ID_WHOLE_WORD as _)? };
+    let entry = Entry {
+        pattern: control_text(hwnd, ID_PATTERN)?,
+        replacement: control_text(hwnd, ID_REPLACEMENT)?,
+        whole_word: unsafe { SendMessageW(whole_word, BM_GETCHECK, None, None) }.0
+            == BST_CHECKED.0 as isize,
+    };
+    ensure!(!entry.pattern.is_empty(), tr(Msg::ErrorLexiconPattern));
+    Ok(entry)
+}
+
+fn command(hwnd: HWND, wparam: WPARAM) -> Result<()> {
+    let id = crate::loword(wparam.0 as _);
+    let code = crate::hiword(wparam.0 as _);
+    let selected = selection(hwnd)?;
+    if id == ID_LIST && code as u32 == LBN_SELCHANGE {
+        selection_changed(hwnd)?;
+    } else if id == ID_ADD {
+        let entry = edited_entry(hwnd)?;
+        let index = ENTRIES.with_borrow_mut(|entries| {
+            entries.push(entry);
+            entries.len() - 1
+        });
+        refresh(hwnd, Some(index))?;
+    } else if id == ID_UPDATE {
+        let Some(index) = selected else {
+            return Ok(());
+        };
+        let entry = edited_entry(hwnd)?;
+        ENTRIES.with_borrow_mut(|entries| entries[index] = entry);
+        refresh(hwnd, Some(index))?;
+    } else if id == ID_DELETE {
+        let Some(index) = selected else {
+            return Ok(());
+        };
+        ENTRIES.with_borrow_mut(|entries| entries.remove(index));
+        refresh(hwnd, Some(index.saturating_sub(1)))?;
+    } else if id == IDOK.0 as u16 {
+        ENTRIES.with_borrow(|entries| settings::save_lexicon(entries))?;
+        dialog::close(hwnd)?;
+    } else if id == IDCANCEL.0 as u16 {
+        dialog::close(hwnd)?;
+    }
+    Ok(())
+}
+
+/// ダイアログのウィンドウプロシージャ
+unsafe extern "system" fn wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_CREATE => {
+            if create(hwnd).is_err() {
+                return LRESULT(-1);
+            }
+        }
+        WM_COMMAND => {
+            if let Err(e) = command(hwnd, wparam) {
+                crate::report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+        WM_CLOSE => {
+            dialog::close(hwnd).ok();
+        }
+        _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+    LRESULT::default()
+}
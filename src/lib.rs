@@ -0,0 +1,22 @@
+//! ウィンドウに依存しない音声合成の処理
+//!
+//! GUI (main.rs) とコマンドラインモードの両方から使う。
+
+pub mod chunk;
+pub mod com;
+pub mod error;
+pub mod language;
+pub mod lexicon;
+pub mod normalize;
+pub mod offsets;
+pub mod rules;
+pub mod search;
+pub mod sentence_export;
+pub mod speech_marks;
+pub mod synthesis;
+pub mod text_file;
+pub mod text_format;
+pub mod unwind;
+pub mod utf16;
+pub mod wav;
+pub mod worker;
@@ -0,0 +1,217 @@
+use anyhow::{Context, Result};
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::OnceLock;
+use std::thread;
+use windows::{
+    System::Profile::AnalyticsInfo,
+    Win32::{Foundation::SYSTEMTIME, System::SystemInformation::GetLocalTime},
+};
+
+/// ログファイルの名前 (古いものは speech.1.log, speech.2.log, ...)
+const FILE_NAME: &str = "speech";
+/// これより大きくなったら新しいログファイルに切り替える
+const MAX_FILE_LEN: u64 = 1024 * 1024;
+/// 残しておく古いログファイルの数
+const KEEP_FILES: usize = 3;
+
+/// ログを書き出すスレッドに行を送る (UI スレッドをファイルの書き込みで待たせない)
+static SENDER: OnceLock<Sender<String>> = OnceLock::new();
+
+/// ログの重要度
+#[derive(Clone, Copy)]
+enum Level {
+    Info,
+    Error,
+}
+
+/// ログを置くディレクトリ (%LOCALAPPDATA%\speech\logs)
+pub fn log_dir() -> Result<PathBuf> {
+    let local_app_data = std::env::var_os("LOCALAPPDATA").context("no LOCALAPPDATA.")?;
+    Ok(PathBuf::from(local_app_data).join("speech").join("logs"))
+}
+
+/// ログの書き出しを始め、起動時の情報を記録する
+///
+/// ログを書けなくてもアプリケーションは使えるので、失敗しても何もしない。
+pub fn init() {
+    let Ok(dir) = log_dir() else {
+        return;
+    };
+    let (sender, receiver) = mpsc::channel::<String>();
+    let spawned = thread::Builder::new().name("log".into()).spawn(move || {
+        let mut writer = Writer::new(dir, MAX_FILE_LEN);
+        for line in receiver {
+            writer.write(&line);
+        }
+    });
+    if spawned.is_ok() {
+        _ = SENDER.set(sender);
+    }
+    info(format_args!(
+        "speech {} started on Windows {}",
+        env!("CARGO_PKG_VERSION"),
+        os_version().unwrap_or_else(|_| "(unknown)".into())
+    ));
+}
+
+/// 情報を記録する
+pub fn info(msg: impl Display) {
+    write(Level::Info, msg);
+}
+
+/// エラーを原因の連鎖も含めて記録する
+pub fn error(context: &str, err: &anyhow::Error) {
+    write(Level::Error, format_args!("{context}: {err:#}"));
+}
+
+fn write(level: Level, msg: impl Display) {
+    let Some(sender) = SENDER.get() else {
+        return;
+    };
+    let time = unsafe { GetLocalTime() };
+    _ = sender.send(format_line(&time, level, msg));
+}
+
+/// 1 行分のログを作る
+fn format_line(time: &SYSTEMTIME, level: Level, msg: impl Display) -> String {
+    let level = match level {
+        Level::Info => "INFO ",
+        Level::Error => "ERROR",
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03} {level} {msg}\r\n",
+        time.wYear,
+        time.wMonth,
+        time.wDay,
+        time.wHour,
+        time.wMinute,
+        time.wSecond,
+        time.wMilliseconds
+    )
+}
+
+/// OS のバージョン (10.0.22631.4317 のような形式)
+fn os_version() -> Result<String> {
+    let version = AnalyticsInfo::VersionInfo()?.DeviceFamilyVersion()?;
+    let version: u64 = version.to_string().parse()?;
+    Ok(format!(
+        "{}.{}.{}.{}",
+        version >> 48,
+        (version >> 32) & 0xFFFF,
+        (version >> 16) & 0xFFFF,
+        version & 0xFFFF
+    ))
+}
+
+/// ログファイルのパス (0 が書き込み中のファイルで、数字が大きいほど古い)
+fn file_path(dir: &Path, index: usize) -> PathBuf {
+    match index {
+        0 => dir.join(format!("{FILE_NAME}.log")),
+        i => dir.join(format!("{FILE_NAME}.{i}.log")),
+    }
+}
+
+/// ログファイルを一つずつ古い方にずらし、最も古いものを消す
+fn rotate(dir: &Path) {
+    _ = fs::remove_file(file_path(dir, KEEP_FILES));
+    for i in (0..KEEP_FILES).rev() {
+        _ = fs::rename(file_path(dir, i), file_path(dir, i + 1));
+    }
+}
+
+/// 大きさで切り替えながらログファイルに書き込む
+///
+/// 書き込めなかった行は捨てる (ログのためにパニックしたり止まったりしない)。
+struct Writer {
+    dir: PathBuf,
+    max_len: u64,
+    file: Option<File>,
+    len: u64,
+}
+
+impl Writer {
+    fn new(dir: PathBuf, max_len: u64) -> Self {
+        Self {
+            dir,
+            max_len,
+            file: None,
+            len: 0,
+        }
+    }
+
+    fn write(&mut self, line: &str) {
+        if self.file.is_none() {
+            self.open();
+        }
+        if self.len > 0 && self.len + line.len() as u64 > self.max_len {
+            self.file = None;
+            rotate(&self.dir);
+            self.open();
+        }
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        if file.write_all(line.as_bytes()).is_ok() {
+            self.len += line.len() as u64;
+        }
+    }
+
+    fn open(&mut self) {
+        _ = fs::create_dir_all(&self.dir);
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path(&self.dir, 0))
+            .ok();
+        self.len = self
+            .file
+            .as_ref()
+            .and_then(|file| file.metadata().ok())
+            .map_or(0, |metadata| metadata.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_a_line() {
+        let time = SYSTEMTIME {
+            wYear: 2024,
+            wMonth: 3,
+            wDay: 9,
+            wHour: 7,
+            wMinute: 5,
+            wSecond: 1,
+            wMilliseconds: 42,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_line(&time, Level::Error, "failed."),
+            "2024-03-09 07:05:01.042 ERROR failed.\r\n"
+        );
+    }
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = std::env::temp_dir().join(format!("speech-log-test-{}", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        let mut writer = Writer::new(dir.clone(), 10);
+        for line in ["first\n", "second\n", "third\n", "fourth\n", "fifth\n"] {
+            writer.write(line);
+        }
+        drop(writer);
+        let read = |index| fs::read_to_string(file_path(&dir, index)).ok();
+        assert_eq!(read(0).as_deref(), Some("fifth\n"));
+        assert_eq!(read(1).as_deref(), Some("fourth\n"));
+        assert_eq!(read(KEEP_FILES).as_deref(), Some("second\n"));
+        // 最も古いファイルは消える
+        assert_eq!(read(KEEP_FILES + 1), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,48 +1,150 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use anyhow::{ensure, Context, Result};
+mod about;
+mod accessibility;
+mod args;
+mod autosave;
+mod cli;
+mod clipboard;
+mod crash;
+mod dialog;
+mod dpi;
+mod find_dialog;
+mod folder_watch;
+mod history;
+mod hotkey;
+mod hotkey_dialog;
+mod icon;
+mod instance;
+mod jump_list;
+mod labels;
+mod lexicon_dialog;
+mod logging;
+mod media_controls;
+mod menu;
+mod panel;
+mod pipe;
+mod playback;
+mod power;
+mod queue;
+mod remote;
+mod rules_dialog;
+mod schedule;
+mod schedule_dialog;
+mod sentence_dialog;
+mod settings;
+mod sleep_dialog;
+mod state;
+mod status;
+mod strings;
+mod tail;
+mod taskbar;
+mod toast;
+mod toolbar;
+mod tooltip;
+mod tray;
+mod ui_message;
+mod wav_output;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use hotkey::{Action, Hotkey};
+use instance::{Argument, CommandLine};
+use menu::Item;
+use playback::{AppSynth, Engine, Status};
+use remote::Request;
+use settings::WindowRect;
+use speech::error::SpeechError;
+use speech::language::{self, Language};
+use speech::synthesis::{self, SynthOptions, Voiced};
+use speech::{
+    chunk, com, search, sentence_export, speech_marks, text_file, text_format, utf16, worker,
+};
+use state::{choose_voice, format_remaining, group_digits, voice_ids, AppState, VoiceChoice};
+use status::Part;
+use std::cell::{Cell, RefCell};
 use std::char::{decode_utf16, REPLACEMENT_CHARACTER};
 use std::mem;
-use std::path::PathBuf;
-use std::slice;
-use std::sync::{
-    mpsc::{self, Sender},
-    Mutex, OnceLock,
-};
-use std::thread;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use strings::{tr, trf, Lang, Msg};
+#[cfg(test)]
+use tests::message_box;
+use ui_message::{UiMessage, WM_UI_MESSAGE};
+use wav_output::{wav_tags, WavOutput};
 use windows::{
-    core::{w, Interface, HSTRING, PCWSTR, PWSTR},
-    Foundation::TypedEventHandler,
-    Media::{
-        Core::MediaSource,
-        Playback::MediaPlayer,
-        SpeechSynthesis::{SpeechSynthesisStream, SpeechSynthesizer, VoiceInformation},
-    },
-    Storage::Streams::DataReader,
+    core::{w, HSTRING, PCWSTR, PWSTR},
+    Media::SystemMediaTransportControlsButton,
     Win32::{
-        Foundation::{HWND, LPARAM, LRESULT, RECT, WPARAM},
+        Foundation::{BOOL, ERROR_CANCELLED, HWND, LPARAM, LRESULT, POINT, RECT, TRUE, WPARAM},
         Graphics::Gdi::{
-            BeginPaint, EndPaint, GetSysColorBrush, SetBkMode, TextOutW, UpdateWindow,
-            COLOR_MENUBAR, PAINTSTRUCT, TRANSPARENT,
+            ClientToScreen, DeleteObject, GetSysColorBrush, InvalidateRect, MonitorFromRect,
+            UpdateWindow, COLOR_MENUBAR, HFONT, MONITOR_DEFAULTTONULL,
+        },
+        System::{
+            Com::{
+                CoCreateInstance, CoInitializeEx, CoTaskMemFree, CLSCTX_INPROC_SERVER,
+                COINIT_APARTMENTTHREADED,
+            },
+            DataExchange::{IsClipboardFormatAvailable, COPYDATASTRUCT},
+            Diagnostics::Debug::MessageBeep,
+            LibraryLoader::GetModuleHandleW,
+            Ole::CF_UNICODETEXT,
+            SystemInformation::GetLocalTime,
         },
-        System::{LibraryLoader::GetModuleHandleW, WinRT::IBufferByteAccess},
         UI::{
             Controls::{
-                Dialogs::{GetSaveFileNameW, OPENFILENAMEW},
-                InitCommonControlsEx, ICC_BAR_CLASSES, INITCOMMONCONTROLSEX, TBM_SETPAGESIZE,
-                TBM_SETPOS, TBM_SETRANGE, TBM_SETTICFREQ, TBS_AUTOTICKS, TBS_TOOLTIPS,
-                WC_COMBOBOXW,
+                Dialogs::{
+                    ChooseFontW, CommDlgExtendedError, GetOpenFileNameW, GetSaveFileNameW,
+                    CF_INITTOLOGFONTSTRUCT, CF_NOVERTFONTS, CF_SCREENFONTS, CHOOSEFONTW,
+                    OFN_FILEMUSTEXIST, OFN_OVERWRITEPROMPT, OFN_PATHMUSTEXIST, OPENFILENAMEW,
+                },
+                InitCommonControlsEx, DRAWITEMSTRUCT, ICC_BAR_CLASSES, ICC_DATE_CLASSES,
+                ICC_PROGRESS_CLASS, ICC_UPDOWN_CLASS, INITCOMMONCONTROLSEX, NMHDR, NMTTDISPINFOW,
+                TBM_SETPAGESIZE, TBM_SETPOS, TBM_SETRANGE, TBM_SETTICFREQ, TBS_AUTOTICKS,
+                TBS_TOOLTIPS, TB_ENDTRACK, TTN_GETDISPINFOW, UDM_SETBUDDY, UDM_SETPOS32,
+                UDM_SETRANGE32, UDS_ALIGNRIGHT, UDS_ARROWKEYS, UDS_NOTHOUSANDS, UDS_SETBUDDYINT,
+                UPDOWN_CLASS, WC_COMBOBOXW,
+            },
+            HiDpi::{
+                GetDpiForSystem, SetProcessDpiAwarenessContext,
+                DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2,
+            },
+            Input::KeyboardAndMouse::{
+                EnableWindow, GetKeyState, SetFocus, VK_CONTROL, VK_F3, VK_RETURN, VK_SHIFT, VK_TAB,
+            },
+            Shell::{
+                FileOpenDialog, IFileOpenDialog, ShellExecuteW, FOS_PICKFOLDERS, SIGDN_FILESYSPATH,
             },
             WindowsAndMessaging::{
-                CreateWindowExW, DefWindowProcW, DispatchMessageW, GetClientRect, GetMessageW,
-                GetWindowTextLengthW, GetWindowTextW, MessageBoxW, PostQuitMessage, RegisterClassW,
-                SendMessageW, ShowWindow, TranslateMessage, BS_PUSHBUTTON, CBS_DROPDOWNLIST,
-                CBS_HASSTRINGS, CBS_SORT, CB_ADDSTRING, CB_GETCURSEL, CB_GETLBTEXT,
-                CB_SELECTSTRING, CW_USEDEFAULT, ES_AUTOVSCROLL, ES_MULTILINE, ES_WANTRETURN, HMENU,
-                MB_OK, MSG, SW_SHOW, WINDOW_EX_STYLE, WINDOW_STYLE, WM_COMMAND, WM_CREATE,
-                WM_DESTROY, WM_PAINT, WM_SETTEXT, WNDCLASSW, WS_BORDER, WS_CAPTION, WS_CHILD,
-                WS_EX_STATICEDGE, WS_MINIMIZEBOX, WS_OVERLAPPED, WS_SYSMENU, WS_TABSTOP,
-                WS_VISIBLE, WS_VSCROLL,
+                CallWindowProcW, CreateAcceleratorTableW, CreateWindowExW, DefWindowProcW,
+                DestroyAcceleratorTable, DestroyMenu, DestroyWindow, DispatchMessageW,
+                EnumChildWindows, FlashWindowEx, GetCaretPos, GetClientRect, GetDlgItem,
+                GetForegroundWindow, GetMenu, GetMessageW, GetParent, GetSubMenu,
+                GetWindowLongPtrW, GetWindowPlacement, GetWindowTextLengthW, IsDialogMessageW,
+                KillTimer, LoadIconW, MoveWindow, PostMessageW, PostQuitMessage, RegisterClassW,
+                SendMessageW, SetForegroundWindow, SetMenu, SetTimer, SetWindowLongPtrW,
+                SetWindowPlacement, SetWindowPos, SetWindowTextW, ShowWindow, TrackPopupMenu,
+                TranslateAcceleratorW, TranslateMessage, ACCEL, ACCEL_VIRT_FLAGS, CBN_SELCHANGE,
+                CBS_DROPDOWNLIST, CBS_HASSTRINGS, CBS_SORT, CB_ADDSTRING, CB_RESETCONTENT,
+                CB_SETITEMDATA, CW_USEDEFAULT, DLGC_WANTALLKEYS, DLGC_WANTMESSAGE, DLGC_WANTTAB,
+                EM_CANUNDO, EM_REPLACESEL, EM_SCROLLCARET, EM_SETSEL, EN_CHANGE, ES_AUTOVSCROLL,
+                ES_MULTILINE, ES_NOHIDESEL, ES_NUMBER, ES_WANTRETURN, FAPPCOMMAND_MASK, FCONTROL,
+                FLASHWINFO, FLASHW_TIMERNOFG, FLASHW_TRAY, FVIRTKEY, GWLP_USERDATA, GWLP_WNDPROC,
+                HACCEL, HMENU, HWND_NOTOPMOST, HWND_TOPMOST, ICON_BIG, ICON_SMALL, IDOK, IDYES,
+                MB_ICONERROR, MB_ICONQUESTION, MB_ICONWARNING, MB_OK, MB_YESNO, MESSAGEBOX_RESULT,
+                MESSAGEBOX_STYLE, MINMAXINFO, MSG, PBT_APMRESUMEAUTOMATIC, PBT_APMSUSPEND,
+                SHOW_WINDOW_CMD, SIZE_MINIMIZED, SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOSIZE,
+                SWP_NOZORDER, SW_HIDE, SW_RESTORE, SW_SHOW, SW_SHOWMAXIMIZED, SW_SHOWNORMAL,
+                TPM_LEFTALIGN, TPM_RIGHTBUTTON, TPM_TOPALIGN, WINDOWPLACEMENT, WINDOW_EX_STYLE,
+                WINDOW_STYLE, WM_ACTIVATEAPP, WM_APP, WM_APPCOMMAND, WM_CHAR, WM_CLEAR, WM_CLOSE,
+                WM_COMMAND, WM_CONTEXTMENU, WM_COPY, WM_COPYDATA, WM_CREATE, WM_CUT, WM_DESTROY,
+                WM_DPICHANGED, WM_DRAWITEM, WM_ENDSESSION, WM_GETDLGCODE, WM_GETMINMAXINFO,
+                WM_HOTKEY, WM_HSCROLL, WM_INITMENUPOPUP, WM_KEYDOWN, WM_LBUTTONDBLCLK,
+                WM_NCDESTROY, WM_NOTIFY, WM_PASTE, WM_POWERBROADCAST, WM_RBUTTONUP, WM_SETFOCUS,
+                WM_SETFONT, WM_SETICON, WM_SETTEXT, WM_SIZE, WM_TIMER, WM_UNDO, WNDCLASSW, WNDPROC,
+                WPF_RESTORETOMAXIMIZED, WS_BORDER, WS_CHILD, WS_EX_STATICEDGE, WS_OVERLAPPEDWINDOW,
+                WS_TABSTOP, WS_VISIBLE, WS_VSCROLL,
             },
         },
     },
@@ -50,344 +152,3492 @@ use windows::{
 
 /// メインウィンドウのクラス名
 const CLASS_NAME: PCWSTR = w!("speech_window_cls42");
-/// 再生ボタンの ID
+/// 再生コマンドの ID
 const ID_PLAY: u16 = 5890;
-/// クリアボタンの ID
+/// クリアコマンドの ID
 const ID_CLEAR: u16 = 5891;
-/// 保存ボタンの ID
+/// 保存コマンドの ID
 const ID_SAVE: u16 = 5892;
+/// ウィンドウを表示するメニューの ID
+const ID_SHOW_WINDOW: u16 = 5901;
+/// 閉じるときにタスクトレイに格納するメニューの ID
+const ID_CLOSE_TO_TRAY: u16 = 5902;
+/// 最前面に表示するメニューの ID
+const ID_TOPMOST: u16 = 5903;
+/// 表示言語を自動で選ぶメニューの ID
+const ID_LANG_AUTO: u16 = 5904;
+/// 表示言語を日本語にするメニューの ID
+const ID_LANG_JA: u16 = 5905;
+/// 表示言語を英語にするメニューの ID
+const ID_LANG_EN: u16 = 5906;
+/// 二重起動を許可するメニューの ID
+const ID_MULTIPLE_INSTANCES: u16 = 5912;
+/// 転送されたテキストをすぐに読み上げるメニューの ID
+const ID_PLAY_FORWARDED: u16 = 5913;
+/// 選択範囲を再生するメニューの ID
+const ID_SPEAK_SELECTION: u16 = 5914;
+/// 選択範囲を保存するメニューの ID
+const ID_SAVE_SELECTION: u16 = 5915;
+/// カーソル位置から読み上げるメニューの ID
+const ID_SPEAK_FROM_CURSOR: u16 = 5975;
+/// 貼り付けて読み上げるメニューの ID
+const ID_PASTE_AND_SPEAK: u16 = 5976;
+/// 読み上げの履歴をクリアするメニューの ID
+const ID_HISTORY_CLEAR: u16 = 5977;
+/// 読み上げの履歴を保存するメニューの ID
+const ID_SAVE_HISTORY: u16 = 5978;
+/// 読み上げの履歴の前の区切り線の ID
+const ID_HISTORY_SEPARATOR: u16 = 5979;
+/// 読み上げの履歴の最初の ID (ここから history::MAX_ENTRIES 個を使う)
+const ID_HISTORY_ENTRY: u16 = 5980;
+/// 音声の一覧を読み込み直すメニューの ID
+const ID_RELOAD_VOICES: u16 = 6000;
+/// 検索ダイアログを開くメニューの ID
+const ID_FIND: u16 = 6001;
+/// 次を検索するメニューの ID
+const ID_FIND_NEXT: u16 = 6002;
+/// 置換ダイアログを開くメニューの ID
+const ID_REPLACE: u16 = 6003;
+/// テキストの言語に合わせて音声を選ぶかどうかを切り替えるメニューの ID
+const ID_AUTO_VOICE: u16 = 6004;
+/// 言語の混ざったテキストを言語ごとに音声を切り替えて読むかどうかを切り替えるメニューの ID
+const ID_MIXED_VOICES: u16 = 6005;
+/// 再生中はスリープを防ぐかどうかを切り替えるメニューの ID
+const ID_PREVENT_SLEEP: u16 = 6006;
+/// スリープを防ぐ間は画面も消さないかどうかを切り替えるメニューの ID
+const ID_KEEP_DISPLAY_ON: u16 = 6007;
+/// 読み上げ用に整形するメニューの ID
+const ID_FORMAT_TEXT: u16 = 5916;
+/// 元に戻すメニューの ID
+const ID_UNDO: u16 = 5917;
+/// 切り取りメニューの ID
+const ID_CUT: u16 = 5918;
+/// コピーメニューの ID
+const ID_COPY: u16 = 5919;
+/// 貼り付けメニューの ID
+const ID_PASTE: u16 = 5920;
+/// 削除メニューの ID
+const ID_DELETE: u16 = 5921;
+/// 一時停止コマンドの ID
+const ID_PAUSE: u16 = 5907;
+/// 設定メニューを表示するツールバーボタンの ID
+const ID_SETTINGS: u16 = 5908;
+/// エディットコントロールの ID
+const ID_EDIT: u16 = 5925;
 /// コンボボックスの ID
 const ID_COMBO: u16 = 5893;
 /// トラックバーの ID
 const ID_TRACKBAR: u16 = 5894;
-/// エディットコントロールの [HWND](https://microsoft.github.io/windows-docs-rs/doc/windows/Win32/Foundation/struct.HWND.html) を保持するためのグローバル変数
-static EDIT_HWND: OnceLock<Hwnd> = OnceLock::new();
-/// コンボボックスの [HWND](https://microsoft.github.io/windows-docs-rs/doc/windows/Win32/Foundation/struct.HWND.html) を保持するためのグローバル変数
-static COMBOBOX_HWND: OnceLock<Hwnd> = OnceLock::new();
-/// トラックバーの [HWND](https://microsoft.github.io/windows-docs-rs/doc/windows/Win32/Foundation/struct.HWND.html) を保持するためのグローバル変数
-static TRACKBAR_HWND: OnceLock<Hwnd> = OnceLock::new();
-/// スピーチ再生スレッド実行待ちのための [Sender] を保持しておくグローバル変数
-static STOP: Mutex<Vec<Sender<()>>> = Mutex::new(vec![]);
+/// 開くメニューの ID
+const ID_OPEN: u16 = 5895;
+/// 終了メニューの ID
+const ID_EXIT: u16 = 5896;
+/// すべて選択メニューの ID
+const ID_SELECT_ALL: u16 = 5897;
+/// 停止メニューの ID
+const ID_STOP: u16 = 5898;
+/// バージョン情報メニューの ID
+const ID_ABOUT: u16 = 5899;
+/// ショートカットキー設定メニューの ID
+const ID_HOTKEYS: u16 = 5922;
+/// 名前付きパイプメニューの ID
+const ID_PIPE_SERVER: u16 = 5923;
+/// ログフォルダーを開くメニューの ID
+const ID_OPEN_LOG_FOLDER: u16 = 5924;
+/// 起動時にクリップボードを読み上げるメニューの ID
+const ID_SPEAK_CLIPBOARD_AT_LAUNCH: u16 = 5926;
+/// 続きから再生メニューの ID
+const ID_RESUME: u16 = 5927;
+/// 再生する回数のエディットの ID
+const ID_REPEAT: u16 = 5928;
+/// 再生する回数のアップダウンコントロールの ID
+const ID_REPEAT_SPIN: u16 = 5929;
+/// スリープタイマーを解除するメニューの ID
+const ID_SLEEP_OFF: u16 = 5930;
+/// スリープタイマーを 5 分に設定するメニューの ID
+const ID_SLEEP_5: u16 = 5931;
+/// スリープタイマーを 15 分に設定するメニューの ID
+const ID_SLEEP_15: u16 = 5932;
+/// スリープタイマーを 30 分に設定するメニューの ID
+const ID_SLEEP_30: u16 = 5933;
+/// スリープタイマーの時間を入力するメニューの ID
+const ID_SLEEP_CUSTOM: u16 = 5934;
+/// 時刻を指定して再生するメニューの ID
+const ID_SCHEDULE: u16 = 5935;
+/// 時刻を指定した再生を取り消すメニューの ID
+const ID_SCHEDULE_CANCEL: u16 = 5936;
+/// ファイルを監視するメニューの ID
+const ID_WATCH_FILE: u16 = 5937;
+/// 文ごとに書き出すメニューの ID
+const ID_EXPORT_SENTENCES: u16 = 5938;
+/// 置換ルールのメニューの ID
+const ID_RULES: u16 = 5939;
+/// フォントのメニューの ID
+const ID_FONT: u16 = 5940;
+/// テキストを自動保存するメニューの ID
+const ID_AUTOSAVE: u16 = 5941;
+/// クイック入力モードのメニューの ID
+const ID_QUICK_INPUT: u16 = 5953;
+/// WAV と一緒にスピーチマークを保存するメニューの ID
+const ID_SPEECH_MARKS: u16 = 5954;
+/// 前の文に戻るコマンドの ID
+const ID_PREVIOUS_SENTENCE: u16 = 5955;
+/// 次の文に進むコマンドの ID
+const ID_NEXT_SENTENCE: u16 = 5956;
+/// 読み上げ中に他の音声を小さくするメニューの ID
+const ID_DUCK_OTHERS: u16 = 5957;
+/// フォルダーを監視して WAV に変換するメニューの ID
+const ID_WATCH_FOLDER: u16 = 5958;
+/// 読み方の辞書のメニューの ID
+const ID_LEXICON: u16 = 5959;
+/// 合成する前に日付を読み方に直すメニューの ID
+const ID_NORMALIZE_DATES: u16 = 5960;
+/// 合成する前に数値の桁区切りを取り除くメニューの ID
+const ID_NORMALIZE_NUMBERS: u16 = 5961;
+/// 合成する前に URL をドメインごとに読むメニューの ID
+const ID_NORMALIZE_URLS: u16 = 5962;
+/// 段落の間に間を空けないメニューの ID
+const ID_PARAGRAPH_PAUSE_NONE: u16 = 5963;
+/// 段落の間を 0.5 秒空けるメニューの ID
+const ID_PARAGRAPH_PAUSE_HALF: u16 = 5964;
+/// 段落の間を 1 秒空けるメニューの ID
+const ID_PARAGRAPH_PAUSE_1: u16 = 5965;
+/// 段落の間を 2 秒空けるメニューの ID
+const ID_PARAGRAPH_PAUSE_2: u16 = 5966;
+/// 保存する WAV の音量を正規化するメニューの ID
+const ID_NORMALIZE_VOLUME: u16 = 5967;
+/// 保存する WAV の前後の無音を切り取るメニューの ID
+const ID_TRIM_SILENCE: u16 = 5968;
+/// 保存する WAV を合成したサンプリングレートのままにするメニューの ID
+const ID_OUTPUT_NATIVE: u16 = 5969;
+/// 保存する WAV を 8 kHz にするメニューの ID
+const ID_OUTPUT_8000: u16 = 5970;
+/// 保存する WAV を 16 kHz にするメニューの ID
+const ID_OUTPUT_16000: u16 = 5971;
+/// 保存する WAV を 22.05 kHz にするメニューの ID
+const ID_OUTPUT_22050: u16 = 5972;
+/// 保存する WAV を 44.1 kHz にするメニューの ID
+const ID_OUTPUT_44100: u16 = 5973;
+/// 保存する WAV をモノラルにするメニューの ID
+const ID_OUTPUT_MONO: u16 = 5974;
+/// ファイルメニューの最近開いたファイルの前の区切り線の ID
+const ID_RECENT_SEPARATOR: u16 = 5942;
+/// ファイルメニューの最近開いたファイルの最初の ID (ここから MAX_RECENT_FILES 個を使う)
+const ID_RECENT_FILE: u16 = 5943;
+/// コントロールが重ならないウィンドウの最小の幅 (96 DPI 基準)
+const MIN_WINDOW_WIDTH: i32 = 600;
+/// コントロールが重ならないウィンドウの最小の高さ (96 DPI 基準)
+const MIN_WINDOW_HEIGHT: i32 = 360;
+/// 履歴のメニュー項目に表示するテキストの最大の文字数
+const MAX_HISTORY_LABEL_CHARS: usize = 40;
+/// 文ごとの書き出しで、ファイル名と文の一覧を書き出すファイルの名前
+const MANIFEST_FILE_NAME: &str = "manifest.csv";
+/// スリープタイマーのメニューで選べる時間 (メニュー ID, 分)
+const SLEEP_PRESETS: [(u16, u64); 3] = [(ID_SLEEP_5, 5), (ID_SLEEP_15, 15), (ID_SLEEP_30, 30)];
+/// 段落間の間隔のメニューで選べる長さ (メニュー ID, ミリ秒)
+const PARAGRAPH_PAUSE_PRESETS: [(u16, u64); 4] = [
+    (ID_PARAGRAPH_PAUSE_NONE, 0),
+    (ID_PARAGRAPH_PAUSE_HALF, 500),
+    (ID_PARAGRAPH_PAUSE_1, 1000),
+    (ID_PARAGRAPH_PAUSE_2, 2000),
+];
+/// 保存する形式のメニューで選べるサンプリングレート (メニュー ID, Hz、None は合成したまま)
+const SAMPLE_RATE_PRESETS: [(u16, Option<u32>); 5] = [
+    (ID_OUTPUT_NATIVE, None),
+    (ID_OUTPUT_8000, Some(8000)),
+    (ID_OUTPUT_16000, Some(16000)),
+    (ID_OUTPUT_22050, Some(22050)),
+    (ID_OUTPUT_44100, Some(44100)),
+];
+/// 繰り返し再生できる最大の回数
+const MAX_REPEATS: u32 = 99;
+/// 終了するときに、保存などの作業スレッドが終わるのを待つ時間
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// メインウィンドウの大きさ (96 DPI 基準)
+const WINDOW_SIZE: (i32, i32) = (600, 480);
+/// ツールバーのボタン
+const TOOLBAR_BUTTONS: [toolbar::Button; 8] = [
+    toolbar::Button {
+        id: ID_PLAY,
+        icon: icon::IDI_PLAY,
+        tip: Msg::TipPlay,
+    },
+    toolbar::Button {
+        id: ID_PAUSE,
+        icon: icon::IDI_PAUSE,
+        tip: Msg::TipPause,
+    },
+    toolbar::Button {
+        id: ID_STOP,
+        icon: icon::IDI_STOP,
+        tip: Msg::TipStop,
+    },
+    toolbar::Button {
+        id: ID_PREVIOUS_SENTENCE,
+        icon: icon::IDI_PREVIOUS,
+        tip: Msg::TipPrevious,
+    },
+    toolbar::Button {
+        id: ID_NEXT_SENTENCE,
+        icon: icon::IDI_NEXT,
+        tip: Msg::TipNext,
+    },
+    toolbar::Button {
+        id: ID_OPEN,
+        icon: icon::IDI_OPEN,
+        tip: Msg::TipOpen,
+    },
+    toolbar::Button {
+        id: ID_SAVE,
+        icon: icon::IDI_SAVE,
+        tip: Msg::TipSave,
+    },
+    toolbar::Button {
+        id: ID_SETTINGS,
+        icon: icon::IDI_SETTINGS,
+        tip: Msg::TipSettings,
+    },
+];
+/// キーボードショートカットの一覧 (修飾キー, 仮想キーコード, コマンド ID)
+///
+/// ショートカットを追加する場合はここに追加する。再生・一時停止・停止のキーは
+/// 設定で変更できるので [Action] で管理する。
+const SHORTCUTS: [(ACCEL_VIRT_FLAGS, u16, u16); 7] = [
+    (FCONTROL, b'S' as _, ID_SAVE),
+    (FCONTROL, b'L' as _, ID_CLEAR),
+    (FCONTROL, b'O' as _, ID_OPEN),
+    (FCONTROL, b'T' as _, ID_TOPMOST),
+    (FCONTROL, b'F' as _, ID_FIND),
+    (FCONTROL, b'H' as _, ID_REPLACE),
+    (ACCEL_VIRT_FLAGS(0), VK_F3.0, ID_FIND_NEXT),
+];
+/// スクリーンリーダーが読み上げる名前 (コントロール ID, 名前)
+const ACCESSIBLE_NAMES: [(u16, Msg); 3] = [
+    (ID_COMBO, Msg::AccVoice),
+    (ID_TRACKBAR, Msg::AccRate),
+    (ID_REPEAT, Msg::AccRepeat),
+];
+/// ツールチップの説明 (コントロール ID, 説明)
+const TOOLTIPS: [(u16, Msg); 4] = [
+    (panel::ID_TOGGLE, Msg::TipPanelToggle),
+    (ID_COMBO, Msg::TipVoice),
+    (ID_TRACKBAR, Msg::TipRate),
+    (ID_REPEAT, Msg::TipRepeat),
+];
+/// 再生ごとに割り振る番号 (古い再生からの通知を見分けるため)
+static SPEECH_ID: AtomicUsize = AtomicUsize::new(0);
+/// 書き出し待ちの次のファイルを WAV に書き出すメッセージ
+const WM_EXPORT_NEXT: u32 = WM_APP + 4;
+/// 名前付きパイプで受け付けたコマンドを UI スレッドで実行するメッセージ
+const WM_PIPE_COMMAND: u32 = WM_APP + 5;
+/// 使える音声がないことをウィンドウの表示後に知らせるメッセージ
+const WM_NO_VOICES: u32 = WM_APP + 8;
+/// 監視しているファイルに追記された行を UI スレッドで読み上げるメッセージ
+const WM_TAIL_LINES: u32 = WM_APP + 9;
+/// スリープから復帰した後に、止めた再生を続けるか尋ねるメッセージ
+const WM_RESUME_AFTER_SLEEP: u32 = WM_APP + 10;
+/// 再生が終わらないまま止まっていないか確かめるタイマー
+const WATCHDOG_TIMER: usize = 1;
+/// 再生が止まっていないか確かめる間隔 (ミリ秒)
+const WATCHDOG_INTERVAL: u32 = 1000;
+/// スリープタイマーの残り時間を表示し、時間が来たら停止するタイマー
+const SLEEP_TIMER: usize = 2;
+/// スリープタイマーの残り時間を更新する間隔 (ミリ秒)
+const SLEEP_INTERVAL: u32 = 1000;
+/// 時刻を指定した再生までの残り時間を表示し、時刻が来たら再生するタイマー
+const SCHEDULE_TIMER: usize = 3;
+/// 時刻を指定した再生の時刻を確かめる間隔 (ミリ秒)
+const SCHEDULE_INTERVAL: u32 = 1000;
+/// 入力が止まってから単語を数えるタイマー
+const COUNT_TIMER: usize = 4;
+/// 入力が止まってから単語を数えるまでの時間 (ミリ秒)
+const COUNT_DELAY: u32 = 500;
+/// 入力が止まってからテキストを自動保存するタイマー
+const AUTOSAVE_TIMER: usize = 5;
+/// 入力が止まってからテキストを自動保存するまでの時間 (ミリ秒)
+const AUTOSAVE_DELAY: u32 = 2000;
+/// タスクバーのボタンに再生や保存の進み具合を表示するタイマー
+const PROGRESS_TIMER: usize = 6;
+/// タスクバーの進み具合を更新する間隔 (ミリ秒)
+const PROGRESS_INTERVAL: u32 = 500;
+/// タスクバーのボタンの失敗の表示を消すタイマー
+const TASKBAR_ERROR_TIMER: usize = 7;
+/// タスクバーのボタンに失敗を表示しておく時間 (ミリ秒)
+const TASKBAR_ERROR_DURATION: u32 = 2000;
+
+thread_local! {
+    /// 現在の DPI に合わせて生成した UI 用フォント
+    static UI_FONT: Cell<HFONT> = Cell::new(HFONT::default());
+    /// エディットコントロールに設定したフォント
+    static EDIT_FONT: Cell<HFONT> = Cell::new(HFONT::default());
+    /// 音声を合成していて、まだ再生が始まっていないかどうか
+    static SYNTHESIZING: Cell<bool> = Cell::new(false);
+    /// 最後に合成を始めた時刻 (ログに所要時間を記録するため)
+    static SPEECH_BEGAN: Cell<Option<Instant>> = Cell::new(None);
+    /// 最後に始めたスピーチで音声を切り替える区間 (一つの音声で読み上げている場合は None)
+    static SPEECH_PARTS: RefCell<Option<Vec<Voiced<AppSynth>>>> = const { RefCell::new(None) };
+    /// エラーをメッセージボックスで表示している最中かどうか
+    static REPORTING_ERROR: Cell<bool> = Cell::new(false);
+    /// ショートカットキーのアクセラレータテーブル (設定の変更時に作り直す)
+    static ACCELERATORS: Cell<HACCEL> = Cell::new(HACCEL::default());
+    /// 最後に数えたエディットコントロールの単語数 (テキストの変更後、数え直すまでは None)
+    static WORD_COUNT: Cell<Option<usize>> = const { Cell::new(None) };
+    /// 再生中にスリープしたため再生を止めたかどうか (復帰したら続けるか尋ねる)
+    static SLEPT_WHILE_SPEAKING: Cell<bool> = const { Cell::new(false) };
+}
 
-/// [HWND](https://microsoft.github.io/windows-docs-rs/doc/windows/Win32/Foundation/struct.HWND.html) をグローバル変数に保持するためのラッパ構造体
-struct Hwnd(HWND);
+/// 生成済みのウィンドウの [HWND]。まだ生成されていなければエラーを返す
+fn created(hwnd: HWND) -> Result<HWND> {
+    ensure!(!hwnd.is_invalid(), "no handle.");
+    Ok(hwnd)
+}
 
-/// [Hwnd] 構造体を別スレッドに送れるようにマーカトレイトである Send, Sync を実装する
-unsafe impl Sync for Hwnd {}
-unsafe impl Send for Hwnd {}
+/// トラックバーを動かして読み上げ速度を変更する
+fn set_speaking_rate(hwnd: HWND, rate: f64) -> Result<()> {
+    let trackbar = AppState::get(hwnd)?.trackbar()?;
+    let pos = synthesis::pos_from_rate(rate);
+    unsafe { SendMessageW(trackbar, TBM_SETPOS, WPARAM(1), LPARAM(pos)) };
+    // TBM_SETPOS では WM_HSCROLL が送られないので、ここで音声合成エンジンに反映する
+    AppState::get(hwnd)?.update_synthesizer()?;
+    update_rate_value(hwnd)?;
+    panel::update_rate_label(hwnd)
+}
 
-impl Hwnd {
-    fn new(hwnd: HWND) -> Self {
-        Self(hwnd)
+/// 選び直した音声で最後に使った読み上げ速度に戻す。覚えていなければ今の速度のままにする
+///
+/// 選んだ音声は、自動で音声を選ぶときにその言語で優先する音声として覚えておく。
+fn voice_changed(hwnd: HWND) -> Result<()> {
+    let voice = AppState::get(hwnd)?.selected_voice()?;
+    let id = voice.id.to_string();
+    let language = language::primary_language(&voice.language);
+    if settings::get().language_voices.get(&language) != Some(&id) {
+        let id = id.clone();
+        settings::update(|s| _ = s.language_voices.insert(language, id))?;
+    }
+    let rate = settings::get().voice_rates.get(&id).copied();
+    match rate {
+        Some(rate) => set_speaking_rate(hwnd, rate),
+        None => AppState::get(hwnd)?.update_synthesizer(),
     }
+}
 
-    fn handle(&self) -> HWND {
-        self.0
+/// トラックバーで変えた読み上げ速度を、選択中の音声の速度として覚えておく
+fn remember_rate(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let id = state.selected_voice()?.id.to_string();
+    let rate = state.speaking_rate()?;
+    if settings::get().voice_rates.get(&id) == Some(&rate) {
+        return Ok(());
     }
+    settings::update(|s| _ = s.voice_rates.insert(id, rate))
 }
 
-fn get_selected_voice_information() -> Result<VoiceInformation> {
-    let hwnd = COMBOBOX_HWND.get().context("no handle")?.handle();
-    let ret = unsafe { SendMessageW(hwnd, CB_GETCURSEL, None, None) };
-    ensure!(ret.0 >= 0, "failed to get selected item index.");
+/// 表示名または ID で指定した音声をコンボボックスで選ぶ
+fn select_voice(hwnd: HWND, name: &str) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    state.select_voice_id(&synthesis::find_voice(name)?.Id()?)?;
+    // CB_SETCURSEL では CBN_SELCHANGE が送られないので、ここで音声合成エンジンに反映する
+    state.update_synthesizer()
+}
 
-    let buf = [0u16; 64];
-    let ret = unsafe {
-        SendMessageW(
-            hwnd,
-            CB_GETLBTEXT,
-            WPARAM(ret.0 as _),
-            LPARAM(buf.as_ptr() as _),
-        )
-    };
+/// テキストの読み上げを始める
+///
+/// 合成には時間がかかることがあるので、再生が始まるまではプログレスバーを表示し、
+/// 重ねて再生しようとしても無視する。
+fn speech(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let text = state.edit_text()?;
+    // ダブルクリックなどで同じテキストを続けて再生し始めると、音が重なって聞こえる
+    if state.play_guard.is_double_play(&text, Instant::now()) {
+        return Ok(());
+    }
+    let len = text.len();
+    let was_synthesizing = SYNTHESIZING.get();
+    let result = speak(hwnd, text.clone(), Some((0, len)));
+    if result.is_err() {
+        state.play_guard.clear();
+    }
+    // 合成を始めたときだけ履歴に残す (合成中で無視した場合などは残さない)
+    if result.is_ok() && !was_synthesizing && SYNTHESIZING.get() {
+        if let Err(e) = remember_history(hwnd, &text) {
+            logging::error("failed to update the history", &e);
+        }
+    }
+    result
+}
 
-    SpeechSynthesizer::AllVoices()?
-        .into_iter()
-        .filter_map(|v| {
-            if v.DisplayName().ok()?.as_wide() == &buf[..ret.0 as _] {
-                Some(v)
-            } else {
-                None
-            }
-        })
-        .next()
-        .context("no voice.")
-}
-
-fn get_speaking_rate() -> Result<f64> {
-    let hwnd = TRACKBAR_HWND.get().context("no handle.")?.handle();
-    let ret = unsafe { SendMessageW(hwnd, 1024, None, None) }.0 as f64 / 10.0;
-    ensure!(0.5 <= ret && ret <= 2.5, "invalid speaking rate.");
-    Ok(ret)
-}
-
-fn speech_synthesis_stream(source: &[u16]) -> Result<SpeechSynthesisStream> {
-    let source = HSTRING::from_wide(source)?;
-    let synth = SpeechSynthesizer::new()?;
-    let voice = get_selected_voice_information()?;
-    synth.SetVoice(&voice)?;
-    let speaking_rate = get_speaking_rate()?;
-    synth.Options()?.SetSpeakingRate(speaking_rate)?;
-    let stream = synth.SynthesizeTextToStreamAsync(&source)?.get()?;
-    Ok(stream)
-}
-
-fn speech() -> Result<()> {
-    let text = get_edit_control_text()?;
-    thread::spawn(move || -> Result<()> {
-        let stream = speech_synthesis_stream(&text)?;
-        let player = MediaPlayer::new()?;
-        let media_source = MediaSource::CreateFromStream(&stream, &stream.ContentType()?)?;
-        player.SetSource(&media_source)?;
-        let (tx, rx) = mpsc::channel();
-        {
-            let mut stop = STOP.lock().unwrap();
-            stop.push(tx.clone());
-        }
-        let tx_clone = tx.clone();
-        let token_media_ended = player.MediaEnded(&TypedEventHandler::new(move |_, _| {
-            tx_clone.send(()).ok();
-            Ok(())
-        }))?;
-        let token_media_failed = player.MediaFailed(&TypedEventHandler::new(move |_, _| {
-            tx.send(()).ok();
-            Ok(())
-        }))?;
-        player.Play()?;
-        rx.recv()?;
-        player.Close()?;
-        player.RemoveMediaEnded(token_media_ended)?;
-        player.RemoveMediaFailed(token_media_failed)?;
-        Ok(())
+/// 読み上げたテキストを、そのときの音声と読み上げ速度と一緒に履歴に残す
+fn remember_history(hwnd: HWND, text: &[u16]) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    history::push(history::Entry {
+        text: String::from_utf16_lossy(text),
+        voice_id: state.selected_voice()?.id.to_string(),
+        rate: state.speaking_rate()?,
     });
+    if settings::get().save_history {
+        history::save()?;
+    }
+    update_history_menu(hwnd)
+}
+
+/// 履歴のテキストをエディットコントロールに読み込んで読み上げる
+///
+/// 読み上げたときの音声と読み上げ速度に戻してから読み上げる。use_current なら今の音声と速度のままにする。
+fn speak_history(hwnd: HWND, index: usize, use_current: bool) -> Result<()> {
+    let Some(entry) = history::get(index) else {
+        return Ok(());
+    };
+    if SYNTHESIZING.get() {
+        return Ok(());
+    }
+    if !use_current {
+        let state = AppState::get(hwnd)?;
+        // 音声がアンインストールされていたら、今の音声で読み上げる
+        if let Err(e) = state.select_voice_id(&HSTRING::from(entry.voice_id.as_str())) {
+            logging::error("the voice in the history is not available", &e);
+        }
+        // CB_SETCURSEL の音声も、ここで音声合成エンジンに反映される
+        set_speaking_rate(hwnd, entry.rate)?;
+    }
+    let text = entry.text.encode_utf16().collect::<Vec<_>>();
+    set_edit_control_text(hwnd, &text)?;
+    speech(hwnd)
+}
+
+/// 読み上げの履歴をクリアする (エディットコントロールのテキストはそのまま)
+fn clear_history(hwnd: HWND) -> Result<()> {
+    history::clear();
+    if settings::get().save_history {
+        history::delete()?;
+    }
+    update_history_menu(hwnd)
+}
+
+/// 履歴の保存を切り替える。保存しないようにしたら保存した履歴も削除する
+fn toggle_save_history() -> Result<()> {
+    settings::update(|s| s.save_history = !s.save_history)?;
+    if settings::get().save_history {
+        history::save()
+    } else {
+        history::delete()
+    }
+}
+
+/// 履歴のサブメニューのクリアの前に、履歴を番号付きで並べ直す
+fn update_history_menu(hwnd: HWND) -> Result<()> {
+    let menu = unsafe { GetMenu(hwnd) };
+    ensure!(!menu.is_invalid(), "no menu.");
+    menu::delete_item(menu, ID_HISTORY_SEPARATOR);
+    for id in (ID_HISTORY_ENTRY..).take(history::MAX_ENTRIES) {
+        menu::delete_item(menu, id);
+    }
+    let entries = history::entries();
+    for (i, entry) in entries.iter().enumerate() {
+        let label = history_label(i + 1, &entry.text);
+        menu::insert_item(menu, ID_HISTORY_CLEAR, ID_HISTORY_ENTRY + i as u16, &label)?;
+    }
+    if !entries.is_empty() {
+        menu::insert_separator(menu, ID_HISTORY_CLEAR, ID_HISTORY_SEPARATOR)?;
+    }
     Ok(())
 }
 
-fn get_save_file_path(hwnd: HWND) -> Result<PathBuf> {
-    let mut buf = "speech.wav"
-        .encode_utf16()
-        .chain([0; 502])
+/// 履歴のメニュー項目のラベル (最初の行を短くして、番号をアクセスキーにする)
+fn history_label(number: usize, text: &str) -> String {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    let mut label = line
+        .chars()
+        .take(MAX_HISTORY_LABEL_CHARS)
+        .collect::<String>()
+        .replace('&', "&&");
+    if line.chars().nth(MAX_HISTORY_LABEL_CHARS).is_some() {
+        label.push('…');
+    }
+    match number {
+        1..=9 => format!("&{number} {label}"),
+        10 => format!("1&0 {label}"),
+        _ => format!("{number} {label}"),
+    }
+}
+
+/// 起動時にクリップボードから読み込むテキストの最大の長さ (UTF-16 単位)
+///
+/// 長すぎるテキストをエディットコントロールに読み込むと、起動が終わらないように見える。
+const MAX_LAUNCH_CLIPBOARD_LEN: usize = 200_000;
+
+/// クリップボードの文字列をエディットコントロールに読み込んで読み上げる
+///
+/// 文字列がない場合や空白だけの場合は何もしない。長すぎる場合は先頭だけを読み込み、ステータスバーで知らせる。
+fn load_and_speak_clipboard(hwnd: HWND) -> Result<()> {
+    let Some(mut text) = clipboard::get_text(hwnd)? else {
+        return Ok(());
+    };
+    if synthesis::prepare_text(&text).is_err() {
+        return Ok(());
+    }
+    let truncated = clipboard::truncate(&mut text, MAX_LAUNCH_CLIPBOARD_LEN);
+    set_edit_control_text(hwnd, &text_file::normalize_newlines(&text))?;
+    if truncated {
+        let notice = trf(
+            Msg::ClipboardTruncated,
+            &[&MAX_LAUNCH_CLIPBOARD_LEN.to_string()],
+        );
+        status::set_status(hwnd, Part::Misc, &notice)?;
+    }
+    speech(hwnd)
+}
+
+/// クリップボードの文字列をエディットコントロールを使わずに読み上げる
+fn speak_clipboard(hwnd: HWND) -> Result<()> {
+    match clipboard::get_text(hwnd)? {
+        Some(text) if !text.is_empty() => speak(hwnd, text, None),
+        _ => Ok(()),
+    }
+}
+
+/// 選択範囲だけを読み上げる
+fn speak_selection(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    // 選択範囲がサロゲートペアの途中から始まっていれば、切り出したテキストと同じように前に戻す
+    let (start, _) = state.selection()?;
+    let start = utf16::floor_char_boundary(&state.edit_text()?, start);
+    match state.selected_text()? {
+        Some(text) => {
+            let end = start + text.len();
+            speak(hwnd, text, Some((start, end)))
+        }
+        None => Ok(()),
+    }
+}
+
+/// キャレットの位置 (選択範囲があればその先頭) から最後までを読み上げる
+fn speak_from_cursor(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let text = state.edit_text()?;
+    let (start, _) = state.selection()?;
+    let start = utf16::floor_char_boundary(&text, start);
+    let end = text.len();
+    speak(hwnd, text[start..].to_vec(), Some((start, end)))
+}
+
+/// クリップボードのテキストをキャレットの位置に貼り付け、貼り付けた部分を読み上げる
+fn paste_and_speak(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let (start, _) = state.selection()?;
+    send_edit_command(hwnd, WM_PASTE)?;
+    // 貼り付けるとキャレットは貼り付けたテキストの後ろに移る
+    let (end, _) = state.selection()?;
+    let text = state.edit_text()?;
+    match text.get(start..end) {
+        Some(pasted) if start < end => speak(hwnd, pasted.to_vec(), Some((start, end))),
+        _ => Ok(()),
+    }
+}
+
+/// 前回停止した位置から続きを再生する。位置を覚えていなければ最初から再生する
+fn resume_speech(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let text = state.edit_text()?;
+    let Some(offset) = state.resume.offset().filter(|&offset| offset < text.len()) else {
+        return speech(hwnd);
+    };
+    logging::info(format_args!("resuming from {offset}"));
+    speak(hwnd, text[offset..].to_vec(), Some((offset, text.len())))
+}
+
+/// 再生中のエディットのテキストをどこまで読み上げたかを、続きから再生できるように覚える
+fn remember_resume_point(state: &AppState) -> Result<()> {
+    let position = match (state.resume.spoken(), state.playback.progress()) {
+        (Some((start, end)), Some((index, fraction))) => {
+            let text = state.edit_text()?;
+            let parts = SPEECH_PARTS.take();
+            text.get(start..end).map(|spoken| match &parts {
+                Some(parts) => synthesis::voiced_resume_offset(spoken, parts, index, fraction),
+                None => synthesis::resume_offset(spoken, index, fraction),
+            })
+        }
+        _ => None,
+    };
+    state.resume.stopped(position);
+    Ok(())
+}
+
+/// テキストの合成を始め、終わったら再生する
+///
+/// spoken はエディットのテキストを読み上げる場合の範囲で、停止したときに続きの位置を求めるのに使う。
+/// 合成や再生の失敗は [UiMessage::SpeechFinished] で UI スレッドに届き、[report_error] で表示される。
+fn speak(hwnd: HWND, text: Vec<u16>, spoken: Option<(usize, usize)>) -> Result<()> {
+    if SYNTHESIZING.get() {
+        return Ok(());
+    }
+    let state = AppState::get(hwnd)?;
+    // よくある間違いは合成を始める前に確かめて、すぐに知らせる
+    synthesis::prepare_text(&text).context(tr(Msg::ErrorNoText))?;
+    state.selected_voice().context(tr(Msg::ErrorNoVoice))?;
+    // 一覧を作ったあとに音声がなくなっていたら、一覧を作り直して選び直してもらう
+    if !state.is_selected_voice_available()? {
+        logging::info("the selected voice is no longer available; reloading voices");
+        message_box(hwnd, tr(Msg::VoiceMissing), MB_OK | MB_ICONWARNING);
+        return reload_voices(hwnd);
+    }
+    let (parts, key) = speech_parts(hwnd, &text)?;
+    let parts = playback_parts(state, &parts)?;
+    begin_voiced_speech(hwnd, parts, text, key, spoken)
+}
+
+/// テキストを合成する区間ごとの設定と、合成結果を使い回すためのキー
+///
+/// 言語ごとに音声を切り替える設定なら区間ごとに、自動で音声を選ぶ設定ならテキストの言語に合う音声で、
+/// どちらでもなければ選択中の音声でテキスト全体を合成する。再生と保存で同じ音声になるように、どちらもこれを使う。
+fn speech_parts(hwnd: HWND, text: &[u16]) -> Result<(Vec<(usize, usize, SynthOptions)>, u64)> {
+    let state = AppState::get(hwnd)?;
+    if let Some(parts) = mixed_voice_parts(hwnd, text)? {
+        let key = synthesis::voiced_cache_key(text, &parts);
+        return Ok((parts, key));
+    }
+    let options = match auto_voice_options(hwnd, text)? {
+        Some(options) => options,
+        None => state.synth_options()?,
+    };
+    let key = synthesis::cache_key(text, &options);
+    Ok((vec![(0, text.len(), options)], key))
+}
+
+/// 自動で音声を選ぶ設定のとき、テキストの言語に合う音声で合成する設定を作る
+///
+/// 選択中の音声が合っているか、言語がわからないか、合う音声がなければ None (選択中の音声で読み上げる)。
+/// コンボボックスの選択は変えず、この読み上げだけで使う。
+fn auto_voice_options(hwnd: HWND, text: &[u16]) -> Result<Option<SynthOptions>> {
+    if !settings::get().auto_voice {
+        return Ok(None);
+    }
+    let Some(detected) = language::detect(text) else {
+        return Ok(None);
+    };
+    let state = AppState::get(hwnd)?;
+    let Some(id) = voice_for_language(state, detected)? else {
+        note_missing_voice(hwnd, detected)?;
+        return Ok(None);
+    };
+    if id == state.selected_voice()?.id.to_string() {
+        return Ok(None);
+    }
+    logging::info(format_args!(
+        "auto voice: using {id} for {}",
+        detected.code()
+    ));
+    // 切り替えた音声で最後に使った速度があればそれを使う
+    let rate = settings::get().voice_rates.get(&id).copied();
+    let rate = match rate {
+        Some(rate) => rate,
+        None => state.speaking_rate()?,
+    };
+    Ok(Some(SynthOptions {
+        voice_id: Some(id),
+        rate,
+        ..Default::default()
+    }))
+}
+
+/// 言語ごとに音声を切り替える設定のとき、テキストを言語の区間に分けてそれぞれの区間の設定を作る
+///
+/// 区間の位置はテキストでの UTF-16 単位の位置。区間が一つしかないか、どの区間も同じ音声になる場合は
+/// None (一つの音声で読み上げる)。区間ごとに速さが変わらないように、どの音声もトラックバーの速度で合成する。
+fn mixed_voice_parts(
+    hwnd: HWND,
+    text: &[u16],
+) -> Result<Option<Vec<(usize, usize, SynthOptions)>>> {
+    if !settings::get().mixed_voices {
+        return Ok(None);
+    }
+    let segments = language::segments(text, language::MIN_SEGMENT_WEIGHT);
+    if segments.len() < 2 {
+        return Ok(None);
+    }
+    let state = AppState::get(hwnd)?;
+    let selected = state.synth_options()?;
+    let mut parts: Vec<(usize, usize, SynthOptions)> = vec![];
+    for segment in segments {
+        let options = match voice_for_language(state, segment.language)? {
+            Some(id) => SynthOptions {
+                voice_id: Some(id),
+                ..selected.clone()
+            },
+            None => {
+                note_missing_voice(hwnd, segment.language)?;
+                selected.clone()
+            }
+        };
+        // 同じ音声が続く区間はつなげて、境目で合成を区切らないようにする
+        match parts.last_mut() {
+            Some((_, end, last)) if *last == options => *end = segment.end,
+            _ => parts.push((segment.start, segment.end, options)),
+        }
+    }
+    Ok((parts.len() > 1).then_some(parts))
+}
+
+/// 区間ごとの設定から、create で音声合成エンジンを作る (同じ設定の区間では一つを使い回す)
+fn voiced_parts<S: Clone>(
+    parts: &[(usize, usize, SynthOptions)],
+    mut create: impl FnMut(&SynthOptions) -> Result<S>,
+) -> Result<Vec<Voiced<S>>> {
+    let mut synths: Vec<(&SynthOptions, S)> = vec![];
+    let mut voiced = Vec::with_capacity(parts.len());
+    for (start, end, options) in parts {
+        let synth = match synths.iter().find(|(o, _)| *o == options) {
+            Some((_, synth)) => synth.clone(),
+            None => {
+                let synth = create(options)?;
+                synths.push((options, synth.clone()));
+                synth
+            }
+        };
+        voiced.push(Voiced {
+            start: *start,
+            end: *end,
+            synth,
+        });
+    }
+    Ok(voiced)
+}
+
+/// 再生する区間ごとの音声合成エンジン
+///
+/// 選択中の音声の区間ではいつもの音声合成エンジンを使い、ほかの音声は音声ごとに一つ作る。
+fn playback_parts(
+    state: &AppState,
+    parts: &[(usize, usize, SynthOptions)],
+) -> Result<Vec<Voiced<AppSynth>>> {
+    let selected = state.synth_options()?;
+    voiced_parts(parts, |options| {
+        if *options == selected {
+            state.synthesizer()
+        } else {
+            state.playback.engine().create_synthesizer(options)
+        }
+    })
+}
+
+/// 言語に合う音声の ID (合う音声がなければ None)
+///
+/// 選択中の音声が合っていればそれを、合っていなければその言語で最後に選んだ音声を優先して選ぶ。
+fn voice_for_language(state: &AppState, lang: Language) -> Result<Option<String>> {
+    let selected = state.selected_voice()?;
+    if lang.matches(&selected.language) {
+        return Ok(Some(selected.id.to_string()));
+    }
+    let voices = state
+        .voices
+        .borrow()
+        .iter()
+        .map(|voice| (voice.language.clone(), voice.id.to_string()))
         .collect::<Vec<_>>();
+    let preferred = settings::get().language_voices.get(lang.code()).cloned();
+    let index = language::choose_voice(lang, &voices, preferred.as_deref());
+    Ok(index.map(|index| voices[index].1.clone()))
+}
+
+/// 言語に合う音声がなく、選択中の音声で読み上げることをステータスバーに表示する
+fn note_missing_voice(hwnd: HWND, lang: Language) -> Result<()> {
+    status::set_status(
+        hwnd,
+        Part::Misc,
+        &trf(Msg::StatusNoVoiceForLanguage, &[lang.code()]),
+    )
+}
+
+/// 音声合成エンジンを指定して合成を始め、終わったら再生する
+fn begin_speech(
+    hwnd: HWND,
+    synth: &AppSynth,
+    text: Vec<u16>,
+    key: u64,
+    spoken: Option<(usize, usize)>,
+) -> Result<()> {
+    let whole = Voiced::whole(&text, synth);
+    begin_voiced_speech(hwnd, vec![whole], text, key, spoken)
+}
+
+/// 区間ごとに音声合成エンジンを切り替えて合成を始め、終わったら再生する
+fn begin_voiced_speech(
+    hwnd: HWND,
+    parts: Vec<Voiced<AppSynth>>,
+    text: Vec<u16>,
+    key: u64,
+    spoken: Option<(usize, usize)>,
+) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let id = SPEECH_ID.fetch_add(1, Ordering::Relaxed) + 1;
+    let handle = hwnd.0 as isize;
+    // 一時停止できるように、同時に再生するのは一つだけにする (それまでのスピーチは停止される)
+    state.playback.begin(
+        id,
+        synthesis::voiced_chunks(&text, &parts)?,
+        key,
+        move |round| UiMessage::SpeechStarted(id, round).post(handle),
+        move |result| UiMessage::SpeechFinished(id, result).post(handle),
+    )?;
+    state.resume.start(spoken);
+    keep_awake(id);
+    // 停止したときに続きの位置を求めるには、同じように区切ったチャンクが必要
+    SPEECH_PARTS.set((parts.len() > 1).then_some(parts));
+    // メディアキーが使えなくても読み上げはできるので、失敗は記録するだけにする
+    if let Err(e) = show_media_controls(hwnd, &text, &parts) {
+        logging::error("failed to show the media controls", &e);
+    }
+    set_synthesizing(hwnd, true)?;
+    SPEECH_BEGAN.set(Some(Instant::now()));
+    logging::info(format_args!(
+        "speech {id}: synthesizing {} units",
+        text.len()
+    ));
+    status::set_status(hwnd, Part::State, tr(Msg::StatusSynthesizing))
+}
+
+/// 設定されていれば、番号 id の再生が終わるまでスリープを防ぐ
+fn keep_awake(id: usize) {
+    let (prevent_sleep, display) = {
+        let settings = settings::get();
+        (settings.prevent_sleep, settings.keep_display_on)
+    };
+    if prevent_sleep {
+        power::hold(id, display);
+    }
+}
+
+/// 合成中かどうかを切り替え、プログレスバーと再生ボタンの状態を合わせる
+fn set_synthesizing(hwnd: HWND, synthesizing: bool) -> Result<()> {
+    SYNTHESIZING.set(synthesizing);
+    status::set_busy(hwnd, synthesizing)?;
+    update_taskbar_progress(hwnd)?;
+    update_media_controls(hwnd)?;
+    update_toolbar(hwnd)
+}
+
+/// 再生の状態をメディアのフライアウトに反映する。再生が終わっていれば表示を消す
+fn update_media_controls(hwnd: HWND) -> Result<()> {
+    let status = AppState::get(hwnd)?.playback.status();
+    if let Err(e) = media_controls::update(status) {
+        logging::error("failed to update the media controls", &e);
+    }
+    Ok(())
+}
+
+/// タスクバーのボタンに再生や保存の進み具合を表示する
+///
+/// 最小化していてもわかるように、どちらかをしている間は [PROGRESS_TIMER] で更新し続ける。
+/// どちらもしていなければ表示を消してタイマーを止める。
+fn update_taskbar_progress(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let saving = state
+        .saving
+        .borrow()
+        .as_ref()
+        .map(|saving| saving.progress());
+    let progress = match (saving, state.playback.status()) {
+        (Some(fraction), _) => Some((Some(fraction), false)),
+        (None, Status::Idle) => None,
+        (None, Status::Synthesizing) => Some((None, false)),
+        (None, status) => Some((state.playback.overall_progress(), status == Status::Paused)),
+    };
+    match progress {
+        Some((fraction, paused)) => {
+            taskbar::set_progress(hwnd, fraction, paused);
+            unsafe { SetTimer(hwnd, PROGRESS_TIMER, PROGRESS_INTERVAL, None) };
+        }
+        None => {
+            taskbar::clear(hwnd);
+            _ = unsafe { KillTimer(hwnd, PROGRESS_TIMER) };
+        }
+    }
+    Ok(())
+}
+
+/// 再生や保存に失敗したことを、タスクバーのボタンにしばらく表示する
+fn show_taskbar_error(hwnd: HWND) {
+    _ = unsafe { KillTimer(hwnd, PROGRESS_TIMER) };
+    taskbar::set_error(hwnd);
+    unsafe { SetTimer(hwnd, TASKBAR_ERROR_TIMER, TASKBAR_ERROR_DURATION, None) };
+}
+
+/// 再生が始まったことをステータスバーに表示する (古い再生からの通知は無視する)
+///
+/// 繰り返している場合は、回ごとに何回目かを表示し直す。
+fn speech_started(hwnd: HWND, id: usize, round: u32) -> Result<()> {
+    if id != SPEECH_ID.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    if round == 1 {
+        set_synthesizing(hwnd, false)?;
+        unsafe { SetTimer(hwnd, WATCHDOG_TIMER, WATCHDOG_INTERVAL, None) };
+        logging::info(format_args!(
+            "speech {id}: first audio after {:?}",
+            speech_elapsed()
+        ));
+    }
+    let repeats = AppState::get(hwnd)?.playback.repeats();
+    status::set_status(hwnd, Part::State, &playing_status(round, repeats))
+}
+
+/// 再生中の表示。繰り返す場合は「再生中 (2/5)」のように何回目かを添える
+fn playing_status(round: u32, repeats: u32) -> String {
+    if repeats > 1 {
+        trf(
+            Msg::StatusPlayingRound,
+            &[&round.to_string(), &repeats.to_string()],
+        )
+    } else {
+        tr(Msg::StatusPlaying).to_string()
+    }
+}
+
+/// 再生が終わったことを処理する (古い再生からの通知は無視する)
+///
+/// 失敗した場合は原因を表示する。最後まで再生できたら、再生待ちの次のファイルに進む。
+fn speech_finished(hwnd: HWND, id: usize, result: Result<()>) -> Result<()> {
+    if id != SPEECH_ID.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    set_synthesizing(hwnd, false)?;
+    _ = unsafe { KillTimer(hwnd, WATCHDOG_TIMER) };
+    let state = AppState::get(hwnd)?;
+    state.play_guard.clear();
+    state.resume.finished();
+    logging::info(format_args!(
+        "speech {id}: finished after {:?}",
+        speech_elapsed()
+    ));
+    if let Err(e) = result {
+        show_taskbar_error(hwnd);
+        let status = trf(Msg::StatusPlayFailed, &[&e.to_string()]);
+        operation_finished(hwnd, Part::State, &status)?;
+        report_error(hwnd, Msg::ErrorPlay, &e);
+        return Ok(());
+    }
+    if queue::playback_len() > 0 {
+        return play_next_queued(hwnd);
+    }
+    operation_finished(hwnd, Part::State, tr(Msg::StatusStopped))
+}
+
+/// 最後に合成を始めてからの時間
+fn speech_elapsed() -> Duration {
+    SPEECH_BEGAN
+        .get()
+        .map_or(Duration::ZERO, |began| began.elapsed())
+}
+
+/// 再生や保存が終わったことをステータスバーに表示する
+///
+/// ウィンドウがアクティブでなければ、クリックされるまでタスクバーのボタンを点滅させる。
+fn operation_finished(hwnd: HWND, part: Part, text: &str) -> Result<()> {
+    status::set_status(hwnd, part, text)?;
+    if unsafe { GetForegroundWindow() } != hwnd {
+        let info = FLASHWINFO {
+            cbSize: mem::size_of::<FLASHWINFO>() as _,
+            hwnd,
+            // FLASHW_TIMERNOFG によりウィンドウが前面に来ると点滅が止まる
+            dwFlags: FLASHW_TRAY | FLASHW_TIMERNOFG,
+            ..Default::default()
+        };
+        _ = unsafe { FlashWindowEx(&info) };
+    }
+    Ok(())
+}
+
+/// 別スレッドからの通知を UI に反映する
+fn ui_message(hwnd: HWND, message: UiMessage) {
+    match message {
+        UiMessage::SpeechStarted(id, round) => _ = speech_started(hwnd, id, round),
+        UiMessage::SpeechFinished(id, result) => {
+            // 古い再生の通知でも、スリープを防いだまま残らないように外す
+            power::release(id);
+            _ = speech_finished(hwnd, id, result);
+        }
+        UiMessage::SaveFinished(finished) => {
+            if let Err(e) = save_finished(hwnd, finished) {
+                report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+        UiMessage::ExportFinished(finished) => {
+            if let Err(e) = export_file_finished(hwnd, finished) {
+                report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+        UiMessage::SentenceExported(result) => {
+            if let Err(e) = sentence_exported(hwnd, result) {
+                report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+        UiMessage::FolderConverted(converted) => _ = folder_converted(hwnd, converted),
+        UiMessage::MediaButton(button) => {
+            if let Err(e) = media_button(hwnd, button) {
+                report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+    }
+}
+
+/// メディアキーやメディアのフライアウトのボタンで再生を操作する
+fn media_button(hwnd: HWND, button: SystemMediaTransportControlsButton) -> Result<()> {
+    let status = AppState::get(hwnd)?.playback.status();
+    match button {
+        SystemMediaTransportControlsButton::Play if status == Status::Idle => speech(hwnd),
+        SystemMediaTransportControlsButton::Play if status == Status::Paused => toggle_pause(hwnd),
+        SystemMediaTransportControlsButton::Pause if status == Status::Playing => {
+            toggle_pause(hwnd)
+        }
+        SystemMediaTransportControlsButton::Stop => stop(hwnd),
+        SystemMediaTransportControlsButton::Next => skip_sentence(hwnd, true),
+        SystemMediaTransportControlsButton::Previous => skip_sentence(hwnd, false),
+        _ => Ok(()),
+    }
+}
+
+/// 読み上げるテキストの最初の行と音声の名前を、メディアのフライアウトに表示する
+fn show_media_controls(hwnd: HWND, text: &[u16], parts: &[Voiced<AppSynth>]) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let tags = wav_tags(state.playback.engine(), text, parts)?;
+    let controls = state.playback.media_controls()?;
+    media_controls::show(hwnd.0 as isize, &controls, &tags.title, &tags.artist)
+}
+
+/// 保存先のファイルパスをユーザーに選択させる。キャンセルされた場合は None を返す
+///
+/// ファイル名はテキストの最初の行から提案し、前回保存したフォルダーを開く。
+fn get_save_file_path(hwnd: HWND, text: &[u16]) -> Result<Option<PathBuf>> {
+    debug_assert!(
+        com::is_sta(),
+        "common dialogs must be shown from the STA UI thread"
+    );
+    let name = sentence_export::suggest_file_name(&String::from_utf16_lossy(text))
+        .unwrap_or_else(timestamp_file_name);
+    let mut buf = name.encode_utf16().collect::<Vec<_>>();
+    buf.resize(512, 0);
+    let initial_dir = settings::get()
+        .last_save_dir
+        .as_ref()
+        .filter(|dir| dir.is_dir())
+        .map(|dir| HSTRING::from(dir.as_os_str()));
+    let filter = strings::wide(Msg::FilterWave);
     let mut filename = OPENFILENAMEW {
         lStructSize: mem::size_of::<OPENFILENAMEW>() as _,
         hwndOwner: hwnd,
         lpstrFile: PWSTR::from_raw(buf.as_mut_ptr()),
-        lpstrFilter: w!("Wave File (.wav)\0*.wav\0\0"),
+        lpstrFilter: PCWSTR(filter.as_ptr()),
+        lpstrInitialDir: initial_dir
+            .as_ref()
+            .map_or(PCWSTR::null(), |dir| PCWSTR(dir.as_ptr())),
         lpstrDefExt: w!("wav"),
         nMaxFile: buf.len() as _,
+        Flags: OFN_OVERWRITEPROMPT | OFN_PATHMUSTEXIST,
+        ..Default::default()
+    };
+    if !unsafe { GetSaveFileNameW(&mut filename) }.as_bool() {
+        return dialog_cancelled();
+    }
+    let path: String = decode_utf16(buf.iter().take_while(|v| *v != &0).copied())
+        .map(|r| r.unwrap_or(REPLACEMENT_CHARACTER))
+        .collect();
+    let path = PathBuf::from(path);
+    if let Some(dir) = path.parent() {
+        // フォルダーを覚えられなくても保存はできるので、失敗は無視する
+        settings::update(|s| s.last_save_dir = Some(dir.to_path_buf())).ok();
+    }
+    Ok(Some(path))
+}
+
+/// テキストからファイル名を提案できないときの、日時を使ったファイル名 (speech_20240501_1432.wav)
+fn timestamp_file_name() -> String {
+    let now = unsafe { GetLocalTime() };
+    format!(
+        "speech_{:04}{:02}{:02}_{:02}{:02}.wav",
+        now.wYear, now.wMonth, now.wDay, now.wHour, now.wMinute
+    )
+}
+
+/// 開くファイルのパスをユーザーに選択させる。キャンセルされた場合は None を返す
+fn get_open_file_path(hwnd: HWND) -> Result<Option<PathBuf>> {
+    debug_assert!(
+        com::is_sta(),
+        "common dialogs must be shown from the STA UI thread"
+    );
+    let mut buf = vec![0u16; 512];
+    let filter = strings::wide(Msg::FilterText);
+    let mut filename = OPENFILENAMEW {
+        lStructSize: mem::size_of::<OPENFILENAMEW>() as _,
+        hwndOwner: hwnd,
+        lpstrFile: PWSTR::from_raw(buf.as_mut_ptr()),
+        lpstrFilter: PCWSTR(filter.as_ptr()),
+        lpstrDefExt: w!("txt"),
+        nMaxFile: buf.len() as _,
+        Flags: OFN_FILEMUSTEXIST | OFN_PATHMUSTEXIST,
         ..Default::default()
     };
-    unsafe { GetSaveFileNameW(&mut filename).ok()? };
+    if !unsafe { GetOpenFileNameW(&mut filename) }.as_bool() {
+        return dialog_cancelled();
+    }
     let path: String = decode_utf16(buf.iter().take_while(|v| *v != &0).copied())
         .map(|r| r.unwrap_or(REPLACEMENT_CHARACTER))
         .collect();
-    Ok(path.into())
+    Ok(Some(path.into()))
 }
 
-fn save_to_wav(hwnd: HWND) -> Result<()> {
-    let file_path = get_save_file_path(hwnd)?;
+/// 書き出し先のフォルダーをユーザーに選択させる。キャンセルされた場合は None を返す
+fn get_folder_path(hwnd: HWND) -> Result<Option<PathBuf>> {
+    debug_assert!(
+        com::is_sta(),
+        "common dialogs must be shown from the STA UI thread"
+    );
+    let dialog: IFileOpenDialog =
+        unsafe { CoCreateInstance(&FileOpenDialog, None, CLSCTX_INPROC_SERVER)? };
+    unsafe { dialog.SetOptions(dialog.GetOptions()? | FOS_PICKFOLDERS)? };
+    if let Err(e) = unsafe { dialog.Show(hwnd) } {
+        if e.code() == ERROR_CANCELLED.to_hresult() {
+            return Ok(None);
+        }
+        return Err(e.into());
+    }
+    let item = unsafe { dialog.GetResult()? };
+    let path = unsafe { item.GetDisplayName(SIGDN_FILESYSPATH)? };
+    let result = unsafe { path.to_string() };
+    unsafe { CoTaskMemFree(Some(path.0 as _)) };
+    Ok(Some(result?.into()))
+}
 
-    let text = get_edit_control_text()?;
-    let stream = speech_synthesis_stream(&text)?;
-    let reader = DataReader::CreateDataReader(&stream)?;
-    let size = stream.Size()? as u32;
-    reader.LoadAsync(size)?.get()?;
-    let buffer: IBufferByteAccess = reader.ReadBuffer(size)?.cast()?;
-    let ptr = unsafe { buffer.Buffer()? };
+/// ファイルダイアログが閉じられた原因がキャンセルなら None を、エラーなら Err を返す
+fn dialog_cancelled() -> Result<Option<PathBuf>> {
+    let code = unsafe { CommDlgExtendedError() };
+    ensure!(code.0 == 0, "file dialog failed. (0x{:x})", code.0);
+    Ok(None)
+}
 
-    let slice = unsafe { slice::from_raw_parts(ptr, size as usize) };
-    std::fs::write(&file_path, slice)?;
+/// コマンドライン引数で渡されたファイルやテキストをエディットコントロールに表示する
+fn open_argument(hwnd: HWND, argument: &Argument) -> Result<()> {
+    match argument {
+        Argument::Files(files) => open_files(hwnd, files),
+        Argument::Text(text) => {
+            let text = text.encode_utf16().collect::<Vec<_>>();
+            set_edit_control_text(hwnd, &text_file::normalize_newlines(&text))
+        }
+    }
+}
 
-    let file_name = file_path.file_name().context("no file name.")?;
-    let msg = format!("{} を保存しました。", file_name.to_string_lossy());
-    let msg = msg.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
-    unsafe { MessageBoxW(hwnd, PCWSTR(msg.as_ptr()), w!("speech"), MB_OK) };
+/// コマンドラインの引数を開き、play が true なら再生する
+///
+/// `--export-dir` が指定されている場合は、ファイルを開かずに WAV に書き出す。
+/// 開くものがなく `--speak-clipboard` が指定されている場合は、クリップボードのテキストを読み上げる。
+fn open_command_line(hwnd: HWND, command_line: CommandLine, play: bool) -> Result<()> {
+    let Some(argument) = command_line.argument else {
+        if command_line.speak_clipboard {
+            return load_and_speak_clipboard(hwnd);
+        }
+        return Ok(());
+    };
+    if let (Some(dir), Argument::Files(files)) = (&command_line.export_dir, &argument) {
+        return export_files(hwnd, dir, files.clone());
+    }
+    // 再生中のファイルがある場合は再生待ちに追加されるだけなので、再生し直さない
+    let speaking = is_speaking(hwnd)?;
+    open_argument(hwnd, &argument)?;
+    if play && !speaking {
+        speech(hwnd)?;
+    }
     Ok(())
 }
 
-fn paint(hwnd: HWND) -> Result<()> {
-    let mut ps = PAINTSTRUCT::default();
-    let hdc = unsafe { BeginPaint(hwnd, &mut ps) };
-    unsafe { SetBkMode(hdc, TRANSPARENT) };
-    unsafe { TextOutW(hdc, 10, 50, w!("読み上げ速度：遅").as_wide()).ok()? };
-    unsafe { TextOutW(hdc, 550, 50, w!("速").as_wide()).ok()? };
-    unsafe { EndPaint(hwnd, &mut ps).ok()? };
-    Ok(())
+/// ほかのプログラムから WM_COPYDATA で送られた操作を実行する
+fn remote_request(hwnd: HWND, request: Request) -> Result<()> {
+    match request {
+        Request::Speak(text) => speak(hwnd, text, None),
+        Request::Load(text) => set_edit_control_text(hwnd, &text_file::normalize_newlines(&text)),
+        Request::Stop => stop(hwnd),
+    }
+}
+
+/// 名前付きパイプで受け付けたコマンドを実行して結果を返す
+///
+/// 保存は UI スレッドを止めないように、合成が終わってから結果を返す。
+fn pipe_command(hwnd: HWND, request: pipe::Request) {
+    let result = match &request.command {
+        pipe::Command::Speak(text) => speak(hwnd, text.encode_utf16().collect(), None),
+        pipe::Command::Stop => stop(hwnd),
+        pipe::Command::Save(path, text) => {
+            let path = path.clone();
+            let text = text.encode_utf16().collect::<Vec<_>>();
+            return save_for_pipe(hwnd, &text, path, request);
+        }
+        pipe::Command::Rate(rate) => set_speaking_rate(hwnd, *rate),
+        pipe::Command::Voice(name) => select_voice(hwnd, name),
+    };
+    request.reply(result);
+}
+
+/// パイプで求められた保存を、保存ボタンと同じように始める。始められなければすぐに結果を返す
+fn save_for_pipe(hwnd: HWND, text: &[u16], path: PathBuf, request: pipe::Request) {
+    let mut reply = Some(request);
+    let result = AppState::get(hwnd).and_then(|state| {
+        ensure!(state.saving.borrow().is_none(), "already saving.");
+        save_wav(hwnd, text, path, &mut reply)
+    });
+    if let Err(e) = result {
+        match reply {
+            Some(request) => request.reply(Err(e)),
+            None => logging::error("failed to start saving for the pipe", &e),
+        }
+    }
+}
+
+/// 別のインスタンスから転送された引数を開き、ウィンドウを前面に出す
+fn receive_argument(hwnd: HWND, command_line: CommandLine) -> Result<()> {
+    restore_window(hwnd);
+    let play = command_line.play || settings::get().play_forwarded;
+    open_command_line(hwnd, command_line, play)
 }
 
-fn get_edit_control_text() -> Result<Vec<u16>> {
-    let hwnd = EDIT_HWND.get().context("no handle.")?.handle();
-    let len = unsafe { GetWindowTextLengthW(hwnd) };
-    let mut buf = vec![0; len as usize + 1];
-    unsafe { GetWindowTextW(hwnd, &mut buf) };
-    Ok(buf)
+/// 先頭のファイルを開き、残りを再生待ちに追加する
+///
+/// すでに再生中か再生待ちがある場合は、すべて再生待ちの最後に追加する。
+fn open_files(hwnd: HWND, files: &[PathBuf]) -> Result<()> {
+    let rest = if is_speaking(hwnd)? || queue::playback_len() > 0 {
+        files
+    } else {
+        let Some((first, rest)) = files.split_first() else {
+            return Ok(());
+        };
+        load_file(hwnd, first)?;
+        rest
+    };
+    queue::push_playback(rest.iter().cloned());
+    update_queue_status(hwnd)
+}
+
+/// 再生待ちのファイルの数をステータスバーに表示する
+fn update_queue_status(hwnd: HWND) -> Result<()> {
+    match queue::playback_len() {
+        0 => Ok(()),
+        len => status::set_status(
+            hwnd,
+            Part::Misc,
+            &trf(Msg::StatusQueued, &[&len.to_string()]),
+        ),
+    }
 }
 
-fn clear_edit_control_text() -> Result<()> {
-    let hwnd = EDIT_HWND.get().context("no handle.")?.handle();
-    unsafe { SendMessageW(hwnd, WM_SETTEXT, None, None) };
-    let mut stop = STOP.lock().unwrap();
-    while !stop.is_empty() {
-        if let Some(tx) = stop.pop() {
-            _ = tx.send(());
+/// 再生待ちの次のファイルを開いて再生する。テキストはエディットコントロールを使わずに再生する
+///
+/// 開けなかったファイルはステータスバーに表示して飛ばす。
+fn play_next_queued(hwnd: HWND) -> Result<()> {
+    let mut failed = false;
+    while let Some(queued) = queue::pop_playback() {
+        let path = match queued {
+            queue::Queued::File(path) => path,
+            queue::Queued::Text(text) => return speak(hwnd, text, None),
+        };
+        match load_file(hwnd, &path) {
+            Ok(()) => {
+                if !failed {
+                    status::set_status(hwnd, Part::Misc, "")?;
+                    update_queue_status(hwnd)?;
+                }
+                return speech(hwnd);
+            }
+            Err(e) => {
+                failed = true;
+                let msg = trf(Msg::StatusQueueSkipped, &[&format!("{e:#}")]);
+                status::set_status(hwnd, Part::Misc, &msg)?;
+            }
         }
     }
     Ok(())
 }
 
-fn command(hwnd: HWND, wparam: WPARAM) -> Result<()> {
-    let id = loword(wparam.0 as _);
+/// ファイルを書き出し待ちに追加し、書き出し中でなければ書き出しを始める
+fn export_files(hwnd: HWND, dir: &Path, files: Vec<PathBuf>) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    if queue::push_exports(dir, files) {
+        status::set_busy(hwnd, true)?;
+        unsafe { PostMessageW(hwnd, WM_EXPORT_NEXT, WPARAM(0), LPARAM(0))? };
+    }
+    Ok(())
+}
 
-    if id.eq(&ID_PLAY) {
-        speech()?;
-    } else if id.eq(&ID_CLEAR) {
-        clear_edit_control_text()?;
-    } else if id.eq(&ID_SAVE) {
-        save_to_wav(hwnd)?;
+/// 書き出し待ちのファイルを一つ WAV に書き出す。すべて終わったら結果を表示する
+///
+/// 一つずつメッセージを投げ直すことで、書き出しの合間にも転送されたファイルを受け付ける。
+fn export_next(hwnd: HWND) -> Result<()> {
+    let Some(job) = queue::next_export() else {
+        return export_finished(hwnd);
+    };
+    let name = job.source.file_name().unwrap_or_default().to_string_lossy();
+    let msg = trf(
+        Msg::StatusExporting,
+        &[&job.index.to_string(), &job.total.to_string(), &name],
+    );
+    status::set_status(hwnd, Part::Misc, &msg)?;
+    if let Err(e) = export_file(hwnd, job.source.clone(), job.target) {
+        queue::export_failed(&job.source, &e);
+        unsafe { PostMessageW(hwnd, WM_EXPORT_NEXT, WPARAM(0), LPARAM(0))? };
     }
+    Ok(())
+}
+
+/// 一つのファイルを書き出した結果
+struct ExportFinished {
+    source: PathBuf,
+    result: Result<()>,
+}
 
+/// テキストファイルを選択中の音声と速度で WAV に書き出し始める
+///
+/// 書き出し終わると [UiMessage::ExportFinished] で結果を知らせる。
+fn export_file(hwnd: HWND, source: PathBuf, target: PathBuf) -> Result<()> {
+    let text = text_file::read_text_file(&source)?;
+    let synth = synthesis::create_synthesizer(&AppState::get(hwnd)?.synth_options()?)?;
+    let output = WavOutput::new(&text, &[Voiced::whole(&text, &synth)])?;
+    let handle = hwnd.0 as isize;
+    synthesis::start_wav(&synth, &text, move |bytes| {
+        let result = bytes.and_then(|bytes| output.write(bytes, &target).map(drop));
+        let finished = ExportFinished { source, result };
+        UiMessage::ExportFinished(finished).post(handle);
+    });
     Ok(())
 }
 
-fn create_button(
-    hwnd: HWND,
-    label: PCWSTR,
-    x: i32,
-    y: i32,
-    width: i32,
-    height: i32,
-    id: u16,
-) -> Result<()> {
-    unsafe {
+/// 書き出し終えたファイルの結果を記録して、次のファイルに進む
+fn export_file_finished(hwnd: HWND, finished: ExportFinished) -> Result<()> {
+    if let Err(e) = finished.result {
+        queue::export_failed(&finished.source, &e);
+    }
+    export_next(hwnd)
+}
+
+/// エディットのテキストを文に区切り、選んだフォルダーに文ごとの WAV ファイルを書き出し始める
+///
+/// ファイル名はダイアログで入力したパターンから決める。停止ボタンで残りを取り消せる。
+fn export_sentences(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    if state.saving.borrow().is_some() {
+        return Ok(());
+    }
+    let text = state.edit_text()?;
+    let Some(pattern) = sentence_dialog::show(hwnd)? else {
+        return Ok(());
+    };
+    let sentences =
+        sentence_export::plan(&text, &pattern).context(tr(Msg::ErrorSentencePattern))?;
+    ensure!(!sentences.is_empty(), tr(Msg::ErrorNoText));
+    let Some(dir) = get_folder_path(hwnd)? else {
+        return Ok(());
+    };
+    logging::info(format_args!(
+        "exporting {} sentences to {}",
+        sentences.len(),
+        dir.display()
+    ));
+    if queue::start_sentences(&dir, sentences) {
+        status::set_busy(hwnd, true)?;
+        export_next_sentence(hwnd)?;
+    }
+    Ok(())
+}
+
+/// 次の文を WAV に書き出し始める。すべて終わったら結果を表示する
+fn export_next_sentence(hwnd: HWND) -> Result<()> {
+    let Some(job) = queue::next_sentence() else {
+        return sentences_exported(hwnd, false);
+    };
+    let msg = trf(
+        Msg::StatusExportingSentence,
+        &[&job.index.to_string(), &job.total.to_string()],
+    );
+    status::set_status(hwnd, Part::Misc, &msg)?;
+    let state = AppState::get(hwnd)?;
+    let synth = synthesis::create_synthesizer(&state.synth_options()?)?;
+    let text: Vec<u16> = job.text.encode_utf16().collect();
+    let output = WavOutput::new(&text, &[Voiced::whole(&text, &synth)])?;
+    let handle = hwnd.0 as isize;
+    let saving = synthesis::start_wav(&synth, &text, move |bytes| {
+        let result = bytes.and_then(|bytes| output.write(bytes, &job.path).map(drop));
+        UiMessage::SentenceExported(result).post(handle);
+    });
+    // 停止ボタンで取り消せるようにしておく
+    state.saving.replace(Some(saving));
+    update_taskbar_progress(hwnd)?;
+    update_toolbar(hwnd)
+}
+
+/// 一つの文を書き出し終えたら結果を記録して次の文に進む。取り消されていたら残りをやめる
+fn sentence_exported(hwnd: HWND, result: Result<()>) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    state.saving.take();
+    let cancelled = result
+        .as_ref()
+        .is_err_and(|e| e.is::<synthesis::Cancelled>());
+    if cancelled || state.closing.get() {
+        queue::cancel_sentences();
+    } else {
+        queue::sentence_finished(&result);
+    }
+    if state.closing.get() {
+        queue::finish_sentences();
+        unsafe { DestroyWindow(hwnd)? };
+        return Ok(());
+    }
+    update_taskbar_progress(hwnd)?;
+    update_toolbar(hwnd)?;
+    if cancelled {
+        return sentences_exported(hwnd, true);
+    }
+    export_next_sentence(hwnd)
+}
+
+/// 文ごとの書き出しを終え、一覧を書き出して結果を知らせる
+fn sentences_exported(hwnd: HWND, cancelled: bool) -> Result<()> {
+    let Some(summary) = queue::finish_sentences() else {
+        return Ok(());
+    };
+    status::set_busy(hwnd, SYNTHESIZING.get())?;
+    if settings::get().sentence_manifest && !summary.done.is_empty() {
+        let csv = sentence_export::manifest(&summary.done);
+        // Excel で文字化けしないように BOM を付ける
+        let bytes = [&[0xEF, 0xBB, 0xBF][..], csv.as_bytes()].concat();
+        std::fs::write(summary.dir.join(MANIFEST_FILE_NAME), bytes)?;
+    }
+    let done = summary.done.len().to_string();
+    if cancelled {
+        return status::set_status(
+            hwnd,
+            Part::Misc,
+            &trf(Msg::SentenceExportCancelled, &[&done]),
+        );
+    }
+    let msg = trf(Msg::SentencesExported, &[&done, &summary.total.to_string()]);
+    operation_finished(hwnd, Part::Misc, &msg)?;
+    let details = summary
+        .errors
+        .iter()
+        .fold(msg, |text, error| text + "\r\n" + error);
+    show_message(hwnd, &details);
+    Ok(())
+}
+
+/// 書き出しの結果をステータスバーとダイアログで知らせる
+fn export_finished(hwnd: HWND) -> Result<()> {
+    status::set_busy(hwnd, false)?;
+    let summary = queue::finish_export();
+    let succeeded = summary.total - summary.errors.len();
+    let msg = trf(
+        Msg::ExportFinished,
+        &[&succeeded.to_string(), &summary.total.to_string()],
+    );
+    operation_finished(hwnd, Part::Misc, &msg)?;
+    let details = summary
+        .errors
+        .iter()
+        .fold(msg, |text, error| text + "\r\n" + error);
+    show_message(hwnd, &details);
+    Ok(())
+}
+
+/// ウィンドウのタイトルを「speech - ファイル名」にする。path が None ならアプリケーション名だけにする
+fn set_window_title(hwnd: HWND, path: Option<&Path>) -> Result<()> {
+    let title = match path.and_then(Path::file_name) {
+        Some(name) => format!("{} - {}", tr(Msg::AppName), name.to_string_lossy()),
+        None => tr(Msg::AppName).to_string(),
+    };
+    unsafe { SetWindowTextW(hwnd, &HSTRING::from(title))? };
+    Ok(())
+}
+
+/// テキストファイルを読み込んでエディットコントロールに表示し、タイトルにファイル名を表示する
+fn load_file(hwnd: HWND, path: &Path) -> Result<()> {
+    let text = text_file::read_text_file(path)
+        .with_context(|| format!("failed to read {}.", path.display()))?;
+    set_edit_control_text(hwnd, &text)?;
+    set_window_title(hwnd, Some(path))?;
+    // ジャンプリストは補助的な機能なので、更新に失敗しても読み込みは成功とする
+    jump_list::add_recent_file(path).ok();
+    update_recent_menu(hwnd)
+}
+
+/// 最近開いたファイルを開く。見つからない場合は知らせて一覧から削除する
+fn open_recent_file(hwnd: HWND, index: usize) -> Result<()> {
+    let Some(path) = settings::get().recent_files.get(index).cloned() else {
+        return Ok(());
+    };
+    if !path.exists() {
+        settings::update(|s| s.recent_files.retain(|p| p != &path))?;
+        jump_list::update().ok();
+        update_recent_menu(hwnd)?;
+        let msg = trf(Msg::ErrorRecentFileMissing, &[&path.to_string_lossy()]);
+        show_message(hwnd, &msg);
+        return Ok(());
+    }
+    load_file(hwnd, &path)
+}
+
+/// ファイルメニューの終了の前に、最近開いたファイルを番号付きで並べ直す
+fn update_recent_menu(hwnd: HWND) -> Result<()> {
+    let menu = unsafe { GetSubMenu(GetMenu(hwnd), 0) };
+    ensure!(!menu.is_invalid(), "no file menu.");
+    menu::delete_item(menu, ID_RECENT_SEPARATOR);
+    for id in (ID_RECENT_FILE..).take(jump_list::MAX_RECENT_FILES) {
+        menu::delete_item(menu, id);
+    }
+    let files = settings::get().recent_files.clone();
+    for (i, path) in files.iter().take(jump_list::MAX_RECENT_FILES).enumerate() {
+        let label = recent_file_label(i + 1, path);
+        menu::insert_item(menu, ID_EXIT, ID_RECENT_FILE + i as u16, &label)?;
+    }
+    if !files.is_empty() {
+        menu::insert_separator(menu, ID_EXIT, ID_RECENT_SEPARATOR)?;
+    }
+    Ok(())
+}
+
+/// 最近開いたファイルのメニュー項目のラベル (「&1 C:\file.txt」のように番号をアクセスキーにする)
+fn recent_file_label(number: usize, path: &Path) -> String {
+    // パスの & はアクセスキーにならないように重ねる
+    let path = path.to_string_lossy().replace('&', "&&");
+    match number {
+        10 => format!("1&0 {path}"),
+        n => format!("&{n} {path}"),
+    }
+}
+
+fn open_file(hwnd: HWND) -> Result<()> {
+    let Some(file_path) = get_open_file_path(hwnd)? else {
+        return Ok(());
+    };
+    load_file(hwnd, &file_path)
+}
+
+/// ファイルを選んで、追記された行を読み上げる監視を始める。監視中なら停止する
+fn toggle_watch_file(hwnd: HWND) -> Result<()> {
+    if tail::is_watching() {
+        tail::stop();
+        logging::info("stopped watching the file");
+        return status::set_status(hwnd, Part::Misc, tr(Msg::StatusWatchStopped));
+    }
+    let Some(path) = get_open_file_path(hwnd)? else {
+        return Ok(());
+    };
+    tail::start(hwnd, &path, WM_TAIL_LINES)?;
+    logging::info(format_args!("watching {}", path.display()));
+    let name = path
+        .file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy();
+    status::set_status(hwnd, Part::Misc, &trf(Msg::StatusWatching, &[&name]))
+}
+
+/// フォルダーを選んで、追加されたテキストファイルを WAV に変換する監視を始める。監視中なら停止する
+///
+/// 変換には選択中の音声と読み上げ速度を使う。
+fn toggle_watch_folder(hwnd: HWND) -> Result<()> {
+    if folder_watch::is_watching() {
+        folder_watch::stop();
+        logging::info("stopped watching the folder");
+        return status::set_status(hwnd, Part::Misc, tr(Msg::StatusWatchFolderStopped));
+    }
+    let options = AppState::get(hwnd)?.synth_options()?;
+    let Some(dir) = get_folder_path(hwnd)? else {
+        return Ok(());
+    };
+    folder_watch::start(hwnd.0 as isize, &dir, options)?;
+    logging::info(format_args!("watching the folder {}", dir.display()));
+    status::set_status(
+        hwnd,
+        Part::Misc,
+        &trf(Msg::StatusWatchingFolder, &[&dir.to_string_lossy()]),
+    )
+}
+
+/// 監視しているフォルダーのテキストファイルを変換した結果を表示する (失敗の理由はログに記録してある)
+fn folder_converted(hwnd: HWND, converted: folder_watch::Converted) -> Result<()> {
+    let name = |path: &Path| {
+        path.file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    };
+    let source = name(&converted.source);
+    let text = match converted.result {
+        Ok(()) => trf(
+            Msg::StatusFolderConverted,
+            &[&source, &name(&converted.target)],
+        ),
+        Err(_) => trf(Msg::StatusFolderSkipped, &[&source]),
+    };
+    status::set_status(hwnd, Part::Misc, &text)
+}
+
+/// 監視しているファイルに追記された行を読み上げる
+fn tail_lines(hwnd: HWND, text: Vec<u16>) -> Result<()> {
+    // 停止した後に届いた行は読み上げない
+    if !tail::is_watching() {
+        return Ok(());
+    }
+    speak_or_queue(hwnd, text)
+}
+
+/// エディットコントロールを使わずにテキストを読み上げる
+///
+/// 再生中や合成中なら重ねて再生せずに再生待ちに追加し、続けて届いたテキストは一つにまとめる。
+fn speak_or_queue(hwnd: HWND, text: Vec<u16>) -> Result<()> {
+    if SYNTHESIZING.get() || is_speaking(hwnd)? || queue::playback_len() > 0 {
+        queue::push_text(text);
+        return Ok(());
+    }
+    speak(hwnd, text, None)
+}
+
+/// クイック入力モードで Enter キーが押されたら、入力したテキストを読み上げる
+///
+/// 次に入力した文字で置き換わるように、読み上げたテキストはすべて選択しておく。
+fn quick_speak(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    if !state.has_speakable_text()? {
+        return Ok(());
+    }
+    if !state.has_voices() {
+        show_no_voices(hwnd);
+        return Ok(());
+    }
+    speak_or_queue(hwnd, state.edit_text()?)?;
+    select_all_edit_control_text(hwnd)
+}
+
+fn save_to_wav(hwnd: HWND) -> Result<()> {
+    save_text_to_wav(hwnd, &AppState::get(hwnd)?.edit_text()?)
+}
+
+/// 選択範囲だけを WAV ファイルに保存する。何も選択していなければキャレットのある文を保存する
+fn save_selection_to_wav(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let text = match state.selected_text()? {
+        Some(text) => text,
+        None => {
+            let text = state.edit_text()?;
+            let (caret, _) = state.selection()?;
+            let caret = utf16::floor_char_boundary(&text, caret);
+            let (start, end) = chunk::sentence_at(&text, caret);
+            text[start..end].to_vec()
+        }
+    };
+    if synthesis::prepare_text(&text).is_err() {
+        show_message(hwnd, tr(Msg::NothingToSave));
+        return Ok(());
+    }
+    save_text_to_wav(hwnd, &text)
+}
+
+fn save_text_to_wav(hwnd: HWND, text: &[u16]) -> Result<()> {
+    let Some(file_path) = get_save_file_path(hwnd, text)? else {
+        return Ok(());
+    };
+    save_wav(hwnd, text, file_path, &mut None)
+}
+
+/// テキストを合成して file_path の WAV ファイルに保存し始める。終わったら [UiMessage::SaveFinished] で知らせる
+///
+/// パイプから求められた保存では reply に要求を渡す。合成を始めるときに取り出し、保存が終わったら結果を返す。
+fn save_wav(
+    hwnd: HWND,
+    text: &[u16],
+    file_path: PathBuf,
+    reply: &mut Option<pipe::Request>,
+) -> Result<()> {
+    let started = Instant::now();
+    let state = AppState::get(hwnd)?;
+    // 言語ごとに音声を切り替える場合は、区間ごとに合成して一つの WAV につなげる
+    let (parts, key) = speech_parts(hwnd, text)?;
+    // スピーチマークの設定を変えても再生に影響しないように、保存用の音声合成エンジンを作る
+    let parts = voiced_parts(&parts, synthesis::create_synthesizer)?;
+    let cached = state.playback.cached(key);
+    let output = WavOutput::new(text, &parts)?;
+    let marks_path = settings::get()
+        .speech_marks
+        .then(|| speech_marks::path_for(&file_path));
+    // 再生には境界のキューを使わないので、スピーチマークを保存するときだけ含める
+    for part in &parts {
+        speech_marks::set_enabled(&part.synth, marks_path.is_some())?;
+    }
+    // 再生用に合成した結果にはキューが含まれていないので、スピーチマークを保存するときは合成し直す
+    let cached = cached.filter(|_| marks_path.is_none());
+    status::set_busy(hwnd, true)?;
+    let handle = hwnd.0 as isize;
+    let reply = reply.take();
+    let done = move |synthesized: Result<(Vec<u8>, Vec<speech_marks::Mark>)>| {
+        let result = synthesized.and_then(|(bytes, mut marks)| {
+            let lead = output.write(bytes, &file_path)?;
+            // 切り取った先頭の長さだけ、スピーチマークの時刻を早める
+            for mark in &mut marks {
+                mark.time = mark.time.saturating_sub(lead);
+            }
+            match &marks_path {
+                Some(path) => speech_marks::write(&marks, path),
+                None => Ok(()),
+            }
+        });
+        let finished = SaveFinished {
+            path: file_path,
+            started,
+            result,
+            reply,
+        };
+        UiMessage::SaveFinished(finished).post(handle);
+    };
+    // 再生して確かめたばかりのテキストは、合成し直さずに保存する
+    let saving = match cached {
+        Some(chunks) => synthesis::wav_from_voiced_chunks(text, &parts, chunks, move |bytes| {
+            done(bytes.map(|b| (b, vec![])))
+        }),
+        None => synthesis::start_voiced_wav_with_marks(text, &parts, done),
+    };
+    // 停止ボタンで取り消せるようにしておく
+    state.saving.replace(Some(saving));
+    update_taskbar_progress(hwnd)?;
+    update_toolbar(hwnd)
+}
+
+/// WAV ファイルへの保存の結果
+struct SaveFinished {
+    path: PathBuf,
+    /// 保存を始めた時刻
+    started: Instant,
+    result: Result<()>,
+    /// パイプから求められた保存なら、結果を返す要求
+    reply: Option<pipe::Request>,
+}
+
+/// 保存が終わったことを知らせる
+fn save_finished(hwnd: HWND, finished: SaveFinished) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    state.saving.take();
+    if let Some(request) = finished.reply {
+        // anyhow のエラーは複製できないので、原因をつなげたメッセージにして返す
+        request.reply(
+            finished
+                .result
+                .as_ref()
+                .copied()
+                .map_err(|e| anyhow!("{e:#}")),
+        );
+    }
+    if state.closing.get() {
+        unsafe { DestroyWindow(hwnd)? };
+        return Ok(());
+    }
+    update_taskbar_progress(hwnd)?;
+    update_toolbar(hwnd)?;
+    // 読み上げの合成中はプログレスバーを表示したままにする
+    status::set_busy(hwnd, SYNTHESIZING.get())?;
+    if let Err(e) = &finished.result {
+        // 停止ボタンで取り消した場合はエラーとして扱わない
+        if e.is::<synthesis::Cancelled>() {
+            return status::set_status(hwnd, Part::Misc, tr(Msg::SaveCancelled));
+        }
+        show_taskbar_error(hwnd);
+    }
+    finished.result?;
+    logging::info(format_args!(
+        "saved {} in {:?}",
+        finished.path.display(),
+        finished.started.elapsed()
+    ));
+    let file_name = finished.path.file_name().context("no file name.")?;
+    let msg = trf(Msg::Saved, &[&file_name.to_string_lossy()]);
+    operation_finished(hwnd, Part::Misc, &msg)?;
+    notify_saved(hwnd, &msg, &finished.path);
+    Ok(())
+}
+
+/// 保存の完了を、入力の邪魔をしないようにトースト通知で知らせる
+///
+/// トースト通知が使えない場合はタスクトレイのバルーン通知、それも使えない場合はメッセージボックスで知らせる。
+fn notify_saved(hwnd: HWND, msg: &str, path: &Path) {
+    if toast::show_saved(msg, path)
+        .or_else(|_| tray::show_balloon(hwnd, tr(Msg::AppName), msg))
+        .is_err()
+    {
+        show_message(hwnd, msg);
+    }
+}
+
+fn set_edit_control_text(hwnd: HWND, text: &[u16]) -> Result<()> {
+    let edit = AppState::get(hwnd)?.edit()?;
+    let text = text.iter().copied().chain(Some(0)).collect::<Vec<_>>();
+    unsafe { SetWindowTextW(edit, PCWSTR(text.as_ptr()))? };
+    // ファイル以外のテキストに入れ替えたら、タイトルのファイル名を消す
+    set_window_title(hwnd, None)
+}
+
+/// 選択範囲を読み上げ用に整形する。何も選択されていない場合はテキスト全体を整形する
+fn format_edit_control_text(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let text = match state.selected_text()? {
+        Some(text) => String::from_utf16_lossy(&text),
+        None => {
+            select_all_edit_control_text(hwnd)?;
+            state.edit_string()?
+        }
+    };
+    let formatted = text_format::format_for_speech(&text)
+        .encode_utf16()
+        .chain(Some(0))
+        .collect::<Vec<_>>();
+    // 元に戻せるように EM_REPLACESEL で置き換える
+    unsafe {
+        SendMessageW(
+            state.edit()?,
+            EM_REPLACESEL,
+            WPARAM(1),
+            LPARAM(formatted.as_ptr() as _),
+        )
+    };
+    Ok(())
+}
+
+/// 検索ダイアログ (replace が true なら置換ダイアログ) を開く
+///
+/// 一行に収まる範囲を選択していれば、検索する文字列の初期値にする。
+fn show_find_dialog(hwnd: HWND, replace: bool) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let selected = state
+        .selected_text()?
+        .filter(|text| !text.iter().any(|&c| c == b'\r' as u16 || c == b'\n' as u16));
+    let dialog = find_dialog::show(hwnd, state.find_dialog.get(), replace, selected.as_deref())?;
+    state.find_dialog.set(dialog);
+    Ok(())
+}
+
+/// エディットコントロールの範囲を選択し、見えるところまでスクロールする
+fn select_edit_range(hwnd: HWND, start: usize, end: usize) -> Result<()> {
+    let edit = AppState::get(hwnd)?.edit()?;
+    unsafe {
+        SendMessageW(edit, EM_SETSEL, WPARAM(start), LPARAM(end as _));
+        SendMessageW(edit, EM_SCROLLCARET, None, None);
+    }
+    Ok(())
+}
+
+/// 最後に検索した文字列を、選択範囲の後ろ (上方向なら前) から探して選択する
+///
+/// テキストの端で折り返したときはビープ音で知らせる。まだ検索していなければ検索ダイアログを開く。
+fn find_in_edit(hwnd: HWND) -> Result<()> {
+    let Some(last) = find_dialog::last_search() else {
+        return show_find_dialog(hwnd, false);
+    };
+    let state = AppState::get(hwnd)?;
+    let text = state.edit_text()?;
+    let (start, end) = state.selection()?;
+    let found = if last.forward {
+        search::find_next(&text, last.query(), end)
+    } else {
+        search::find_previous(&text, last.query(), start)
+    };
+    let Some(found) = found else {
+        let pattern = String::from_utf16_lossy(&last.pattern);
+        show_message(hwnd, &trf(Msg::FindNotFound, &[&pattern]));
+        return Ok(());
+    };
+    if found.wrapped {
+        _ = unsafe { MessageBeep(MB_OK) };
+    }
+    select_edit_range(hwnd, found.start, found.end)
+}
+
+/// 選択範囲が検索する文字列と一致していれば置き換えてから、次を検索する
+fn replace_in_edit(hwnd: HWND) -> Result<()> {
+    let Some(last) = find_dialog::last_search() else {
+        return Ok(());
+    };
+    let state = AppState::get(hwnd)?;
+    if let Some(selected) = state.selected_text()? {
+        if search::is_match(&selected, last.query()) {
+            let replacement = last.replacement.iter().copied().chain(Some(0));
+            let replacement = replacement.collect::<Vec<_>>();
+            // 元に戻せるように EM_REPLACESEL で置き換える
+            unsafe {
+                SendMessageW(
+                    state.edit()?,
+                    EM_REPLACESEL,
+                    WPARAM(1),
+                    LPARAM(replacement.as_ptr() as _),
+                )
+            };
+        }
+    }
+    find_in_edit(hwnd)
+}
+
+/// 一致する文字列をすべて置き換える
+///
+/// 長いテキストでも遅くならないように、置き換えたテキストを組み立ててから一度に差し替える。
+fn replace_all_in_edit(hwnd: HWND) -> Result<()> {
+    let Some(last) = find_dialog::last_search() else {
+        return Ok(());
+    };
+    let state = AppState::get(hwnd)?;
+    let (replaced, count) =
+        search::replace_all(&state.edit_text()?, last.query(), &last.replacement);
+    if count == 0 {
+        let pattern = String::from_utf16_lossy(&last.pattern);
+        show_message(hwnd, &trf(Msg::FindNotFound, &[&pattern]));
+        return Ok(());
+    }
+    let replaced = replaced.into_iter().chain(Some(0)).collect::<Vec<_>>();
+    select_all_edit_control_text(hwnd)?;
+    // 全体を一回で置き換えるので、元に戻すと置換前のテキストに戻る
+    unsafe {
+        SendMessageW(
+            state.edit()?,
+            EM_REPLACESEL,
+            WPARAM(1),
+            LPARAM(replaced.as_ptr() as _),
+        )
+    };
+    status::set_status(
+        hwnd,
+        Part::Misc,
+        &trf(Msg::StatusReplaced, &[&count.to_string()]),
+    )
+}
+
+/// エディットコントロールの右クリックメニューの項目
+fn edit_context_menu_items() -> Vec<Item> {
+    vec![
+        Item::Command(ID_UNDO, Msg::MenuUndo),
+        Item::Separator,
+        Item::Command(ID_CUT, Msg::MenuCut),
+        Item::Command(ID_COPY, Msg::MenuCopy),
+        Item::Command(ID_PASTE, Msg::MenuPaste),
+        Item::Command(ID_DELETE, Msg::MenuDelete),
+        Item::Separator,
+        Item::Command(ID_SELECT_ALL, Msg::MenuSelectAll),
+        Item::Separator,
+        Item::Command(ID_SPEAK_SELECTION, Msg::MenuSpeakSelection),
+        Item::Command(ID_SPEAK_FROM_CURSOR, Msg::MenuSpeakFromCursor),
+        Item::Command(ID_PASTE_AND_SPEAK, Msg::MenuPasteAndSpeak),
+        Item::Command(ID_SAVE_SELECTION, Msg::MenuSaveSelection),
+        Item::Command(ID_FORMAT_TEXT, Msg::MenuFormatText),
+    ]
+}
+
+/// エディットコントロールの右クリックメニューを表示する
+///
+/// キーボード (Shift+F10 やアプリケーションキー) で開いた場合はキャレットの位置に表示する。
+fn show_edit_context_menu(edit: HWND, lparam: LPARAM) -> Result<()> {
+    let pt = if lparam.0 == -1 {
+        let mut pt = POINT::default();
+        unsafe { GetCaretPos(&mut pt)? };
+        _ = unsafe { ClientToScreen(edit, &mut pt) };
+        pt
+    } else {
+        POINT {
+            x: loword(lparam.0 as _) as i16 as _,
+            y: hiword(lparam.0 as _) as i16 as _,
+        }
+    };
+    // コマンドはメインウィンドウの WM_COMMAND で処理する
+    let owner = unsafe { GetParent(edit)? };
+    let state = AppState::get(owner)?;
+    let popup = menu::create_popup(&edit_context_menu_items())?;
+    let (start, end) = state.selection()?;
+    let selected = start != end;
+    let has_text = !state.is_edit_empty()?;
+    let can_undo = unsafe { SendMessageW(edit, EM_CANUNDO, None, None) }.0 != 0;
+    let can_paste = unsafe { IsClipboardFormatAvailable(CF_UNICODETEXT.0 as _) }.is_ok();
+    menu::enable_item(popup, ID_UNDO, can_undo);
+    menu::enable_item(popup, ID_CUT, selected);
+    menu::enable_item(popup, ID_COPY, selected);
+    menu::enable_item(popup, ID_PASTE, can_paste);
+    menu::enable_item(popup, ID_DELETE, selected);
+    menu::enable_item(popup, ID_SELECT_ALL, has_text);
+    let idle = !SYNTHESIZING.get();
+    menu::enable_item(popup, ID_SPEAK_SELECTION, selected && idle);
+    menu::enable_item(popup, ID_SPEAK_FROM_CURSOR, has_text && idle);
+    menu::enable_item(popup, ID_PASTE_AND_SPEAK, can_paste && idle);
+    menu::enable_item(popup, ID_SAVE_SELECTION, has_text);
+    menu::enable_item(popup, ID_FORMAT_TEXT, has_text);
+    _ = unsafe {
+        TrackPopupMenu(
+            popup,
+            TPM_LEFTALIGN | TPM_TOPALIGN | TPM_RIGHTBUTTON,
+            pt.x,
+            pt.y,
+            0,
+            owner,
+            None,
+        )
+    };
+    unsafe { DestroyMenu(popup)? };
+    Ok(())
+}
+
+/// 切り取りなどの標準的な編集コマンドをエディットコントロールに送る
+fn send_edit_command(hwnd: HWND, msg: u32) -> Result<()> {
+    let edit = AppState::get(hwnd)?.edit()?;
+    unsafe { SendMessageW(edit, msg, None, None) };
+    Ok(())
+}
+
+fn select_all_edit_control_text(hwnd: HWND) -> Result<()> {
+    let edit = AppState::get(hwnd)?.edit()?;
+    unsafe { SendMessageW(edit, EM_SETSEL, WPARAM(0), LPARAM(-1)) };
+    Ok(())
+}
+
+fn clear_edit_control_text(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    unsafe { SendMessageW(state.edit()?, WM_SETTEXT, None, None) };
+    state.playback.stop_all();
+    set_window_title(hwnd, None)
+}
+
+/// 再生待ちを取り消して、読み上げと保存中の合成を停止する
+///
+/// 手動で停止したら、スリープタイマーも解除する。
+fn stop(hwnd: HWND) -> Result<()> {
+    cancel_sleep_timer(hwnd)?;
+    stop_playback(hwnd)
+}
+
+/// 読み上げと保存中の合成を停止する (スリープタイマーはそのまま)
+fn stop_playback(hwnd: HWND) -> Result<()> {
+    queue::clear_playback();
+    let state = AppState::get(hwnd)?;
+    remember_resume_point(state).ok();
+    state.playback.stop_all();
+    state.play_guard.clear();
+    if let Some(saving) = state.saving.borrow().as_ref() {
+        saving.cancel();
+    }
+    Ok(())
+}
+
+/// スリープする前に再生を止めて、復帰したら続きから読み上げられるように位置を覚えておく
+///
+/// 保存中の合成は復帰してから続けられるので止めない。
+fn stop_for_sleep(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    if !state.playback.is_speaking() {
+        return Ok(());
+    }
+    logging::info("stopping the speech before sleeping");
+    // 止めた再生の終わりの通知で次のファイルに進まないように、再生待ちも空にする
+    queue::clear_playback();
+    remember_resume_point(state).ok();
+    state.playback.stop_all();
+    state.play_guard.clear();
+    SLEPT_WHILE_SPEAKING.set(true);
+    Ok(())
+}
+
+/// スリープで止めた再生を続きから読み上げるか尋ねる
+fn offer_resume_after_sleep(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    if state.resume.offset().is_none() || state.playback.is_speaking() {
+        return Ok(());
+    }
+    if message_box(hwnd, tr(Msg::ResumeAfterSleep), MB_YESNO | MB_ICONQUESTION) == IDYES {
+        resume_speech(hwnd)?;
+    }
+    Ok(())
+}
+
+/// スリープタイマーを duration 後に設定する。設定済みなら置き換える
+fn arm_sleep_timer(hwnd: HWND, duration: Duration) -> Result<()> {
+    AppState::get(hwnd)?
+        .sleep_timer
+        .arm(Instant::now(), duration);
+    unsafe { SetTimer(hwnd, SLEEP_TIMER, SLEEP_INTERVAL, None) };
+    logging::info(format_args!("sleep timer: {}s", duration.as_secs()));
+    update_countdown_status(hwnd)
+}
+
+/// スリープタイマーを解除する
+fn cancel_sleep_timer(hwnd: HWND) -> Result<()> {
+    let timer = &AppState::get(hwnd)?.sleep_timer;
+    if timer.is_armed() {
+        timer.cancel();
+        _ = unsafe { KillTimer(hwnd, SLEEP_TIMER) };
+        update_countdown_status(hwnd)?;
+    }
+    Ok(())
+}
+
+/// スリープタイマーと時刻を指定した再生の残り時間をステータスバーに表示する
+///
+/// どちらも設定されていなければ表示を消す。
+fn update_countdown_status(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let mut texts = vec![];
+    if let Some(schedule) = state.schedule.borrow().as_ref() {
+        let remaining = schedule.remaining(schedule::local_now());
+        texts.push(trf(
+            Msg::StatusScheduled,
+            &[
+                &schedule::format_time_of_day(schedule.time_of_day()),
+                &format_remaining(Duration::from_secs(remaining as u64)),
+            ],
+        ));
+    }
+    if let Some(remaining) = state.sleep_timer.remaining(Instant::now()) {
+        texts.push(trf(Msg::StatusSleepTimer, &[&format_remaining(remaining)]));
+    }
+    status::set_status(hwnd, Part::Misc, &texts.join("  "))
+}
+
+/// 残り時間の表示を更新し、時間が来たら通常の停止と同じように再生を停止する
+fn sleep_timer_tick(hwnd: HWND) -> Result<()> {
+    if !AppState::get(hwnd)?.sleep_timer.expire(Instant::now()) {
+        return update_countdown_status(hwnd);
+    }
+    _ = unsafe { KillTimer(hwnd, SLEEP_TIMER) };
+    logging::info("sleep timer expired");
+    stop_playback(hwnd)?;
+    status::set_status(hwnd, Part::Misc, tr(Msg::StatusSleepStopped))
+}
+
+/// エディットのテキストを、選択中の音声と読み上げ速度で time_of_day (0 時からの秒数) に再生するように予約する
+///
+/// 予約済みなら置き換える。後からテキストや音声を変えても、予約した内容で再生する。
+fn arm_schedule(hwnd: HWND, time_of_day: i64, daily: bool) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let text = state.edit_text()?;
+    synthesis::prepare_text(&text).context(tr(Msg::ErrorNoText))?;
+    let options = state.synth_options()?;
+    let schedule =
+        schedule::Schedule::new(schedule::local_now(), time_of_day, daily, text, options);
+    logging::info(format_args!(
+        "scheduled speech at {} (daily: {daily})",
+        schedule::format_time_of_day(schedule.time_of_day())
+    ));
+    state.schedule.replace(Some(schedule));
+    unsafe { SetTimer(hwnd, SCHEDULE_TIMER, SCHEDULE_INTERVAL, None) };
+    update_countdown_status(hwnd)
+}
+
+/// 時刻を指定した再生を取り消す
+fn cancel_schedule(hwnd: HWND) -> Result<()> {
+    if AppState::get(hwnd)?.schedule.take().is_some() {
+        _ = unsafe { KillTimer(hwnd, SCHEDULE_TIMER) };
+        logging::info("cancelled the scheduled speech");
+        update_countdown_status(hwnd)?;
+    }
+    Ok(())
+}
+
+/// 再生する時刻が来たか確かめ、来ていれば予約した内容で再生する
+///
+/// スリープなどで時刻を大きく過ぎていたら再生せずに知らせる。毎日繰り返さない予約はここで終わる。
+fn schedule_tick(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let mut slot = state.schedule.borrow_mut();
+    let Some(schedule) = slot.as_mut() else {
+        return Ok(());
+    };
+    let poll = schedule.poll(schedule::local_now());
+    if let schedule::Poll::Wait(_) = poll {
+        drop(slot);
+        return update_countdown_status(hwnd);
+    }
+    let time = schedule::format_time_of_day(schedule.time_of_day());
+    let fired =
+        (poll == schedule::Poll::Fire).then(|| (schedule.text.clone(), schedule.options.clone()));
+    if !schedule.is_daily() {
+        *slot = None;
+        _ = unsafe { KillTimer(hwnd, SCHEDULE_TIMER) };
+    }
+    drop(slot);
+    update_countdown_status(hwnd)?;
+    match fired {
+        Some((text, options)) => {
+            logging::info(format_args!("speaking the text scheduled at {time}"));
+            speak_scheduled(hwnd, text, &options)
+        }
+        None => {
+            logging::info(format_args!("missed the speech scheduled at {time}"));
+            show_message(hwnd, &trf(Msg::ScheduleMissed, &[&time]));
+            Ok(())
+        }
+    }
+}
+
+/// 予約したときの音声と読み上げ速度でテキストを再生する
+///
+/// 合成中の再生があっても、予約した時刻に間に合うように止めてから始める。
+fn speak_scheduled(hwnd: HWND, text: Vec<u16>, options: &SynthOptions) -> Result<()> {
+    if SYNTHESIZING.get() {
+        stop_playback(hwnd)?;
+        set_synthesizing(hwnd, false)?;
+    }
+    let engine = AppState::get(hwnd)?.playback.engine();
+    let synth = engine.create_synthesizer(options)?;
+    let key = synthesis::cache_key(&text, options);
+    begin_speech(hwnd, &synth, text, key, None)
+}
+
+/// 再生中のスピーチを一時停止する。一時停止中であれば再開する
+fn toggle_pause(hwnd: HWND) -> Result<()> {
+    let playback = &AppState::get(hwnd)?.playback;
+    let id = SPEECH_ID.load(Ordering::Relaxed);
+    if playback.pause()? {
+        // 一時停止している間はスリープしてもよい
+        power::release(id);
+        status::set_status(hwnd, Part::State, tr(Msg::StatusPaused))?;
+    } else if playback.resume()? {
+        keep_awake(id);
+        let status = playing_status(playback.round(), playback.repeats());
+        status::set_status(hwnd, Part::State, &status)?;
+    }
+    update_media_controls(hwnd)?;
+    update_taskbar_progress(hwnd)
+}
+
+/// 再生中のスピーチで次の文か前の文に移る
+fn skip_sentence(hwnd: HWND, forward: bool) -> Result<()> {
+    AppState::get(hwnd)?.playback.skip(forward)?;
+    Ok(())
+}
+
+/// 再生中のスピーチがあるかどうか
+fn is_speaking(hwnd: HWND) -> Result<bool> {
+    Ok(AppState::get(hwnd)?.playback.is_speaking())
+}
+
+/// エラーの内容を原因までさかのぼってメッセージボックスで表示する
+fn report_error(hwnd: HWND, context: Msg, err: &anyhow::Error) {
+    // メッセージボックスの表示中に同じエラーが繰り返し報告されないようにする
+    logging::error(tr(context), err);
+    let known = SpeechError::find(err);
+    if matches!(known, Some(SpeechError::DialogCancelled)) || REPORTING_ERROR.replace(true) {
+        return;
+    }
+    let mut msg = tr(context).to_string();
+    // 見分けられる失敗は対処を案内し、それ以外は原因をそのまま並べる
+    if let Some(e) = known {
+        msg.push_str("\r\n");
+        msg.push_str(&strings::user_message(e, strings::lang()));
+    } else {
+        for cause in err.chain() {
+            msg.push_str("\r\n");
+            msg.push_str(&cause.to_string());
+        }
+    }
+    message_box(hwnd, &msg, MB_OK | MB_ICONERROR);
+    REPORTING_ERROR.set(false);
+}
+
+/// アプリケーション名をタイトルにしたメッセージボックスを表示する
+fn show_message(hwnd: HWND, msg: &str) {
+    message_box(hwnd, msg, MB_OK);
+}
+
+/// アプリケーション名をタイトルにしたメッセージボックスを表示し、押されたボタンを返す
+#[cfg(not(test))]
+fn message_box(hwnd: HWND, text: &str, style: MESSAGEBOX_STYLE) -> MESSAGEBOX_RESULT {
+    use windows::Win32::UI::WindowsAndMessaging::MessageBoxW;
+    unsafe {
+        MessageBoxW(
+            hwnd,
+            &HSTRING::from(text),
+            &strings::wide(Msg::AppName),
+            style,
+        )
+    }
+}
+
+/// 文字数と読み上げにかかる時間の目安の表示を更新する
+///
+/// 入力のたびに呼ばれるので、テキストは取得せずに長さだけを使う。
+/// 単語数は入力が止まってから [count_words] で数えたものを表示する。
+/// 再生や保存の前に確認するほど長い場合は赤で表示する。
+fn update_counts(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let len = unsafe { GetWindowTextLengthW(state.edit()?) } as usize;
+    let chars = group_digits(len);
+    if len == 0 {
+        return status::set_status(hwnd, Part::Counts, &trf(Msg::StatusCharCount, &[&chars]));
+    }
+    let minutes = estimated_minutes(len, state.speaking_rate()?);
+    let text = match WORD_COUNT.get() {
+        Some(words) => trf(
+            Msg::StatusCountsWords,
+            &[&chars, &group_digits(words), &minutes],
+        ),
+        None => trf(Msg::StatusCounts, &[&chars, &minutes]),
+    };
+    if settings::get().is_long_text(len) {
+        status::set_warning(hwnd, Part::Counts, &text)
+    } else {
+        status::set_status(hwnd, Part::Counts, &text)
+    }
+}
+
+/// 読み上げにかかるおおよその分数 (1 分未満も 1 分とする)
+fn estimated_minutes(len: usize, rate: f64) -> String {
+    let secs = synthesis::estimate_duration(len, rate).as_secs();
+    group_digits(secs.div_ceil(60).max(1) as usize)
+}
+
+/// 入力が止まったらエディットコントロールの単語数を数えて表示を更新する
+fn count_words(hwnd: HWND) -> Result<()> {
+    _ = unsafe { KillTimer(hwnd, COUNT_TIMER) };
+    let text = AppState::get(hwnd)?.edit_text()?;
+    let words = String::from_utf16_lossy(&text).split_whitespace().count();
+    WORD_COUNT.set(Some(words));
+    update_counts(hwnd)
+}
+
+/// エディットコントロールのテキストを自動保存する
+fn autosave_text(hwnd: HWND) -> Result<()> {
+    _ = unsafe { KillTimer(hwnd, AUTOSAVE_TIMER) };
+    if settings::get().autosave_disabled {
+        return Ok(());
+    }
+    autosave::save(&AppState::get(hwnd)?.edit_text()?)
+}
+
+/// 前回自動保存したテキストをエディットコントロールに復元する
+fn restore_autosaved_text(hwnd: HWND) -> Result<()> {
+    if settings::get().autosave_disabled {
+        return Ok(());
+    }
+    match autosave::load()? {
+        Some(text) => set_edit_control_text(hwnd, &text),
+        None => Ok(()),
+    }
+}
+
+/// 自動保存を切り替える。無効にしたら保存したテキストも削除する
+fn toggle_autosave(hwnd: HWND) -> Result<()> {
+    settings::update(|s| s.autosave_disabled = !s.autosave_disabled)?;
+    if settings::get().autosave_disabled {
+        _ = unsafe { KillTimer(hwnd, AUTOSAVE_TIMER) };
+        autosave::delete()
+    } else {
+        autosave_text(hwnd)
+    }
+}
+
+/// 長いテキストの再生や保存を始める前に確認する。続ける場合は true を返す
+fn confirm_long_text(hwnd: HWND) -> Result<bool> {
+    let state = AppState::get(hwnd)?;
+    let len = unsafe { GetWindowTextLengthW(state.edit()?) } as usize;
+    if !settings::get().is_long_text(len) {
+        return Ok(true);
+    }
+    let minutes = estimated_minutes(len, state.speaking_rate()?);
+    let msg = trf(Msg::ConfirmLongText, &[&group_digits(len), &minutes]);
+    Ok(message_box(hwnd, &msg, MB_YESNO | MB_ICONWARNING) == IDYES)
+}
+
+fn command(hwnd: HWND, wparam: WPARAM, lparam: LPARAM) -> Result<()> {
+    let id = loword(wparam.0 as _);
+    let code = hiword(wparam.0 as _);
+
+    if code as u32 == EN_CHANGE {
+        let edit = AppState::get(hwnd)?.edit()?;
+        if lparam.0 == edit.0 as isize {
+            let state = AppState::get(hwnd)?;
+            // テキストが変わったら、合成結果も続きの位置も使えない
+            state.playback.clear_cache();
+            state.resume.clear();
+            // 単語数はテキスト全体を取得して数えるので、入力が止まるまで待つ
+            WORD_COUNT.set(None);
+            unsafe { SetTimer(hwnd, COUNT_TIMER, COUNT_DELAY, None) };
+            if !settings::get().autosave_disabled {
+                unsafe { SetTimer(hwnd, AUTOSAVE_TIMER, AUTOSAVE_DELAY, None) };
+            }
+            update_counts(hwnd)?;
+            update_toolbar(hwnd)?;
+        } else if id == ID_REPEAT {
+            let state = AppState::get(hwnd)?;
+            state.playback.set_repeats(state.repeat_count()?);
+        }
+        return Ok(());
+    }
+
+    if code as u32 == CBN_SELCHANGE && id == ID_COMBO {
+        return voice_changed(hwnd);
+    }
+
+    // メニュー (0) とアクセラレータ (1) からのコマンドだけを記録する
+    if code <= 1 {
+        logging::info(format_args!("command {id}"));
+    }
+
+    // ボタンが無効でもアクセラレータや Enter キーからは届くので、ここでも確かめる
+    let needs_text = [ID_PLAY, IDOK.0 as u16, ID_SAVE].contains(&id);
+    if needs_text && !AppState::get(hwnd)?.has_voices() {
+        show_no_voices(hwnd);
+        return Ok(());
+    }
+    if needs_text && !AppState::get(hwnd)?.has_speakable_text()? {
+        show_message(hwnd, tr(Msg::ErrorNoText));
+        return Ok(());
+    }
+    if needs_text && !confirm_long_text(hwnd)? {
+        return Ok(());
+    }
+
+    // エディットコントロール以外で Enter キーを押すと IsDialogMessageW から IDOK が送られる
+    if id.eq(&ID_PLAY) || id.eq(&(IDOK.0 as u16)) {
+        speech(hwnd)?;
+    } else if id.eq(&ID_RESUME) {
+        resume_speech(hwnd)?;
+    } else if id.eq(&ID_PAUSE) {
+        toggle_pause(hwnd)?;
+    } else if id.eq(&ID_PREVIOUS_SENTENCE) {
+        skip_sentence(hwnd, false)?;
+    } else if id.eq(&ID_NEXT_SENTENCE) {
+        skip_sentence(hwnd, true)?;
+    } else if id.eq(&ID_SETTINGS) {
+        show_settings_menu(hwnd)?;
+    } else if id.eq(&panel::ID_TOGGLE) {
+        settings::update(|s| s.panel_collapsed = !s.panel_collapsed)?;
+        apply_panel_state(hwnd)?;
+    } else if id.eq(&ID_CLEAR) {
+        clear_edit_control_text(hwnd)?;
+    } else if id.eq(&ID_SAVE) {
+        save_to_wav(hwnd)?;
+    } else if id.eq(&ID_OPEN) {
+        open_file(hwnd)?;
+    } else if id.eq(&ID_EXIT) {
+        exit(hwnd)?;
+    } else if id.eq(&ID_SELECT_ALL) {
+        select_all_edit_control_text(hwnd)?;
+    } else if id.eq(&ID_UNDO) {
+        send_edit_command(hwnd, WM_UNDO)?;
+    } else if id.eq(&ID_CUT) {
+        send_edit_command(hwnd, WM_CUT)?;
+    } else if id.eq(&ID_COPY) {
+        send_edit_command(hwnd, WM_COPY)?;
+    } else if id.eq(&ID_PASTE) {
+        send_edit_command(hwnd, WM_PASTE)?;
+    } else if id.eq(&ID_DELETE) {
+        send_edit_command(hwnd, WM_CLEAR)?;
+    } else if id.eq(&ID_SPEAK_SELECTION) {
+        speak_selection(hwnd)?;
+    } else if id.eq(&ID_SPEAK_FROM_CURSOR) {
+        speak_from_cursor(hwnd)?;
+    } else if id.eq(&ID_PASTE_AND_SPEAK) {
+        paste_and_speak(hwnd)?;
+    } else if id.eq(&ID_SAVE_SELECTION) {
+        save_selection_to_wav(hwnd)?;
+    } else if id.eq(&ID_FORMAT_TEXT) {
+        format_edit_control_text(hwnd)?;
+    } else if id.eq(&ID_FIND) {
+        show_find_dialog(hwnd, false)?;
+    } else if id.eq(&ID_FIND_NEXT) {
+        find_in_edit(hwnd)?;
+    } else if id.eq(&ID_REPLACE) {
+        show_find_dialog(hwnd, true)?;
+    } else if id.eq(&ID_STOP) {
+        stop(hwnd)?;
+    } else if id.eq(&ID_EXPORT_SENTENCES) {
+        export_sentences(hwnd)?;
+    } else if id.eq(&ID_WATCH_FILE) {
+        toggle_watch_file(hwnd)?;
+    } else if id.eq(&ID_WATCH_FOLDER) {
+        toggle_watch_folder(hwnd)?;
+    } else if id.eq(&ID_SCHEDULE) {
+        schedule_dialog::show(hwnd)?;
+    } else if id.eq(&ID_SCHEDULE_CANCEL) {
+        cancel_schedule(hwnd)?;
+    } else if id.eq(&ID_SLEEP_OFF) {
+        cancel_sleep_timer(hwnd)?;
+    } else if let Some(&(_, minutes)) = SLEEP_PRESETS.iter().find(|(preset, _)| id.eq(preset)) {
+        arm_sleep_timer(hwnd, Duration::from_secs(minutes * 60))?;
+    } else if id.eq(&ID_SLEEP_CUSTOM) {
+        sleep_dialog::show(hwnd)?;
+    } else if id.eq(&ID_HOTKEYS) {
+        hotkey_dialog::show(hwnd)?;
+    } else if id.eq(&ID_RULES) {
+        rules_dialog::show(hwnd)?;
+    } else if id.eq(&ID_LEXICON) {
+        lexicon_dialog::show(hwnd)?;
+    } else if id.eq(&ID_FONT) {
+        choose_edit_font(hwnd)?;
+    } else if id.eq(&ID_AUTO_VOICE) {
+        settings::update(|s| s.auto_voice = !s.auto_voice)?;
+    } else if id.eq(&ID_MIXED_VOICES) {
+        settings::update(|s| s.mixed_voices = !s.mixed_voices)?;
+    } else if id.eq(&ID_PREVENT_SLEEP) {
+        settings::update(|s| s.prevent_sleep = !s.prevent_sleep)?;
+        let prevent_sleep = settings::get().prevent_sleep;
+        let playback = &AppState::get(hwnd)?.playback;
+        if !prevent_sleep {
+            power::reset();
+        } else if playback.status() == Status::Playing || SYNTHESIZING.get() {
+            keep_awake(SPEECH_ID.load(Ordering::Relaxed));
+        }
+    } else if id.eq(&ID_KEEP_DISPLAY_ON) {
+        settings::update(|s| s.keep_display_on = !s.keep_display_on)?;
+        let display = settings::get().keep_display_on;
+        power::set_display(display);
+    } else if id.eq(&ID_DUCK_OTHERS) {
+        settings::update(|s| s.duck_others = !s.duck_others)?;
+        let ducking = settings::get().duck_others;
+        AppState::get(hwnd)?.playback.set_ducking(ducking);
+    } else if id.eq(&ID_SPEECH_MARKS) {
+        settings::update(|s| s.speech_marks = !s.speech_marks)?;
+    } else if id.eq(&ID_NORMALIZE_VOLUME) {
+        settings::update(|s| s.normalize_volume = !s.normalize_volume)?;
+    } else if id.eq(&ID_TRIM_SILENCE) {
+        settings::update(|s| s.trim_silence = !s.trim_silence)?;
+    } else if let Some(&(_, rate)) = SAMPLE_RATE_PRESETS.iter().find(|(preset, _)| id.eq(preset)) {
+        settings::update(|s| s.output_sample_rate = rate)?;
+    } else if id.eq(&ID_OUTPUT_MONO) {
+        settings::update(|s| s.output_mono = !s.output_mono)?;
+    } else if id.eq(&ID_NORMALIZE_DATES) {
+        settings::update(|s| s.normalize_dates = !s.normalize_dates)?;
+        settings::activate_normalizers();
+    } else if id.eq(&ID_NORMALIZE_NUMBERS) {
+        settings::update(|s| s.normalize_numbers = !s.normalize_numbers)?;
+        settings::activate_normalizers();
+    } else if id.eq(&ID_NORMALIZE_URLS) {
+        settings::update(|s| s.normalize_urls = !s.normalize_urls)?;
+        settings::activate_normalizers();
+    } else if let Some(&(_, ms)) = PARAGRAPH_PAUSE_PRESETS
+        .iter()
+        .find(|(preset, _)| id.eq(preset))
+    {
+        settings::update(|s| s.paragraph_pause_ms = Some(ms))?;
+        synthesis::set_paragraph_pause(Duration::from_millis(ms));
+    } else if id.eq(&ID_QUICK_INPUT) {
+        settings::update(|s| s.quick_input = !s.quick_input)?;
+    } else if id.eq(&ID_AUTOSAVE) {
+        toggle_autosave(hwnd)?;
+    } else if (ID_HISTORY_ENTRY..ID_HISTORY_ENTRY + history::MAX_ENTRIES as u16).contains(&id) {
+        // Shift キーを押しながら選ぶと、今の音声と読み上げ速度で読み上げる
+        let use_current = unsafe { GetKeyState(VK_SHIFT.0 as _) } < 0;
+        speak_history(hwnd, (id - ID_HISTORY_ENTRY) as usize, use_current)?;
+    } else if id.eq(&ID_RELOAD_VOICES) {
+        reload_voices_from_menu(hwnd)?;
+    } else if id.eq(&ID_HISTORY_CLEAR) {
+        clear_history(hwnd)?;
+    } else if id.eq(&ID_SAVE_HISTORY) {
+        toggle_save_history()?;
+    } else if (ID_RECENT_FILE..ID_RECENT_FILE + jump_list::MAX_RECENT_FILES as u16).contains(&id) {
+        open_recent_file(hwnd, (id - ID_RECENT_FILE) as usize)?;
+    } else if id.eq(&ID_ABOUT) {
+        about::show(hwnd)?;
+    } else if id.eq(&ID_OPEN_LOG_FOLDER) {
+        open_log_folder(hwnd)?;
+    } else if id.eq(&ID_SHOW_WINDOW) {
+        restore_window(hwnd);
+    } else if id.eq(&ID_CLOSE_TO_TRAY) {
+        settings::update(|s| s.close_to_tray = !s.close_to_tray)?;
+    } else if id.eq(&ID_MULTIPLE_INSTANCES) {
+        settings::update(|s| s.allow_multiple_instances = !s.allow_multiple_instances)?;
+    } else if id.eq(&ID_PLAY_FORWARDED) {
+        settings::update(|s| s.play_forwarded = !s.play_forwarded)?;
+    } else if id.eq(&ID_SPEAK_CLIPBOARD_AT_LAUNCH) {
+        settings::update(|s| s.speak_clipboard_at_launch = !s.speak_clipboard_at_launch)?;
+    } else if id.eq(&ID_PIPE_SERVER) {
+        settings::update(|s| s.pipe_server = !s.pipe_server)?;
+        let enabled = settings::get().pipe_server;
+        if enabled {
+            pipe::start(hwnd, WM_PIPE_COMMAND)?;
+        } else {
+            pipe::stop();
+        }
+    } else if id.eq(&ID_TOPMOST) {
+        toggle_topmost(hwnd)?;
+    } else if id.eq(&ID_LANG_AUTO) {
+        set_language(hwnd, None)?;
+    } else if id.eq(&ID_LANG_JA) {
+        set_language(hwnd, Some(Lang::Ja))?;
+    } else if id.eq(&ID_LANG_EN) {
+        set_language(hwnd, Some(Lang::En))?;
+    }
+
+    Ok(())
+}
+
+/// 設定メニューの項目 (メニューバーとツールバーの設定ボタンで共通)
+fn settings_menu_items() -> Vec<Item> {
+    vec![
+        Item::Command(ID_TOPMOST, Msg::MenuTopmost),
+        Item::Command(ID_CLOSE_TO_TRAY, Msg::MenuCloseToTray),
+        Item::Command(ID_MULTIPLE_INSTANCES, Msg::MenuMultipleInstances),
+        Item::Command(ID_PLAY_FORWARDED, Msg::MenuPlayForwarded),
+        Item::Command(
+            ID_SPEAK_CLIPBOARD_AT_LAUNCH,
+            Msg::MenuSpeakClipboardAtLaunch,
+        ),
+        Item::Command(ID_PIPE_SERVER, Msg::MenuPipeServer),
+        Item::Command(ID_HOTKEYS, Msg::MenuHotkeys),
+        Item::Command(ID_RULES, Msg::MenuRules),
+        Item::Command(ID_LEXICON, Msg::MenuLexicon),
+        Item::Command(ID_RELOAD_VOICES, Msg::MenuReloadVoices),
+        Item::Command(ID_AUTO_VOICE, Msg::MenuAutoVoice),
+        Item::Command(ID_MIXED_VOICES, Msg::MenuMixedVoices),
+        Item::Command(ID_FONT, Msg::MenuFont),
+        Item::Command(ID_AUTOSAVE, Msg::MenuAutosave),
+        Item::Command(ID_SPEECH_MARKS, Msg::MenuSpeechMarks),
+        Item::Command(ID_NORMALIZE_VOLUME, Msg::MenuNormalizeVolume),
+        Item::Command(ID_TRIM_SILENCE, Msg::MenuTrimSilence),
+        Item::Submenu(
+            Msg::MenuOutputFormat,
+            vec![
+                Item::Command(ID_OUTPUT_NATIVE, Msg::MenuOutputNative),
+                Item::Command(ID_OUTPUT_8000, Msg::MenuOutput8000),
+                Item::Command(ID_OUTPUT_16000, Msg::MenuOutput16000),
+                Item::Command(ID_OUTPUT_22050, Msg::MenuOutput22050),
+                Item::Command(ID_OUTPUT_44100, Msg::MenuOutput44100),
+                Item::Separator,
+                Item::Command(ID_OUTPUT_MONO, Msg::MenuOutputMono),
+            ],
+        ),
+        Item::Command(ID_DUCK_OTHERS, Msg::MenuDuckOthers),
+        Item::Command(ID_PREVENT_SLEEP, Msg::MenuPreventSleep),
+        Item::Command(ID_KEEP_DISPLAY_ON, Msg::MenuKeepDisplayOn),
+        Item::Submenu(
+            Msg::MenuNormalize,
+            vec![
+                Item::Command(ID_NORMALIZE_DATES, Msg::MenuNormalizeDates),
+                Item::Command(ID_NORMALIZE_NUMBERS, Msg::MenuNormalizeNumbers),
+                Item::Command(ID_NORMALIZE_URLS, Msg::MenuNormalizeUrls),
+            ],
+        ),
+        Item::Submenu(
+            Msg::MenuParagraphPause,
+            vec![
+                Item::Command(ID_PARAGRAPH_PAUSE_NONE, Msg::MenuParagraphPauseNone),
+                Item::Command(ID_PARAGRAPH_PAUSE_HALF, Msg::MenuParagraphPauseHalf),
+                Item::Command(ID_PARAGRAPH_PAUSE_1, Msg::MenuParagraphPause1),
+                Item::Command(ID_PARAGRAPH_PAUSE_2, Msg::MenuParagraphPause2),
+            ],
+        ),
+        Item::Separator,
+        Item::Submenu(
+            Msg::MenuLanguage,
+            vec![
+                Item::Command(ID_LANG_AUTO, Msg::MenuLanguageAuto),
+                Item::Command(ID_LANG_JA, Msg::MenuLanguageJa),
+                Item::Command(ID_LANG_EN, Msg::MenuLanguageEn),
+            ],
+        ),
+    ]
+}
+
+/// ツールバーの設定ボタンの下に設定メニューを表示する
+fn show_settings_menu(hwnd: HWND) -> Result<()> {
+    let popup = menu::create_popup(&settings_menu_items())?;
+    update_menu_state(hwnd, popup)?;
+    let pt = toolbar::menu_position(hwnd, ID_SETTINGS)?;
+    _ = unsafe {
+        TrackPopupMenu(
+            popup,
+            TPM_LEFTALIGN | TPM_TOPALIGN,
+            pt.x,
+            pt.y,
+            0,
+            hwnd,
+            None,
+        )
+    };
+    unsafe { DestroyMenu(popup)? };
+    Ok(())
+}
+
+fn create_menu(hwnd: HWND) -> Result<()> {
+    let menu = menu::create_menu_bar(&[
+        Item::Submenu(
+            Msg::MenuFile,
+            vec![
+                Item::Command(ID_OPEN, Msg::MenuOpen),
+                Item::Command(ID_SAVE, Msg::MenuSave),
+                Item::Command(ID_SAVE_SELECTION, Msg::MenuSaveSelection),
+                Item::Command(ID_EXPORT_SENTENCES, Msg::MenuExportSentences),
+                Item::Command(ID_WATCH_FILE, Msg::MenuWatchFile),
+                Item::Command(ID_WATCH_FOLDER, Msg::MenuWatchFolder),
+                Item::Separator,
+                Item::Command(ID_EXIT, Msg::MenuExit),
+            ],
+        ),
+        Item::Submenu(
+            Msg::MenuEdit,
+            vec![
+                Item::Command(ID_CLEAR, Msg::MenuClear),
+                Item::Command(ID_SELECT_ALL, Msg::MenuSelectAll),
+                Item::Separator,
+                Item::Command(ID_FIND, Msg::MenuFind),
+                Item::Command(ID_FIND_NEXT, Msg::MenuFindNext),
+                Item::Command(ID_REPLACE, Msg::MenuReplace),
+                Item::Separator,
+                Item::Command(ID_QUICK_INPUT, Msg::MenuQuickInput),
+            ],
+        ),
+        Item::Submenu(
+            Msg::MenuPlayback,
+            vec![
+                Item::Command(ID_PLAY, Msg::MenuPlay),
+                Item::Command(ID_RESUME, Msg::MenuResume),
+                Item::Command(ID_PAUSE, Msg::MenuPause),
+                Item::Command(ID_STOP, Msg::MenuStop),
+                Item::Command(ID_PREVIOUS_SENTENCE, Msg::MenuPreviousSentence),
+                Item::Command(ID_NEXT_SENTENCE, Msg::MenuNextSentence),
+                Item::Separator,
+                Item::Submenu(
+                    Msg::MenuHistory,
+                    vec![
+                        Item::Command(ID_HISTORY_CLEAR, Msg::MenuHistoryClear),
+                        Item::Command(ID_SAVE_HISTORY, Msg::MenuSaveHistory),
+                    ],
+                ),
+                Item::Separator,
+                Item::Command(ID_SCHEDULE, Msg::MenuSchedule),
+                Item::Command(ID_SCHEDULE_CANCEL, Msg::MenuScheduleCancel),
+                Item::Submenu(
+                    Msg::MenuSleepTimer,
+                    vec![
+                        Item::Command(ID_SLEEP_OFF, Msg::MenuSleepOff),
+                        Item::Command(ID_SLEEP_5, Msg::MenuSleep5),
+                        Item::Command(ID_SLEEP_15, Msg::MenuSleep15),
+                        Item::Command(ID_SLEEP_30, Msg::MenuSleep30),
+                        Item::Command(ID_SLEEP_CUSTOM, Msg::MenuSleepCustom),
+                    ],
+                ),
+            ],
+        ),
+        Item::Submenu(Msg::MenuSettings, settings_menu_items()),
+        Item::Submenu(
+            Msg::MenuHelp,
+            vec![
+                Item::Command(ID_OPEN_LOG_FOLDER, Msg::MenuOpenLogFolder),
+                Item::Separator,
+                Item::Command(ID_ABOUT, Msg::MenuAbout),
+            ],
+        ),
+    ])?;
+    unsafe { SetMenu(hwnd, menu)? };
+    update_recent_menu(hwnd)?;
+    update_history_menu(hwnd)
+}
+
+/// メニューを開く直前に各項目の有効・無効を更新する
+fn update_menu(hwnd: HWND) -> Result<()> {
+    update_menu_state(hwnd, unsafe { GetMenu(hwnd) })
+}
+
+/// メニュー項目の有効・無効とチェック状態を現在の状態に合わせる
+fn update_menu_state(hwnd: HWND, menu: HMENU) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let has_text = state.has_speakable_text()? && state.has_voices();
+    let speaking = state.playback.is_speaking();
+    menu::enable_item(menu, ID_PLAY, has_text && !SYNTHESIZING.get());
+    menu::enable_item(menu, ID_HISTORY_CLEAR, !history::entries().is_empty());
+    menu::check_item(menu, ID_SAVE_HISTORY, settings::get().save_history);
+    let can_resume = state.resume.offset().is_some();
+    menu::enable_item(
+        menu,
+        ID_RESUME,
+        has_text && can_resume && !SYNTHESIZING.get(),
+    );
+    menu::enable_item(menu, ID_SAVE, has_text);
+    menu::enable_item(menu, ID_SAVE_SELECTION, has_text);
+    menu::enable_item(menu, ID_PAUSE, speaking);
+    let playing = matches!(state.playback.status(), Status::Playing | Status::Paused);
+    menu::enable_item(menu, ID_PREVIOUS_SENTENCE, playing);
+    menu::enable_item(menu, ID_NEXT_SENTENCE, playing);
+    menu::enable_item(menu, ID_STOP, speaking || state.saving.borrow().is_some());
+    menu::enable_item(
+        menu,
+        ID_EXPORT_SENTENCES,
+        has_text && state.saving.borrow().is_none(),
+    );
+    menu::check_item(menu, ID_WATCH_FILE, tail::is_watching());
+    menu::check_item(menu, ID_WATCH_FOLDER, folder_watch::is_watching());
+    menu::enable_item(menu, ID_SCHEDULE, has_text);
+    menu::enable_item(menu, ID_SCHEDULE_CANCEL, state.schedule.borrow().is_some());
+    // 選んだ時間が一覧になければカスタムで設定している
+    let sleep = state.sleep_timer.duration();
+    let preset = |minutes| sleep == Some(Duration::from_secs(minutes * 60));
+    menu::check_item(menu, ID_SLEEP_OFF, sleep.is_none());
+    for (id, minutes) in SLEEP_PRESETS {
+        menu::check_item(menu, id, preset(minutes));
+    }
+    let custom = sleep.is_some() && !SLEEP_PRESETS.iter().any(|&(_, m)| preset(m));
+    menu::check_item(menu, ID_SLEEP_CUSTOM, custom);
+    let settings = settings::get();
+    menu::check_item(menu, ID_TOPMOST, settings.always_on_top);
+    menu::check_item(menu, ID_CLOSE_TO_TRAY, settings.close_to_tray);
+    menu::check_item(
+        menu,
+        ID_MULTIPLE_INSTANCES,
+        settings.allow_multiple_instances,
+    );
+    menu::check_item(menu, ID_PLAY_FORWARDED, settings.play_forwarded);
+    menu::check_item(
+        menu,
+        ID_SPEAK_CLIPBOARD_AT_LAUNCH,
+        settings.speak_clipboard_at_launch,
+    );
+    menu::check_item(menu, ID_PIPE_SERVER, settings.pipe_server);
+    menu::check_item(menu, ID_AUTOSAVE, !settings.autosave_disabled);
+    menu::check_item(menu, ID_QUICK_INPUT, settings.quick_input);
+    menu::check_item(menu, ID_SPEECH_MARKS, settings.speech_marks);
+    menu::check_item(menu, ID_NORMALIZE_VOLUME, settings.normalize_volume);
+    menu::check_item(menu, ID_TRIM_SILENCE, settings.trim_silence);
+    for (id, rate) in SAMPLE_RATE_PRESETS {
+        menu::check_item(menu, id, settings.output_sample_rate == rate);
+    }
+    menu::check_item(menu, ID_OUTPUT_MONO, settings.output_mono);
+    menu::check_item(menu, ID_DUCK_OTHERS, settings.duck_others);
+    menu::check_item(menu, ID_PREVENT_SLEEP, settings.prevent_sleep);
+    menu::check_item(menu, ID_KEEP_DISPLAY_ON, settings.keep_display_on);
+    menu::enable_item(menu, ID_KEEP_DISPLAY_ON, settings.prevent_sleep);
+    menu::check_item(menu, ID_AUTO_VOICE, settings.auto_voice);
+    menu::check_item(menu, ID_MIXED_VOICES, settings.mixed_voices);
+    menu::check_item(menu, ID_NORMALIZE_DATES, settings.normalize_dates);
+    menu::check_item(menu, ID_NORMALIZE_NUMBERS, settings.normalize_numbers);
+    menu::check_item(menu, ID_NORMALIZE_URLS, settings.normalize_urls);
+    let pause = settings.paragraph_pause();
+    for (id, ms) in PARAGRAPH_PAUSE_PRESETS {
+        menu::check_item(menu, id, pause == Duration::from_millis(ms));
+    }
+    menu::check_item(menu, ID_LANG_AUTO, settings.language.is_none());
+    menu::check_item(menu, ID_LANG_JA, settings.language == Some(Lang::Ja));
+    menu::check_item(menu, ID_LANG_EN, settings.language == Some(Lang::En));
+    Ok(())
+}
+
+/// ツールバーのボタンの有効・無効を現在の状態に合わせる
+fn update_toolbar(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let has_text = state.has_speakable_text()? && state.has_voices();
+    let speaking = state.playback.is_speaking();
+    toolbar::enable_button(hwnd, ID_PLAY, has_text && !SYNTHESIZING.get())?;
+    toolbar::enable_button(hwnd, ID_SAVE, has_text)?;
+    toolbar::enable_button(hwnd, ID_PAUSE, speaking)?;
+    // 合成中はまだ移る文がない
+    let playing = matches!(state.playback.status(), Status::Playing | Status::Paused);
+    toolbar::enable_button(hwnd, ID_PREVIOUS_SENTENCE, playing)?;
+    toolbar::enable_button(hwnd, ID_NEXT_SENTENCE, playing)?;
+    toolbar::enable_button(hwnd, ID_STOP, speaking || state.saving.borrow().is_some())?;
+    Ok(())
+}
+
+/// ウィンドウを最前面に固定するかどうかを切り替える
+fn set_topmost(hwnd: HWND, topmost: bool) -> Result<()> {
+    let insert_after = if topmost {
+        HWND_TOPMOST
+    } else {
+        HWND_NOTOPMOST
+    };
+    unsafe {
+        SetWindowPos(
+            hwnd,
+            insert_after,
+            0,
+            0,
+            0,
+            0,
+            SWP_NOMOVE | SWP_NOSIZE | SWP_NOACTIVATE,
+        )?
+    };
+    Ok(())
+}
+
+fn toggle_topmost(hwnd: HWND) -> Result<()> {
+    let topmost = !settings::get().always_on_top;
+    set_topmost(hwnd, topmost)?;
+    settings::update(|s| s.always_on_top = topmost)
+}
+
+/// 表示言語の設定を変更する。変更は次回の起動時に反映される
+fn set_language(hwnd: HWND, language: Option<Lang>) -> Result<()> {
+    if settings::get().language == language {
+        return Ok(());
+    }
+    settings::update(|s| s.language = language)?;
+    show_message(hwnd, tr(Msg::LanguageChanged));
+    Ok(())
+}
+
+/// タスクトレイに格納したウィンドウを元に戻す
+fn restore_window(hwnd: HWND) {
+    unsafe {
+        _ = ShowWindow(hwnd, SW_RESTORE);
+        _ = SetForegroundWindow(hwnd);
+    }
+}
+
+/// タスクトレイのアイコンに対するマウス操作を処理する
+fn tray_icon(hwnd: HWND, lparam: LPARAM) -> Result<()> {
+    match lparam.0 as u32 {
+        WM_LBUTTONDBLCLK => restore_window(hwnd),
+        WM_RBUTTONUP => tray::show_menu(
+            hwnd,
+            &[
+                Item::Command(ID_PLAY, Msg::TrayPlay),
+                Item::Command(ID_STOP, Msg::TrayStop),
+                Item::Separator,
+                Item::Command(ID_SHOW_WINDOW, Msg::TrayShow),
+                Item::Separator,
+                Item::Command(ID_EXIT, Msg::TrayExit),
+            ],
+        )?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// 閉じるボタンが押されたとき、設定に応じてタスクトレイに格納するか終了する
+fn close(hwnd: HWND) -> Result<()> {
+    if settings::get().close_to_tray {
+        _ = unsafe { ShowWindow(hwnd, SW_HIDE) };
+        return Ok(());
+    }
+    exit(hwnd)
+}
+
+/// アプリケーションを終了する
+///
+/// 再生中や保存中の場合は確認し、キャンセルされたら何もしない。
+/// 保存中の場合は、書きかけのファイルが残らないように保存が終わってから閉じる。
+fn exit(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let saving = state.saving.borrow().clone();
+    if let Some(msg) = exit_confirmation(state.playback.is_speaking(), saving.is_some()) {
+        if message_box(hwnd, tr(msg), MB_YESNO | MB_ICONQUESTION) != IDYES {
+            return Ok(());
+        }
+    }
+    state.playback.stop_all();
+    if let Some(saving) = saving {
+        saving.cancel();
+        state.closing.set(true);
+        return Ok(());
+    }
+    unsafe { DestroyWindow(hwnd)? };
+    Ok(())
+}
+
+/// 終了する前に確かめるメッセージ (保存を中止する確認を優先する)
+fn exit_confirmation(speaking: bool, saving: bool) -> Option<Msg> {
+    if saving {
+        Some(Msg::ConfirmExitSaving)
+    } else if speaking {
+        Some(Msg::ConfirmExitPlaying)
+    } else {
+        None
+    }
+}
+
+/// 音声を選択するコンボボックスを設定パネルの中に生成する
+fn create_combobox(state: &AppState, panel: HWND) -> Result<()> {
+    let (x, y, width, height) = panel::COMBO_RECT.scale(dpi::dpi_for_window(panel));
+    unsafe {
+        CreateWindowExW(
+            WS_EX_STATICEDGE,
+            WC_COMBOBOXW,
+            None,
+            WINDOW_STYLE((CBS_DROPDOWNLIST | CBS_HASSTRINGS | CBS_SORT) as _)
+                | WS_CHILD
+                | WS_VISIBLE
+                | WS_TABSTOP
+                | WS_VSCROLL,
+            x,
+            y,
+            width,
+            height,
+            panel,
+            HMENU(ID_COMBO as _),
+            None,
+            None,
+        )?
+    };
+    fill_voices(state)
+}
+
+/// インストールされている音声をコンボボックスに並べ、既定の音声を選ぶ
+///
+/// 音声が一つもない環境 (N エディションなど) では空のままにする。
+fn fill_voices(state: &AppState) -> Result<()> {
+    let hwnd = state.combobox()?;
+    unsafe { SendMessageW(hwnd, CB_RESETCONTENT, None, None) };
+    let engine = state.playback.engine();
+    let voices = engine.voices().unwrap_or_default();
+    // 並べ替えで位置が変わっても音声がわかるように、項目データに一覧の位置を持たせる
+    for (i, v) in voices.iter().enumerate() {
+        let name = &v.name;
+        let index = unsafe { SendMessageW(hwnd, CB_ADDSTRING, None, LPARAM(name.as_ptr() as _)) };
+        ensure!(index.0 >= 0, "failed to add a voice.");
+        unsafe { SendMessageW(hwnd, CB_SETITEMDATA, WPARAM(index.0 as _), LPARAM(i as _)) };
+    }
+    logging::info(format_args!(
+        "voices: {}",
+        voices
+            .iter()
+            .map(|v| v.name.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    ));
+    *state.voices.borrow_mut() = voices;
+
+    // 既定の音声を取得できないか一覧にない場合は先頭の音声を選ぶ
+    let ids = voice_ids(&state.voices.borrow());
+    forget_uninstalled_voices(&ids)?;
+    let default_voice = engine.default_voice().map(|voice| voice.id);
+    if let VoiceChoice::Fallback(Some(index)) =
+        choose_voice(None, &ids, default_voice.as_ref().ok())
+    {
+        state.select_voice_id(&ids[index])?;
+    }
+    // 選べる音声がなければ、空のコンボボックスを操作できないようにする
+    _ = unsafe { EnableWindow(hwnd, !ids.is_empty()) };
+    Ok(())
+}
+
+/// アンインストールされた音声の読み上げ速度と、言語ごとに選んだ音声を忘れる
+/// (音声を取得できなかった場合は何もしない)
+fn forget_uninstalled_voices(ids: &[HSTRING]) -> Result<()> {
+    let installed = ids.iter().map(HSTRING::to_string).collect::<Vec<_>>();
+    let uninstalled = {
+        let settings = settings::get();
+        settings
+            .voice_rates
+            .keys()
+            .chain(settings.language_voices.values())
+            .any(|id| !installed.contains(id))
+    };
+    if installed.is_empty() || !uninstalled {
+        return Ok(());
+    }
+    settings::update(|s| {
+        s.voice_rates.retain(|id, _| installed.contains(id));
+        s.language_voices.retain(|_, id| installed.contains(id));
+    })
+}
+
+/// ログファイルを置いているフォルダーをエクスプローラーで開く
+fn open_log_folder(hwnd: HWND) -> Result<()> {
+    let dir = logging::log_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    unsafe {
+        ShellExecuteW(
+            hwnd,
+            w!("open"),
+            &HSTRING::from(dir.as_path()),
+            None,
+            None,
+            SW_SHOWNORMAL,
+        )
+    };
+    Ok(())
+}
+
+/// 音声がない場合に、Windows の音声の設定を開くか尋ねる
+fn show_no_voices(hwnd: HWND) {
+    let ret = message_box(hwnd, tr(Msg::NoVoices), MB_YESNO | MB_ICONWARNING);
+    if ret == IDYES {
+        unsafe {
+            ShellExecuteW(
+                hwnd,
+                w!("open"),
+                w!("ms-settings:speech"),
+                None,
+                None,
+                SW_SHOWNORMAL,
+            )
+        };
+    }
+}
+
+/// 音声がなかった場合に、あとから追加された音声を読み込み直す
+fn refresh_voices(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    if state.has_voices() {
+        return Ok(());
+    }
+    reload_voices(hwnd)
+}
+
+/// 音声の一覧を作り直す。選んでいた音声が残っていれば選び直し、なければ既定の音声を選ぶ
+fn reload_voices(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let previous = state.selected_voice().map(|voice| voice.id).ok();
+    fill_voices(state)?;
+    if let Some(id) = previous {
+        state.select_voice_id(&id).ok();
+    }
+    if state.has_voices() {
+        state.update_synthesizer()?;
+    }
+    update_toolbar(hwnd)
+}
+
+/// メニューから音声の一覧を読み込み直し、見つかった音声の数を知らせる
+///
+/// 音声を追加したあとにアプリケーションを再起動しなくても使えるようにする。
+fn reload_voices_from_menu(hwnd: HWND) -> Result<()> {
+    reload_voices(hwnd)?;
+    let count = AppState::get(hwnd)?.voices.borrow().len();
+    if count == 0 {
+        show_no_voices(hwnd);
+        return Ok(());
+    }
+    let msg = trf(Msg::StatusVoicesReloaded, &[&count.to_string()]);
+    status::set_status(hwnd, Part::Misc, &msg)
+}
+
+fn create_edit(hwnd: HWND) -> Result<()> {
+    let (x, y, width, height) = edit_rect(hwnd)?;
+    let hwnd = unsafe {
+        CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            w!("EDIT"),
+            None,
+            WINDOW_STYLE((ES_MULTILINE | ES_WANTRETURN | /*ES_AUTOHSCROLL|*/ ES_AUTOVSCROLL) as _)
+                // 検索ダイアログにフォーカスがあっても見つかった範囲が見えるようにする
+                | WINDOW_STYLE(ES_NOHIDESEL as _)
+                | WS_CHILD
+                | WS_VISIBLE
+                | WS_BORDER
+                | WS_TABSTOP
+                //| WS_HSCROLL,
+            | WS_VSCROLL,
+            x,
+            y,
+            width,
+            height,
+            hwnd,
+            HMENU(ID_EDIT as _),
+            GetModuleHandleW(None)?,
+            None,
+        )?
+    };
+    // サブクラス化する前のウィンドウプロシージャはエディットコントロール自身に持たせる
+    let old_proc = unsafe { SetWindowLongPtrW(hwnd, GWLP_WNDPROC, edit_proc as usize as _) };
+    unsafe { SetWindowLongPtrW(hwnd, GWLP_USERDATA, old_proc) };
+    Ok(())
+}
+
+/// エディットコントロールのサブクラスプロシージャ
+///
+/// Tab キーはフォーカスの移動に使い、Ctrl+Tab でタブ文字を入力する。
+/// Enter キーは ES_WANTRETURN により改行として入力される。
+unsafe extern "system" fn edit_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    let old_proc = mem::transmute::<isize, WNDPROC>(GetWindowLongPtrW(hwnd, GWLP_USERDATA));
+    let is_tab = |key: WPARAM| key.0 == VK_TAB.0 as usize;
+    let ctrl = GetKeyState(VK_CONTROL.0 as _) < 0;
+    // クイック入力モードでは Enter キーで読み上げ、Shift+Enter で改行する
+    let is_quick_enter = |key: WPARAM| {
+        key.0 == VK_RETURN.0 as usize
+            && GetKeyState(VK_SHIFT.0 as _) >= 0
+            && settings::get().quick_input
+    };
+    match msg {
+        WM_GETDLGCODE => {
+            let code = CallWindowProcW(old_proc, hwnd, msg, wparam, lparam).0 as u32;
+            let key_msg = lparam.0 as *const MSG;
+            if !key_msg.is_null() && (*key_msg).message == WM_KEYDOWN && is_tab((*key_msg).wParam) {
+                let code = if ctrl {
+                    code | DLGC_WANTMESSAGE
+                } else {
+                    code & !(DLGC_WANTALLKEYS | DLGC_WANTTAB | DLGC_WANTMESSAGE)
+                };
+                return LRESULT(code as _);
+            }
+            LRESULT(code as _)
+        }
+        WM_KEYDOWN if is_tab(wparam) && ctrl => {
+            SendMessageW(
+                hwnd,
+                EM_REPLACESEL,
+                WPARAM(1),
+                LPARAM(w!("\t").as_ptr() as _),
+            );
+            LRESULT(0)
+        }
+        // Ctrl+Tab で文字メッセージが届いた場合は WM_KEYDOWN で入力済みなので捨てる
+        WM_CHAR if is_tab(wparam) && ctrl => LRESULT(0),
+        WM_KEYDOWN if is_quick_enter(wparam) => {
+            if let Ok(parent) = GetParent(hwnd) {
+                if let Err(e) = quick_speak(parent) {
+                    report_error(parent, Msg::ErrorPlay, &e);
+                }
+            }
+            LRESULT(0)
+        }
+        // Enter キーの文字コード '\r' は VK_RETURN と同じ値なので、読み上げた分の改行は捨てる
+        WM_CHAR if is_quick_enter(wparam) => LRESULT(0),
+        WM_CONTEXTMENU => {
+            if let Err(e) = show_edit_context_menu(hwnd, lparam) {
+                report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+            LRESULT(0)
+        }
+        _ => CallWindowProcW(old_proc, hwnd, msg, wparam, lparam),
+    }
+}
+
+/// 読み上げ速度を調整するトラックバーを設定パネルの中に生成する
+fn create_trackbar(panel: HWND) -> Result<()> {
+    let (x, y, width, height) = panel::TRACKBAR_RECT.scale(dpi::dpi_for_window(panel));
+    let hwnd = unsafe {
+        CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            w!("msctls_trackbar32"),
+            w!("Track Bar"),
+            WS_CHILD | WS_VISIBLE | WS_TABSTOP | WINDOW_STYLE(TBS_TOOLTIPS | TBS_AUTOTICKS),
+            x,
+            y,
+            width,
+            height,
+            panel,
+            HMENU(ID_TRACKBAR as _),
+            None,
+            None,
+        )
+    }?;
+    let (min, max) = (
+        synthesis::pos_from_rate(synthesis::MIN_RATE),
+        synthesis::pos_from_rate(synthesis::MAX_RATE),
+    );
+    let range = makelong(min as _, max as _);
+    unsafe { SendMessageW(hwnd, TBM_SETRANGE, WPARAM(1), LPARAM(range as _)) };
+    unsafe { SendMessageW(hwnd, TBM_SETPAGESIZE, None, LPARAM(5)) };
+    unsafe { SendMessageW(hwnd, TBM_SETTICFREQ, WPARAM(5), LPARAM(0)) };
+    unsafe { SendMessageW(hwnd, TBM_SETPOS, WPARAM(1), LPARAM(10)) };
+    Ok(())
+}
+
+/// 再生する回数のエディットとアップダウンコントロールを生成する
+///
+/// 回数は起動している間だけ覚えておき、設定には保存しない。
+fn create_repeat(panel: HWND) -> Result<()> {
+    let (x, y, width, height) = panel::REPEAT_RECT.scale(dpi::dpi_for_window(panel));
+    let edit = unsafe {
+        CreateWindowExW(
+            WS_EX_STATICEDGE,
+            w!("EDIT"),
+            w!("1"),
+            WINDOW_STYLE(ES_NUMBER as _) | WS_CHILD | WS_VISIBLE | WS_TABSTOP,
+            x,
+            y,
+            width,
+            height,
+            panel,
+            HMENU(ID_REPEAT as _),
+            None,
+            None,
+        )?
+    };
+    let spin = unsafe {
         CreateWindowExW(
             WINDOW_EX_STYLE::default(),
-            w!("BUTTON"),
-            label,
-            WS_CHILD | WS_VISIBLE | WINDOW_STYLE(BS_PUSHBUTTON as _),
-            x,
-            y,
-            width,
-            height,
-            hwnd,
-            HMENU(id as _),
+            UPDOWN_CLASS,
+            None,
+            WINDOW_STYLE(UDS_SETBUDDYINT | UDS_ALIGNRIGHT | UDS_ARROWKEYS | UDS_NOTHOUSANDS)
+                | WS_CHILD
+                | WS_VISIBLE,
+            0,
+            0,
+            0,
+            0,
+            panel,
+            HMENU(ID_REPEAT_SPIN as _),
             None,
             None,
         )?
     };
+    unsafe {
+        SendMessageW(spin, UDM_SETBUDDY, WPARAM(edit.0 as _), None);
+        SendMessageW(spin, UDM_SETRANGE32, WPARAM(1), LPARAM(MAX_REPEATS as _));
+        SendMessageW(spin, UDM_SETPOS32, None, LPARAM(1));
+    }
     Ok(())
 }
 
-fn create_play_button(hwnd: HWND) -> Result<()> {
-    create_button(hwnd, w!("再生"), 10, 10, 100, 30, ID_PLAY)?;
-    Ok(())
+/// クライアント領域の大きさからエディットコントロールの物理座標を求める
+fn edit_rect(hwnd: HWND) -> Result<(i32, i32, i32, i32)> {
+    let rc = unsafe {
+        let mut rc = RECT::default();
+        GetClientRect(hwnd, &mut rc)?;
+        rc
+    };
+    let top = toolbar::height(hwnd)? + panel::height(dpi::dpi_for_window(hwnd));
+    let bottom = rc.bottom - status::height(hwnd)?;
+    Ok((0, top, rc.right, bottom - top))
 }
 
-fn create_clear_button(hwnd: HWND) -> Result<()> {
-    create_button(hwnd, w!("クリア"), 120, 10, 100, 30, ID_CLEAR)?;
+fn move_child(hwnd: HWND, (x, y, width, height): (i32, i32, i32, i32)) -> Result<()> {
+    unsafe { MoveWindow(hwnd, x, y, width, height, true)? };
     Ok(())
 }
 
-fn create_save_button(hwnd: HWND) -> Result<()> {
-    create_button(hwnd, w!("保存"), 230, 10, 100, 30, ID_SAVE)?;
-    Ok(())
+/// 設定パネルの折りたたみ状態に合わせてコントロールの表示を切り替え、配置し直す
+fn apply_panel_state(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
+    let collapsed = settings::get().panel_collapsed;
+    let cmd = if collapsed { SW_HIDE } else { SW_SHOW };
+    let panel = panel::handle(hwnd)?;
+    let spin = unsafe { GetDlgItem(panel, ID_REPEAT_SPIN as _)? };
+    for child in [
+        state.combobox()?,
+        state.trackbar()?,
+        state.repeat_edit()?,
+        spin,
+    ] {
+        _ = unsafe { ShowWindow(child, cmd) };
+    }
+    panel::set_collapsed(hwnd, collapsed)?;
+    layout(hwnd)
 }
 
-fn create_combobox(hwnd: HWND) -> Result<()> {
-    let hwnd = unsafe {
-        CreateWindowExW(
-            WS_EX_STATICEDGE,
-            WC_COMBOBOXW,
-            None,
-            WINDOW_STYLE((CBS_DROPDOWNLIST | CBS_HASSTRINGS | CBS_SORT) as _)
-                | WS_CHILD
-                | WS_VISIBLE
-                | WS_VSCROLL,
-            340,
-            12,
-            227,
-            200,
-            hwnd,
-            HMENU(ID_COMBO as _),
-            None,
-            None,
-        )?
+/// 現在の DPI と大きさに合わせて子ウィンドウを配置する
+fn layout(hwnd: HWND) -> Result<()> {
+    let dpi = dpi::dpi_for_window(hwnd);
+    status::resize(hwnd, dpi)?;
+    toolbar::resize(hwnd, dpi)?;
+    let width = unsafe {
+        let mut rc = RECT::default();
+        GetClientRect(hwnd, &mut rc)?;
+        rc.right
     };
+    let panel = panel::handle(hwnd)?;
+    move_child(
+        panel,
+        (0, toolbar::height(hwnd)?, width, panel::height(dpi)),
+    )?;
+    panel::layout(hwnd, dpi)?;
+    for (id, rect) in [
+        (ID_COMBO, panel::COMBO_RECT),
+        (ID_TRACKBAR, panel::TRACKBAR_RECT),
+        (ID_REPEAT, panel::REPEAT_RECT),
+    ] {
+        let child = unsafe { GetDlgItem(panel, id as _)? };
+        move_child(child, rect.scale(dpi))?;
+    }
+    // アップダウンコントロールはエディットに付け直すと右端に配置し直される
+    let repeat = unsafe { GetDlgItem(panel, ID_REPEAT as _)? };
+    let spin = unsafe { GetDlgItem(panel, ID_REPEAT_SPIN as _)? };
+    unsafe { SendMessageW(spin, UDM_SETBUDDY, WPARAM(repeat.0 as _), None) };
+    let edit = AppState::get(hwnd)?.edit()?;
+    move_child(edit, edit_rect(hwnd)?)?;
+    _ = unsafe { InvalidateRect(hwnd, None, true) };
+    Ok(())
+}
 
-    SpeechSynthesizer::AllVoices()?
-        .into_iter()
-        .try_for_each(|v| -> Result<()> {
-            let name = v.DisplayName()?;
-            unsafe { SendMessageW(hwnd, CB_ADDSTRING, None, LPARAM(name.as_ptr() as _)) };
-            Ok(())
-        })?;
+unsafe extern "system" fn set_font_proc(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    SendMessageW(hwnd, WM_SETFONT, WPARAM(lparam.0 as _), LPARAM(1));
+    TRUE
+}
 
-    let default_voice = SpeechSynthesizer::DefaultVoice()?.DisplayName()?;
-    unsafe {
-        SendMessageW(
-            hwnd,
-            CB_SELECTSTRING,
-            None,
-            LPARAM(default_voice.as_ptr() as _),
-        )
-    };
-    COMBOBOX_HWND.get_or_init(|| Hwnd::new(hwnd));
+/// 現在の DPI に合わせたフォントを生成してすべての子ウィンドウに設定する
+fn update_font(hwnd: HWND) -> Result<()> {
+    let font = dpi::create_message_font(dpi::dpi_for_window(hwnd))?;
+    let old_font = UI_FONT.replace(font);
+    _ = unsafe { EnumChildWindows(hwnd, Some(set_font_proc), LPARAM(font.0 as _)) };
+    if !old_font.is_invalid() {
+        _ = unsafe { DeleteObject(old_font) };
+    }
+    // エディットコントロールには設定で選んだフォントを使う
+    update_edit_font(hwnd)
+}
+
+/// 設定で選んだフォントを現在の DPI に合わせて生成し、エディットコントロールに設定する
+fn update_edit_font(hwnd: HWND) -> Result<()> {
+    let font = settings::get().edit_font.clone().unwrap_or_default();
+    let logfont = dpi::logfont(
+        &font.face,
+        font.size,
+        font.weight,
+        dpi::dpi_for_window(hwnd),
+    );
+    let font = dpi::create_font(&logfont)?;
+    let edit = AppState::get(hwnd)?.edit()?;
+    unsafe { SendMessageW(edit, WM_SETFONT, WPARAM(font.0 as _), LPARAM(1)) };
+    let old_font = EDIT_FONT.replace(font);
+    if !old_font.is_invalid() {
+        _ = unsafe { DeleteObject(old_font) };
+    }
     Ok(())
 }
 
-fn create_edit(hwnd: HWND) -> Result<()> {
-    let rc = unsafe {
-        let mut rc = RECT::default();
-        GetClientRect(hwnd, &mut rc)?;
-        rc
+/// フォントの選択ダイアログを表示し、選んだフォントを保存してエディットコントロールに設定する
+fn choose_edit_font(hwnd: HWND) -> Result<()> {
+    let font = settings::get().edit_font.clone().unwrap_or_default();
+    // フォントダイアログはシステムの DPI でポイント数を計算する
+    let mut logfont = dpi::logfont(&font.face, font.size, font.weight, unsafe {
+        GetDpiForSystem()
+    });
+    let mut cf = CHOOSEFONTW {
+        lStructSize: mem::size_of::<CHOOSEFONTW>() as _,
+        hwndOwner: hwnd,
+        lpLogFont: &mut logfont,
+        Flags: CF_INITTOLOGFONTSTRUCT | CF_SCREENFONTS | CF_NOVERTFONTS,
+        ..Default::default()
     };
-    let hwnd = unsafe {
-        CreateWindowExW(
-            WINDOW_EX_STYLE::default(),
-            w!("EDIT"),
-            None,
-            WINDOW_STYLE((ES_MULTILINE | ES_WANTRETURN | /*ES_AUTOHSCROLL|*/ ES_AUTOVSCROLL) as _)
-                | WS_CHILD
-                | WS_VISIBLE
-                | WS_BORDER
-                | WS_TABSTOP
-                //| WS_HSCROLL,
-            | WS_VSCROLL,
-            0,
-            80,
-            rc.right,
-            rc.bottom - 80,
+    if !unsafe { ChooseFontW(&mut cf) }.as_bool() {
+        // キャンセルされた場合は何もしない
+        let code = unsafe { CommDlgExtendedError() };
+        ensure!(code.0 == 0, "font dialog failed. (0x{:x})", code.0);
+        return Ok(());
+    }
+    let len = logfont
+        .lfFaceName
+        .iter()
+        .position(|&c| c == 0)
+        .unwrap_or(logfont.lfFaceName.len());
+    let font = settings::EditFont {
+        face: String::from_utf16_lossy(&logfont.lfFaceName[..len]),
+        size: cf.iPointSize,
+        weight: logfont.lfWeight,
+    };
+    settings::update(|s| s.edit_font = Some(font).filter(|f| *f != Default::default()))?;
+    update_edit_font(hwnd)
+}
+
+/// WM_DPICHANGED で提案された矩形にウィンドウを合わせて再配置する
+fn dpi_changed(hwnd: HWND, lparam: LPARAM) -> Result<()> {
+    let rc = unsafe { *(lparam.0 as *const RECT) };
+    unsafe {
+        SetWindowPos(
             hwnd,
             None,
-            GetModuleHandleW(None)?,
-            None,
+            rc.left,
+            rc.top,
+            rc.right - rc.left,
+            rc.bottom - rc.top,
+            SWP_NOZORDER | SWP_NOACTIVATE,
         )?
     };
-    EDIT_HWND.get_or_init(|| Hwnd::new(hwnd));
-    Ok(())
+    update_font(hwnd)?;
+    update_icon(hwnd)?;
+    let tooltip = created(AppState::get(hwnd)?.tooltip.get())?;
+    tooltip::set_max_width(tooltip, dpi::dpi_for_window(hwnd))?;
+    layout(hwnd)
 }
 
-fn create_trackbar(hwnd: HWND) -> Result<()> {
-    let hwnd = unsafe {
-        CreateWindowExW(
-            WINDOW_EX_STYLE::default(),
-            w!("msctls_trackbar32"),
-            w!("Track Bar"),
-            WS_CHILD | WS_VISIBLE | WINDOW_STYLE(TBS_TOOLTIPS | TBS_AUTOTICKS),
-            145,
-            50,
-            400,
-            30,
-            hwnd,
-            HMENU(ID_TRACKBAR as _),
-            None,
-            None,
-        )
-    }?;
-    unsafe { SendMessageW(hwnd, TBM_SETRANGE, WPARAM(1), LPARAM(makelong(5, 25) as _)) };
-    unsafe { SendMessageW(hwnd, TBM_SETPAGESIZE, None, LPARAM(5)) };
-    unsafe { SendMessageW(hwnd, TBM_SETTICFREQ, WPARAM(5), LPARAM(0)) };
-    unsafe { SendMessageW(hwnd, TBM_SETPOS, WPARAM(1), LPARAM(10)) };
-    TRACKBAR_HWND.get_or_init(|| Hwnd::new(hwnd));
+/// 現在の DPI に合わせたアイコンをタイトルバーとタスクバーに設定する
+fn update_icon(hwnd: HWND) -> Result<()> {
+    let dpi = dpi::dpi_for_window(hwnd);
+    for (kind, icon) in [
+        (ICON_BIG, icon::load_large(dpi)?),
+        (ICON_SMALL, icon::load_small(dpi)?),
+    ] {
+        unsafe { SendMessageW(hwnd, WM_SETICON, WPARAM(kind as _), LPARAM(icon.0 as _)) };
+    }
     Ok(())
 }
 
@@ -395,21 +3645,106 @@ fn create_trackbar(hwnd: HWND) -> Result<()> {
 fn init_common_control() -> Result<()> {
     let icc = INITCOMMONCONTROLSEX {
         dwSize: size_of::<INITCOMMONCONTROLSEX>() as _,
-        dwICC: ICC_BAR_CLASSES,
+        dwICC: ICC_BAR_CLASSES | ICC_PROGRESS_CLASS | ICC_UPDOWN_CLASS | ICC_DATE_CLASSES,
     };
     unsafe { InitCommonControlsEx(&icc).ok()? };
     Ok(())
 }
 
+/// スクリーンリーダー向けに各コントロールの名前と値を設定する
+fn set_accessible_names(hwnd: HWND) -> Result<()> {
+    let edit = AppState::get(hwnd)?.edit()?;
+    accessibility::set_name(edit, tr(Msg::AccText))?;
+    accessibility::set_name(toolbar::handle(hwnd)?, tr(Msg::AccToolbar))?;
+    let panel = panel::handle(hwnd)?;
+    for (id, name) in ACCESSIBLE_NAMES {
+        let control = unsafe { GetDlgItem(panel, id as _)? };
+        accessibility::set_name(control, tr(name))?;
+    }
+    update_rate_value(hwnd)
+}
+
+/// トラックバーの値を 5～25 ではなく読み上げ速度の倍率として読み上げさせる
+fn update_rate_value(hwnd: HWND) -> Result<()> {
+    let trackbar = AppState::get(hwnd)?.trackbar()?;
+    accessibility::set_value(trackbar, &rate_text(hwnd)?)?;
+    // 読み上げにかかる時間の目安は速度で変わる
+    update_counts(hwnd)
+}
+
+/// 現在の読み上げ速度を倍率で表した文字列
+fn rate_text(hwnd: HWND) -> Result<String> {
+    let rate = format!("{:.1}", AppState::get(hwnd)?.speaking_rate()?);
+    Ok(trf(Msg::AccRateValue, &[&rate]))
+}
+
+/// 各コントロールにツールチップを登録する
+fn create_tooltips(hwnd: HWND) -> Result<()> {
+    let tooltip = tooltip::create(hwnd)?;
+    AppState::get(hwnd)?.tooltip.set(tooltip);
+    let panel = panel::handle(hwnd)?;
+    for (id, text) in TOOLTIPS {
+        let control = unsafe { GetDlgItem(panel, id as _)? };
+        tooltip::add_tool(tooltip, hwnd, control, tr(text))?;
+    }
+    Ok(())
+}
+
 /// 各種 UI を生成する
 fn create(hwnd: HWND) -> Result<()> {
+    let state = AppState::get(hwnd)?;
     init_common_control()?;
-    create_play_button(hwnd)?;
-    create_clear_button(hwnd)?;
-    create_save_button(hwnd)?;
+    update_icon(hwnd)?;
+    // 履歴はなくても使えるので、読み込めなくても起動を続ける
+    if settings::get().save_history {
+        if let Err(e) = history::load() {
+            logging::error("failed to load the history", &e);
+        }
+    }
+    create_menu(hwnd)?;
+    status::create(hwnd)?;
+    toolbar::create(hwnd, &TOOLBAR_BUTTONS)?;
+    // Tab キーでのフォーカス移動は生成順になる
     create_edit(hwnd)?;
-    create_combobox(hwnd)?;
-    create_trackbar(hwnd)?;
+    let panel = panel::create(hwnd)?;
+    create_combobox(state, panel)?;
+    create_repeat(panel)?;
+    create_trackbar(panel)?;
+    // 最初の読み上げを待たせないように先に作っておく (失敗しても読み上げるときに作り直す)
+    state.update_synthesizer().ok();
+    if !state.has_voices() {
+        // エディットコントロールは使えるように、起動は続けてから知らせる
+        unsafe { PostMessageW(hwnd, WM_NO_VOICES, WPARAM(0), LPARAM(0))? };
+    }
+    // ツールチップとトレイアイコンはなくても使えるので、失敗しても起動を続ける
+    if let Err(e) = create_tooltips(hwnd)
+        .and_then(|_| set_accessible_names(hwnd))
+        .and_then(|_| tray::add(hwnd, tr(Msg::AppName)))
+    {
+        report_error(hwnd, Msg::ErrorCreate, &e);
+    }
+    // ホットキーはほかのアプリケーションと競合することがあるので、失敗しても起動を続ける
+    let hotkey = settings::get().hotkey(Action::SpeakClipboard);
+    if let Err(e) = hotkey::register(hwnd, hotkey::ID_SPEAK_CLIPBOARD, hotkey) {
+        report_error(hwnd, Msg::ErrorHotkey, &e);
+    }
+    let ducking = settings::get().duck_others;
+    state.playback.set_ducking(ducking);
+    let pipe_server = settings::get().pipe_server;
+    if pipe_server {
+        if let Err(e) = pipe::start(hwnd, WM_PIPE_COMMAND) {
+            report_error(hwnd, Msg::ErrorPipe, &e);
+        }
+    }
+    update_font(hwnd)?;
+    apply_panel_state(hwnd)?;
+    // コマンドライン引数のファイルやテキストは、この後で開いて置き換える
+    if let Err(e) = restore_autosaved_text(hwnd) {
+        logging::error("failed to restore the autosaved text", &e);
+    }
+    update_counts(hwnd)?;
+    update_toolbar(hwnd)?;
+    status::set_status(hwnd, Part::State, tr(Msg::StatusStopped))?;
     Ok(())
 }
 
@@ -420,64 +3755,493 @@ unsafe extern "system" fn wnd_proc(
     wparam: WPARAM,
     lparam: LPARAM,
 ) -> LRESULT {
+    let state = AppState::get(hwnd).ok();
     match msg {
         WM_CREATE => {
-            create(hwnd).ok();
+            AppState::attach(hwnd);
+            if let Err(e) = create(hwnd) {
+                report_error(hwnd, Msg::ErrorCreate, &e);
+                // ウィンドウの生成を中止する
+                return LRESULT(-1);
+            }
+        }
+        WM_NO_VOICES => show_no_voices(hwnd),
+        // 音声の設定から戻ってきたときに、追加された音声を読み込む
+        WM_ACTIVATEAPP if wparam.0 != 0 => {
+            refresh_voices(hwnd).ok();
+            return DefWindowProcW(hwnd, msg, wparam, lparam);
         }
         WM_COMMAND => {
-            command(hwnd, wparam).ok();
+            if let Err(e) = command(hwnd, wparam, lparam) {
+                report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+        WM_SETFOCUS => {
+            // ウィンドウがアクティブになったときはエディットコントロールにフォーカスを移す
+            if let Some(edit) = state.and_then(|s| s.edit().ok()) {
+                _ = SetFocus(edit);
+            }
+        }
+        WM_NOTIFY => {
+            let hdr = &*(lparam.0 as *const NMHDR);
+            if hdr.code == TTN_GETDISPINFOW {
+                toolbar::set_tooltip_text(&mut *(lparam.0 as *mut NMTTDISPINFOW));
+            }
+        }
+        WM_HSCROLL => {
+            if let Some(state) =
+                state.filter(|s| s.trackbar().is_ok_and(|t| t.0 as isize == lparam.0))
+            {
+                state.update_synthesizer().ok();
+                // 再生中のスピーチは合成し直さずに、再生速度を変えて合わせる
+                if let Ok(rate) = state.speaking_rate() {
+                    state.playback.set_rate(rate).ok();
+                }
+                update_rate_value(hwnd).ok();
+                panel::update_rate_label(hwnd).ok();
+                // つまみをドラッグしている間は保存せず、離したときに一度だけ保存する
+                if loword(wparam.0 as _) as u32 == TB_ENDTRACK {
+                    remember_rate(hwnd).ok();
+                }
+            }
+        }
+        WM_INITMENUPOPUP => {
+            update_menu(hwnd).ok();
+        }
+        WM_TIMER if wparam.0 == WATCHDOG_TIMER => {
+            if state.is_some_and(|s| s.playback.check_stalled(Instant::now())) {
+                logging::info("playback stalled; closed the speech");
+            }
+        }
+        WM_TIMER if wparam.0 == SLEEP_TIMER => {
+            sleep_timer_tick(hwnd).ok();
         }
-        WM_PAINT => {
-            paint(hwnd).ok();
+        WM_TIMER if wparam.0 == COUNT_TIMER => {
+            count_words(hwnd).ok();
+        }
+        WM_TIMER if wparam.0 == PROGRESS_TIMER => {
+            update_taskbar_progress(hwnd).ok();
+        }
+        WM_TIMER if wparam.0 == TASKBAR_ERROR_TIMER => {
+            _ = KillTimer(hwnd, TASKBAR_ERROR_TIMER);
+            update_taskbar_progress(hwnd).ok();
+        }
+        WM_TIMER if wparam.0 == AUTOSAVE_TIMER => {
+            if let Err(e) = autosave_text(hwnd) {
+                logging::error("failed to autosave the text", &e);
+            }
+        }
+        WM_DRAWITEM => {
+            return LRESULT(status::draw_item(&*(lparam.0 as *const DRAWITEMSTRUCT)) as _);
+        }
+        WM_TIMER if wparam.0 == SCHEDULE_TIMER => {
+            if let Err(e) = schedule_tick(hwnd) {
+                report_error(hwnd, Msg::ErrorPlay, &e);
+            }
+        }
+        WM_POWERBROADCAST if wparam.0 == PBT_APMSUSPEND as usize => {
+            if let Err(e) = stop_for_sleep(hwnd) {
+                logging::error("failed to stop the speech before sleeping", &e);
+            }
+        }
+        // スリープ中はタイマーが止まるので、復帰したらすぐに時刻を確かめる
+        WM_POWERBROADCAST if wparam.0 == PBT_APMRESUMEAUTOMATIC as usize => {
+            if let Err(e) = schedule_tick(hwnd) {
+                report_error(hwnd, Msg::ErrorPlay, &e);
+            }
+            // 復帰の通知を受け取るほかのウィンドウを待たせないように、尋ねるのは後にする
+            if SLEPT_WHILE_SPEAKING.take() {
+                _ = PostMessageW(hwnd, WM_RESUME_AFTER_SLEEP, WPARAM(0), LPARAM(0));
+            }
+        }
+        WM_RESUME_AFTER_SLEEP => {
+            if let Err(e) = offer_resume_after_sleep(hwnd) {
+                report_error(hwnd, Msg::ErrorPlay, &e);
+            }
+        }
+        WM_SIZE => {
+            if wparam.0 as u32 == SIZE_MINIMIZED {
+                _ = ShowWindow(hwnd, SW_HIDE);
+            } else {
+                layout(hwnd).ok();
+            }
+        }
+        WM_CLOSE => {
+            close(hwnd).ok();
+        }
+        // ログオフやシャットダウンでは待てないので、すぐに後片付けをしてウィンドウを閉じる
+        WM_ENDSESSION if wparam.0 != 0 => {
+            _ = DestroyWindow(hwnd);
+        }
+        WM_UI_MESSAGE => {
+            ui_message(hwnd, UiMessage::from_lparam(lparam));
+        }
+        WM_PIPE_COMMAND => {
+            let request = *Box::from_raw(lparam.0 as *mut pipe::Request);
+            pipe_command(hwnd, request);
+        }
+        WM_TAIL_LINES => {
+            let text = *Box::from_raw(lparam.0 as *mut Vec<u16>);
+            if let Err(e) = tail_lines(hwnd, text) {
+                report_error(hwnd, Msg::ErrorPlay, &e);
+            }
+        }
+        WM_EXPORT_NEXT => {
+            if let Err(e) = export_next(hwnd) {
+                report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+        // システムメディアトランスポートコントロールを使えないときも、キーボードのメディアキーで操作できるようにする
+        WM_APPCOMMAND => {
+            let command = (hiword(lparam.0 as _) & !(FAPPCOMMAND_MASK as u16)) as u32;
+            let playing = state.is_some_and(|s| s.playback.status() == Status::Playing);
+            let Some(button) = media_controls::button_for_app_command(command, playing) else {
+                return DefWindowProcW(hwnd, msg, wparam, lparam);
+            };
+            if let Err(e) = media_button(hwnd, button) {
+                report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+            return LRESULT(1);
+        }
+        WM_HOTKEY if wparam.0 == hotkey::ID_SPEAK_CLIPBOARD as usize => {
+            if let Err(e) = speak_clipboard(hwnd) {
+                report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+        WM_COPYDATA => {
+            // ほかのプログラムからの操作は、不正なデータでもダイアログを出さずに 0 を返す
+            let data = &*(lparam.0 as *const COPYDATASTRUCT);
+            if let Some(request) = Request::from_copy_data(data) {
+                return LRESULT(request.and_then(|r| remote_request(hwnd, r)).is_ok() as _);
+            }
+            let Some(command_line) = CommandLine::from_copy_data(lparam) else {
+                return LRESULT(0);
+            };
+            if let Err(e) = receive_argument(hwnd, command_line) {
+                report_error(hwnd, Msg::ErrorOpenFile, &e);
+            }
+            return LRESULT(1);
+        }
+        tray::WM_TRAYICON => {
+            tray_icon(hwnd, lparam).ok();
+        }
+        WM_DPICHANGED => {
+            dpi_changed(hwnd, lparam).ok();
+        }
+        WM_GETMINMAXINFO => {
+            // ウィンドウを小さくしすぎてコントロールが重ならないようにする
+            let info = &mut *(lparam.0 as *mut MINMAXINFO);
+            let dpi = dpi::dpi_for_window(hwnd);
+            info.ptMinTrackSize.x = dpi::scale(MIN_WINDOW_WIDTH, dpi);
+            info.ptMinTrackSize.y = dpi::scale(MIN_WINDOW_HEIGHT, dpi);
+        }
+        WM_DESTROY => {
+            save_window_placement(hwnd).ok();
+            // 子ウィンドウはまだ破棄されていないので、エディットコントロールのテキストを取得できる
+            if let Err(e) = autosave_text(hwnd) {
+                logging::error("failed to autosave the text", &e);
+            }
+            if let Some(state) = state {
+                find_dialog::close(state.find_dialog.take());
+            }
+            media_controls::detach();
+            tray::remove(hwnd);
+            hotkey::unregister(hwnd, hotkey::ID_SPEAK_CLIPBOARD);
+            pipe::stop();
+            tail::stop();
+            folder_watch::stop();
+            toolbar::destroy(hwnd);
+            if let Some(state) = state {
+                state.playback.close();
+                if let Some(saving) = state.saving.take() {
+                    saving.cancel();
+                }
+                let controls = [
+                    state.edit(),
+                    toolbar::handle(hwnd),
+                    state.combobox(),
+                    state.trackbar(),
+                    state.repeat_edit(),
+                ];
+                for control in controls.into_iter().flatten() {
+                    accessibility::clear(control);
+                }
+            }
+            for font in [UI_FONT.take(), EDIT_FONT.take()] {
+                if !font.is_invalid() {
+                    _ = DeleteObject(font);
+                }
+            }
+            // 書き込み中のファイルを閉じてから終わるように、作業スレッドを待つ
+            if !worker::shutdown(SHUTDOWN_TIMEOUT) {
+                logging::info("some workers did not finish before exit");
+            }
+            // 終わりの通知が届かなかった再生があっても、スリープできない状態を残さない
+            power::reset();
+            PostQuitMessage(0);
+        }
+        WM_NCDESTROY => {
+            AppState::detach(hwnd);
+            return DefWindowProcW(hwnd, msg, wparam, lparam);
+        }
+        _ if msg == taskbar::button_created_message() => {
+            if let Err(e) = taskbar::init() {
+                logging::error("failed to initialize the taskbar progress", &e);
+            }
+        }
+        _ if msg == tray::taskbar_created_message() => {
+            tray::add(hwnd, tr(Msg::AppName)).ok();
+        }
+        _ if msg == find_dialog::message() => {
+            let result = match find_dialog::command(lparam) {
+                Some(find_dialog::Command::FindNext) => find_in_edit(hwnd),
+                Some(find_dialog::Command::Replace) => replace_in_edit(hwnd),
+                Some(find_dialog::Command::ReplaceAll) => replace_all_in_edit(hwnd),
+                Some(find_dialog::Command::Closed) => {
+                    if let Some(state) = state {
+                        state.find_dialog.take();
+                    }
+                    Ok(())
+                }
+                None => Ok(()),
+            };
+            if let Err(e) = result {
+                report_error(hwnd, Msg::ErrorCommand, &e);
+            }
         }
-        WM_DESTROY => PostQuitMessage(0),
         _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
     }
     LRESULT::default()
 }
 
+/// ウィンドウの位置と大きさを設定ファイルに保存する
+fn save_window_placement(hwnd: HWND) -> Result<()> {
+    let mut placement = WINDOWPLACEMENT {
+        length: mem::size_of::<WINDOWPLACEMENT>() as _,
+        ..Default::default()
+    };
+    unsafe { GetWindowPlacement(hwnd, &mut placement)? };
+    let rc = placement.rcNormalPosition;
+    settings::update(|s| {
+        s.window_rect = Some(WindowRect {
+            left: rc.left,
+            top: rc.top,
+            right: rc.right,
+            bottom: rc.bottom,
+        });
+        s.maximized = placement.showCmd == SW_SHOWMAXIMIZED.0 as u32
+            || (placement.flags & WPF_RESTORETOMAXIMIZED).0 != 0;
+    })
+}
+
+/// 保存しておいたウィンドウの位置と大きさを復元し、ShowWindow に渡す値を返す
+///
+/// 保存された位置が現在接続されているどのモニターとも重ならない場合は復元しない。
+fn restore_window_placement(hwnd: HWND) -> Result<SHOW_WINDOW_CMD> {
+    let (rect, maximized) = {
+        let settings = settings::get();
+        (
+            settings.window_rect.context("no window rect.")?,
+            settings.maximized,
+        )
+    };
+    let rc = RECT {
+        left: rect.left,
+        top: rect.top,
+        right: rect.right,
+        bottom: rect.bottom,
+    };
+    ensure!(
+        rc.right > rc.left && rc.bottom > rc.top,
+        "invalid window rect."
+    );
+    let monitor = unsafe { MonitorFromRect(&rc, MONITOR_DEFAULTTONULL) };
+    ensure!(!monitor.is_invalid(), "window rect is off-screen.");
+
+    let placement = WINDOWPLACEMENT {
+        length: mem::size_of::<WINDOWPLACEMENT>() as _,
+        showCmd: SW_HIDE.0 as _,
+        rcNormalPosition: rc,
+        ..Default::default()
+    };
+    unsafe { SetWindowPlacement(hwnd, &placement)? };
+    Ok(if maximized { SW_SHOWMAXIMIZED } else { SW_SHOW })
+}
+
+/// アプリケーション内のショートカットキーで実行するコマンド ID
+fn action_command(action: Action) -> Option<u16> {
+    match action {
+        Action::SpeakClipboard => None,
+        Action::Play => Some(ID_PLAY),
+        Action::Pause => Some(ID_PAUSE),
+        Action::Stop => Some(ID_STOP),
+    }
+}
+
+/// [SHORTCUTS] と設定されたショートカットキーからアクセラレータテーブルを生成する
+fn create_accelerator_table() -> Result<HACCEL> {
+    let settings = settings::get();
+    let accels = SHORTCUTS
+        .iter()
+        .map(|&(modifier, key, cmd)| ACCEL {
+            fVirt: FVIRTKEY | modifier,
+            key,
+            cmd,
+        })
+        .chain(Action::ALL.into_iter().filter_map(|action| {
+            action_command(action).map(|cmd| settings.hotkey(action).to_accel(cmd))
+        }))
+        .collect::<Vec<_>>();
+    let accel = unsafe { CreateAcceleratorTableW(&accels)? };
+    Ok(accel)
+}
+
+/// アクセラレータテーブルを作り直し、古いテーブルを破棄する
+fn rebuild_accelerator_table() -> Result<()> {
+    let old = ACCELERATORS.replace(create_accelerator_table()?);
+    if !old.is_invalid() {
+        unsafe { DestroyAcceleratorTable(old)? };
+    }
+    Ok(())
+}
+
+/// ショートカットキーの設定を保存して反映する
+///
+/// グローバルホットキーを登録できなかった場合は、元のキーに戻してエラーを返す。
+fn apply_hotkeys(hwnd: HWND, hotkeys: &[(Action, Hotkey)]) -> Result<()> {
+    for &(action, hotkey) in hotkeys {
+        let Some(id) = action.hotkey_id() else {
+            continue;
+        };
+        let old = settings::get().hotkey(action);
+        if old == hotkey {
+            continue;
+        }
+        hotkey::unregister(hwnd, id);
+        if let Err(e) = hotkey::register(hwnd, id, hotkey) {
+            hotkey::register(hwnd, id, old).ok();
+            return Err(e);
+        }
+    }
+    settings::update(|s| {
+        s.hotkeys = hotkeys
+            .iter()
+            .filter(|&&(action, hotkey)| hotkey != action.default_hotkey())
+            .copied()
+            .collect();
+    })?;
+    rebuild_accelerator_table()
+}
+
 /// エントリーポイント
-fn main() -> Result<()> {
+/// メインウィンドウのクラスを登録してウィンドウを作る (まだ表示しない)
+fn create_main_window() -> Result<HWND> {
     let wnd_class = WNDCLASSW {
         lpfnWndProc: Some(wnd_proc),
         lpszClassName: CLASS_NAME,
+        hIcon: unsafe {
+            LoadIconW(
+                GetModuleHandleW(None)?,
+                icon::make_int_resource(icon::IDI_APP),
+            )?
+        },
         hbrBackground: unsafe { GetSysColorBrush(COLOR_MENUBAR) },
         ..Default::default()
     };
 
     unsafe { RegisterClassW(&wnd_class) };
 
+    let dpi = unsafe { GetDpiForSystem() };
+    let (width, height) = WINDOW_SIZE;
+
     let hwnd = unsafe {
         CreateWindowExW(
             WINDOW_EX_STYLE::default(),
             CLASS_NAME,
-            w!("speech"),
-            WS_OVERLAPPED | WS_CAPTION | WS_SYSMENU | WS_VISIBLE | WS_MINIMIZEBOX,
+            &strings::wide(Msg::AppName),
+            WS_OVERLAPPEDWINDOW,
             CW_USEDEFAULT,
             CW_USEDEFAULT,
-            600,
-            480,
+            dpi::scale(width, dpi),
+            dpi::scale(height, dpi),
             None,
             None,
             None,
             None,
         )?
     };
+    Ok(hwnd)
+}
+
+fn main() -> Result<()> {
+    unsafe { SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2)? };
+    // スクリーンリーダー向けのプロパティを設定するために COM を初期化する
+    unsafe { CoInitializeEx(None, COINIT_APARTMENTTHREADED).ok()? };
+    // オプションで WAV ファイルの作成だけを指示された場合は、ウィンドウを作らずに終了する
+    if cli::is_requested() {
+        std::process::exit(cli::run());
+    }
+    logging::init();
+    crash::install();
+    if let Err(e) = settings::activate_rules() {
+        logging::error("failed to load rules", &e);
+    }
+    // トースト通知とジャンプリストを同じアプリケーションとしてまとめる
+    toast::init()?;
+
+    let mut command_line = CommandLine::from_args();
+    let allow_multiple_instances = settings::get().allow_multiple_instances;
+    if !allow_multiple_instances && instance::forward_to_existing(CLASS_NAME, &command_line)? {
+        return Ok(());
+    }
+    command_line.speak_clipboard |= settings::get().speak_clipboard_at_launch;
+
+    let hwnd = create_main_window()?;
+
+    if settings::get().always_on_top {
+        set_topmost(hwnd, true)?;
+    }
 
-    unsafe { ShowWindow(hwnd, SW_SHOW).ok()? };
+    let show_cmd = restore_window_placement(hwnd).unwrap_or(SW_SHOW);
+    _ = unsafe { ShowWindow(hwnd, show_cmd) };
     unsafe { UpdateWindow(hwnd).ok()? };
 
+    // 関連付けやジャンプリストから起動された場合などは、引数のファイルやテキストを開く。
+    // 開けなかった場合もエラーを表示して、空のエディットで起動を続ける
+    let play = command_line.play;
+    if let Err(e) = open_command_line(hwnd, command_line, play) {
+        report_error(hwnd, Msg::ErrorOpenFile, &e);
+    }
+    // 見つからなくなったファイルを取り除いたので、ファイルメニューにも反映する
+    if jump_list::update().is_ok() {
+        update_recent_menu(hwnd).ok();
+    }
+
+    rebuild_accelerator_table()?;
     let mut msg = MSG::default();
 
     loop {
         if !unsafe { GetMessageW(&mut msg, None, 0, 0) }.as_bool() {
             break;
         }
+        // 検索・置換ダイアログへの入力は、メインウィンドウのショートカットより先に処理する
+        let dialog = AppState::get(hwnd).map_or(HWND::default(), |state| state.find_dialog.get());
+        if find_dialog::is_dialog_message(dialog, &msg) {
+            continue;
+        }
+        if unsafe { TranslateAcceleratorW(hwnd, ACCELERATORS.get(), &msg) } != 0 {
+            continue;
+        }
+        // Tab キーでのフォーカス移動や既定のボタンを処理する
+        if unsafe { IsDialogMessageW(hwnd, &msg) }.as_bool() {
+            continue;
+        }
         unsafe {
             _ = TranslateMessage(&msg);
             DispatchMessageW(&msg);
         }
     }
+    unsafe { DestroyAcceleratorTable(ACCELERATORS.take())? };
     Ok(())
 }
 
@@ -492,3 +4256,122 @@ fn makelong(a: u16, b: u16) -> i32 {
 fn loword(dword: u32) -> u16 {
     ((dword << 16) >> 16) as _
 }
+
+/// ヘルパー関数
+#[inline]
+fn hiword(dword: u32) -> u16 {
+    (dword >> 16) as _
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playback::Status;
+    use std::cell::RefCell;
+    use windows::Win32::UI::WindowsAndMessaging::{PeekMessageW, IDCANCEL, PM_REMOVE};
+
+    thread_local! {
+        /// メッセージボックスの代わりに記録した内容
+        static MESSAGES: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+    }
+
+    /// テストではメッセージボックスで止まらないように、表示する代わりに記録してキャンセルを返す
+    pub fn message_box(_hwnd: HWND, text: &str, _style: MESSAGEBOX_STYLE) -> MESSAGEBOX_RESULT {
+        MESSAGES.with_borrow_mut(|messages| messages.push(text.to_string()));
+        IDCANCEL
+    }
+
+    /// 溜まっているメッセージを処理する (終わらないタイマーなどで止まらないように回数を限る)
+    fn pump() {
+        let mut msg = MSG::default();
+        for _ in 0..1000 {
+            if !unsafe { PeekMessageW(&mut msg, None, 0, 0, PM_REMOVE) }.as_bool() {
+                break;
+            }
+            unsafe {
+                _ = TranslateMessage(&msg);
+                DispatchMessageW(&msg);
+            }
+        }
+    }
+
+    fn send_command(hwnd: HWND, id: u16) {
+        unsafe { SendMessageW(hwnd, WM_COMMAND, WPARAM(id as _), None) };
+        pump();
+    }
+
+    fn set_text(hwnd: HWND, text: &str) {
+        let edit = AppState::get(hwnd).unwrap().edit().unwrap();
+        let text = HSTRING::from(text);
+        unsafe { SendMessageW(edit, WM_SETTEXT, None, LPARAM(text.as_ptr() as _)) };
+        pump();
+    }
+
+    fn take_messages() -> Vec<String> {
+        MESSAGES.with_borrow_mut(mem::take)
+    }
+
+    #[test]
+    fn confirm_before_exit() {
+        assert_eq!(exit_confirmation(false, false), None);
+        assert_eq!(
+            exit_confirmation(true, false),
+            Some(Msg::ConfirmExitPlaying)
+        );
+        assert_eq!(exit_confirmation(false, true), Some(Msg::ConfirmExitSaving));
+        assert_eq!(exit_confirmation(true, true), Some(Msg::ConfirmExitSaving));
+    }
+
+    #[test]
+    fn recent_file_labels() {
+        let path = Path::new(r"C:\R&D\a.txt");
+        assert_eq!(recent_file_label(1, path), r"&1 C:\R&&D\a.txt");
+        assert_eq!(recent_file_label(10, path), r"1&0 C:\R&&D\a.txt");
+    }
+
+    #[test]
+    fn history_labels() {
+        assert_eq!(history_label(1, "\r\n  Q&A  \r\n次の行"), "&1 Q&&A");
+        assert_eq!(history_label(10, "はい"), "1&0 はい");
+        assert_eq!(
+            history_label(20, &"あ".repeat(41)),
+            format!("20 {}…", "あ".repeat(40))
+        );
+    }
+
+    /// 表示しないメインウィンドウでコマンドを順に実行する
+    ///
+    /// ウィンドウクラスやトレイアイコンなどを共有するので、一つのテストの中で続けて確かめる。
+    #[test]
+    fn drive_hidden_window() {
+        let hwnd = create_main_window().unwrap();
+        pump();
+        let state = AppState::get(hwnd).unwrap();
+        take_messages();
+
+        // インストールされている音声ではなく、偽物のエンジンの音声を並べて選ぶ
+        let voice = state.playback.engine().default_voice().unwrap();
+        assert_eq!(*state.voices.borrow(), [voice.clone()]);
+        assert_eq!(state.selected_voice().unwrap(), voice);
+
+        // テキストがなければ再生せずに知らせる
+        send_command(hwnd, ID_PLAY);
+        assert_eq!(state.playback.status(), Status::Idle);
+        assert_eq!(take_messages(), [tr(Msg::ErrorNoText)]);
+
+        // 偽物のエンジンは合成を終わらせないので、停止するまで再生中のまま
+        set_text(hwnd, "テスト");
+        assert_eq!(state.edit_string().unwrap(), "テスト");
+        send_command(hwnd, ID_PLAY);
+        assert_ne!(state.playback.status(), Status::Idle);
+        send_command(hwnd, ID_STOP);
+        assert_eq!(state.playback.status(), Status::Idle);
+
+        send_command(hwnd, ID_CLEAR);
+        assert_eq!(state.edit_string().unwrap(), "");
+        assert!(take_messages().is_empty());
+
+        unsafe { DestroyWindow(hwnd).unwrap() };
+        pump();
+    }
+}
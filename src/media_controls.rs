@@ -0,0 +1,121 @@
+use crate::playback::Status;
+use crate::ui_message::UiMessage;
+use anyhow::Result;
+use std::cell::RefCell;
+use windows::{
+    core::HSTRING,
+    Foundation::{EventRegistrationToken, TypedEventHandler},
+    Media::{
+        MediaPlaybackStatus, MediaPlaybackType, SystemMediaTransportControls,
+        SystemMediaTransportControlsButton, SystemMediaTransportControlsButtonPressedEventArgs,
+    },
+    Win32::UI::WindowsAndMessaging::{
+        APPCOMMAND_MEDIA_NEXTTRACK, APPCOMMAND_MEDIA_PAUSE, APPCOMMAND_MEDIA_PLAY,
+        APPCOMMAND_MEDIA_PLAY_PAUSE, APPCOMMAND_MEDIA_PREVIOUSTRACK, APPCOMMAND_MEDIA_STOP,
+    },
+};
+
+thread_local! {
+    /// ボタンのイベントを登録済みのシステムメディアトランスポートコントロール (最初に再生するときに登録する)
+    static CONTROLS: RefCell<Option<(SystemMediaTransportControls, EventRegistrationToken)>> =
+        const { RefCell::new(None) };
+}
+
+/// 読み上げるテキストの最初の行と音声の名前をメディアのフライアウトに表示し、ボタンを有効にする
+///
+/// ボタンやメディアキーが押されると [UiMessage::MediaButton] でウィンドウに知らせる。
+/// HWND はスレッドに送れないので handle は値で受け取る。
+pub fn show(
+    handle: isize,
+    controls: &SystemMediaTransportControls,
+    title: &str,
+    artist: &str,
+) -> Result<()> {
+    CONTROLS.with_borrow_mut(|registered| -> Result<()> {
+        if registered.is_none() {
+            // ButtonPressed は UI スレッド以外で呼ばれるので、ウィンドウに送って UI スレッドで処理する
+            let token = controls.ButtonPressed(&TypedEventHandler::new(
+                move |_: &Option<SystemMediaTransportControls>,
+                      args: &Option<SystemMediaTransportControlsButtonPressedEventArgs>| {
+                    if let Some(args) = args {
+                        UiMessage::MediaButton(args.Button()?).post(handle);
+                    }
+                    Ok(())
+                },
+            ))?;
+            *registered = Some((controls.clone(), token));
+        }
+        Ok(())
+    })?;
+    controls.SetIsEnabled(true)?;
+    controls.SetIsPlayEnabled(true)?;
+    controls.SetIsPauseEnabled(true)?;
+    controls.SetIsStopEnabled(true)?;
+    controls.SetIsNextEnabled(true)?;
+    controls.SetIsPreviousEnabled(true)?;
+    let updater = controls.DisplayUpdater()?;
+    updater.SetType(MediaPlaybackType::Music)?;
+    let music = updater.MusicProperties()?;
+    music.SetTitle(&HSTRING::from(title))?;
+    music.SetArtist(&HSTRING::from(artist))?;
+    updater.Update()?;
+    Ok(())
+}
+
+/// 再生の状態をフライアウトに反映する
+///
+/// 再生が終わったら、古い表示が残らないように表示を消してボタンを無効にする。
+pub fn update(status: Status) -> Result<()> {
+    CONTROLS.with_borrow(|registered| {
+        let Some((controls, _)) = registered else {
+            return Ok(());
+        };
+        match status {
+            Status::Idle => {
+                controls.SetPlaybackStatus(MediaPlaybackStatus::Stopped)?;
+                let updater = controls.DisplayUpdater()?;
+                updater.ClearAll()?;
+                updater.Update()?;
+                controls.SetIsEnabled(false)?;
+            }
+            Status::Paused => controls.SetPlaybackStatus(MediaPlaybackStatus::Paused)?,
+            Status::Synthesizing | Status::Playing => {
+                controls.SetPlaybackStatus(MediaPlaybackStatus::Playing)?
+            }
+        }
+        Ok(())
+    })
+}
+
+/// ボタンのイベントを外して、フライアウトの表示を消す (終了時に呼ぶ)
+pub fn detach() {
+    if let Some((controls, token)) = CONTROLS.take() {
+        _ = controls.RemoveButtonPressed(token);
+        _ = controls
+            .DisplayUpdater()
+            .and_then(|updater| updater.ClearAll());
+        _ = controls.SetIsEnabled(false);
+    }
+}
+
+/// WM_APPCOMMAND のメディアキーを、システムメディアトランスポートコントロールのボタンに読み替える
+///
+/// 再生と一時停止を兼ねるキーは、再生中なら一時停止、それ以外なら再生にする。
+pub fn button_for_app_command(
+    command: u32,
+    playing: bool,
+) -> Option<SystemMediaTransportControlsButton> {
+    let button = match command {
+        c if c == APPCOMMAND_MEDIA_PLAY_PAUSE.0 && playing => {
+            SystemMediaTransportControlsButton::Pause
+        }
+        c if c == APPCOMMAND_MEDIA_PLAY_PAUSE.0 => SystemMediaTransportControlsButton::Play,
+        c if c == APPCOMMAND_MEDIA_PLAY.0 => SystemMediaTransportControlsButton::Play,
+        c if c == APPCOMMAND_MEDIA_PAUSE.0 => SystemMediaTransportControlsButton::Pause,
+        c if c == APPCOMMAND_MEDIA_STOP.0 => SystemMediaTransportControlsButton::Stop,
+        c if c == APPCOMMAND_MEDIA_NEXTTRACK.0 => SystemMediaTransportControlsButton::Next,
+        c if c == APPCOMMAND_MEDIA_PREVIOUSTRACK.0 => SystemMediaTransportControlsButton::Previous,
+        _ => return None,
+    };
+    Some(button)
+}
@@ -0,0 +1,89 @@
+use crate::strings::{self, Msg};
+use anyhow::Result;
+use windows::{
+    core::HSTRING,
+    Win32::UI::WindowsAndMessaging::{
+        AppendMenuW, CheckMenuItem, CreateMenu, CreatePopupMenu, DeleteMenu, EnableMenuItem,
+        InsertMenuW, HMENU, MF_BYCOMMAND, MF_CHECKED, MF_ENABLED, MF_GRAYED, MF_POPUP,
+        MF_SEPARATOR, MF_STRING, MF_UNCHECKED,
+    },
+};
+
+/// メニュー項目
+pub enum Item {
+    /// コマンド ID とラベル
+    Command(u16, Msg),
+    /// サブメニュー
+    Submenu(Msg, Vec<Item>),
+    /// 区切り線
+    Separator,
+}
+
+/// メニューに項目を追加する
+fn append_items(menu: HMENU, items: &[Item]) -> Result<()> {
+    for item in items {
+        match item {
+            Item::Command(id, label) => unsafe {
+                AppendMenuW(menu, MF_STRING, *id as _, &strings::wide(*label))?
+            },
+            Item::Submenu(label, items) => {
+                let popup = create_popup(items)?;
+                unsafe { AppendMenuW(menu, MF_POPUP, popup.0 as _, &strings::wide(*label))? };
+            }
+            Item::Separator => unsafe { AppendMenuW(menu, MF_SEPARATOR, 0, None)? },
+        }
+    }
+    Ok(())
+}
+
+/// ポップアップメニューを生成する
+pub fn create_popup(items: &[Item]) -> Result<HMENU> {
+    let menu = unsafe { CreatePopupMenu()? };
+    append_items(menu, items)?;
+    Ok(menu)
+}
+
+/// メニューバーを生成する
+pub fn create_menu_bar(items: &[Item]) -> Result<HMENU> {
+    let menu = unsafe { CreateMenu()? };
+    append_items(menu, items)?;
+    Ok(menu)
+}
+
+/// メニュー項目のチェック状態を切り替える
+pub fn check_item(menu: HMENU, id: u16, check: bool) {
+    let flag = if check { MF_CHECKED } else { MF_UNCHECKED };
+    unsafe { CheckMenuItem(menu, id as _, (MF_BYCOMMAND | flag).0) };
+}
+
+/// メニュー項目の有効・無効を切り替える
+pub fn enable_item(menu: HMENU, id: u16, enable: bool) {
+    let flag = if enable { MF_ENABLED } else { MF_GRAYED };
+    _ = unsafe { EnableMenuItem(menu, id as _, MF_BYCOMMAND | flag) };
+}
+
+/// before の項目の前に、ラベルを指定してコマンドを挿入する (ファイル名など翻訳しないラベル向け)
+pub fn insert_item(menu: HMENU, before: u16, id: u16, label: &str) -> Result<()> {
+    let label = HSTRING::from(label);
+    unsafe { InsertMenuW(menu, before as _, MF_BYCOMMAND | MF_STRING, id as _, &label)? };
+    Ok(())
+}
+
+/// before の項目の前に、ID を付けた区切り線を挿入する (後で [delete_item] で取り除けるように)
+pub fn insert_separator(menu: HMENU, before: u16, id: u16) -> Result<()> {
+    unsafe {
+        InsertMenuW(
+            menu,
+            before as _,
+            MF_BYCOMMAND | MF_SEPARATOR,
+            id as _,
+            None,
+        )?
+    };
+    Ok(())
+}
+
+/// メニュー項目を取り除く。項目がなければ何もしない
+pub fn delete_item(menu: HMENU, id: u16) {
+    _ = unsafe { DeleteMenu(menu, id as _, MF_BYCOMMAND) };
+}
@@ -0,0 +1,295 @@
+//! 合成する前に日付や数値、URL を読みやすい形に整える
+//!
+//! それぞれの整形は `fn(&str, lang: &str) -> (String, OffsetMap)` で、lang は音声の言語 (`ja-JP` など) になる。
+//! エディットのテキストは変えずに、合成するテキストだけを整える。
+
+use crate::offsets::{self, OffsetMap};
+use regex::{Captures, Regex};
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+/// 合成する前に適用する整形
+static ACTIVE: RwLock<Options> = RwLock::new(Options {
+    dates: false,
+    numbers: false,
+    urls: false,
+});
+
+/// 年/月/日 (区切りは / - . のどれか)
+const DATE_PATTERN: &str = r"(\d{4})([/.-])(\d{1,2})([/.-])(\d{1,2})";
+/// 3 桁ごとに区切った数値
+const GROUPED_NUMBER_PATTERN: &str = r"\d{1,3}(?:,\d{3})+";
+/// http または https の URL (括弧や引用符で終わる)
+const URL_PATTERN: &str = r#"https?://[^\s/?#<>"'「」（）()]+[^\s<>"'「」（）()]*"#;
+
+/// 英語の月の名前
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// 整形 (テキストと音声の言語を受け取り、整えたテキストと元の位置に戻す対応を返す)
+type Normalizer = fn(&str, &str) -> (String, OffsetMap);
+
+/// 有効にする整形
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Options {
+    /// 日付を読み方に直す ([expand_dates])
+    pub dates: bool,
+    /// 数値の桁区切りを取り除く ([strip_digit_separators])
+    pub numbers: bool,
+    /// URL をドメインごとに読む ([spell_urls])
+    pub urls: bool,
+}
+
+impl Options {
+    /// 有効な整形を順に適用する
+    pub fn apply(&self, text: &str, lang: &str) -> String {
+        self.apply_mapped(text, lang).0
+    }
+
+    /// [Self::apply] と同じように整え、整えたテキストの位置を元のテキストの位置に戻す対応も返す
+    pub fn apply_mapped(&self, text: &str, lang: &str) -> (String, OffsetMap) {
+        let normalizers: [(bool, Normalizer); 3] = [
+            // URL の中の数字を日付や数値として読まないように、先に URL を整える
+            (self.urls, spell_urls),
+            (self.dates, expand_dates),
+            (self.numbers, strip_digit_separators),
+        ];
+        normalizers
+            .into_iter()
+            .filter(|(enabled, _)| *enabled)
+            .fold(
+                (text.to_string(), OffsetMap::default()),
+                |(text, map), (_, normalize)| {
+                    let (text, next) = normalize(&text, lang);
+                    (text, map.then(next))
+                },
+            )
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// 合成する前に適用する整形を差し替える
+pub fn set_active(options: Options) {
+    *ACTIVE.write().unwrap_or_else(PoisonError::into_inner) = options;
+}
+
+/// 合成する前に適用する整形
+pub fn active() -> Options {
+    *ACTIVE.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// 合成する前に適用する整形でテキストを整え、元のテキストの位置に戻す対応と一緒に返す
+pub fn apply_active(text: &[u16], lang: &str) -> (Vec<u16>, OffsetMap) {
+    let options = active();
+    if options.is_empty() {
+        return (text.to_vec(), OffsetMap::default());
+    }
+    let (text, map) = options.apply_mapped(&String::from_utf16_lossy(text), lang);
+    (text.encode_utf16().collect(), map)
+}
+
+/// パターンを一度だけコンパイルする
+fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).unwrap())
+}
+
+fn is_japanese(lang: &str) -> bool {
+    lang.get(..2)
+        .is_some_and(|code| code.eq_ignore_ascii_case("ja"))
+}
+
+/// 一致した部分の前後が数字や、数字に続く区切りでないか (長い数字の並びの一部には一致させない)
+fn is_standalone(text: &str, start: usize, end: usize, separators: &[char]) -> bool {
+    let before = text[..start].chars().next_back();
+    let mut after = text[end..].chars();
+    let (next, next2) = (after.next(), after.next());
+    !before.is_some_and(|c| c.is_ascii_digit() || separators.contains(&c))
+        && !next.is_some_and(|c| c.is_ascii_digit())
+        && (!next.is_some_and(|c| separators.contains(&c))
+            || !next2.is_some_and(|c| c.is_ascii_digit()))
+}
+
+/// 英語の序数 (1st, 2nd, 3rd, 4th, 11th など)
+fn ordinal(n: u32) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{n}{suffix}")
+}
+
+/// 2024/05/01 のような日付を、日本語なら「2024年5月1日」、英語なら「May 1st, 2024」にする
+///
+/// 月と日が範囲外のものや区切りがそろっていないものは日付とみなさない。ほかの言語では変えない。
+pub fn expand_dates(text: &str, lang: &str) -> (String, OffsetMap) {
+    let english = lang
+        .get(..2)
+        .is_some_and(|code| code.eq_ignore_ascii_case("en"));
+    if !is_japanese(lang) && !english {
+        return (text.to_string(), OffsetMap::default());
+    }
+    static DATE: OnceLock<Regex> = OnceLock::new();
+    offsets::replace_all(regex(&DATE, DATE_PATTERN), text, |caps: &Captures| {
+        let whole = caps.get(0).unwrap();
+        let year = &caps[1];
+        let (Ok(month @ 1..=12), Ok(day @ 1..=31)) = (caps[3].parse::<usize>(), caps[5].parse())
+        else {
+            return whole.as_str().to_string();
+        };
+        let standalone = is_standalone(text, whole.start(), whole.end(), &['/', '-', '.', ',']);
+        if caps[2] != caps[4] || !standalone {
+            return whole.as_str().to_string();
+        }
+        if english {
+            format!("{} {}, {year}", MONTHS[month - 1], ordinal(day))
+        } else {
+            format!("{year}年{month}月{day}日")
+        }
+    })
+}
+
+/// 1,234 のような 3 桁ごとの区切りを取り除き、一つの数として読ませる
+///
+/// 1,2,3 のような区切り方の違う並びは変えない。
+pub fn strip_digit_separators(text: &str, _lang: &str) -> (String, OffsetMap) {
+    static GROUPED_NUMBER: OnceLock<Regex> = OnceLock::new();
+    let grouped = regex(&GROUPED_NUMBER, GROUPED_NUMBER_PATTERN);
+    offsets::replace_all(grouped, text, |caps: &Captures| {
+        let whole = caps.get(0).unwrap();
+        if is_standalone(text, whole.start(), whole.end(), &[',']) {
+            whole.as_str().replace(',', "")
+        } else {
+            whole.as_str().to_string()
+        }
+    })
+}
+
+/// URL を一文字ずつではなく、ドメインとパスの区切りごとに読ませる
+///
+/// スキームとクエリーは読まない。`https://www.example.com/docs` は「www ドット example ドット com スラッシュ docs」になる。
+pub fn spell_urls(text: &str, lang: &str) -> (String, OffsetMap) {
+    let (dot, slash) = if is_japanese(lang) {
+        (" ドット ", " スラッシュ ")
+    } else {
+        (" dot ", " slash ")
+    };
+    static URL: OnceLock<Regex> = OnceLock::new();
+    offsets::replace_all(regex(&URL, URL_PATTERN), text, |caps: &Captures| {
+        let url = &caps[0];
+        let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let rest = rest.split(['?', '#']).next().unwrap_or_default();
+        let mut parts = rest.split('/').filter(|part| !part.is_empty());
+        let host = parts.next().unwrap_or_default();
+        // ポート番号と利用者名は読まない
+        let host = host.rsplit('@').next().unwrap_or(host);
+        let host = host.split(':').next().unwrap_or(host);
+        let host = host.split('.').collect::<Vec<_>>().join(dot);
+        let path = parts.collect::<Vec<_>>();
+        if path.is_empty() {
+            host
+        } else {
+            format!("{host}{slash}{}", path.join(slash))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(normalize: Normalizer, cases: &[(&str, &str, &str)]) {
+        for &(text, lang, expected) in cases {
+            assert_eq!(normalize(text, lang).0, expected, "{text} ({lang})");
+        }
+    }
+
+    #[test]
+    fn expand_dates_for_the_language() {
+        check(
+            expand_dates,
+            &[
+                ("2024/05/01に", "ja-JP", "2024年5月1日に"),
+                ("締切は2024-12-31です", "ja-JP", "締切は2024年12月31日です"),
+                ("on 2024/05/01.", "en-US", "on May 1st, 2024."),
+                ("2023.11.22", "en-GB", "November 22nd, 2023"),
+                ("2024/02/13", "en-US", "February 13th, 2024"),
+                // 日付でないものは変えない
+                ("2024/13/01", "ja-JP", "2024/13/01"),
+                ("2024/05-01", "ja-JP", "2024/05-01"),
+                ("12024/05/01", "ja-JP", "12024/05/01"),
+                ("2024/05/01/02", "ja-JP", "2024/05/01/02"),
+                ("2024/05/01", "fr-FR", "2024/05/01"),
+            ],
+        );
+    }
+
+    #[test]
+    fn strip_thousands_separators() {
+        check(
+            strip_digit_separators,
+            &[
+                ("1,234円", "ja-JP", "1234円"),
+                ("合計 12,345,678 件", "ja-JP", "合計 12345678 件"),
+                ("$1,000.50", "en-US", "$1000.50"),
+                // 3 桁ごとでない区切りは変えない
+                ("1,2,3", "en-US", "1,2,3"),
+                ("1,2345", "ja-JP", "1,2345"),
+                ("12,345,6", "ja-JP", "12,345,6"),
+                ("1234", "ja-JP", "1234"),
+            ],
+        );
+    }
+
+    #[test]
+    fn spell_urls_by_domain() {
+        check(
+            spell_urls,
+            &[
+                (
+                    "詳しくは https://www.example.com/docs/ を見てください",
+                    "ja-JP",
+                    "詳しくは www ドット example ドット com スラッシュ docs を見てください",
+                ),
+                (
+                    "See http://example.org:8080/a/b?q=1#top.",
+                    "en-US",
+                    "See example dot org slash a slash b",
+                ),
+                ("（https://example.jp）", "ja-JP", "（example ドット jp）"),
+                ("example.com", "en-US", "example.com"),
+            ],
+        );
+    }
+
+    #[test]
+    fn apply_only_enabled_normalizers() {
+        let text = "2024/05/01 に 1,234 人 https://example.com/2024/05/01";
+        assert_eq!(Options::default().apply(text, "ja-JP"), text);
+        let options = Options {
+            dates: true,
+            numbers: true,
+            urls: true,
+        };
+        assert_eq!(
+            options.apply(text, "ja-JP"),
+            "2024年5月1日 に 1234 人 example ドット com スラッシュ 2024 スラッシュ 05 スラッシュ 01"
+        );
+    }
+}
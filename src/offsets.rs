@@ -0,0 +1,183 @@
+//! 置き換えで長さが変わったテキストの位置を、元のテキストの位置に戻す
+//!
+//! 合成する前に整形や置換ルール、読み方の辞書を適用すると、音声合成エンジンが返す位置は置き換えた後の
+//! テキストでの位置になる。置き換えた区間を記録しておき、スピーチマークの位置を元のテキストに戻すのに使う。
+//! 位置はどれも UTF-16 単位。
+
+use regex::{Captures, Regex};
+
+/// 元のテキストの source の区間を、置き換えた後のテキストの target の区間にした置き換え (開始位置, 終了位置)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Edit {
+    source: (usize, usize),
+    target: (usize, usize),
+}
+
+/// 置き換えた後のテキストの位置から、元のテキストの位置を求める対応
+///
+/// 置き換えを何段も続けた場合は、段ごとに置き換えた区間を位置の順に並べて持つ。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OffsetMap(Vec<Vec<Edit>>);
+
+impl OffsetMap {
+    /// この置き換えの結果に、さらに next の置き換えを適用したときの対応
+    pub fn then(mut self, next: OffsetMap) -> Self {
+        self.0.extend(next.0);
+        self
+    }
+
+    /// 置き換えた後の開始位置を元のテキストの位置にする。置き換えた区間の途中なら、その区間の先頭にする
+    pub fn start(&self, pos: usize) -> usize {
+        self.0
+            .iter()
+            .rev()
+            .fold(pos, |pos, edits| map(edits, pos, false))
+    }
+
+    /// 置き換えた後の終了位置 (この位置は含まない) を元のテキストの位置にする。置き換えた区間の途中なら、その区間の末尾にする
+    pub fn end(&self, pos: usize) -> usize {
+        self.0
+            .iter()
+            .rev()
+            .fold(pos, |pos, edits| map(edits, pos, true))
+    }
+}
+
+/// 一段の置き換えで、置き換えた後の位置 pos を元のテキストの位置にする
+fn map(edits: &[Edit], pos: usize, end: bool) -> usize {
+    // pos までに終わった置き換えの数。消しただけの区間は、開始位置ならその後ろに、終了位置なら前に寄せる
+    let done = edits.partition_point(|edit| edit.target.1 < pos || (!end && edit.target.1 == pos));
+    if let Some(edit) = edits.get(done).filter(|edit| edit.target.0 < pos) {
+        return if end { edit.source.1 } else { edit.source.0 };
+    }
+    match done.checked_sub(1) {
+        Some(last) => pos - edits[last].target.1 + edits[last].source.1,
+        None => pos,
+    }
+}
+
+/// 置き換えた区間を記録しながら、置き換えた後のテキストを作る
+#[derive(Default)]
+pub struct Rewriter {
+    text: Vec<u16>,
+    edits: Vec<Edit>,
+    /// 元のテキストでどこまで進んだか
+    source: usize,
+}
+
+impl Rewriter {
+    /// 元のテキストの続きをそのまま使う
+    pub fn keep(&mut self, text: &[u16]) {
+        self.text.extend_from_slice(text);
+        self.source += text.len();
+    }
+
+    /// 元のテキストの続きの len 単位を replacement に置き換える
+    pub fn replace(&mut self, len: usize, replacement: &[u16]) {
+        let target = self.text.len();
+        self.edits.push(Edit {
+            source: (self.source, self.source + len),
+            target: (target, target + replacement.len()),
+        });
+        self.text.extend_from_slice(replacement);
+        self.source += len;
+    }
+
+    /// 置き換えた後のテキストと、元のテキストの位置に戻す対応
+    pub fn finish(self) -> (Vec<u16>, OffsetMap) {
+        (self.text, OffsetMap(vec![self.edits]))
+    }
+}
+
+/// regex に一致した部分を f が返す文字列に置き換え、元のテキストの位置に戻す対応と一緒に返す
+///
+/// [Regex::replace_all] と同じ部分に一致させる。一致しても同じ文字列を返した部分は、置き換えたことにしない。
+pub fn replace_all(
+    regex: &Regex,
+    text: &str,
+    mut f: impl FnMut(&Captures) -> String,
+) -> (String, OffsetMap) {
+    let mut result = String::with_capacity(text.len());
+    let mut edits = vec![];
+    // 一致した部分の終わり (UTF-8 単位) と、元のテキストと置き換えた後のテキストでの位置 (UTF-16 単位)
+    let (mut last, mut source, mut target) = (0, 0, 0);
+    for caps in regex.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        let kept = &text[last..whole.start()];
+        result.push_str(kept);
+        source += utf16_len(kept);
+        target += utf16_len(kept);
+        let replacement = f(&caps);
+        let (matched, replaced) = (utf16_len(whole.as_str()), utf16_len(&replacement));
+        if replacement != whole.as_str() {
+            edits.push(Edit {
+                source: (source, source + matched),
+                target: (target, target + replaced),
+            });
+        }
+        result.push_str(&replacement);
+        source += matched;
+        target += replaced;
+        last = whole.end();
+    }
+    result.push_str(&text[last..]);
+    (result, OffsetMap(vec![edits]))
+}
+
+/// UTF-16 にしたときの長さ
+fn utf16_len(text: &str) -> usize {
+    text.chars().map(char::len_utf16).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn map_positions_around_replacements() {
+        // 「ab」を「xyz」に、「d」を消す: abcde → xyzce
+        let mut rewriter = Rewriter::default();
+        rewriter.replace(2, &wide("xyz"));
+        rewriter.keep(&wide("c"));
+        rewriter.replace(1, &[]);
+        rewriter.keep(&wide("e"));
+        let (text, map) = rewriter.finish();
+        assert_eq!(text, wide("xyzce"));
+        // 置き換えていない文字は、ずれた分だけ戻す
+        assert_eq!((map.start(3), map.end(4)), (2, 3));
+        assert_eq!((map.start(4), map.end(5)), (4, 5));
+        // 消した「d」の位置で終わるなら、「c」の後ろで終わる
+        assert_eq!(map.end(4), 3);
+        // 置き換えた区間の途中は、その区間全体にする
+        assert_eq!((map.start(1), map.end(2)), (0, 2));
+        assert_eq!((map.start(0), map.end(3)), (0, 2));
+    }
+
+    #[test]
+    fn chain_replacements() {
+        let number = Regex::new(r"\d+").unwrap();
+        let (text, first) = replace_all(&number, "1 と 22", |caps| format!("<{}>", &caps[0]));
+        assert_eq!(text, "<1> と <22>");
+        let (text, second) = replace_all(&Regex::new("と").unwrap(), &text, |_| "and".into());
+        assert_eq!(text, "<1> and <22>");
+        let map = first.then(second);
+        // 「<22>」は元のテキストの「22」
+        assert_eq!((map.start(8), map.end(12)), (4, 6));
+        // 「and」は元のテキストの「と」
+        assert_eq!((map.start(4), map.end(7)), (2, 3));
+    }
+
+    #[test]
+    fn count_surrogate_pairs_as_two_units() {
+        let (text, map) = replace_all(&Regex::new("x").unwrap(), "😀x😀", |_| "abc".into());
+        assert_eq!(text, "😀abc😀");
+        assert_eq!((map.start(5), map.end(7)), (3, 5));
+        // 同じ文字列に置き換えた部分は、置き換えたことにしない
+        let (_, map) = replace_all(&Regex::new("x").unwrap(), "axb", |_| "x".into());
+        assert_eq!(map, OffsetMap(vec![vec![]]));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::dpi::{self, LogicalRect};
+use crate::labels::{self, Label, Side};
+use crate::settings;
+use crate::state::control;
+use crate::strings::{self, Msg};
+use anyhow::Result;
+use std::sync::OnceLock;
+use windows::{
+    core::{w, PCWSTR},
+    Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+        Graphics::Gdi::{GetSysColorBrush, InvalidateRect, COLOR_MENUBAR},
+        UI::WindowsAndMessaging::{
+            CreateWindowExW, DefWindowProcW, GetParent, MoveWindow, RegisterClassW, SendMessageW,
+            SetWindowTextW, BS_PUSHBUTTON, HMENU, WINDOW_EX_STYLE, WINDOW_STYLE, WM_COMMAND,
+            WM_ERASEBKGND, WM_HSCROLL, WM_NOTIFY, WM_PAINT, WNDCLASSW, WS_CHILD, WS_CLIPCHILDREN,
+            WS_EX_CONTROLPARENT, WS_TABSTOP, WS_VISIBLE,
+        },
+    },
+};
+
+/// 設定パネルのクラス名
+const CLASS_NAME: PCWSTR = w!("speech_panel_cls42");
+/// 設定パネルの ID
+pub const ID_PANEL: u16 = 5911;
+/// 設定パネルを折りたたむボタンの ID
+pub const ID_TOGGLE: u16 = 5910;
+/// 展開しているときの高さ (96 DPI 基準)
+const EXPANDED_HEIGHT: i32 = 52;
+/// 折りたたんでいるときの高さ (96 DPI 基準)
+const COLLAPSED_HEIGHT: i32 = 28;
+/// 折りたたみボタンの配置
+const TOGGLE_RECT: LogicalRect = LogicalRect::new(6, 4, 20, 20);
+/// コンボボックスの配置 (高さはドロップダウンを含む)
+pub const COMBO_RECT: LogicalRect = LogicalRect::new(32, 14, 190, 200);
+/// トラックバーの配置 (上側に現在の読み上げ速度を表示する)
+pub const TRACKBAR_RECT: LogicalRect = LogicalRect::new(340, 20, 190, 30);
+/// 再生する回数のエディットの配置 (アップダウンコントロールを右端に含む)
+pub const REPEAT_RECT: LogicalRect = LogicalRect::new(240, 20, 52, 22);
+/// 現在の読み上げ速度を表示するラベル
+const RATE_LABEL: Label = Label {
+    control: crate::ID_TRACKBAR,
+    side: Side::Above,
+    text: |panel| crate::rate_text(unsafe { GetParent(panel) }.ok()?).ok(),
+};
+/// 設定パネルに描画するラベル
+const LABELS: [Label; 5] = [
+    Label {
+        control: crate::ID_TRACKBAR,
+        side: Side::Left,
+        text: |_| Some(strings::tr(Msg::LabelSlow).to_string()),
+    },
+    RATE_LABEL,
+    Label {
+        control: crate::ID_REPEAT,
+        side: Side::Above,
+        text: |_| Some(strings::tr(Msg::LabelRepeat).to_string()),
+    },
+    Label {
+        control: crate::ID_TRACKBAR,
+        side: Side::Right,
+        text: |_| Some(strings::tr(Msg::LabelFast).to_string()),
+    },
+    // 折りたたんでいるときだけ見出しを表示する
+    Label {
+        control: ID_TOGGLE,
+        side: Side::Right,
+        text: |_| {
+            settings::get()
+                .panel_collapsed
+                .then(|| strings::tr(Msg::PanelTitle).to_string())
+        },
+    },
+];
+/// ウィンドウクラスを一度だけ登録するためのグローバル変数
+static REGISTERED: OnceLock<()> = OnceLock::new();
+
+/// 設定パネルを生成する
+///
+/// 音声や読み上げ速度のコントロールはこのウィンドウの子として生成する。
+pub fn create(parent: HWND) -> Result<HWND> {
+    REGISTERED.get_or_init(|| {
+        let wnd_class = WNDCLASSW {
+            lpfnWndProc: Some(wnd_proc),
+            lpszClassName: CLASS_NAME,
+            hbrBackground: unsafe { GetSysColorBrush(COLOR_MENUBAR) },
+            ..Default::default()
+        };
+        unsafe { RegisterClassW(&wnd_class) };
+    });
+    // WS_EX_CONTROLPARENT で子コントロールにも Tab キーでフォーカスを移せるようにする
+    let panel = unsafe {
+        CreateWindowExW(
+            WS_EX_CONTROLPARENT,
+            CLASS_NAME,
+            None,
+            WS_CHILD | WS_VISIBLE | WS_CLIPCHILDREN,
+            0,
+            0,
+            0,
+            0,
+            parent,
+            HMENU(ID_PANEL as _),
+            None,
+            None,
+        )?
+    };
+
+    let (x, y, width, height) = TOGGLE_RECT.scale(dpi::dpi_for_window(parent));
+    unsafe {
+        CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            w!("BUTTON"),
+            None,
+            WS_CHILD | WS_VISIBLE | WS_TABSTOP | WINDOW_STYLE(BS_PUSHBUTTON as _),
+            x,
+            y,
+            width,
+            height,
+            panel,
+            HMENU(ID_TOGGLE as _),
+            None,
+            None,
+        )?
+    };
+    set_collapsed(parent, settings::get().panel_collapsed)?;
+    Ok(panel)
+}
+
+/// 設定パネルの [HWND]
+pub fn handle(parent: HWND) -> Result<HWND> {
+    control(parent, ID_PANEL)
+}
+
+/// 折りたたみ状態に応じた設定パネルの高さ
+pub fn height(dpi: u32) -> i32 {
+    let height = if settings::get().panel_collapsed {
+        COLLAPSED_HEIGHT
+    } else {
+        EXPANDED_HEIGHT
+    };
+    dpi::scale(height, dpi)
+}
+
+/// 折りたたみボタンの表示を切り替えて再描画する
+pub fn set_collapsed(parent: HWND, collapsed: bool) -> Result<()> {
+    let panel = handle(parent)?;
+    let toggle = control(panel, ID_TOGGLE)?;
+    let label = if collapsed { w!("▼") } else { w!("▲") };
+    unsafe { SetWindowTextW(toggle, label)? };
+    _ = unsafe { InvalidateRect(panel, None, false) };
+    Ok(())
+}
+
+/// DPI に合わせて折りたたみボタンを配置し直す
+pub fn layout(parent: HWND, dpi: u32) -> Result<()> {
+    let panel = handle(parent)?;
+    let toggle = control(panel, ID_TOGGLE)?;
+    let (x, y, width, height) = TOGGLE_RECT.scale(dpi);
+    unsafe { MoveWindow(toggle, x, y, width, height, true)? };
+    _ = unsafe { InvalidateRect(panel, None, false) };
+    Ok(())
+}
+
+/// 読み上げ速度のラベルだけを再描画させる
+pub fn update_rate_label(parent: HWND) -> Result<()> {
+    labels::invalidate(handle(parent)?, &RATE_LABEL)
+}
+
+fn paint(hwnd: HWND) -> Result<()> {
+    let background = unsafe { GetSysColorBrush(COLOR_MENUBAR) };
+    labels::paint(hwnd, &LABELS, crate::UI_FONT.get(), background)
+}
+
+/// 設定パネルのウィンドウプロシージャ
+///
+/// 子コントロールからの通知はメインウィンドウに転送する。
+unsafe extern "system" fn wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_PAINT => {
+            if let Err(e) = paint(hwnd) {
+                crate::report_error(hwnd, Msg::ErrorPaint, &e);
+            }
+        }
+        // 背景は WM_PAINT でまとめて描画する
+        WM_ERASEBKGND => return LRESULT(1),
+        WM_COMMAND | WM_HSCROLL | WM_NOTIFY => {
+            if let Ok(parent) = GetParent(hwnd) {
+                return SendMessageW(parent, msg, wparam, lparam);
+            }
+        }
+        _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+    LRESULT::default()
+}
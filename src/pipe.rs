@@ -0,0 +1,357 @@
+use crate::logging;
+use anyhow::{bail, ensure, Context, Result};
+use speech::com;
+use speech::synthesis::{MAX_RATE, MIN_RATE};
+use std::os::windows::io::AsRawHandle;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use windows::{
+    core::{w, HSTRING, PCWSTR, PWSTR},
+    Win32::{
+        Foundation::{
+            CloseHandle, LocalFree, ERROR_PIPE_CONNECTED, HANDLE, HLOCAL, HWND, LPARAM, WPARAM,
+        },
+        Security::{
+            Authorization::{
+                ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW,
+                SDDL_REVISION_1,
+            },
+            GetTokenInformation, TokenUser, PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES, TOKEN_QUERY,
+            TOKEN_USER,
+        },
+        Storage::FileSystem::{
+            ReadFile, WriteFile, FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX,
+        },
+        System::{
+            Pipes::{
+                ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE,
+                PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_WAIT,
+            },
+            Threading::{GetCurrentProcess, OpenProcessToken},
+            IO::CancelSynchronousIo,
+        },
+        UI::WindowsAndMessaging::PostMessageW,
+    },
+};
+
+/// 名前付きパイプの名前
+pub const PIPE_NAME: PCWSTR = w!(r"\\.\pipe\speech");
+/// パイプの送受信バッファの大きさ
+const BUFFER_SIZE: u32 = 4096;
+/// 1 行のコマンドの最大バイト数
+const MAX_LINE: usize = 1024 * 1024;
+/// UI スレッドの応答を待つ間に終了要求を確かめる間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// 終了時に待ち受けスレッドの終了を待つ最大の時間
+const JOIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 待ち受けスレッド
+static SERVER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+/// 待ち受けを終了しようとしているかどうか
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// 名前付きパイプで受け付けるコマンド
+///
+/// UTF-8 で 1 行に一つずつ送り、`OK` または `ERR <理由>` の行が返る。
+///
+/// - `SPEAK <テキスト>` テキストを読み上げる
+/// - `STOP` 読み上げを停止する
+/// - `SAVE <パス> <テキスト>` テキストを WAV ファイルに保存する (空白を含むパスは `"` で囲む)
+/// - `RATE <速度>` 読み上げ速度を変更する (0.5 ～ 2.5)
+/// - `VOICE <名前>` 音声を表示名または ID で選ぶ
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Speak(String),
+    Stop,
+    Save(PathBuf, String),
+    Rate(f64),
+    Voice(String),
+}
+
+impl FromStr for Command {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match name.to_ascii_uppercase().as_str() {
+            "SPEAK" => {
+                ensure!(!rest.trim().is_empty(), "no text.");
+                Ok(Self::Speak(rest.to_string()))
+            }
+            "STOP" => Ok(Self::Stop),
+            "SAVE" => {
+                let (path, text) = split_path(rest)?;
+                ensure!(!text.trim().is_empty(), "no text.");
+                Ok(Self::Save(path.into(), text.to_string()))
+            }
+            "RATE" => {
+                let rate = rest
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("invalid rate: {rest}"))?;
+                ensure!(
+                    (MIN_RATE..=MAX_RATE).contains(&rate),
+                    "rate must be between {MIN_RATE} and {MAX_RATE}."
+                );
+                Ok(Self::Rate(rate))
+            }
+            "VOICE" => {
+                ensure!(!rest.trim().is_empty(), "no voice name.");
+                Ok(Self::Voice(rest.trim().to_string()))
+            }
+            _ => bail!("unknown command: {name}"),
+        }
+    }
+}
+
+/// `SAVE` の引数をパスとテキストに分ける
+fn split_path(args: &str) -> Result<(&str, &str)> {
+    let (path, text) = match args.strip_prefix('"') {
+        Some(rest) => rest.split_once('"').context("unterminated path.")?,
+        None => args.split_once(' ').context("no text.")?,
+    };
+    ensure!(!path.is_empty(), "no path.");
+    Ok((path, text.trim_start()))
+}
+
+/// UI スレッドで実行するコマンドと、結果を返す先
+pub struct Request {
+    pub command: Command,
+    reply: Sender<Result<()>>,
+}
+
+impl Request {
+    /// コマンドの結果を待ち受けスレッドに返す
+    pub fn reply(self, result: Result<()>) {
+        _ = self.reply.send(result);
+    }
+}
+
+/// 待ち受けスレッドを開始する。コマンドは msg の LPARAM に `Box<Request>` を入れて hwnd に送る
+///
+/// すでに開始している場合は何もしない。
+pub fn start(hwnd: HWND, msg: u32) -> Result<()> {
+    let mut server = SERVER.lock().unwrap();
+    if server.is_some() {
+        return Ok(());
+    }
+    let security = user_only_security_descriptor()?;
+    STOPPING.store(false, Ordering::Relaxed);
+    // HWND はスレッドに送れないので、値として渡す
+    let handle = hwnd.0 as isize;
+    *server = Some(thread::spawn(move || {
+        let _com = com::MtaGuard::new();
+        while !STOPPING.load(Ordering::Relaxed) {
+            if listen(&security, handle, msg).is_err() {
+                break;
+            }
+        }
+        _ = unsafe { LocalFree(HLOCAL(security.0 .0)) };
+    }));
+    Ok(())
+}
+
+/// 待ち受けを終了し、スレッドが終わるまで待つ
+pub fn stop() {
+    let Some(server) = SERVER.lock().unwrap().take() else {
+        return;
+    };
+    STOPPING.store(true, Ordering::Relaxed);
+    // 接続待ちや読み込みで止まっているスレッドを起こす
+    let thread = HANDLE(server.as_raw_handle());
+    let deadline = Instant::now() + JOIN_TIMEOUT;
+    while !server.is_finished() {
+        if Instant::now() >= deadline {
+            // 終了を妨げないよう、待ちきれないスレッドはそのまま残す
+            logging::info("the pipe server did not stop in time");
+            return;
+        }
+        _ = unsafe { CancelSynchronousIo(thread) };
+        thread::sleep(Duration::from_millis(10));
+    }
+    _ = server.join();
+}
+
+/// セキュリティ記述子をスレッドに渡すためのラッパ構造体
+struct SecurityDescriptor(PSECURITY_DESCRIPTOR);
+
+unsafe impl Send for SecurityDescriptor {}
+
+/// 現在のユーザーだけが接続できるセキュリティ記述子を作る
+fn user_only_security_descriptor() -> Result<SecurityDescriptor> {
+    let sid = current_user_sid()?;
+    let sddl = HSTRING::from(format!("D:P(A;;GA;;;{sid})"));
+    let mut descriptor = PSECURITY_DESCRIPTOR::default();
+    unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            &sddl,
+            SDDL_REVISION_1,
+            &mut descriptor,
+            None,
+        )?
+    };
+    Ok(SecurityDescriptor(descriptor))
+}
+
+/// 現在のユーザーの SID を文字列で取得する
+fn current_user_sid() -> Result<String> {
+    let mut token = HANDLE::default();
+    unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token)? };
+    let mut len = 0;
+    _ = unsafe { GetTokenInformation(token, TokenUser, None, 0, &mut len) };
+    let mut buf = vec![0u8; len as usize];
+    let ret = unsafe {
+        GetTokenInformation(token, TokenUser, Some(buf.as_mut_ptr() as _), len, &mut len)
+    };
+    _ = unsafe { CloseHandle(token) };
+    ret?;
+    let user = unsafe { &*(buf.as_ptr() as *const TOKEN_USER) };
+    let mut sid = PWSTR::null();
+    unsafe { ConvertSidToStringSidW(user.User.Sid, &mut sid)? };
+    let text = unsafe { sid.to_string() };
+    _ = unsafe { LocalFree(HLOCAL(sid.0 as _)) };
+    Ok(text?)
+}
+
+/// パイプを作って一つのクライアントの接続を待ち、切断されるまでコマンドを処理する
+fn listen(security: &SecurityDescriptor, handle: isize, msg: u32) -> Result<()> {
+    let attributes = SECURITY_ATTRIBUTES {
+        nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as _,
+        lpSecurityDescriptor: security.0 .0,
+        bInheritHandle: false.into(),
+    };
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            PIPE_NAME,
+            PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            BUFFER_SIZE,
+            BUFFER_SIZE,
+            0,
+            Some(&attributes),
+        )
+    };
+    if pipe.is_invalid() {
+        return Err(windows::core::Error::from_win32().into());
+    }
+    let connected = match unsafe { ConnectNamedPipe(pipe, None) } {
+        Ok(()) => true,
+        Err(e) => e.code() == ERROR_PIPE_CONNECTED.to_hresult(),
+    };
+    if connected && !STOPPING.load(Ordering::Relaxed) {
+        // クライアントごとのエラーは切断して次の接続を待つ
+        _ = serve(pipe, handle, msg);
+    }
+    unsafe {
+        _ = DisconnectNamedPipe(pipe);
+        _ = CloseHandle(pipe);
+    }
+    Ok(())
+}
+
+/// 接続したクライアントから 1 行ずつコマンドを読み、結果を返す
+fn serve(pipe: HANDLE, handle: isize, msg: u32) -> Result<()> {
+    let mut pending = vec![];
+    let mut buf = [0u8; BUFFER_SIZE as usize];
+    loop {
+        let mut read = 0;
+        unsafe { ReadFile(pipe, Some(&mut buf), Some(&mut read), None)? };
+        if read == 0 {
+            return Ok(());
+        }
+        pending.extend_from_slice(&buf[..read as usize]);
+        while let Some(pos) = pending.iter().position(|&b| b == b'\n') {
+            let line = pending.drain(..=pos).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_start_matches('\u{feff}').trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            let response = match execute(line, handle, msg) {
+                Ok(()) => "OK\n".to_string(),
+                Err(e) => format!("ERR {}\n", format!("{e:#}").replace(['\r', '\n'], " ")),
+            };
+            write(pipe, response.as_bytes())?;
+        }
+        if pending.len() > MAX_LINE {
+            write(pipe, b"ERR too long command.\n")?;
+            bail!("too long command.");
+        }
+    }
+}
+
+/// コマンドを UI スレッドに送り、結果を待つ
+fn execute(line: &str, handle: isize, msg: u32) -> Result<()> {
+    let command = line.parse()?;
+    let (tx, rx) = mpsc::channel();
+    let request = Box::into_raw(Box::new(Request { command, reply: tx }));
+    if let Err(e) = unsafe { PostMessageW(HWND(handle as _), msg, WPARAM(0), LPARAM(request as _)) }
+    {
+        drop(unsafe { Box::from_raw(request) });
+        return Err(e.into());
+    }
+    loop {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(result) => return result,
+            Err(RecvTimeoutError::Timeout) if !STOPPING.load(Ordering::Relaxed) => (),
+            Err(_) => bail!("the app is closing."),
+        }
+    }
+}
+
+fn write(pipe: HANDLE, bytes: &[u8]) -> Result<()> {
+    unsafe { WriteFile(pipe, Some(bytes), None, None)? };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            "SPEAK こんにちは 世界".parse::<Command>().unwrap(),
+            Command::Speak("こんにちは 世界".into())
+        );
+        assert_eq!("stop".parse::<Command>().unwrap(), Command::Stop);
+        assert_eq!("RATE 1.5".parse::<Command>().unwrap(), Command::Rate(1.5));
+        assert_eq!(
+            "VOICE Microsoft Haruka".parse::<Command>().unwrap(),
+            Command::Voice("Microsoft Haruka".into())
+        );
+    }
+
+    #[test]
+    fn parse_save_paths() {
+        assert_eq!(
+            r"SAVE C:\out.wav hello".parse::<Command>().unwrap(),
+            Command::Save(r"C:\out.wav".into(), "hello".into())
+        );
+        assert_eq!(
+            r#"SAVE "C:\my files\out.wav" hello world"#.parse::<Command>().unwrap(),
+            Command::Save(r"C:\my files\out.wav".into(), "hello world".into())
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        for line in [
+            "SPEAK",
+            "SAVE out.wav",
+            r#"SAVE "out.wav hello"#,
+            "RATE 3",
+            "RATE x",
+            "VOICE",
+            "PLAY",
+        ] {
+            assert!(line.parse::<Command>().is_err(), "{line}");
+        }
+    }
+}
@@ -0,0 +1,1830 @@
+use crate::logging;
+use crate::strings::{tr, Msg};
+use anyhow::{anyhow, Context, Result};
+use speech::error::SpeechError;
+use speech::speech_marks::{self, Kind};
+use speech::synthesis::{self, SynthOptions};
+use speech::{chunk, com, unwind, worker};
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use windows::{
+    core::{IUnknown, Interface, HSTRING},
+    Foundation::{EventRegistrationToken, IAsyncOperation, TimeSpan, TypedEventHandler},
+    Media::{
+        Core::MediaSource,
+        Playback::{
+            IMediaPlaybackSource, MediaPlaybackAudioCategory, MediaPlayer, MediaPlayerError,
+            MediaPlayerFailedEventArgs,
+        },
+        SpeechSynthesis::{SpeechSynthesisStream, SpeechSynthesizer, VoiceInformation},
+        SystemMediaTransportControls,
+    },
+};
+
+/// 合成の完了ハンドラやメディアのイベントと共有する再生の状態
+///
+/// [MediaPlayer] は一つを使い回し、再生ごとにソースだけを差し替える。
+/// 長いテキストは区切って、前のチャンクを再生している間に次のチャンクを合成しておく。
+/// 合成と再生の操作は [Engine] を通して行う。
+#[derive(Default)]
+pub struct Playback<E: Engine = WinRt> {
+    /// 合成と再生に使うエンジン
+    engine: E,
+    /// 合成中または再生中のスピーチ
+    current: Mutex<Option<Speech<E>>>,
+    /// 使い回すプレーヤー (最初に再生するときに作る)
+    player: Mutex<Option<E::Player>>,
+    /// 最後まで再生したスピーチの合成結果 (メモリを使いすぎないように一つだけ持つ)
+    cache: Mutex<Option<Cached<E>>>,
+    /// 一つのスピーチを繰り返し再生する回数 (0 は 1 回とみなす)
+    repeats: AtomicU32,
+    /// 音量のフェードを始めるたびに増やす (前のフェードを途中でやめるのに使う)
+    fade: AtomicU32,
+    /// 再生中は他のアプリケーションの音声を小さくする (ダッキング)
+    ducking: AtomicBool,
+}
+
+/// 再生時間に対して、止まったとみなすまでに待つ倍率
+const WATCHDOG_FACTOR: u32 = 2;
+/// 再生時間に加えて待つ時間
+const WATCHDOG_SLACK: Duration = Duration::from_secs(10);
+/// 再生時間がわからない場合に待つ時間
+const WATCHDOG_FALLBACK: Duration = Duration::from_secs(30 * 60);
+/// 停止するときに音量を下げていく時間
+const FADE_OUT: Duration = Duration::from_millis(250);
+/// 再生を始めるときに音量を上げていく時間
+const FADE_IN: Duration = Duration::from_millis(80);
+/// フェードで音量を変える回数
+const FADE_STEPS: u32 = 10;
+/// 文の始まりからこの時間のうちに前の文に移ると、その文の始まりではなく一つ前の文に戻る
+const SKIP_BACK_GRACE: Duration = Duration::from_secs(2);
+/// [MediaPlayer] の音量 (読み上げの音量は合成の設定で変えるので、プレーヤーはいつも最大にしておく)
+const FULL_VOLUME: f64 = 1.0;
+
+/// アプリケーションの再生に使うエンジン
+///
+/// テストでは WinRT で合成も再生もしない偽物に差し替えて、ウィンドウの操作だけを確かめる。
+/// 音声の一覧も偽物が返すので、インストールされている音声には左右されない。
+#[cfg(not(test))]
+pub type AppEngine = WinRt;
+#[cfg(test)]
+pub type AppEngine = tests::Fake<(), SpeechSynthesisStream>;
+
+/// アプリケーションの再生に使う音声合成エンジン
+pub type AppSynth = <AppEngine as Engine>::Synth;
+
+/// 音声の一覧に並べる音声
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Voice {
+    pub id: HSTRING,
+    /// コンボボックスに表示する名前
+    pub name: HSTRING,
+    /// 音声の言語 (ja-JP など)
+    pub language: String,
+}
+
+/// [Playback] が使う合成と再生の操作
+///
+/// プレーヤーのイベントは、作るときに渡した [Events] のハンドラで受け取る。
+/// [Playback] はロックを持ったまま操作するので、[Self::on_completed] 以外の操作の中でハンドラを呼び出してはいけない。
+pub trait Engine: Default + Clone + Send + Sync + 'static {
+    /// 音声合成エンジン
+    type Synth: Clone + Send + 'static;
+    /// 合成したチャンク
+    type Stream: Clone + Send + 'static;
+    /// 合成中のチャンク
+    type Operation: Clone + Send + 'static;
+    /// プレーヤーで再生するチャンクのソース
+    type Source: Clone + Send + 'static;
+    /// イベントハンドラを登録済みのプレーヤー
+    type Player: Clone + Send + 'static;
+
+    /// インストールされている音声 (一つもない環境では空)
+    fn voices(&self) -> Result<Vec<Voice>>;
+    fn default_voice(&self) -> Result<Voice>;
+    /// options の音声と速度で合成する音声合成エンジンを作る
+    fn create_synthesizer(&self, options: &SynthOptions) -> Result<Self::Synth>;
+    /// 音声合成エンジンの音声と速度を options に合わせる
+    fn apply_options(&self, synth: &Self::Synth, options: &SynthOptions) -> Result<()>;
+    /// synth に設定している音声
+    fn voice(&self, synth: &Self::Synth) -> Result<Voice>;
+    /// synth に設定している読み上げ速度
+    fn speaking_rate(&self, synth: &Self::Synth) -> Result<f64>;
+    /// text の合成を始める (完了は [Self::on_completed] で受け取る)
+    fn synthesize(&self, synth: &Self::Synth, text: &[u16]) -> Result<Self::Operation>;
+    /// 合成が終わったら結果を渡して f を呼び出す。すでに終わっていればこの中で呼び出す
+    fn on_completed(
+        &self,
+        operation: &Self::Operation,
+        f: Box<dyn FnOnce(Result<Self::Stream>) + Send>,
+    ) -> Result<()>;
+    fn cancel(&self, operation: &Self::Operation);
+    /// 合成したチャンクで各文が始まる時刻 (文の境界がわからなければ空)
+    fn sentence_starts(&self, stream: &Self::Stream) -> Vec<Duration>;
+
+    fn create_player(&self, events: Events<Self::Source>) -> Result<Self::Player>;
+    /// イベントハンドラを外してプレーヤーを閉じる
+    fn close_player(&self, player: &Self::Player);
+    fn media_controls(&self, player: &Self::Player) -> Result<SystemMediaTransportControls>;
+    /// 合成したチャンクを再生するソースを作る
+    fn open(&self, stream: &Self::Stream) -> Result<Self::Source>;
+    /// ソースを差し替える (None なら外す)。ducking なら再生中は他のアプリケーションの音声を小さくする
+    fn set_source(
+        &self,
+        player: &Self::Player,
+        source: Option<&Self::Source>,
+        ducking: bool,
+    ) -> Result<()>;
+    /// 二つのソースが同じものかどうか
+    fn same_source(&self, a: &Self::Source, b: &Self::Source) -> bool;
+    fn play(&self, player: &Self::Player) -> Result<()>;
+    fn pause(&self, player: &Self::Player) -> Result<()>;
+    fn volume(&self, player: &Self::Player) -> Result<f64>;
+    fn set_volume(&self, player: &Self::Player, volume: f64) -> Result<()>;
+    /// 再生中のチャンクをどこまで再生したか
+    fn position(&self, player: &Self::Player) -> Result<Duration>;
+    fn set_position(&self, player: &Self::Player, position: Duration) -> Result<()>;
+    /// 再生中のチャンクの長さ
+    fn duration(&self, player: &Self::Player) -> Result<Duration>;
+    fn set_playback_rate(&self, player: &Self::Player, rate: f64) -> Result<()>;
+}
+
+/// プレーヤーのイベントで呼び出すハンドラ
+///
+/// どちらにも、イベントが起きたときにプレーヤーが再生していたソースを渡す。
+pub struct Events<S> {
+    /// ソースを開いて、チャンクの長さがわかった
+    pub opened: Box<dyn Fn(Option<S>) + Send + Sync>,
+    /// チャンクを最後まで再生したか、再生に失敗した
+    pub ended: Box<dyn Fn(Option<S>, Result<()>) + Send + Sync>,
+}
+
+/// 合成し直さずに使い回す合成結果
+struct Cached<E: Engine> {
+    /// テキストと合成の設定から求めたキー ([synthesis::cache_key])
+    key: u64,
+    chunks: Vec<E::Stream>,
+}
+
+/// 再生の状態
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// スピーチがない
+    Idle,
+    /// 最初のチャンクを合成している
+    Synthesizing,
+    Playing,
+    Paused,
+}
+
+/// 合成中または再生中のスピーチ
+struct Speech<E: Engine> {
+    /// 再生ごとに割り振った番号
+    id: usize,
+    /// トラックバーで選んでいる読み上げ速度 (合成したときの速度と違えば、再生速度を変えて合わせる)
+    rate: f64,
+    /// 各チャンクを合成したときの読み上げ速度
+    chunk_rates: Vec<f64>,
+    /// まだ合成を始めていないチャンクと、それを合成する音声合成エンジン
+    pending: VecDeque<(E::Synth, Vec<u16>)>,
+    /// 各チャンクが段落の終わりかどうか (終わりなら次のチャンクの前に間を空ける)
+    paragraph_ends: Vec<bool>,
+    /// 合成中のチャンク (停止したときに取り消す)
+    operation: Option<E::Operation>,
+    /// 合成し終わって、再生中のチャンクが終わるのを待っているチャンク
+    ready: VecDeque<E::Stream>,
+    /// 合成結果を使い回すためのキー
+    key: u64,
+    /// 再生を始めたチャンク (最後まで再生したら使い回せるように取っておく)
+    played: Vec<E::Stream>,
+    /// 再生中のチャンクのソース
+    source: Option<E::Source>,
+    /// 再生中のチャンクで各文が始まる時刻 (前後の文に移るのに使う)
+    sentences: Vec<Duration>,
+    /// 一時停止しているかどうか
+    paused: bool,
+    /// 段落の終わりのチャンクを再生し終わって、次のチャンクの前の間を待っている
+    waiting: bool,
+    /// 再生中のチャンクが終わらないまま止まっていないか見張る
+    watchdog: Watchdog,
+    /// 何回目の再生か (1 から数える)
+    round: u32,
+    /// 再生が始まったことをまだ知らせていない回
+    unannounced: Option<u32>,
+    /// 各回の再生が始まったときに、何回目かを渡して呼び出す
+    started: Arc<dyn Fn(u32) + Send + Sync>,
+    /// 終わったときに一度だけ呼び出して結果を知らせる
+    finished: Box<dyn FnOnce(Result<()>) + Send>,
+}
+
+impl<E: Engine> Playback<E> {
+    /// 区切ったチャンクの合成を始めてスピーチを登録する。それまでのスピーチは停止する
+    ///
+    /// チャンクはそれぞれ組にした音声合成エンジンで合成する ([synthesis::voiced_chunks] で区切る)。
+    /// 合成が終わると再生を始め、最後のチャンクを再生し終わると finished で知らせる。
+    /// 同じ key の合成結果が残っていれば、合成し直さずにすぐ再生する。
+    /// [Self::set_repeats] で回数を指定していれば、合成結果を使い回して繰り返す。
+    /// 段落の終わりのチャンクの後は、[synthesis::paragraph_pause] だけ待ってから次のチャンクを再生する。
+    /// 読み上げ速度は最初のチャンクの音声合成エンジンの速度を使う。
+    pub fn begin(
+        self: &Arc<Self>,
+        id: usize,
+        chunks: Vec<(E::Synth, Vec<u16>)>,
+        key: u64,
+        started: impl Fn(u32) + Send + Sync + 'static,
+        finished: impl FnOnce(Result<()>) + Send + 'static,
+    ) -> Result<()> {
+        let cached = self.cached(key);
+        let (first, _) = chunks.first().context("no text to speak.")?;
+        let rate = self.engine.speaking_rate(first)?;
+        let paragraph_ends = chunks
+            .iter()
+            .map(|(_, c)| chunk::ends_paragraph(c))
+            .collect();
+        let pending = match cached {
+            Some(_) => VecDeque::new(),
+            None => VecDeque::from(chunks),
+        };
+        self.stop_all();
+        let chunk_rates = match &cached {
+            Some(cached) => vec![rate; cached.len()],
+            None => vec![],
+        };
+        let mut speech = Speech {
+            id,
+            rate,
+            chunk_rates,
+            pending,
+            paragraph_ends,
+            operation: None,
+            ready: VecDeque::from(cached.unwrap_or_default()),
+            key,
+            played: vec![],
+            source: None,
+            sentences: vec![],
+            paused: false,
+            waiting: false,
+            watchdog: Watchdog::default(),
+            round: 1,
+            unannounced: Some(1),
+            started: Arc::new(started),
+            finished: Box::new(finished),
+        };
+        if let Some(first) = speech.ready.pop_front() {
+            // イベントが登録より先に届かないように、ロックしたまま再生を始める
+            let mut current = lock(&self.current);
+            self.play_chunk(&mut speech, &first)?;
+            *current = Some(speech);
+            return Ok(());
+        }
+        let operation = speech
+            .synthesize_next(&self.engine)?
+            .context("no text to speak.")?;
+        // 合成中でも停止できるように、完了を待つ前に登録しておく
+        *lock(&self.current) = Some(speech);
+        self.continue_or_finish(id, Ok(Some(operation)));
+        Ok(())
+    }
+
+    /// id のスピーチを終わらせて結果を知らせる (すでに停止されていれば何もしない)
+    fn finish(&self, id: usize, result: Result<()>) {
+        let speech = {
+            let mut current = lock(&self.current);
+            match current.as_ref() {
+                Some(speech) if speech.id == id => current.take(),
+                _ => None,
+            }
+        };
+        if let Some(speech) = speech {
+            self.close_speech(speech, result);
+        }
+    }
+
+    /// 合成中の処理を取り消し、再生中のスピーチを停止する
+    ///
+    /// 途中で切ると音がぷつっと鳴るので、再生中なら音量を下げてから止める。
+    /// スピーチは先に外しておくので、フェード中に届いたイベントや続けての停止は無視される。
+    pub fn stop_all(&self) {
+        let speech = lock(&self.current).take();
+        if let Some(speech) = speech {
+            if speech.source.is_some() && !speech.paused {
+                self.fade_out();
+            }
+            self.close_speech(speech, Ok(()));
+        }
+    }
+
+    /// プレーヤーの音量を今の音量から 0 まで下げる (終わるまで戻らない)
+    fn fade_out(&self) {
+        // 再生を始めたときのフェードが残っていれば止める
+        self.fade.fetch_add(1, Ordering::Relaxed);
+        let Some(player) = lock(&self.player).clone() else {
+            return;
+        };
+        let from = self.engine.volume(&player).unwrap_or(FULL_VOLUME);
+        let set_volume = |volume| _ = self.engine.set_volume(&player, volume);
+        fade(set_volume, from, 0.0, FADE_OUT, || false);
+    }
+
+    /// プレーヤーの音量を 0 から上げていく
+    ///
+    /// 再生を止めないように別のスレッドで上げ、途中で停止や次のフェードが始まったらやめる。
+    fn fade_in(self: &Arc<Self>, player: &E::Player) -> Result<()> {
+        let generation = self.fade.fetch_add(1, Ordering::Relaxed) + 1;
+        self.engine.set_volume(player, 0.0)?;
+        let engine = self.engine.clone();
+        let player = player.clone();
+        let playback = Arc::downgrade(self);
+        worker::spawn(move || {
+            let set_volume = |volume| _ = engine.set_volume(&player, volume);
+            let Ok(_guard) = com::MtaGuard::new() else {
+                set_volume(FULL_VOLUME);
+                return;
+            };
+            fade(set_volume, 0.0, FULL_VOLUME, FADE_IN, || {
+                playback
+                    .upgrade()
+                    .is_none_or(|p| p.fade.load(Ordering::Relaxed) != generation)
+            });
+        });
+        Ok(())
+    }
+
+    /// 合成中または再生中のスピーチがあるかどうか
+    pub fn is_speaking(&self) -> bool {
+        lock(&self.current).is_some()
+    }
+
+    /// key の合成結果が残っていればチャンクを返す
+    pub fn cached(&self, key: u64) -> Option<Vec<E::Stream>> {
+        lock(&self.cache)
+            .as_ref()
+            .filter(|cached| cached.key == key)
+            .map(|cached| cached.chunks.clone())
+    }
+
+    /// テキストや設定が変わったら、残っている合成結果を捨てる
+    pub fn clear_cache(&self) {
+        *lock(&self.cache) = None;
+    }
+
+    /// 最後まで再生したスピーチの合成結果を、それまでのものと入れ替えて取っておく
+    fn store_cache(&self, key: u64, chunks: Vec<E::Stream>) {
+        *lock(&self.cache) = Some(Cached { key, chunks });
+    }
+
+    /// 読み上げ速度を変える。再生中なら、合成し直さずに再生速度を変えて合わせる
+    ///
+    /// 再生速度を変えると声の高さも少し変わる。これから合成するチャンクは新しい速度で合成するので、
+    /// そのチャンクからは元の再生速度に戻る。スピーチがなければ何もしない。
+    pub fn set_rate(&self, rate: f64) -> Result<()> {
+        let mut current = lock(&self.current);
+        let Some(speech) = current.as_mut() else {
+            return Ok(());
+        };
+        speech.rate = rate;
+        if speech.source.is_none() {
+            return Ok(());
+        }
+        let player = lock(&self.player);
+        let player = player.as_ref().context("no media player.")?;
+        let playback_rate = speech.playback_rate();
+        self.engine.set_playback_rate(player, playback_rate)?;
+        // 残りを再生し終わるまでの時間が変わるので、期限を見直す
+        let remaining = self
+            .engine
+            .duration(player)?
+            .saturating_sub(self.engine.position(player)?);
+        speech
+            .watchdog
+            .start(Instant::now(), Some(remaining.div_f64(playback_rate)));
+        Ok(())
+    }
+
+    /// 再生の状態
+    pub fn status(&self) -> Status {
+        match lock(&self.current).as_ref() {
+            None => Status::Idle,
+            Some(speech) if speech.source.is_none() && !speech.waiting => Status::Synthesizing,
+            Some(speech) if speech.paused => Status::Paused,
+            Some(_) => Status::Playing,
+        }
+    }
+
+    /// 一つのスピーチを繰り返し再生する回数を変える
+    ///
+    /// 再生中のスピーチにも、その回が終わったときから反映する。
+    pub fn set_repeats(&self, repeats: u32) {
+        self.repeats.store(repeats, Ordering::Relaxed);
+    }
+
+    /// 他のアプリケーションの音声を小さくするかどうかを変える
+    ///
+    /// 再生中のチャンクには反映せず、次に再生するチャンクから反映する。
+    pub fn set_ducking(&self, ducking: bool) {
+        self.ducking.store(ducking, Ordering::Relaxed);
+    }
+
+    /// 一つのスピーチを繰り返し再生する回数
+    pub fn repeats(&self) -> u32 {
+        self.repeats.load(Ordering::Relaxed).max(1)
+    }
+
+    /// 再生中のスピーチが何回目か (スピーチがなければ 1)
+    pub fn round(&self) -> u32 {
+        lock(&self.current)
+            .as_ref()
+            .map_or(1, |speech| speech.round)
+    }
+
+    /// 再生中のチャンクの番号と、そのチャンクをどこまで再生したか (0.0 ～ 1.0)
+    ///
+    /// 次のチャンクの合成を待っている間は、次のチャンクの先頭とする。まだ再生を始めていなければ None を返す。
+    pub fn progress(&self) -> Option<(usize, f64)> {
+        let current = lock(&self.current);
+        let speech = current
+            .as_ref()
+            .filter(|speech| !speech.played.is_empty())?;
+        if speech.source.is_none() {
+            return Some((speech.played.len(), 0.0));
+        }
+        let fraction = lock(&self.player)
+            .as_ref()
+            .and_then(|player| {
+                Some((
+                    self.engine.position(player).ok()?,
+                    self.engine.duration(player).ok()?,
+                ))
+            })
+            .filter(|(_, duration)| !duration.is_zero())
+            .map_or(0.0, |(position, duration)| {
+                position.as_secs_f64() / duration.as_secs_f64()
+            });
+        Some((speech.played.len() - 1, fraction.clamp(0.0, 1.0)))
+    }
+
+    /// スピーチ全体をどこまで再生したか (0.0 ～ 1.0)。チャンクの数で割ったおおよその値
+    ///
+    /// まだ再生を始めていなければ None を返す。
+    pub fn overall_progress(&self) -> Option<f64> {
+        let (index, fraction) = self.progress()?;
+        let total = lock(&self.current).as_ref().map_or(0, |speech| {
+            speech.played.len()
+                + speech.ready.len()
+                + speech.pending.len()
+                + speech.operation.is_some() as usize
+        });
+        Some(((index as f64 + fraction) / total.max(1) as f64).min(1.0))
+    }
+
+    /// 再生中なら一時停止する。一時停止したかどうかを返す
+    pub fn pause(&self) -> Result<bool> {
+        self.set_paused(true)
+    }
+
+    /// 一時停止中なら再開する。再開したかどうかを返す
+    pub fn resume(&self) -> Result<bool> {
+        self.set_paused(false)
+    }
+
+    fn set_paused(&self, paused: bool) -> Result<bool> {
+        let mut current = lock(&self.current);
+        let Some(speech) = current
+            .as_mut()
+            .filter(|speech| speech.source.is_some() && speech.paused != paused)
+        else {
+            return Ok(false);
+        };
+        let player = lock(&self.player);
+        let player = player.as_ref().context("no media player.")?;
+        let now = Instant::now();
+        if paused {
+            self.engine.pause(player)?;
+            speech.watchdog.pause(now);
+        } else {
+            self.engine.play(player)?;
+            speech.watchdog.resume(now);
+        }
+        speech.paused = paused;
+        Ok(true)
+    }
+
+    /// 再生中のチャンクで次の文か前の文に移る。移ったかどうかを返す
+    ///
+    /// 次の文がなければチャンクの最後に移るので、続きのチャンクに進むか、最後のチャンクならスピーチが終わる。
+    pub fn skip(&self, forward: bool) -> Result<bool> {
+        let current = lock(&self.current);
+        let Some(speech) = current.as_ref().filter(|speech| speech.source.is_some()) else {
+            return Ok(false);
+        };
+        let player = lock(&self.player);
+        let player = player.as_ref().context("no media player.")?;
+        let position = self.engine.position(player)?;
+        let target = match skip_target(&speech.sentences, position, forward) {
+            Some(target) => target,
+            None => self.engine.duration(player)?,
+        };
+        self.engine.set_position(player, target)?;
+        Ok(true)
+    }
+
+    /// 再生中のチャンクが期限を過ぎても終わらなければ、スピーチを失敗として終わらせる
+    ///
+    /// デバイスの不調などで MediaEnded も MediaFailed も届かないことがあるので、UI スレッドから定期的に呼ぶ。
+    /// 終わらせた場合は true を返す。
+    pub fn check_stalled(&self, now: Instant) -> bool {
+        let speech = {
+            let mut current = lock(&self.current);
+            if !current
+                .as_ref()
+                .is_some_and(|speech| speech.watchdog.expired(now))
+            {
+                return false;
+            }
+            current.take()
+        };
+        if let Some(speech) = speech {
+            self.close_speech(speech, Err(anyhow!("{}", tr(Msg::ErrorPlaybackStalled))));
+        }
+        true
+    }
+
+    /// 再生を止めてプレーヤーを閉じる
+    ///
+    /// イベントハンドラを外してから閉じるので、終了時に呼び出してもプロセスが待たされない。
+    pub fn close(&self) {
+        self.stop_all();
+        let Some(player) = lock(&self.player).take() else {
+            return;
+        };
+        self.engine.close_player(&player);
+    }
+
+    /// 合成と再生に使うエンジン (音声の一覧を取り出したり、音声合成エンジンを作ったりするのにも使う)
+    pub fn engine(&self) -> &E {
+        &self.engine
+    }
+
+    /// 使い回すプレーヤーのシステムメディアトランスポートコントロール (メディアキーとフライアウト)
+    pub fn media_controls(self: &Arc<Self>) -> Result<SystemMediaTransportControls> {
+        self.engine.media_controls(&self.media_player()?)
+    }
+
+    /// 使い回すプレーヤー。まだなければ作ってイベントハンドラを登録する
+    fn media_player(self: &Arc<Self>) -> Result<E::Player> {
+        let mut player = lock(&self.player);
+        if let Some(player) = player.as_ref() {
+            return Ok(player.clone());
+        }
+        // ハンドラが Playback を持ち続けないように弱い参照を渡す
+        let playback = Arc::downgrade(self);
+        let opened = move |source: Option<E::Source>| {
+            with_playback(&playback, |p| p.opened(source.as_ref()));
+        };
+        let playback = Arc::downgrade(self);
+        let ended = move |source: Option<E::Source>, result| {
+            with_playback(&playback, |p| p.ended(source.as_ref(), result));
+        };
+        let created = self.engine.create_player(Events {
+            opened: Box::new(opened),
+            ended: Box::new(ended),
+        })?;
+        *player = Some(created.clone());
+        Ok(created)
+    }
+
+    /// チャンクの合成が終わったら [Self::synthesized] を呼ぶ
+    fn on_synthesized(self: &Arc<Self>, id: usize, operation: &E::Operation) -> Result<()> {
+        let playback = Arc::downgrade(self);
+        self.engine.on_completed(
+            operation,
+            Box::new(move |stream| {
+                with_playback(&playback, |p| p.synthesized(id, stream));
+            }),
+        )
+    }
+
+    /// 合成し終わったチャンクを再生する。前のチャンクを再生中なら終わるまで取っておく
+    ///
+    /// 停止された場合や、次のスピーチが始まっている場合は何もしない。
+    fn synthesized(self: &Arc<Self>, id: usize, stream: Result<E::Stream>) {
+        let next = stream.and_then(|stream| {
+            // ソースの差し替え中に停止や次の再生が割り込まないようにロックしたまま再生する
+            let mut current = lock(&self.current);
+            let Some(speech) = current.as_mut().filter(|speech| speech.id == id) else {
+                return Ok(None);
+            };
+            speech.operation = None;
+            if speech.source.is_some() || speech.waiting {
+                speech.ready.push_back(stream);
+                return Ok(None);
+            }
+            self.play_chunk(speech, &stream)?;
+            speech.synthesize_next(&self.engine)
+        });
+        self.continue_or_finish(id, next);
+    }
+
+    /// 次のチャンクの合成を始めていれば完了を待つ。失敗した場合はスピーチを終わらせる
+    fn continue_or_finish(self: &Arc<Self>, id: usize, next: Result<Option<E::Operation>>) {
+        let waiting = next.and_then(|operation| match operation {
+            Some(operation) => self.on_synthesized(id, &operation),
+            None => Ok(()),
+        });
+        if let Err(e) = waiting {
+            self.finish(id, Err(e));
+        }
+    }
+
+    /// チャンクをプレーヤーで再生する
+    fn play_chunk(self: &Arc<Self>, speech: &mut Speech<E>, stream: &E::Stream) -> Result<()> {
+        let source = self.engine.open(stream)?;
+        let player = self.media_player()?;
+        // 各回の最初のチャンクは、いきなり大きな音で始まらないように音量を上げていく
+        let first = speech.played.is_empty();
+        speech.played.push(stream.clone());
+        speech.source = Some(source.clone());
+        speech.sentences = self.engine.sentence_starts(stream);
+        speech.paused = false;
+        let ducking = self.ducking.load(Ordering::Relaxed);
+        self.engine.set_source(&player, Some(&source), ducking)?;
+        if first {
+            self.fade_in(&player)?;
+        }
+        self.engine.play(&player)?;
+        Ok(())
+    }
+
+    /// 各回の最初のチャンクの再生が始まったことを知らせる
+    ///
+    /// チャンクの長さがわかったら、終わるまでの期限を見張り始める。
+    /// 合成したときから読み上げ速度が変わっていれば、再生速度を変えて合わせる。
+    fn opened(&self, source: Option<&E::Source>) {
+        let player = lock(&self.player).clone();
+        let duration = player
+            .as_ref()
+            .and_then(|player| self.engine.duration(player).ok());
+        let started = {
+            let mut current = lock(&self.current);
+            let speech = current
+                .as_mut()
+                .filter(|speech| self.is_source_of(speech, source));
+            speech.and_then(|speech| {
+                // ソースを差し替えると再生速度は 1.0 に戻る
+                let playback_rate = speech.playback_rate();
+                if let Some(player) = &player {
+                    _ = self.engine.set_playback_rate(player, playback_rate);
+                }
+                let duration = duration.map(|duration| duration.div_f64(playback_rate));
+                speech.watchdog.start(Instant::now(), duration);
+                let round = speech.unannounced.take()?;
+                Some((speech.started.clone(), round))
+            })
+        };
+        if let Some((started, round)) = started {
+            started(round);
+        }
+    }
+
+    /// チャンクを再生し終わったら次のチャンクに進む
+    ///
+    /// 最後のチャンクを再生し終わったか、再生に失敗した場合はスピーチを終わらせる。
+    fn ended(self: &Arc<Self>, source: Option<&E::Source>, result: Result<()>) {
+        let mut current = lock(&self.current);
+        let Some(speech) = current
+            .as_mut()
+            .filter(|speech| self.is_source_of(speech, source))
+        else {
+            return;
+        };
+        let id = speech.id;
+        let has_next = !speech.ready.is_empty() || speech.operation.is_some();
+        // 最後のチャンクまで再生して回数が残っていれば、合成結果を使い回して最初から繰り返す
+        if result.is_ok() && !has_next && speech.round < self.repeats() {
+            speech.round += 1;
+            speech.unannounced = Some(speech.round);
+            speech.ready = mem::take(&mut speech.played).into();
+            speech.source = None;
+            let next = match speech.ready.pop_front() {
+                Some(stream) => self.play_chunk(speech, &stream).map(|()| None),
+                None => Ok(None),
+            };
+            drop(current);
+            self.continue_or_finish(id, next);
+            return;
+        }
+        if result.is_err() || !has_next {
+            let speech = current.take();
+            drop(current);
+            if let Some(speech) = speech {
+                // 途中で速度を変えて合成したチャンクが混ざっていれば、使い回さない
+                let same_rate = speech
+                    .chunk_rates
+                    .iter()
+                    .all(|&r| r == speech.chunk_rates[0]);
+                if result.is_ok() && same_rate {
+                    self.store_cache(speech.key, speech.played.clone());
+                }
+                self.close_speech(speech, result);
+            }
+            return;
+        }
+        speech.source = None;
+        // 段落の終わりなら、間を空けてから次のチャンクに進む
+        let pause = synthesis::paragraph_pause();
+        let index = speech.played.len().saturating_sub(1);
+        if !pause.is_zero() && speech.paragraph_ends.get(index) == Some(&true) {
+            speech.waiting = true;
+            drop(current);
+            self.wait_between_paragraphs(id, pause);
+            return;
+        }
+        let next = self.play_ready(speech);
+        drop(current);
+        self.continue_or_finish(id, next);
+    }
+
+    /// 次のチャンクが合成済みなら続けて再生し、その次のチャンクの合成を始める
+    ///
+    /// 合成中なら終わったときに [Self::synthesized] で再生する。
+    fn play_ready(self: &Arc<Self>, speech: &mut Speech<E>) -> Result<Option<E::Operation>> {
+        match speech.ready.pop_front() {
+            Some(stream) => self
+                .play_chunk(speech, &stream)
+                .and_then(|()| speech.synthesize_next(&self.engine)),
+            None => Ok(None),
+        }
+    }
+
+    /// 段落の間の長さだけ別のスレッドで待ってから、次のチャンクに進む
+    ///
+    /// 待っている間に停止されたか次のスピーチが始まっていれば、進まずに終わる。
+    /// アプリケーションの終了が始まったら、待つのをやめてすぐに終わる。
+    fn wait_between_paragraphs(self: &Arc<Self>, id: usize, pause: Duration) {
+        let playback = Arc::downgrade(self);
+        worker::spawn(move || {
+            if !worker::sleep(pause) {
+                return;
+            }
+            let com = com::MtaGuard::new();
+            with_playback(&playback, move |p| match com {
+                Ok(_guard) => p.paragraph_pause_elapsed(id),
+                Err(e) => p.finish(id, Err(e)),
+            });
+        });
+    }
+
+    /// 段落の間を待ち終わったら次のチャンクを再生する
+    fn paragraph_pause_elapsed(self: &Arc<Self>, id: usize) {
+        let mut current = lock(&self.current);
+        let Some(speech) = current
+            .as_mut()
+            .filter(|speech| speech.id == id && speech.waiting)
+        else {
+            return;
+        };
+        speech.waiting = false;
+        let next = self.play_ready(speech);
+        drop(current);
+        self.continue_or_finish(id, next);
+    }
+
+    /// 合成を取り消し、再生中ならソースを外してから結果を知らせる
+    fn close_speech(&self, speech: Speech<E>, result: Result<()>) {
+        if let Some(operation) = &speech.operation {
+            self.engine.cancel(operation);
+        }
+        if speech.source.is_some() {
+            if let Some(player) = lock(&self.player).as_ref() {
+                _ = self.engine.pause(player);
+                // ソースを外すとダッキングも解除される
+                _ = self.engine.set_source(player, None, false);
+                // フェードアウトで下げた音量を次の再生のために戻す
+                _ = self.engine.set_volume(player, FULL_VOLUME);
+            }
+        }
+        (speech.finished)(result);
+    }
+
+    /// イベントを起こしたソースが、このスピーチの再生中のソースかどうか
+    ///
+    /// 差し替える前のソースのイベントが遅れて届いても、次のスピーチを終わらせないようにする。
+    fn is_source_of(&self, speech: &Speech<E>, source: Option<&E::Source>) -> bool {
+        speech
+            .source
+            .as_ref()
+            .zip(source)
+            .is_some_and(|(current, source)| self.engine.same_source(current, source))
+    }
+}
+
+impl<E: Engine> Speech<E> {
+    /// 残りのチャンクがあれば次のチャンクの合成を始める
+    fn synthesize_next(&mut self, engine: &E) -> Result<Option<E::Operation>> {
+        let Some((synth, chunk)) = self.pending.pop_front() else {
+            return Ok(None);
+        };
+        // 読み上げ速度は UI スレッドでいつでも変わるので、合成を始めるときの速度を覚えておく
+        self.chunk_rates.push(engine.speaking_rate(&synth)?);
+        let operation = engine.synthesize(&synth, &chunk)?;
+        self.operation = Some(operation.clone());
+        Ok(Some(operation))
+    }
+
+    /// 再生中のチャンクの再生速度 (トラックバーの速度と合成したときの速度の比)
+    fn playback_rate(&self) -> f64 {
+        let index = self.played.len().saturating_sub(1);
+        self.chunk_rates
+            .get(index)
+            .map_or(1.0, |&synthesized| playback_rate(self.rate, synthesized))
+    }
+}
+
+/// 速度 synthesized で合成した音声を、速度 rate で読み上げているように聞こえる再生速度
+fn playback_rate(rate: f64, synthesized: f64) -> f64 {
+    if synthesized > 0.0 {
+        rate / synthesized
+    } else {
+        1.0
+    }
+}
+
+/// 再生が終わらないまま止まってしまったことに気づくための期限
+#[derive(Default)]
+struct Watchdog {
+    /// これを過ぎても終わらなければ止まったとみなす
+    deadline: Option<Instant>,
+    /// 一時停止した時刻 (一時停止していた分だけ期限を延ばす)
+    paused_at: Option<Instant>,
+}
+
+impl Watchdog {
+    /// 長さ duration のチャンクを now に再生し始めた
+    fn start(&mut self, now: Instant, duration: Option<Duration>) {
+        let limit = duration
+            .filter(|duration| !duration.is_zero())
+            .map_or(WATCHDOG_FALLBACK, |duration| {
+                duration * WATCHDOG_FACTOR + WATCHDOG_SLACK
+            });
+        self.deadline = Some(now + limit);
+    }
+
+    fn pause(&mut self, now: Instant) {
+        self.paused_at.get_or_insert(now);
+    }
+
+    fn resume(&mut self, now: Instant) {
+        if let (Some(paused_at), Some(deadline)) = (self.paused_at.take(), self.deadline.as_mut()) {
+            *deadline += now.saturating_duration_since(paused_at);
+        }
+    }
+
+    /// 一時停止せずに期限を過ぎたかどうか
+    fn expired(&self, now: Instant) -> bool {
+        self.paused_at.is_none() && self.deadline.is_some_and(|deadline| now >= deadline)
+    }
+}
+
+/// WinRT の [SpeechSynthesizer] で合成し、[MediaPlayer] で再生するエンジン
+#[derive(Clone, Copy, Default)]
+pub struct WinRt;
+
+/// イベントハンドラを登録済みの [MediaPlayer]
+#[derive(Clone)]
+pub struct Player {
+    media: MediaPlayer,
+    opened: EventRegistrationToken,
+    ended: EventRegistrationToken,
+    failed: EventRegistrationToken,
+}
+
+impl Engine for WinRt {
+    type Synth = SpeechSynthesizer;
+    type Stream = SpeechSynthesisStream;
+    type Operation = IAsyncOperation<SpeechSynthesisStream>;
+    type Source = IMediaPlaybackSource;
+    type Player = Player;
+
+    fn voices(&self) -> Result<Vec<Voice>> {
+        SpeechSynthesizer::AllVoices()?
+            .into_iter()
+            .map(|voice| voice_of(&voice))
+            .collect()
+    }
+
+    fn default_voice(&self) -> Result<Voice> {
+        voice_of(&SpeechSynthesizer::DefaultVoice()?)
+    }
+
+    fn create_synthesizer(&self, options: &SynthOptions) -> Result<SpeechSynthesizer> {
+        synthesis::create_synthesizer(options)
+    }
+
+    fn apply_options(&self, synth: &SpeechSynthesizer, options: &SynthOptions) -> Result<()> {
+        synthesis::apply_options(synth, options)
+    }
+
+    fn voice(&self, synth: &SpeechSynthesizer) -> Result<Voice> {
+        voice_of(&synth.Voice()?)
+    }
+
+    fn speaking_rate(&self, synth: &SpeechSynthesizer) -> Result<f64> {
+        Ok(synth.Options()?.SpeakingRate()?)
+    }
+
+    fn synthesize(
+        &self,
+        synth: &SpeechSynthesizer,
+        text: &[u16],
+    ) -> Result<IAsyncOperation<SpeechSynthesisStream>> {
+        synthesis::start_with(synth, text)
+    }
+
+    fn on_completed(
+        &self,
+        operation: &IAsyncOperation<SpeechSynthesisStream>,
+        f: Box<dyn FnOnce(Result<SpeechSynthesisStream>) + Send>,
+    ) -> Result<()> {
+        synthesis::on_completed(operation, f)
+    }
+
+    fn cancel(&self, operation: &IAsyncOperation<SpeechSynthesisStream>) {
+        _ = operation.Cancel();
+    }
+
+    fn sentence_starts(&self, stream: &SpeechSynthesisStream) -> Vec<Duration> {
+        sentence_starts(stream)
+    }
+
+    fn create_player(&self, events: Events<IMediaPlaybackSource>) -> Result<Player> {
+        let media = MediaPlayer::new()?;
+        // メディアキーは再生中のスピーチに合わせて自分で処理するので、プレーヤーには任せない
+        media.CommandManager()?.SetIsEnabled(false)?;
+        // イベントを起こしたときに再生していたソースを渡す
+        let source = |sender: &Option<MediaPlayer>| sender.as_ref()?.Source().ok();
+        let Events { opened, ended } = events;
+        let ended = Arc::new(ended);
+        let on_failed = ended.clone();
+        let opened = media.MediaOpened(&TypedEventHandler::new(
+            move |sender: &Option<MediaPlayer>, _| {
+                opened(source(sender));
+                Ok(())
+            },
+        ))?;
+        let ended = media.MediaEnded(&TypedEventHandler::new(
+            move |sender: &Option<MediaPlayer>, _| {
+                ended(source(sender), Ok(()));
+                Ok(())
+            },
+        ))?;
+        let failed = media.MediaFailed(&TypedEventHandler::new(
+            move |sender: &Option<MediaPlayer>, args: &Option<MediaPlayerFailedEventArgs>| {
+                let err = args
+                    .as_ref()
+                    .map_or_else(|| anyhow!("{}", tr(Msg::MediaErrorUnknown)), media_error);
+                on_failed(source(sender), Err(err));
+                Ok(())
+            },
+        ))?;
+        Ok(Player {
+            media,
+            opened,
+            ended,
+            failed,
+        })
+    }
+
+    fn close_player(&self, player: &Player) {
+        _ = player.media.RemoveMediaOpened(player.opened);
+        _ = player.media.RemoveMediaEnded(player.ended);
+        _ = player.media.RemoveMediaFailed(player.failed);
+        _ = player.media.Close();
+    }
+
+    fn media_controls(&self, player: &Player) -> Result<SystemMediaTransportControls> {
+        Ok(player.media.SystemMediaTransportControls()?)
+    }
+
+    fn open(&self, stream: &SpeechSynthesisStream) -> Result<IMediaPlaybackSource> {
+        // 取っておいたストリームを読み進めないように、複製して再生する
+        let source = MediaSource::CreateFromStream(&stream.CloneStream()?, &stream.ContentType()?)?;
+        Ok(source.cast()?)
+    }
+
+    fn set_source(
+        &self,
+        player: &Player,
+        source: Option<&IMediaPlaybackSource>,
+        ducking: bool,
+    ) -> Result<()> {
+        // 通信のカテゴリーで再生すると、システムが他のアプリケーションの音声を小さくする。
+        // ソースを外すとカテゴリーのストリームも閉じるので、停止や最後のチャンクの終了で元の音量に戻る
+        if source.is_some() {
+            let category = if ducking {
+                MediaPlaybackAudioCategory::Communications
+            } else {
+                MediaPlaybackAudioCategory::Speech
+            };
+            player.media.SetAudioCategory(category)?;
+        }
+        player.media.SetSource(source)?;
+        Ok(())
+    }
+
+    fn same_source(&self, a: &IMediaPlaybackSource, b: &IMediaPlaybackSource) -> bool {
+        // 同じオブジェクトかどうかは IUnknown で比べる
+        let identity = |source: &IMediaPlaybackSource| source.cast::<IUnknown>().ok();
+        identity(a).is_some_and(|a| identity(b) == Some(a))
+    }
+
+    fn play(&self, player: &Player) -> Result<()> {
+        Ok(player.media.Play()?)
+    }
+
+    fn pause(&self, player: &Player) -> Result<()> {
+        Ok(player.media.Pause()?)
+    }
+
+    fn volume(&self, player: &Player) -> Result<f64> {
+        Ok(player.media.Volume()?)
+    }
+
+    fn set_volume(&self, player: &Player, volume: f64) -> Result<()> {
+        Ok(player.media.SetVolume(volume)?)
+    }
+
+    fn position(&self, player: &Player) -> Result<Duration> {
+        Ok(from_time_span(player.media.PlaybackSession()?.Position()?))
+    }
+
+    fn set_position(&self, player: &Player, position: Duration) -> Result<()> {
+        let session = player.media.PlaybackSession()?;
+        Ok(session.SetPosition(to_time_span(position))?)
+    }
+
+    fn duration(&self, player: &Player) -> Result<Duration> {
+        Ok(from_time_span(
+            player.media.PlaybackSession()?.NaturalDuration()?,
+        ))
+    }
+
+    fn set_playback_rate(&self, player: &Player, rate: f64) -> Result<()> {
+        Ok(player.media.PlaybackSession()?.SetPlaybackRate(rate)?)
+    }
+}
+
+/// WinRT の音声の情報から、一覧に並べる [Voice] を作る
+fn voice_of(voice: &VoiceInformation) -> Result<Voice> {
+    Ok(Voice {
+        id: voice.Id()?,
+        name: voice.DisplayName()?,
+        language: voice.Language()?.to_string(),
+    })
+}
+
+/// 合成したチャンクで各文が始まる時刻 (文の境界のキューがなければ空)
+fn sentence_starts(stream: &SpeechSynthesisStream) -> Vec<Duration> {
+    let mut starts = speech_marks::from_stream(stream)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|mark| mark.kind == Kind::Sentence)
+        .map(|mark| mark.time)
+        .collect::<Vec<_>>();
+    starts.sort();
+    starts
+}
+
+/// 各文が始まる時刻 starts のうち、position から次の文か前の文に移る時刻
+///
+/// 次の文がなければ None を返す。前に戻る場合、今の文が始まってから [SKIP_BACK_GRACE] 以内なら一つ前の文に戻る
+/// (音楽プレーヤーの「前の曲」と同じ)。
+fn skip_target(starts: &[Duration], position: Duration, forward: bool) -> Option<Duration> {
+    if forward {
+        return starts.iter().copied().find(|&start| start > position);
+    }
+    let index = match starts.iter().rposition(|&start| start <= position) {
+        Some(i) if position - starts[i] < SKIP_BACK_GRACE => i.checked_sub(1),
+        index => index,
+    };
+    Some(index.map_or(Duration::ZERO, |i| starts[i]))
+}
+
+fn from_time_span(span: TimeSpan) -> Duration {
+    Duration::from_nanos(span.Duration.max(0) as u64 * 100)
+}
+
+fn to_time_span(duration: Duration) -> TimeSpan {
+    TimeSpan {
+        Duration: (duration.as_nanos() / 100) as i64,
+    }
+}
+
+/// set_volume で音量を from から to まで duration をかけて変える
+///
+/// cancelled が true を返したら途中でやめる。
+fn fade(
+    set_volume: impl Fn(f64),
+    from: f64,
+    to: f64,
+    duration: Duration,
+    cancelled: impl Fn() -> bool,
+) {
+    for step in 1..=FADE_STEPS {
+        if cancelled() {
+            return;
+        }
+        set_volume(fade_volume(from, to, step));
+        thread::sleep(duration / FADE_STEPS);
+    }
+}
+
+/// フェードの step 回目 (1 から [FADE_STEPS] まで) の音量
+fn fade_volume(from: f64, to: f64, step: u32) -> f64 {
+    from + (to - from) * step.min(FADE_STEPS) as f64 / FADE_STEPS as f64
+}
+
+/// [MediaPlayer] の MediaFailed で渡された原因を「(0x80072EE7) ネットワークエラー」の形にする
+///
+/// エラーの種類から説明を選び、詳しいメッセージがあれば後ろに続ける。
+fn media_error(args: &MediaPlayerFailedEventArgs) -> anyhow::Error {
+    let kind = match args.Error() {
+        Ok(MediaPlayerError::Aborted) => Msg::MediaErrorAborted,
+        Ok(MediaPlayerError::NetworkError) => Msg::MediaErrorNetwork,
+        Ok(MediaPlayerError::DecodingError) => Msg::MediaErrorDecoding,
+        Ok(MediaPlayerError::SourceNotSupported) => Msg::MediaErrorUnsupported,
+        _ => Msg::MediaErrorUnknown,
+    };
+    // 詳しいメッセージがあっても、どの種類の失敗かわかるように種類を先に書く
+    let msg = match args.ErrorMessage().map(|msg| msg.to_string()) {
+        Ok(detail) if !detail.trim().is_empty() => format!("{}: {}", tr(kind), detail.trim()),
+        _ => tr(kind).to_string(),
+    };
+    match args.ExtendedErrorCode() {
+        // 見分けられる原因は、対処を案内できるように残しておく
+        Ok(code) if code.is_err() => {
+            let msg = format!("(0x{:08X}) {}", code.0 as u32, msg.trim());
+            match SpeechError::known(code) {
+                Some(e) => anyhow::Error::new(e).context(msg),
+                None => anyhow!(msg),
+            }
+        }
+        _ => anyhow!("{}", msg.trim()),
+    }
+}
+
+/// Playback がまだ残っていれば f を呼び出す
+fn with_playback<E: Engine>(playback: &Weak<Playback<E>>, f: impl FnOnce(&Arc<Playback<E>>)) {
+    let Some(playback) = playback.upgrade() else {
+        return;
+    };
+    // パニックしてもアプリケーションは終了させず、再生中のスピーチを失敗として終わらせる
+    let result = unwind::catch(|| {
+        f(&playback);
+        Ok(())
+    });
+    if let Err(e) = result {
+        let speech = lock(&playback.current).take();
+        if let Some(speech) = speech {
+            playback.close_speech(speech, Err(e));
+        }
+    }
+}
+
+/// ロックを取る
+///
+/// イベントハンドラなどが保持中にパニックしても、以降の再生や停止が道連れにならないように、
+/// ポイズニングされていても中身をそのまま使う。
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| {
+        logging::info("recovered from a poisoned playback lock");
+        mutex.clear_poison();
+        PoisonError::into_inner(e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::marker::PhantomData;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// WinRT を使わずに合成と再生を真似るエンジン
+    ///
+    /// 音声は [fake_voice] だけがインストールされていることにする。
+    /// 音声合成エンジンと合成したチャンクは中身を見ずに受け渡す。合成は [Self::complete] を、
+    /// プレーヤーのイベントは [Self::open] と [Self::end] を呼ぶまで届かないので、状態を一つずつ確かめられる。
+    pub struct Fake<Synth = (), Stream = usize> {
+        state: Arc<Mutex<FakeState<Stream>>>,
+        synth: PhantomData<fn(Synth)>,
+    }
+
+    /// 偽物のソース (割り振った番号と、再生するチャンク)
+    type FakeSource<Stream> = (usize, Stream);
+
+    /// 合成の完了ハンドラ
+    type Completed<Stream> = Box<dyn FnOnce(Result<Stream>) + Send>;
+
+    struct FakeState<Stream> {
+        /// 合成中のチャンクの番号と、登録された完了ハンドラ
+        synthesizing: VecDeque<(usize, Option<Completed<Stream>>)>,
+        /// 取り消した合成の数
+        cancelled: usize,
+        /// 合成やソースに割り振る番号
+        next: usize,
+        events: Option<Arc<Events<FakeSource<Stream>>>>,
+        /// プレーヤーに設定しているソース
+        source: Option<FakeSource<Stream>>,
+        playing: bool,
+        volume: f64,
+        position: Duration,
+        duration: Duration,
+    }
+
+    impl<Synth, Stream> Default for Fake<Synth, Stream> {
+        fn default() -> Self {
+            let state = FakeState {
+                synthesizing: VecDeque::new(),
+                cancelled: 0,
+                next: 0,
+                events: None,
+                source: None,
+                playing: false,
+                volume: FULL_VOLUME,
+                position: Duration::ZERO,
+                duration: Duration::ZERO,
+            };
+            Self {
+                state: Arc::new(Mutex::new(state)),
+                synth: PhantomData,
+            }
+        }
+    }
+
+    impl<Synth, Stream> Clone for Fake<Synth, Stream> {
+        fn clone(&self) -> Self {
+            Self {
+                state: self.state.clone(),
+                synth: PhantomData,
+            }
+        }
+    }
+
+    impl<Synth, Stream: Clone> Fake<Synth, Stream> {
+        fn state(&self) -> MutexGuard<'_, FakeState<Stream>> {
+            lock(&self.state)
+        }
+
+        /// いちばん古い合成を終わらせて、完了ハンドラに結果を渡す
+        pub fn complete(&self, stream: Result<Stream>) {
+            let (_, completed) = self
+                .state()
+                .synthesizing
+                .pop_front()
+                .expect("no synthesis.");
+            completed.expect("no completed handler.")(stream);
+        }
+
+        /// 合成中のチャンクの数
+        pub fn synthesizing(&self) -> usize {
+            self.state().synthesizing.len()
+        }
+
+        pub fn cancelled(&self) -> usize {
+            self.state().cancelled
+        }
+
+        /// 再生中のチャンク (一時停止中やソースがなければ None)
+        pub fn playing(&self) -> Option<Stream> {
+            let state = self.state();
+            let (_, stream) = state.source.as_ref().filter(|_| state.playing)?;
+            Some(stream.clone())
+        }
+
+        pub fn has_source(&self) -> bool {
+            self.state().source.is_some()
+        }
+
+        /// 設定しているソースを開いたことを知らせる
+        pub fn open(&self) {
+            // ハンドラの中でロックを取れるように、取り出してから呼び出す
+            let state = self.state();
+            let events = state.events.clone().expect("no player.");
+            let source = state.source.clone();
+            drop(state);
+            (events.opened)(source);
+        }
+
+        /// 設定しているソースの再生が終わったか、失敗したことを知らせる
+        pub fn end(&self, result: Result<()>) {
+            let mut state = self.state();
+            let events = state.events.clone().expect("no player.");
+            let source = state.source.clone();
+            state.playing = false;
+            drop(state);
+            (events.ended)(source, result);
+        }
+    }
+
+    /// 偽物のエンジンにインストールされている音声
+    pub fn fake_voice() -> Voice {
+        Voice {
+            id: HSTRING::from("fake"),
+            name: HSTRING::from("Fake Voice"),
+            language: "ja-JP".into(),
+        }
+    }
+
+    impl<Synth, Stream> Engine for Fake<Synth, Stream>
+    where
+        Synth: Clone + Default + Send + 'static,
+        Stream: Clone + Send + 'static,
+    {
+        type Synth = Synth;
+        type Stream = Stream;
+        type Operation = usize;
+        type Source = FakeSource<Stream>;
+        type Player = ();
+
+        fn voices(&self) -> Result<Vec<Voice>> {
+            Ok(vec![fake_voice()])
+        }
+
+        fn default_voice(&self) -> Result<Voice> {
+            Ok(fake_voice())
+        }
+
+        fn create_synthesizer(&self, _options: &SynthOptions) -> Result<Synth> {
+            Ok(Synth::default())
+        }
+
+        fn apply_options(&self, _synth: &Synth, _options: &SynthOptions) -> Result<()> {
+            Ok(())
+        }
+
+        fn voice(&self, _synth: &Synth) -> Result<Voice> {
+            Ok(fake_voice())
+        }
+
+        fn speaking_rate(&self, _synth: &Synth) -> Result<f64> {
+            Ok(1.0)
+        }
+
+        fn synthesize(&self, _synth: &Synth, _text: &[u16]) -> Result<usize> {
+            let mut state = self.state();
+            state.next += 1;
+            let id = state.next;
+            state.synthesizing.push_back((id, None));
+            Ok(id)
+        }
+
+        fn on_completed(&self, operation: &usize, f: Completed<Stream>) -> Result<()> {
+            let mut state = self.state();
+            let synthesis = state
+                .synthesizing
+                .iter_mut()
+                .find(|(id, _)| id == operation);
+            if let Some((_, completed)) = synthesis {
+                *completed = Some(f);
+            }
+            Ok(())
+        }
+
+        fn cancel(&self, operation: &usize) {
+            let mut state = self.state();
+            state.synthesizing.retain(|(id, _)| id != operation);
+            state.cancelled += 1;
+        }
+
+        fn sentence_starts(&self, _stream: &Stream) -> Vec<Duration> {
+            vec![]
+        }
+
+        fn create_player(&self, events: Events<FakeSource<Stream>>) -> Result<()> {
+            self.state().events = Some(Arc::new(events));
+            Ok(())
+        }
+
+        fn close_player(&self, _player: &()) {
+            self.state().events = None;
+        }
+
+        fn media_controls(&self, _player: &()) -> Result<SystemMediaTransportControls> {
+            Err(anyhow!("no media controls."))
+        }
+
+        fn open(&self, stream: &Stream) -> Result<FakeSource<Stream>> {
+            let mut state = self.state();
+            state.next += 1;
+            Ok((state.next, stream.clone()))
+        }
+
+        fn set_source(
+            &self,
+            _player: &(),
+            source: Option<&FakeSource<Stream>>,
+            _ducking: bool,
+        ) -> Result<()> {
+            let mut state = self.state();
+            state.source = source.cloned();
+            state.playing = false;
+            state.position = Duration::ZERO;
+            Ok(())
+        }
+
+        fn same_source(&self, a: &FakeSource<Stream>, b: &FakeSource<Stream>) -> bool {
+            a.0 == b.0
+        }
+
+        fn play(&self, _player: &()) -> Result<()> {
+            self.state().playing = true;
+            Ok(())
+        }
+
+        fn pause(&self, _player: &()) -> Result<()> {
+            self.state().playing = false;
+            Ok(())
+        }
+
+        fn volume(&self, _player: &()) -> Result<f64> {
+            Ok(self.state().volume)
+        }
+
+        fn set_volume(&self, _player: &(), volume: f64) -> Result<()> {
+            self.state().volume = volume;
+            Ok(())
+        }
+
+        fn position(&self, _player: &()) -> Result<Duration> {
+            Ok(self.state().position)
+        }
+
+        fn set_position(&self, _player: &(), position: Duration) -> Result<()> {
+            self.state().position = position;
+            Ok(())
+        }
+
+        fn duration(&self, _player: &()) -> Result<Duration> {
+            Ok(self.state().duration)
+        }
+
+        fn set_playback_rate(&self, _player: &(), _rate: f64) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// 終わったスピーチの結果を results に記録する finished のハンドラ (成功なら true)
+    fn record(results: &Arc<Mutex<Vec<bool>>>) -> impl FnOnce(Result<()>) + Send + 'static {
+        let results = results.clone();
+        move |result| results.lock().unwrap().push(result.is_ok())
+    }
+
+    fn text(text: &str) -> Vec<u16> {
+        text.encode_utf16().collect()
+    }
+
+    /// テキスト全体を一つのチャンクにする
+    fn whole(text: &str) -> Vec<((), Vec<u16>)> {
+        vec![((), self::text(text))]
+    }
+
+    #[test]
+    fn finished_speeches_are_not_kept() {
+        let playback = Arc::new(Playback::<Fake>::default());
+        let finished = Arc::new(AtomicUsize::new(0));
+        for id in 1..=5 {
+            let count = finished.clone();
+            playback
+                .begin(
+                    id,
+                    whole("テスト"),
+                    0,
+                    |_| {},
+                    move |_| _ = count.fetch_add(1, Ordering::Relaxed),
+                )
+                .unwrap();
+            assert!(playback.is_speaking());
+            playback.finish(id, Ok(()));
+            assert!(!playback.is_speaking());
+            // 終わったスピーチへの通知は無視される
+            playback.finish(id, Ok(()));
+        }
+        assert_eq!(finished.load(Ordering::Relaxed), 5);
+        assert!(lock(&playback.current).is_none());
+    }
+
+    #[test]
+    fn repeat_at_least_once() {
+        let playback = Playback::<Fake>::default();
+        assert_eq!(playback.repeats(), 1);
+        playback.set_repeats(5);
+        assert_eq!(playback.repeats(), 5);
+        playback.set_repeats(0);
+        assert_eq!(playback.repeats(), 1);
+    }
+
+    #[test]
+    fn status_follows_the_speech() {
+        let playback = Arc::new(Playback::<Fake>::default());
+        assert_eq!(playback.status(), Status::Idle);
+        for (id, result) in [(1, Ok(())), (2, Err(anyhow!("failed.")))] {
+            playback
+                .begin(id, whole("テスト"), 0, |_| {}, |_| {})
+                .unwrap();
+            assert_eq!(playback.status(), Status::Synthesizing);
+            // 再生が始まるまでは一時停止できない
+            assert!(!playback.pause().unwrap());
+            assert!(!playback.resume().unwrap());
+            assert_eq!(playback.status(), Status::Synthesizing);
+            playback.finish(id, result);
+            assert_eq!(playback.status(), Status::Idle);
+        }
+        playback
+            .begin(3, whole("テスト"), 0, |_| {}, |_| {})
+            .unwrap();
+        playback.stop_all();
+        assert_eq!(playback.status(), Status::Idle);
+    }
+
+    #[test]
+    fn pause_and_resume_while_playing() {
+        let playback = Arc::new(Playback::<Fake>::default());
+        let fake = playback.engine.clone();
+        let results = Arc::default();
+        playback
+            .begin(1, whole("テスト"), 0, |_| {}, record(&results))
+            .unwrap();
+        assert_eq!(playback.status(), Status::Synthesizing);
+        assert_eq!(fake.synthesizing(), 1);
+
+        fake.complete(Ok(1));
+        assert_eq!(playback.status(), Status::Playing);
+        assert_eq!(fake.playing(), Some(1));
+
+        assert!(playback.pause().unwrap());
+        assert_eq!(playback.status(), Status::Paused);
+        assert_eq!(fake.playing(), None);
+        // 一時停止中にもう一度一時停止しても何もしない
+        assert!(!playback.pause().unwrap());
+        assert!(playback.resume().unwrap());
+        assert_eq!(playback.status(), Status::Playing);
+        assert_eq!(fake.playing(), Some(1));
+
+        assert!(playback.pause().unwrap());
+        playback.stop_all();
+        assert_eq!(playback.status(), Status::Idle);
+        // 停止したらソースを外し、成功として知らせる
+        assert!(!fake.has_source());
+        assert_eq!(*results.lock().unwrap(), [true]);
+    }
+
+    #[test]
+    fn chunks_play_in_order() {
+        let playback = Arc::new(Playback::<Fake>::default());
+        let fake = playback.engine.clone();
+        let results = Arc::default();
+        let rounds = Arc::new(Mutex::new(vec![]));
+        let started = rounds.clone();
+        let chunks = vec![((), text("一つ目。")), ((), text("二つ目。"))];
+        playback
+            .begin(
+                1,
+                chunks,
+                7,
+                move |round| started.lock().unwrap().push(round),
+                record(&results),
+            )
+            .unwrap();
+
+        // 最初のチャンクを再生している間に次のチャンクを合成する
+        fake.complete(Ok(1));
+        assert_eq!(fake.synthesizing(), 1);
+        fake.open();
+        assert_eq!(*rounds.lock().unwrap(), [1]);
+        fake.complete(Ok(2));
+        assert_eq!(fake.playing(), Some(1));
+        assert_eq!(playback.progress(), Some((0, 0.0)));
+
+        fake.end(Ok(()));
+        assert_eq!(fake.playing(), Some(2));
+        assert_eq!(playback.status(), Status::Playing);
+        // 同じ回の続きのチャンクでは、始まったことを知らせ直さない
+        fake.open();
+        assert_eq!(*rounds.lock().unwrap(), [1]);
+
+        fake.end(Ok(()));
+        assert_eq!(playback.status(), Status::Idle);
+        assert_eq!(*results.lock().unwrap(), [true]);
+        // 最後まで再生したら合成結果を使い回せる
+        assert_eq!(playback.cached(7), Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn failed_synthesis_fails_the_speech() {
+        let playback = Arc::new(Playback::<Fake>::default());
+        let fake = playback.engine.clone();
+        let results = Arc::default();
+        playback
+            .begin(1, whole("テスト"), 7, |_| {}, record(&results))
+            .unwrap();
+        fake.complete(Err(anyhow!("synthesis failed.")));
+        assert_eq!(playback.status(), Status::Idle);
+        assert_eq!(*results.lock().unwrap(), [false]);
+        assert!(playback.cached(7).is_none());
+    }
+
+    #[test]
+    fn failed_playback_fails_the_speech() {
+        let playback = Arc::new(Playback::<Fake>::default());
+        let fake = playback.engine.clone();
+        let results = Arc::default();
+        playback
+            .begin(1, whole("テスト"), 7, |_| {}, record(&results))
+            .unwrap();
+        fake.complete(Ok(1));
+        fake.open();
+        fake.end(Err(anyhow!("device lost.")));
+        assert_eq!(playback.status(), Status::Idle);
+        assert!(!fake.has_source());
+        assert_eq!(*results.lock().unwrap(), [false]);
+        // 失敗したスピーチの合成結果は使い回さない
+        assert!(playback.cached(7).is_none());
+    }
+
+    #[test]
+    fn stop_cancels_the_synthesis() {
+        let playback = Arc::new(Playback::<Fake>::default());
+        let fake = playback.engine.clone();
+        let results = Arc::default();
+        playback
+            .begin(1, whole("テスト"), 0, |_| {}, record(&results))
+            .unwrap();
+        playback.stop_all();
+        assert_eq!(playback.status(), Status::Idle);
+        assert_eq!(fake.synthesizing(), 0);
+        assert_eq!(fake.cancelled(), 1);
+        assert_eq!(*results.lock().unwrap(), [true]);
+    }
+
+    #[test]
+    fn stalled_playback_fails_the_speech() {
+        let playback = Arc::new(Playback::<Fake>::default());
+        let fake = playback.engine.clone();
+        let results = Arc::default();
+        playback
+            .begin(1, whole("テスト"), 0, |_| {}, record(&results))
+            .unwrap();
+        fake.complete(Ok(1));
+        fake.open();
+        let now = Instant::now();
+        assert!(!playback.check_stalled(now));
+        // 長さがわからないチャンクでも、いつまでも終わらなければ失敗とする
+        assert!(playback.check_stalled(now + WATCHDOG_FALLBACK));
+        assert_eq!(playback.status(), Status::Idle);
+        assert_eq!(*results.lock().unwrap(), [false]);
+    }
+
+    #[test]
+    fn close_right_after_starting() {
+        let playback = Arc::new(Playback::<Fake>::default());
+        let finished = Arc::new(AtomicUsize::new(0));
+        let count = finished.clone();
+        playback
+            .begin(
+                1,
+                whole("テスト"),
+                0,
+                |_| {},
+                move |_| _ = count.fetch_add(1, Ordering::Relaxed),
+            )
+            .unwrap();
+        playback.close();
+        // 合成中のスピーチも終わったことになり、プレーヤーも残らない
+        assert!(!playback.is_speaking());
+        assert!(lock(&playback.player).is_none());
+        assert_eq!(finished.load(Ordering::Relaxed), 1);
+        // 閉じた後に届いた通知は無視される
+        playback.finish(1, Ok(()));
+        assert_eq!(finished.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn panicking_handler_fails_the_speech() {
+        let playback = Arc::new(Playback::<Fake>::default());
+        let failed = Arc::new(AtomicUsize::new(0));
+        let count = failed.clone();
+        playback
+            .begin(
+                1,
+                whole("テスト"),
+                0,
+                |_| {},
+                move |result| {
+                    if result.is_err() {
+                        count.fetch_add(1, Ordering::Relaxed);
+                    }
+                },
+            )
+            .unwrap();
+        with_playback(&Arc::downgrade(&playback), |_| panic!("broken handler."));
+        assert!(!playback.is_speaking());
+        assert_eq!(failed.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn watchdog_fires_when_playback_never_ends() {
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+        let mut watchdog = Watchdog::default();
+        assert!(!watchdog.expired(start + secs(24 * 60 * 60)));
+
+        // 5 秒のチャンクは 2 倍と 10 秒待っても終わらなければ止まったとみなす
+        watchdog.start(start, Some(secs(5)));
+        assert!(!watchdog.expired(start + secs(19)));
+        assert!(watchdog.expired(start + secs(20)));
+
+        // 一時停止中は期限を過ぎても止まったとみなさず、再開したら一時停止した分だけ延ばす
+        watchdog.pause(start + secs(10));
+        assert!(!watchdog.expired(start + secs(100)));
+        watchdog.resume(start + secs(70));
+        assert!(!watchdog.expired(start + secs(79)));
+        assert!(watchdog.expired(start + secs(80)));
+
+        // 長さがわからない場合は長めに待つ
+        watchdog.start(start, None);
+        assert!(!watchdog.expired(start + WATCHDOG_FALLBACK - secs(1)));
+        assert!(watchdog.expired(start + WATCHDOG_FALLBACK));
+    }
+
+    #[test]
+    fn skip_to_adjacent_sentences() {
+        let secs = Duration::from_secs;
+        let starts = [secs(0), secs(5), secs(10)];
+        assert_eq!(skip_target(&starts, secs(6), true), Some(secs(10)));
+        // 最後の文から先に進むとチャンクの最後に移る
+        assert_eq!(skip_target(&starts, secs(12), true), None);
+        // 文の途中なら、その文の始まりに戻る
+        assert_eq!(skip_target(&starts, secs(8), false), Some(secs(5)));
+        // 始まってすぐなら前の文に戻る
+        assert_eq!(skip_target(&starts, secs(6), false), Some(secs(0)));
+        assert_eq!(skip_target(&starts, secs(1), false), Some(secs(0)));
+        // 文の境界がわからなければ、チャンクの最初に戻るか最後に進む
+        assert_eq!(skip_target(&[], secs(3), false), Some(Duration::ZERO));
+        assert_eq!(skip_target(&[], secs(3), true), None);
+        assert_eq!(from_time_span(to_time_span(secs(3))), secs(3));
+    }
+
+    #[test]
+    fn fade_reaches_the_target_volume() {
+        assert_eq!(fade_volume(1.0, 0.0, 1), 0.9);
+        assert_eq!(fade_volume(1.0, 0.0, FADE_STEPS), 0.0);
+        assert_eq!(fade_volume(0.0, FULL_VOLUME, FADE_STEPS), FULL_VOLUME);
+        // 停止にかかる時間は気にならない程度に収める
+        assert!(FADE_OUT < Duration::from_millis(300));
+    }
+
+    #[test]
+    fn playback_rate_follows_the_slider() {
+        assert_eq!(playback_rate(1.0, 1.0), 1.0);
+        assert_eq!(playback_rate(1.5, 1.0), 1.5);
+        assert_eq!(playback_rate(1.0, 2.0), 0.5);
+        assert_eq!(playback_rate(1.0, 0.0), 1.0);
+    }
+
+    #[test]
+    fn keep_only_the_last_synthesis() {
+        let playback = Playback::<Fake>::default();
+        assert!(playback.cached(1).is_none());
+        playback.store_cache(1, vec![]);
+        assert!(playback.cached(1).is_some());
+        assert!(playback.cached(2).is_none());
+        // 新しい合成結果で入れ替わる
+        playback.store_cache(2, vec![]);
+        assert!(playback.cached(1).is_none());
+        assert!(playback.cached(2).is_some());
+        playback.clear_cache();
+        assert!(playback.cached(2).is_none());
+    }
+
+    #[test]
+    fn recover_from_poisoned_lock() {
+        let playback = Arc::new(Playback::<Fake>::default());
+        let poisoned = playback.clone();
+        std::thread::spawn(move || {
+            let _guard = poisoned.current.lock().unwrap();
+            panic!("poison the lock.");
+        })
+        .join()
+        .unwrap_err();
+        assert!(playback.current.is_poisoned());
+
+        // 停止や状態の確認はパニックせずに続けられる
+        playback.stop_all();
+        assert!(!playback.is_speaking());
+        assert_eq!(playback.status(), Status::Idle);
+        assert!(!playback.current.is_poisoned());
+    }
+}
@@ -0,0 +1,103 @@
+use crate::logging;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use windows::Win32::System::Power::{
+    SetThreadExecutionState, ES_CONTINUOUS, ES_DISPLAY_REQUIRED, ES_SYSTEM_REQUIRED,
+    EXECUTION_STATE,
+};
+
+thread_local! {
+    /// スリープを防いでいる再生 (実行状態はスレッドごとに設定されるので、UI スレッドだけで扱う)
+    static AWAKE: RefCell<Awake> = RefCell::new(Awake::default());
+}
+
+/// スリープを防いでいる再生の番号と、画面も消さないかどうか
+///
+/// 再生が重なっても、最後の一つが終わるまではスリープを防ぎ続ける。
+#[derive(Default)]
+struct Awake {
+    holders: BTreeSet<usize>,
+    display: bool,
+}
+
+impl Awake {
+    /// 今の状態で設定する実行状態。スリープを防ぐ再生がなければ ES_CONTINUOUS だけにして元に戻す
+    fn flags(&self) -> EXECUTION_STATE {
+        if self.holders.is_empty() {
+            ES_CONTINUOUS
+        } else if self.display {
+            ES_CONTINUOUS | ES_SYSTEM_REQUIRED | ES_DISPLAY_REQUIRED
+        } else {
+            ES_CONTINUOUS | ES_SYSTEM_REQUIRED
+        }
+    }
+}
+
+/// 番号 id の再生が終わるまでシステムのスリープを防ぐ。display なら画面も消さない
+pub fn hold(id: usize, display: bool) {
+    update(|awake| {
+        awake.holders.insert(id);
+        awake.display = display;
+    });
+}
+
+/// 番号 id の再生がスリープを防ぐのをやめる。ほかに防いでいる再生がなければスリープできるようにする
+///
+/// 防いでいない番号なら何もしないので、古い再生の終わりの通知で呼んでもよい。
+pub fn release(id: usize) {
+    update(|awake| _ = awake.holders.remove(&id));
+}
+
+/// スリープを防いでいる間に画面も消さないかどうかを切り替える
+pub fn set_display(display: bool) {
+    update(|awake| awake.display = display);
+}
+
+/// すべての再生がスリープを防ぐのをやめて、実行状態を元に戻す (終了時と設定を切ったときに呼ぶ)
+///
+/// 再生の終わりの通知が届かなかった場合でも、スリープできないまま残らないようにする。
+pub fn reset() {
+    update(|awake| awake.holders.clear());
+}
+
+/// 状態を変え、実行状態が変わったら設定し直す
+fn update(f: impl FnOnce(&mut Awake)) {
+    AWAKE.with_borrow_mut(|awake| {
+        let before = awake.flags();
+        f(awake);
+        let flags = awake.flags();
+        if flags == before {
+            return;
+        }
+        // 失敗すると以前の状態を返さず 0 になる
+        if unsafe { SetThreadExecutionState(flags) }.0 == 0 {
+            logging::info(format_args!(
+                "failed to set the execution state to {:#x}",
+                flags.0
+            ));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_speeches_keep_the_system_awake() {
+        let mut awake = Awake::default();
+        assert_eq!(awake.flags(), ES_CONTINUOUS);
+        awake.holders.insert(1);
+        awake.holders.insert(2);
+        assert_eq!(awake.flags(), ES_CONTINUOUS | ES_SYSTEM_REQUIRED);
+        // 先に始めた再生が終わっても、後の再生が終わるまでは防ぎ続ける
+        awake.holders.remove(&1);
+        awake.display = true;
+        assert_eq!(
+            awake.flags(),
+            ES_CONTINUOUS | ES_SYSTEM_REQUIRED | ES_DISPLAY_REQUIRED
+        );
+        awake.holders.remove(&2);
+        assert_eq!(awake.flags(), ES_CONTINUOUS);
+    }
+}
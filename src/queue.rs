@@ -0,0 +1,231 @@
+use speech::sentence_export::Sentence;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+thread_local! {
+    /// 再生待ちのファイルとテキスト
+    static PLAYBACK: RefCell<VecDeque<Queued>> = RefCell::new(VecDeque::new());
+    /// WAV への書き出しの状態
+    static EXPORT: RefCell<Export> = RefCell::new(Export::default());
+    /// 文ごとの書き出しの状態 (書き出していなければ None)
+    static SENTENCES: RefCell<Option<SentenceExport>> = const { RefCell::new(None) };
+}
+
+/// 文ごとの書き出しの状態
+struct SentenceExport {
+    /// 書き出し先のフォルダー
+    dir: PathBuf,
+    /// 書き出し待ちの文
+    pending: VecDeque<Sentence>,
+    /// 書き出し中の文
+    current: Option<Sentence>,
+    /// 書き出せた文
+    done: Vec<Sentence>,
+    /// 書き出せなかった文とその理由
+    errors: Vec<String>,
+    /// 文の総数
+    total: usize,
+}
+
+/// 書き出す文
+pub struct SentenceJob {
+    /// 書き出すファイルのパス
+    pub path: PathBuf,
+    /// 読み上げる文
+    pub text: String,
+    /// 何番目の文か (1 から数える)
+    pub index: usize,
+    /// 文の総数
+    pub total: usize,
+}
+
+/// 文ごとの書き出しの結果
+pub struct SentenceSummary {
+    pub dir: PathBuf,
+    /// 書き出せた文 (ファイル名と文の一覧を作るため)
+    pub done: Vec<Sentence>,
+    pub errors: Vec<String>,
+    pub total: usize,
+}
+
+/// 再生待ちの項目
+pub enum Queued {
+    /// エディットコントロールに読み込んで再生するファイル
+    File(PathBuf),
+    /// エディットコントロールを使わずに再生するテキスト (監視しているファイルに追記された行など)
+    Text(Vec<u16>),
+}
+
+/// WAV への書き出しの状態
+#[derive(Default)]
+struct Export {
+    /// 書き出し待ちのファイル (元のファイル, 書き出し先)
+    pending: VecDeque<(PathBuf, PathBuf)>,
+    /// 書き出しを始めたファイルの数
+    started: usize,
+    /// 書き出せなかったファイルとその理由
+    errors: Vec<String>,
+}
+
+/// 書き出すファイル
+pub struct ExportJob {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    /// 何番目のファイルか (1 から数える)
+    pub index: usize,
+    /// 書き出すファイルの総数
+    pub total: usize,
+}
+
+/// 書き出しの結果
+pub struct ExportSummary {
+    pub total: usize,
+    pub errors: Vec<String>,
+}
+
+/// 再生待ちの最後にファイルを追加する
+pub fn push_playback(files: impl IntoIterator<Item = PathBuf>) {
+    PLAYBACK.with_borrow_mut(|queue| queue.extend(files.into_iter().map(Queued::File)));
+}
+
+/// 再生待ちの最後にテキストを追加する
+///
+/// 最後の項目もテキストなら、続けて読み上げられるようにつなげる。
+pub fn push_text(text: Vec<u16>) {
+    PLAYBACK.with_borrow_mut(|queue| match queue.back_mut() {
+        Some(Queued::Text(last)) => last.extend(text),
+        _ => queue.push_back(Queued::Text(text)),
+    });
+}
+
+/// 再生待ちの先頭の項目を取り出す
+pub fn pop_playback() -> Option<Queued> {
+    PLAYBACK.with_borrow_mut(|queue| queue.pop_front())
+}
+
+/// 再生待ちをすべて取り消す
+pub fn clear_playback() {
+    PLAYBACK.with_borrow_mut(|queue| queue.clear());
+}
+
+/// 再生待ちのファイルの数
+pub fn playback_len() -> usize {
+    PLAYBACK.with_borrow(|queue| queue.len())
+}
+
+/// 書き出し待ちにファイルを追加する。書き出し先は dir に拡張子を .wav にしたファイル名とする
+///
+/// 書き出し中でなかった場合は true を返すので、呼び出し側で書き出しを始めること。
+pub fn push_exports(dir: &Path, files: impl IntoIterator<Item = PathBuf>) -> bool {
+    EXPORT.with_borrow_mut(|export| {
+        let idle = export.started == 0 && export.pending.is_empty();
+        export.pending.extend(files.into_iter().map(|source| {
+            let name = source.file_stem().unwrap_or(source.as_os_str());
+            let target = dir.join(name).with_extension("wav");
+            (source, target)
+        }));
+        idle
+    })
+}
+
+/// 次に書き出すファイルを取り出す
+pub fn next_export() -> Option<ExportJob> {
+    EXPORT.with_borrow_mut(|export| {
+        let (source, target) = export.pending.pop_front()?;
+        export.started += 1;
+        Some(ExportJob {
+            source,
+            target,
+            index: export.started,
+            total: export.started + export.pending.len(),
+        })
+    })
+}
+
+/// 書き出せなかったファイルを記録する
+pub fn export_failed(source: &Path, err: &anyhow::Error) {
+    EXPORT.with_borrow_mut(|export| {
+        export.errors.push(format!("{}: {err:#}", source.display()));
+    });
+}
+
+/// 文ごとの書き出しを始める。すでに書き出し中なら false を返して何もしない
+pub fn start_sentences(dir: &Path, sentences: Vec<Sentence>) -> bool {
+    SENTENCES.with_borrow_mut(|export| {
+        if export.is_some() {
+            return false;
+        }
+        *export = Some(SentenceExport {
+            dir: dir.to_path_buf(),
+            total: sentences.len(),
+            pending: sentences.into(),
+            current: None,
+            done: vec![],
+            errors: vec![],
+        });
+        true
+    })
+}
+
+/// 次に書き出す文を取り出す
+pub fn next_sentence() -> Option<SentenceJob> {
+    SENTENCES.with_borrow_mut(|export| {
+        let export = export.as_mut()?;
+        let sentence = export.pending.pop_front()?;
+        let job = SentenceJob {
+            path: export.dir.join(&sentence.file_name),
+            text: sentence.text.clone(),
+            index: export.total - export.pending.len(),
+            total: export.total,
+        };
+        export.current = Some(sentence);
+        Some(job)
+    })
+}
+
+/// 書き出し中の文の結果を記録する
+pub fn sentence_finished(result: &anyhow::Result<()>) {
+    SENTENCES.with_borrow_mut(|export| {
+        let Some(export) = export.as_mut() else {
+            return;
+        };
+        let Some(sentence) = export.current.take() else {
+            return;
+        };
+        match result {
+            Ok(()) => export.done.push(sentence),
+            Err(e) => export.errors.push(format!("{}: {e:#}", sentence.file_name)),
+        }
+    });
+}
+
+/// 残りの文を取り消す
+pub fn cancel_sentences() {
+    SENTENCES.with_borrow_mut(|export| {
+        if let Some(export) = export.as_mut() {
+            export.pending.clear();
+            export.current = None;
+        }
+    });
+}
+
+/// 文ごとの書き出しを終えて結果を返す
+pub fn finish_sentences() -> Option<SentenceSummary> {
+    let export = SENTENCES.take()?;
+    Some(SentenceSummary {
+        dir: export.dir,
+        done: export.done,
+        errors: export.errors,
+        total: export.total,
+    })
+}
+
+/// 書き出しを終えて結果を返す
+pub fn finish_export() -> ExportSummary {
+    let export = EXPORT.take();
+    ExportSummary {
+        total: export.started,
+        errors: export.errors,
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::{bail, ensure, Result};
+use std::slice;
+use windows::Win32::System::DataExchange::COPYDATASTRUCT;
+
+/// テキストをすぐに読み上げる
+pub const SPEAK: usize = 1;
+/// テキストをエディットに読み込む
+pub const LOAD: usize = 2;
+/// 読み上げを停止する
+pub const STOP: usize = 3;
+/// 受け付けるテキストの最大バイト数
+pub const MAX_BYTES: u32 = 4 * 1024 * 1024;
+
+/// ほかのプログラムから WM_COPYDATA で送られた操作
+///
+/// メインウィンドウ (クラス名 `speech_window_cls42`) に WM_COPYDATA を送ると、`dwData` で指定した操作を実行する。
+/// `lpData` には UTF-16 のテキスト (終端の NUL は不要) を、`cbData` にはそのバイト数を指定する。
+///
+/// | dwData | 操作 |
+/// |---|---|
+/// | 1 | テキストをすぐに読み上げる |
+/// | 2 | テキストをエディットに読み込む |
+/// | 3 | 読み上げを停止する (テキストは不要) |
+///
+/// 受け付けた場合は 1、不正なデータの場合は 0 を返す。送信例は `examples/send_text.rs` を参照。
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    Speak(Vec<u16>),
+    Load(Vec<u16>),
+    Stop,
+}
+
+impl Request {
+    /// WM_COPYDATA のデータを解釈する。ほかのプログラム向けの操作でなければ None を返す
+    ///
+    /// # Safety
+    /// `data` の `lpData` は `cbData` バイト読めること。
+    pub unsafe fn from_copy_data(data: &COPYDATASTRUCT) -> Option<Result<Self>> {
+        if !matches!(data.dwData, SPEAK | LOAD | STOP) {
+            return None;
+        }
+        Some(
+            check_size(data.cbData, data.lpData.is_null()).and_then(|len| {
+                let text = match len {
+                    0 => &[],
+                    len => slice::from_raw_parts(data.lpData as *const u16, len),
+                };
+                Self::parse(data.dwData, text)
+            }),
+        )
+    }
+
+    /// 操作の種類とテキストから操作を作る
+    fn parse(kind: usize, text: &[u16]) -> Result<Self> {
+        let text = text.strip_suffix(&[0]).unwrap_or(text);
+        match kind {
+            STOP => Ok(Self::Stop),
+            SPEAK | LOAD => {
+                ensure!(!text.is_empty(), "empty text.");
+                Ok(if kind == SPEAK {
+                    Self::Speak(text.to_vec())
+                } else {
+                    Self::Load(text.to_vec())
+                })
+            }
+            _ => bail!("unknown request: {kind}"),
+        }
+    }
+}
+
+/// データの大きさを確かめて UTF-16 の文字数を返す
+fn check_size(bytes: u32, null: bool) -> Result<usize> {
+    ensure!(bytes <= MAX_BYTES, "too large data: {bytes} bytes.");
+    ensure!(bytes % 2 == 0, "data is not UTF-16.");
+    ensure!(bytes == 0 || !null, "no data.");
+    Ok(bytes as usize / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_requests() {
+        let text = "テスト".encode_utf16().collect::<Vec<_>>();
+        assert_eq!(
+            Request::parse(SPEAK, &text).unwrap(),
+            Request::Speak(text.clone())
+        );
+        assert_eq!(
+            Request::parse(LOAD, &text).unwrap(),
+            Request::Load(text.clone())
+        );
+        assert_eq!(Request::parse(STOP, &[]).unwrap(), Request::Stop);
+        assert!(Request::parse(SPEAK, &[0]).is_err());
+    }
+
+    #[test]
+    fn check_sizes() {
+        assert_eq!(check_size(8, false).unwrap(), 4);
+        assert_eq!(check_size(0, true).unwrap(), 0);
+        assert!(check_size(3, false).is_err());
+        assert!(check_size(8, true).is_err());
+        assert!(check_size(MAX_BYTES + 2, false).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::offsets::{self, OffsetMap};
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{PoisonError, RwLock};
+
+/// 置換ルールを保存するファイルのファイル名
+pub const FILE_NAME: &str = "rules.txt";
+/// 合成する前に適用する置換ルール
+static ACTIVE: RwLock<RuleSet> = RwLock::new(RuleSet(Vec::new()));
+/// 置換ルールを変更した回数 (合成結果を使い回すかどうかの判定に使う)
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// 正規表現による置換ルール
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    /// 正規表現のパターン
+    pub pattern: String,
+    /// 置換後の文字列 ($1 などでキャプチャを参照できる)
+    pub replacement: String,
+    /// 無効にしたルールは適用しない
+    pub enabled: bool,
+}
+
+impl Rule {
+    /// パターンを正規表現としてコンパイルする
+    pub fn compile(&self) -> Result<Regex, regex::Error> {
+        Regex::new(&self.pattern)
+    }
+}
+
+/// コンパイル済みの置換ルールの並び
+#[derive(Default)]
+pub struct RuleSet(Vec<(Regex, String)>);
+
+impl RuleSet {
+    /// 有効なルールをコンパイルする。正しくないパターンがあればエラーにする
+    pub fn new(rules: &[Rule]) -> Result<Self> {
+        rules
+            .iter()
+            .filter(|rule| rule.enabled)
+            .map(|rule| {
+                let regex = rule
+                    .compile()
+                    .with_context(|| format!("invalid pattern: {}", rule.pattern))?;
+                Ok((regex, rule.replacement.clone()))
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// ルールを並んでいる順に適用する (前のルールで置き換えた結果に次のルールを適用する)
+    pub fn apply(&self, text: &str) -> String {
+        self.apply_mapped(text).0
+    }
+
+    /// [Self::apply] と同じように置き換え、置き換えた後の位置を元のテキストの位置に戻す対応も返す
+    pub fn apply_mapped(&self, text: &str) -> (String, OffsetMap) {
+        self.0.iter().fold(
+            (text.to_string(), OffsetMap::default()),
+            |(text, map), (regex, replacement)| {
+                let (text, next) = offsets::replace_all(regex, &text, |caps| {
+                    let mut replaced = String::new();
+                    caps.expand(replacement, &mut replaced);
+                    replaced
+                });
+                (text, map.then(next))
+            },
+        )
+    }
+}
+
+/// 合成する前に適用する置換ルールを差し替える
+pub fn set_active(rules: RuleSet) {
+    *ACTIVE.write().unwrap_or_else(PoisonError::into_inner) = rules;
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 置換ルールを変更した回数
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// 合成する前に適用する置換ルールでテキストを置き換え、元のテキストの位置に戻す対応と一緒に返す
+pub fn apply_active(text: &[u16]) -> (Vec<u16>, OffsetMap) {
+    let rules = ACTIVE.read().unwrap_or_else(PoisonError::into_inner);
+    if rules.is_empty() {
+        return (text.to_vec(), OffsetMap::default());
+    }
+    let (text, map) = rules.apply_mapped(&String::from_utf16_lossy(text));
+    (text.encode_utf16().collect(), map)
+}
+
+/// ルールのファイルを読み込む。一行に「有効 (1 か 0)、パターン、置換後」をタブで区切って並べる
+pub fn parse(text: &str) -> Vec<Rule> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let enabled = fields.next()? != "0";
+            let pattern = fields.next().filter(|p| !p.is_empty())?;
+            Some(Rule {
+                pattern: pattern.to_string(),
+                replacement: fields.next().unwrap_or_default().to_string(),
+                enabled,
+            })
+        })
+        .collect()
+}
+
+/// ルールをファイルに保存する形式にする
+///
+/// パターンに含まれるタブは、同じ意味の \t に置き換える。
+pub fn serialize(rules: &[Rule]) -> String {
+    rules
+        .iter()
+        .map(|rule| {
+            let pattern = rule.pattern.replace('\t', "\\t");
+            let replacement = rule.replacement.replace(['\r', '\n'], " ");
+            format!("{}\t{pattern}\t{replacement}\r\n", rule.enabled as u8)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, replacement: &str) -> Rule {
+        Rule {
+            pattern: pattern.into(),
+            replacement: replacement.into(),
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn apply_with_captures() {
+        let rules = RuleSet::new(&[rule(r"v(\d+)\.(\d+)", "バージョン$1点$2")]).unwrap();
+        assert_eq!(
+            rules.apply("v1.2 と v10.0"),
+            "バージョン1点2 と バージョン10点0"
+        );
+    }
+
+    #[test]
+    fn apply_in_order_and_skip_disabled() {
+        let mut rules = vec![rule("a", "b"), rule("b", "c"), rule("c", "d")];
+        assert_eq!(RuleSet::new(&rules).unwrap().apply("abc"), "ddd");
+        rules[2].enabled = false;
+        assert_eq!(RuleSet::new(&rules).unwrap().apply("abc"), "ccc");
+        rules.swap(0, 1);
+        assert_eq!(RuleSet::new(&rules).unwrap().apply("abc"), "bcc");
+    }
+
+    #[test]
+    fn reject_invalid_pattern() {
+        let mut rules = vec![rule("(", "")];
+        assert!(RuleSet::new(&rules).is_err());
+        // 無効にしたルールはコンパイルしない
+        rules[0].enabled = false;
+        assert!(RuleSet::new(&rules).unwrap().is_empty());
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let mut rules = vec![rule(r"v(\d+)", "V$1"), rule("x", "")];
+        rules[1].enabled = false;
+        assert_eq!(parse(&serialize(&rules)), rules);
+        assert_eq!(parse("1\ta\tb\tc\n\n"), vec![rule("a", "b\tc")]);
+        assert_eq!(serialize(&[rule("a\tb", "c")]), "1\ta\\tb\tc\r\n");
+    }
+}
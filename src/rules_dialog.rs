@@ -0,0 +1,364 @@
+use crate::dialog::{self, create_control};
+use crate::dpi::LogicalRect;
+use crate::settings;
+use crate::strings::{self, tr, Msg};
+use anyhow::{Context, Result};
+use speech::rules::{Rule, RuleSet};
+use std::cell::RefCell;
+use windows::{
+    core::{w, HSTRING, PCWSTR},
+    Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+        UI::{
+            Controls::BST_CHECKED,
+            Input::KeyboardAndMouse::SetFocus,
+            WindowsAndMessaging::{
+                DefWindowProcW, GetDlgItem, GetWindowTextLengthW, GetWindowTextW, SendMessageW,
+                SetWindowTextW, BM_GETCHECK, BM_SETCHECK, BS_AUTOCHECKBOX, BS_DEFPUSHBUTTON,
+                BS_PUSHBUTTON, EN_CHANGE, ES_AUTOHSCROLL, ES_READONLY, IDCANCEL, IDOK,
+                LBN_SELCHANGE, LBS_NOINTEGRALHEIGHT, LBS_NOTIFY, LB_ADDSTRING, LB_ERR,
+                LB_GETCURSEL, LB_RESETCONTENT, LB_SETCURSEL, WINDOW_STYLE, WM_CLOSE, WM_COMMAND,
+                WM_CREATE, WS_BORDER, WS_TABSTOP, WS_VSCROLL,
+            },
+        },
+    },
+};
+
+/// 置換ルールのダイアログのクラス名
+const CLASS_NAME: PCWSTR = w!("speech_rules_cls42");
+/// ルールの一覧のリストボックスの ID
+const ID_LIST: u16 = 100;
+/// 上へボタンの ID
+const ID_UP: u16 = 101;
+/// 下へボタンの ID
+const ID_DOWN: u16 = 102;
+/// 削除ボタンの ID
+const ID_DELETE: u16 = 103;
+/// パターンのエディットの ID
+const ID_PATTERN: u16 = 104;
+/// 置換後のエディットの ID
+const ID_REPLACEMENT: u16 = 105;
+/// 有効のチェックボックスの ID
+const ID_ENABLED: u16 = 106;
+/// 追加ボタンの ID
+const ID_ADD: u16 = 107;
+/// 変更ボタンの ID
+const ID_UPDATE: u16 = 108;
+/// 試しに置き換えるテキストのエディットの ID
+const ID_SAMPLE: u16 = 109;
+/// 置き換えた結果のエディットの ID
+const ID_PREVIEW: u16 = 110;
+/// ダイアログのクライアント領域の大きさ (96 DPI 基準)
+const CLIENT_SIZE: (i32, i32) = (480, 370);
+/// ルールの一覧の配置
+const LIST_RECT: LogicalRect = LogicalRect::new(12, 12, 364, 150);
+/// 右側に縦に並べるボタンの配置 (上へ、下へ、削除)
+const SIDE_BUTTON_RECTS: [LogicalRect; 3] = [
+    LogicalRect::new(384, 12, 84, 28),
+    LogicalRect::new(384, 46, 84, 28),
+    LogicalRect::new(384, 80, 84, 28),
+];
+/// ラベルの配置 (パターン、置換後、テスト、結果)
+const LABEL_RECTS: [LogicalRect; 4] = [
+    LogicalRect::new(12, 176, 76, 20),
+    LogicalRect::new(12, 206, 76, 20),
+    LogicalRect::new(12, 272, 76, 20),
+    LogicalRect::new(12, 302, 76, 20),
+];
+/// パターンのエディットの配置
+const PATTERN_RECT: LogicalRect = LogicalRect::new(92, 172, 284, 24);
+/// 置換後のエディットの配置
+const REPLACEMENT_RECT: LogicalRect = LogicalRect::new(92, 202, 284, 24);
+/// 有効のチェックボックスの配置
+const ENABLED_RECT: LogicalRect = LogicalRect::new(92, 232, 284, 20);
+/// 追加ボタンの配置
+const ADD_RECT: LogicalRect = LogicalRect::new(384, 170, 84, 28);
+/// 変更ボタンの配置
+const UPDATE_RECT: LogicalRect = LogicalRect::new(384, 200, 84, 28);
+/// 試しに置き換えるテキストのエディットの配置
+const SAMPLE_RECT: LogicalRect = LogicalRect::new(92, 268, 376, 24);
+/// 置き換えた結果のエディットの配置
+const PREVIEW_RECT: LogicalRect = LogicalRect::new(92, 298, 376, 24);
+/// OK ボタンの配置
+const OK_RECT: LogicalRect = LogicalRect::new(292, 334, 84, 28);
+/// キャンセルボタンの配置
+const CANCEL_RECT: LogicalRect = LogicalRect::new(384, 334, 84, 28);
+/// 試しに置き換えるテキストの初期値
+const SAMPLE_TEXT: &str = "v1.2";
+
+thread_local! {
+    /// 編集中のルール (OK を押すまで保存しない)
+    static RULES: RefCell<Vec<Rule>> = const { RefCell::new(Vec::new()) };
+}
+
+/// 置換ルールを編集するダイアログをモーダルで表示する
+///
+/// OK で閉じたらルールを保存し、以降の読み上げと保存に適用する。
+pub fn show(owner: HWND) -> Result<()> {
+    RULES.set(settings::load_rules()?);
+    dialog::show_modal(
+        owner,
+        CLASS_NAME,
+        Some(wnd_proc),
+        Msg::RulesTitle,
+        CLIENT_SIZE,
+    )?;
+    RULES.take();
+    Ok(())
+}
+
+fn create_button(hwnd: HWND, msg: Msg, rect: LogicalRect, id: u16) -> Result<HWND> {
+    let style = WINDOW_STYLE(BS_PUSHBUTTON as _) | WS_TABSTOP;
+    create_control(hwnd, w!("BUTTON"), &strings::wide(msg), style, rect, id)
+}
+
+fn create_edit(
+    hwnd: HWND,
+    text: &str,
+    style: WINDOW_STYLE,
+    rect: LogicalRect,
+    id: u16,
+) -> Result<HWND> {
+    let style = WINDOW_STYLE(ES_AUTOHSCROLL as _) | WS_BORDER | WS_TABSTOP | style;
+    create_control(hwnd, w!("EDIT"), &HSTRING::from(text), style, rect, id)
+}
+
+fn create(hwnd: HWND) -> Result<()> {
+    let list_style = WINDOW_STYLE((LBS_NOTIFY | LBS_NOINTEGRALHEIGHT) as _)
+        | WS_BORDER
+        | WS_VSCROLL
+        | WS_TABSTOP;
+    let list = create_control(
+        hwnd,
+        w!("LISTBOX"),
+        &HSTRING::new(),
+        list_style,
+        LIST_RECT,
+        ID_LIST,
+    )?;
+    let side = [
+        (Msg::ButtonUp, ID_UP),
+        (Msg::ButtonDown, ID_DOWN),
+        (Msg::ButtonDelete, ID_DELETE),
+    ];
+    for ((msg, id), rect) in side.into_iter().zip(SIDE_BUTTON_RECTS) {
+        create_button(hwnd, msg, rect, id)?;
+    }
+    let labels = [
+        Msg::LabelRulePattern,
+        Msg::LabelRuleReplacement,
+        Msg::LabelRuleSample,
+        Msg::LabelRulePreview,
+    ];
+    for (msg, rect) in labels.into_iter().zip(LABEL_RECTS) {
+        create_control(
+            hwnd,
+            w!("STATIC"),
+            &strings::wide(msg),
+            WINDOW_STYLE::default(),
+            rect,
+            0,
+        )?;
+    }
+    create_edit(hwnd, "", WINDOW_STYLE::default(), PATTERN_RECT, ID_PATTERN)?;
+    create_edit(
+        hwnd,
+        "",
+        WINDOW_STYLE::default(),
+        REPLACEMENT_RECT,
+        ID_REPLACEMENT,
+    )?;
+    let enabled = create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::RuleEnabled),
+        WINDOW_STYLE(BS_AUTOCHECKBOX as _) | WS_TABSTOP,
+        ENABLED_RECT,
+        ID_ENABLED,
+    )?;
+    unsafe { SendMessageW(enabled, BM_SETCHECK, WPARAM(BST_CHECKED.0 as _), None) };
+    create_button(hwnd, Msg::ButtonAdd, ADD_RECT, ID_ADD)?;
+    create_button(hwnd, Msg::ButtonUpdate, UPDATE_RECT, ID_UPDATE)?;
+    create_edit(
+        hwnd,
+        SAMPLE_TEXT,
+        WINDOW_STYLE::default(),
+        SAMPLE_RECT,
+        ID_SAMPLE,
+    )?;
+    create_edit(
+        hwnd,
+        "",
+        WINDOW_STYLE(ES_READONLY as _),
+        PREVIEW_RECT,
+        ID_PREVIEW,
+    )?;
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ButtonOk),
+        WINDOW_STYLE(BS_DEFPUSHBUTTON as _) | WS_TABSTOP,
+        OK_RECT,
+        IDOK.0 as _,
+    )?;
+    create_button(hwnd, Msg::ButtonCancel, CANCEL_RECT, IDCANCEL.0 as _)?;
+    refresh(hwnd, Some(0))?;
+    _ = unsafe { SetFocus(list) };
+    Ok(())
+}
+
+/// コントロールのテキストを取得する
+fn control_text(hwnd: HWND, id: u16) -> Result<String> {
+    let control = unsafe { GetDlgItem(hwnd, id as _)? };
+    let mut buf = vec![0; unsafe { GetWindowTextLengthW(control) } as usize + 1];
+    let len = unsafe { GetWindowTextW(control, &mut buf) } as usize;
+    Ok(String::from_utf16_lossy(&buf[..len]))
+}
+
+fn set_control_text(hwnd: HWND, id: u16, text: &str) -> Result<()> {
+    let control = unsafe { GetDlgItem(hwnd, id as _)? };
+    unsafe { SetWindowTextW(control, &HSTRING::from(text))? };
+    Ok(())
+}
+
+/// 一覧で選択しているルールの位置
+fn selection(hwnd: HWND) -> Result<Option<usize>> {
+    let list = unsafe { GetDlgItem(hwnd, ID_LIST as _)? };
+    let index = unsafe { SendMessageW(list, LB_GETCURSEL, None, None) }.0;
+    Ok((index != LB_ERR as isize).then_some(index as usize))
+}
+
+/// 一覧に表示するルールの文字列
+fn list_item(rule: &Rule) -> String {
+    let check = if rule.enabled { "✓" } else { "　" };
+    format!("{check} {}  →  {}", rule.pattern, rule.replacement)
+}
+
+/// ルールの一覧を表示し直して、指定した位置のルールを選択する
+fn refresh(hwnd: HWND, select: Option<usize>) -> Result<()> {
+    let list = unsafe { GetDlgItem(hwnd, ID_LIST as _)? };
+    unsafe { SendMessageW(list, LB_RESETCONTENT, None, None) };
+    RULES.with_borrow(|rules| {
+        for rule in rules {
+            let item = HSTRING::from(list_item(rule));
+            unsafe { SendMessageW(list, LB_ADDSTRING, None, LPARAM(item.as_ptr() as _)) };
+        }
+    });
+    if let Some(index) = select.filter(|&i| i < RULES.with_borrow(Vec::len)) {
+        unsafe { SendMessageW(list, LB_SETCURSEL, WPARAM(index), None) };
+        selection_changed(hwnd)?;
+    }
+    update_preview(hwnd)
+}
+
+/// 選択したルールをエディットに表示する
+fn selection_changed(hwnd: HWND) -> Result<()> {
+    let Some(rule) =
+        selection(hwnd)?.and_then(|i| RULES.with_borrow(|rules| rules.get(i).cloned()))
+    else {
+        return Ok(());
+    };
+    set_control_text(hwnd, ID_PATTERN, &rule.pattern)?;
+    set_control_text(hwnd, ID_REPLACEMENT, &rule.replacement)?;
+    let enabled = unsafe { GetDlgItem(hwnd, ID_ENABLED as _)? };
+    let check = if rule.enabled { BST_CHECKED.0 } else { 0 };
+    unsafe { SendMessageW(enabled, BM_SETCHECK, WPARAM(check as _), None) };
+    Ok(())
+}
+
+/// エディットに入力したルールを読み取る。パターンが正しくない場合は正規表現のエラーを返す
+fn edited_rule(hwnd: HWND) -> Result<Rule> {
+    let enabled = unsafe { GetDlgItem(hwnd, ID_ENABLED as _)? };
+    let rule = Rule {
+        pattern: control_text(hwnd, ID_PATTERN)?,
+        replacement: control_text(hwnd, ID_REPLACEMENT)?,
+        enabled: unsafe { SendMessageW(enabled, BM_GETCHECK, None, None) }.0
+            == BST_CHECKED.0 as isize,
+    };
+    anyhow::ensure!(!rule.pattern.is_empty(), tr(Msg::ErrorRulePattern));
+    rule.compile().context(tr(Msg::ErrorRulePattern))?;
+    Ok(rule)
+}
+
+/// 試しに入力したテキストに編集中のルールを適用した結果を表示する
+fn update_preview(hwnd: HWND) -> Result<()> {
+    let sample = control_text(hwnd, ID_SAMPLE)?;
+    // 編集中のルールは追加するときに確かめているので、ここでは失敗しない
+    let preview = RULES
+        .with_borrow(|rules| RuleSet::new(rules))
+        .map_or_else(|e| format!("{e:#}"), |rules| rules.apply(&sample));
+    set_control_text(hwnd, ID_PREVIEW, &preview)
+}
+
+fn command(hwnd: HWND, wparam: WPARAM) -> Result<()> {
+    let id = crate::loword(wparam.0 as _);
+    let code = crate::hiword(wparam.0 as _);
+    let selected = selection(hwnd)?;
+    if id == ID_LIST && code as u32 == LBN_SELCHANGE {
+        selection_changed(hwnd)?;
+    } else if id == ID_SAMPLE && code as u32 == EN_CHANGE {
+        update_preview(hwnd)?;
+    } else if id == ID_ADD {
+        let rule = edited_rule(hwnd)?;
+        let index = RULES.with_borrow_mut(|rules| {
+            rules.push(rule);
+            rules.len() - 1
+        });
+        refresh(hwnd, Some(index))?;
+    } else if id == ID_UPDATE {
+        let Some(index) = selected else {
+            return Ok(());
+        };
+        let rule = edited_rule(hwnd)?;
+        RULES.with_borrow_mut(|rules| rules[index] = rule);
+        refresh(hwnd, Some(index))?;
+    } else if id == ID_DELETE {
+        let Some(index) = selected else {
+            return Ok(());
+        };
+        RULES.with_borrow_mut(|rules| rules.remove(index));
+        refresh(hwnd, Some(index.saturating_sub(1)))?;
+    } else if id == ID_UP {
+        if let Some(index) = selected.filter(|&i| i > 0) {
+            RULES.with_borrow_mut(|rules| rules.swap(index, index - 1));
+            refresh(hwnd, Some(index - 1))?;
+        }
+    } else if id == ID_DOWN {
+        if let Some(index) = selected.filter(|&i| i + 1 < RULES.with_borrow(Vec::len)) {
+            RULES.with_borrow_mut(|rules| rules.swap(index, index + 1));
+            refresh(hwnd, Some(index + 1))?;
+        }
+    } else if id == IDOK.0 as u16 {
+        RULES.with_borrow(|rules| settings::save_rules(rules))?;
+        dialog::close(hwnd)?;
+    } else if id == IDCANCEL.0 as u16 {
+        dialog::close(hwnd)?;
+    }
+    Ok(())
+}
+
+/// ダイアログのウィンドウプロシージャ
+unsafe extern "system" fn wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_CREATE => {
+            if create(hwnd).is_err() {
+                return LRESULT(-1);
+            }
+        }
+        WM_COMMAND => {
+            if let Err(e) = command(hwnd, wparam) {
+                crate::report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+        WM_CLOSE => {
+            dialog::close(hwnd).ok();
+        }
+        _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+    LRESULT::default()
+}
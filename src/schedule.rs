@@ -0,0 +1,199 @@
+use speech::synthesis::SynthOptions;
+use windows::Win32::{Foundation::SYSTEMTIME, System::SystemInformation::GetLocalTime};
+
+/// 1 日の秒数
+pub const DAY: i64 = 24 * 60 * 60;
+/// 再生時刻を過ぎてもこの秒数以内なら再生する (スリープから復帰した直後など)
+pub const GRACE: i64 = 5 * 60;
+
+/// 時刻を指定した再生
+///
+/// 時刻はローカル時刻の秒数で扱い、呼び出し側が現在時刻を渡す (テストで時計を差し替えられるように)。
+/// テキストと合成の設定は予約したときのものを使い、後から編集しても変わらない。
+pub struct Schedule {
+    /// 再生する時刻 (ローカル時刻の秒数)
+    target: i64,
+    /// 再生する時刻の 0 時からの秒数
+    time_of_day: i64,
+    /// 毎日繰り返すかどうか
+    daily: bool,
+    /// 読み上げるテキスト
+    pub text: Vec<u16>,
+    /// 予約したときの音声と読み上げ速度
+    pub options: SynthOptions,
+}
+
+/// [Schedule::poll] の結果
+#[derive(Debug, PartialEq)]
+pub enum Poll {
+    /// 再生時刻まであと何秒か
+    Wait(i64),
+    /// 再生する
+    Fire,
+    /// 再生時刻を大きく過ぎたので再生しない
+    Missed,
+}
+
+impl Schedule {
+    /// now より後で最初に time_of_day になる時刻に予約する
+    pub fn new(
+        now: i64,
+        time_of_day: i64,
+        daily: bool,
+        text: Vec<u16>,
+        options: SynthOptions,
+    ) -> Self {
+        let time_of_day = time_of_day.rem_euclid(DAY);
+        Self {
+            target: next_occurrence(now, time_of_day),
+            time_of_day,
+            daily,
+            text,
+            options,
+        }
+    }
+
+    /// 再生時刻の 0 時からの秒数
+    pub fn time_of_day(&self) -> i64 {
+        self.time_of_day
+    }
+
+    /// 毎日繰り返すかどうか
+    pub fn is_daily(&self) -> bool {
+        self.daily
+    }
+
+    /// 再生時刻までの秒数 (時刻を過ぎていれば 0)
+    pub fn remaining(&self, now: i64) -> i64 {
+        (self.target - now).max(0)
+    }
+
+    /// 現在時刻から再生するかどうかを決める
+    ///
+    /// 再生したり見送ったりした後は、毎日繰り返す場合だけ次の日の同じ時刻に予約し直す。
+    pub fn poll(&mut self, now: i64) -> Poll {
+        if now < self.target {
+            return Poll::Wait(self.target - now);
+        }
+        let poll = if now - self.target <= GRACE {
+            Poll::Fire
+        } else {
+            Poll::Missed
+        };
+        if self.daily {
+            self.target = next_occurrence(now, self.time_of_day);
+        }
+        poll
+    }
+}
+
+/// now より後で最初に time_of_day になる時刻
+fn next_occurrence(now: i64, time_of_day: i64) -> i64 {
+    let target = now - now.rem_euclid(DAY) + time_of_day;
+    if target > now {
+        target
+    } else {
+        target + DAY
+    }
+}
+
+/// 0 時からの秒数を「15:00」の形にする (秒があれば「15:00:30」)
+pub fn format_time_of_day(secs: i64) -> String {
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if s == 0 {
+        format!("{h}:{m:02}")
+    } else {
+        format!("{h}:{m:02}:{s:02}")
+    }
+}
+
+/// [SYSTEMTIME] の 0 時からの秒数
+pub fn time_of_day(st: &SYSTEMTIME) -> i64 {
+    i64::from(st.wHour) * 3600 + i64::from(st.wMinute) * 60 + i64::from(st.wSecond)
+}
+
+/// [SYSTEMTIME] を 1970-01-01 0 時からの秒数にする (タイムゾーンは変換しない)
+pub fn seconds(st: &SYSTEMTIME) -> i64 {
+    days_from_civil(i64::from(st.wYear), st.wMonth.into(), st.wDay.into()) * DAY + time_of_day(st)
+}
+
+/// 現在のローカル時刻の秒数
+pub fn local_now() -> i64 {
+    seconds(&unsafe { GetLocalTime() })
+}
+
+/// グレゴリオ暦の日付の 1970-01-01 からの日数
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: i64, h: i64, m: i64) -> i64 {
+        day * DAY + h * 3600 + m * 60
+    }
+
+    fn schedule(now: i64, h: i64, m: i64, daily: bool) -> Schedule {
+        Schedule::new(
+            now,
+            h * 3600 + m * 60,
+            daily,
+            vec![],
+            SynthOptions::default(),
+        )
+    }
+
+    #[test]
+    fn fire_once_at_the_time() {
+        let mut s = schedule(at(10, 14, 30), 15, 0, false);
+        assert_eq!(s.poll(at(10, 14, 30)), Poll::Wait(30 * 60));
+        assert_eq!(s.remaining(at(10, 14, 59)), 60);
+        assert_eq!(s.poll(at(10, 15, 0)), Poll::Fire);
+        // 過ぎた時刻を指定したら次の日にする
+        let mut s = schedule(at(10, 16, 0), 15, 0, false);
+        assert_eq!(s.poll(at(10, 16, 0)), Poll::Wait(23 * 3600));
+    }
+
+    #[test]
+    fn handle_sleeping_through_the_time() {
+        // 猶予の間に復帰したらすぐに再生する
+        let mut s = schedule(at(10, 14, 0), 15, 0, false);
+        assert_eq!(s.poll(at(10, 15, 4)), Poll::Fire);
+        // 大きく過ぎていたら見送る
+        let mut s = schedule(at(10, 14, 0), 15, 0, false);
+        assert_eq!(s.poll(at(10, 16, 0)), Poll::Missed);
+    }
+
+    #[test]
+    fn repeat_daily() {
+        let mut s = schedule(at(10, 14, 0), 15, 0, true);
+        assert_eq!(s.poll(at(10, 15, 0)), Poll::Fire);
+        assert_eq!(s.poll(at(10, 15, 1)), Poll::Wait(DAY - 60));
+        // 何日も眠っていたら見送り、次の 15:00 に予約し直す
+        assert_eq!(s.poll(at(13, 9, 0)), Poll::Missed);
+        assert_eq!(s.poll(at(13, 9, 0)), Poll::Wait(6 * 3600));
+    }
+
+    #[test]
+    fn convert_system_time() {
+        let st = SYSTEMTIME {
+            wYear: 2000,
+            wMonth: 3,
+            wDay: 1,
+            wHour: 15,
+            wMinute: 0,
+            wSecond: 30,
+            ..Default::default()
+        };
+        assert_eq!(seconds(&st), 951868800 + 15 * 3600 + 30);
+        assert_eq!(format_time_of_day(time_of_day(&st)), "15:00:30");
+        assert_eq!(format_time_of_day(9 * 3600 + 5 * 60), "9:05");
+    }
+}
@@ -0,0 +1,160 @@
+use crate::dialog::{self, create_control};
+use crate::dpi::LogicalRect;
+use crate::schedule;
+use crate::strings::{self, Msg};
+use anyhow::Result;
+use windows::{
+    core::{w, HSTRING, PCWSTR},
+    Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, SYSTEMTIME, WPARAM},
+        UI::{
+            Controls::{
+                BST_CHECKED, DATETIMEPICK_CLASSW, DTM_GETSYSTEMTIME, DTS_TIMEFORMAT, DTS_UPDOWN,
+                GDT_VALID,
+            },
+            Input::KeyboardAndMouse::SetFocus,
+            WindowsAndMessaging::{
+                DefWindowProcW, GetDlgItem, GetWindow, SendMessageW, BM_GETCHECK, BS_AUTOCHECKBOX,
+                BS_DEFPUSHBUTTON, BS_PUSHBUTTON, GW_OWNER, IDCANCEL, IDOK, WINDOW_STYLE, WM_CLOSE,
+                WM_COMMAND, WM_CREATE, WS_TABSTOP,
+            },
+        },
+    },
+};
+
+/// 時刻を指定して再生するダイアログのクラス名
+const CLASS_NAME: PCWSTR = w!("speech_schedule_cls42");
+/// 時刻を選ぶコントロールの ID
+const ID_TIME: u16 = 100;
+/// 毎日繰り返すチェックボックスの ID
+const ID_DAILY: u16 = 101;
+/// ダイアログのクライアント領域の大きさ (96 DPI 基準)
+const CLIENT_SIZE: (i32, i32) = (280, 124);
+/// 説明のラベルの配置
+const LABEL_RECT: LogicalRect = LogicalRect::new(12, 16, 150, 20);
+/// 時刻を選ぶコントロールの配置
+const TIME_RECT: LogicalRect = LogicalRect::new(170, 12, 98, 24);
+/// 毎日繰り返すチェックボックスの配置
+const DAILY_RECT: LogicalRect = LogicalRect::new(12, 48, 256, 20);
+/// OK ボタンの配置
+const OK_RECT: LogicalRect = LogicalRect::new(92, 84, 84, 28);
+/// キャンセルボタンの配置
+const CANCEL_RECT: LogicalRect = LogicalRect::new(184, 84, 84, 28);
+
+/// 時刻を指定して再生するダイアログをモーダルで表示する
+///
+/// OK を押すと、その時点のテキストと音声と読み上げ速度で予約する。ダイアログが閉じられるまで戻らない。
+pub fn show(owner: HWND) -> Result<()> {
+    dialog::show_modal(
+        owner,
+        CLASS_NAME,
+        Some(wnd_proc),
+        Msg::ScheduleTitle,
+        CLIENT_SIZE,
+    )
+}
+
+fn create(hwnd: HWND) -> Result<()> {
+    create_control(
+        hwnd,
+        w!("STATIC"),
+        &strings::wide(Msg::LabelScheduleTime),
+        WINDOW_STYLE::default(),
+        LABEL_RECT,
+        0,
+    )?;
+    // 初期値は現在時刻になる
+    let time = create_control(
+        hwnd,
+        DATETIMEPICK_CLASSW,
+        &HSTRING::new(),
+        WINDOW_STYLE((DTS_TIMEFORMAT | DTS_UPDOWN) as _) | WS_TABSTOP,
+        TIME_RECT,
+        ID_TIME,
+    )?;
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ScheduleDaily),
+        WINDOW_STYLE(BS_AUTOCHECKBOX as _) | WS_TABSTOP,
+        DAILY_RECT,
+        ID_DAILY,
+    )?;
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ButtonOk),
+        WINDOW_STYLE(BS_DEFPUSHBUTTON as _) | WS_TABSTOP,
+        OK_RECT,
+        IDOK.0 as _,
+    )?;
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ButtonCancel),
+        WINDOW_STYLE(BS_PUSHBUTTON as _) | WS_TABSTOP,
+        CANCEL_RECT,
+        IDCANCEL.0 as _,
+    )?;
+    _ = unsafe { SetFocus(time) };
+    Ok(())
+}
+
+/// 選んだ時刻で予約する
+fn apply(hwnd: HWND) -> Result<()> {
+    let time = unsafe { GetDlgItem(hwnd, ID_TIME as _)? };
+    let mut st = SYSTEMTIME::default();
+    let result = unsafe {
+        SendMessageW(
+            time,
+            DTM_GETSYSTEMTIME,
+            None,
+            LPARAM(&mut st as *mut _ as _),
+        )
+    };
+    if result.0 != GDT_VALID.0 as isize {
+        return Ok(());
+    }
+    let daily = unsafe { GetDlgItem(hwnd, ID_DAILY as _)? };
+    let checked =
+        unsafe { SendMessageW(daily, BM_GETCHECK, None, None) }.0 == BST_CHECKED.0 as isize;
+    let owner = unsafe { GetWindow(hwnd, GW_OWNER)? };
+    crate::arm_schedule(owner, schedule::time_of_day(&st), checked)?;
+    dialog::close(hwnd)
+}
+
+fn command(hwnd: HWND, wparam: WPARAM) -> Result<()> {
+    let id = crate::loword(wparam.0 as _);
+    if id == IDOK.0 as u16 {
+        apply(hwnd)?;
+    } else if id == IDCANCEL.0 as u16 {
+        dialog::close(hwnd)?;
+    }
+    Ok(())
+}
+
+/// ダイアログのウィンドウプロシージャ
+unsafe extern "system" fn wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_CREATE => {
+            if create(hwnd).is_err() {
+                return LRESULT(-1);
+            }
+        }
+        WM_COMMAND => {
+            if let Err(e) = command(hwnd, wparam) {
+                crate::report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+        WM_CLOSE => {
+            dialog::close(hwnd).ok();
+        }
+        _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+    LRESULT::default()
+}
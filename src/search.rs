@@ -0,0 +1,204 @@
+//! エディットコントロールのテキストの検索と置換
+//!
+//! 位置は UTF-16 単位で、文字単位で比べるのでサロゲートペアの途中からは一致しない。
+//! 大文字と小文字を区別しない場合も、一対一に対応する小文字があるラテン文字などだけを同じとみなす。
+//! かなや漢字には大文字と小文字がないので、区別してもしなくても同じ結果になる。
+
+use std::char::REPLACEMENT_CHARACTER;
+
+/// 検索する文字列と条件
+#[derive(Clone, Copy)]
+pub struct Query<'a> {
+    pub pattern: &'a [u16],
+    /// 大文字と小文字を区別する
+    pub match_case: bool,
+}
+
+/// 見つかった範囲 (UTF-16 単位)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Found {
+    pub start: usize,
+    pub end: usize,
+    /// テキストの端で折り返してから見つかった
+    pub wrapped: bool,
+}
+
+/// from の位置以降で最初に一致する範囲。なければ先頭に折り返して探す
+pub fn find_next(text: &[u16], query: Query, from: usize) -> Option<Found> {
+    let matches = matches(text, query);
+    let first = matches.iter().find(|&&(start, _)| start >= from);
+    match first {
+        Some(&(start, end)) => Some(found(start, end, false)),
+        None => matches.first().map(|&(start, end)| found(start, end, true)),
+    }
+}
+
+/// before の位置より前から始まる最後の一致する範囲。なければ末尾に折り返して探す
+pub fn find_previous(text: &[u16], query: Query, before: usize) -> Option<Found> {
+    let matches = matches(text, query);
+    let last = matches.iter().rev().find(|&&(start, _)| start < before);
+    match last {
+        Some(&(start, end)) => Some(found(start, end, false)),
+        None => matches.last().map(|&(start, end)| found(start, end, true)),
+    }
+}
+
+/// text 全体が検索する文字列と一致するかどうか (選択範囲を置換してよいか確かめる)
+pub fn is_match(text: &[u16], query: Query) -> bool {
+    matches(text, query).first() == Some(&(0, text.len()))
+}
+
+/// 一致する範囲をすべて replacement に置き換えたテキストと、置き換えた数
+///
+/// 一度にテキストを組み立て直すので、長いテキストでもエディットコントロールへの置換は一回で済む。
+pub fn replace_all(text: &[u16], query: Query, replacement: &[u16]) -> (Vec<u16>, usize) {
+    let matches = matches(text, query);
+    let mut replaced = Vec::with_capacity(text.len());
+    let mut pos = 0;
+    for &(start, end) in &matches {
+        replaced.extend_from_slice(&text[pos..start]);
+        replaced.extend_from_slice(replacement);
+        pos = end;
+    }
+    replaced.extend_from_slice(&text[pos..]);
+    (replaced, matches.len())
+}
+
+fn found(start: usize, end: usize, wrapped: bool) -> Found {
+    Found {
+        start,
+        end,
+        wrapped,
+    }
+}
+
+/// 重ならないように先頭から探した、一致する範囲 (開始位置, 終了位置) の一覧
+fn matches(text: &[u16], query: Query) -> Vec<(usize, usize)> {
+    let pattern = chars(query.pattern)
+        .into_iter()
+        .map(|(_, c)| c)
+        .collect::<Vec<_>>();
+    if pattern.is_empty() {
+        return vec![];
+    }
+    let text_chars = chars(text);
+    // 文字の位置から UTF-16 単位の位置を求める (末尾の位置も含める)
+    let offset = |i: usize| text_chars.get(i).map_or(text.len(), |&(pos, _)| pos);
+    let same = |a: char, b: char| a == b || (!query.match_case && fold(a) == fold(b));
+    let mut found = vec![];
+    let mut i = 0;
+    while i + pattern.len() <= text_chars.len() {
+        let candidate = &text_chars[i..i + pattern.len()];
+        if candidate
+            .iter()
+            .zip(&pattern)
+            .all(|(&(_, a), &b)| same(a, b))
+        {
+            found.push((offset(i), offset(i + pattern.len())));
+            i += pattern.len();
+        } else {
+            i += 1;
+        }
+    }
+    found
+}
+
+/// 文字と、その文字が始まる UTF-16 単位の位置の一覧
+fn chars(text: &[u16]) -> Vec<(usize, char)> {
+    let mut pos = 0;
+    char::decode_utf16(text.iter().copied())
+        .map(|c| {
+            let start = pos;
+            let c = c.unwrap_or(REPLACEMENT_CHARACTER);
+            // 対になっていないサロゲートは REPLACEMENT_CHARACTER (一単位) として扱う
+            pos += c.len_utf16();
+            (start, c)
+        })
+        .collect()
+}
+
+/// 大文字と小文字を区別しないで比べるための文字。小文字が一文字に決まらない文字はそのまま使う
+fn fold(c: char) -> char {
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    fn query(pattern: &[u16], match_case: bool) -> Query<'_> {
+        Query {
+            pattern,
+            match_case,
+        }
+    }
+
+    #[test]
+    fn find_forward_and_wrap() {
+        let text = utf16("今日は晴れ。明日も晴れ。");
+        let pattern = utf16("晴れ");
+        let q = query(&pattern, true);
+        assert_eq!(find_next(&text, q, 0), Some(found(3, 5, false)));
+        assert_eq!(find_next(&text, q, 5), Some(found(9, 11, false)));
+        // 末尾まで見つからなければ先頭に戻る
+        assert_eq!(find_next(&text, q, 11), Some(found(3, 5, true)));
+        assert_eq!(find_next(&text, query(&utf16("雨"), true), 0), None);
+        assert_eq!(find_next(&text, query(&[], true), 0), None);
+    }
+
+    #[test]
+    fn find_backward_and_wrap() {
+        let text = utf16("abc abc");
+        let pattern = utf16("abc");
+        let q = query(&pattern, true);
+        assert_eq!(find_previous(&text, q, 4), Some(found(0, 3, false)));
+        assert_eq!(find_previous(&text, q, 0), Some(found(4, 7, true)));
+    }
+
+    #[test]
+    fn ignore_case_only_for_cased_letters() {
+        let text = utf16("Rust と ＲＵＳＴ と rust と ラスト");
+        let pattern = utf16("rust");
+        let (_, count) = replace_all(&text, query(&pattern, false), &[]);
+        assert_eq!(count, 2);
+        let (_, count) = replace_all(&text, query(&pattern, true), &[]);
+        assert_eq!(count, 1);
+        // 全角のラテン文字も大文字と小文字を区別しない
+        let (_, count) = replace_all(&text, query(&utf16("ｒｕｓｔ"), false), &[]);
+        assert_eq!(count, 1);
+        // ひらがなとカタカナは別の文字として扱う
+        let (_, count) = replace_all(&text, query(&utf16("らすと"), false), &[]);
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn do_not_match_inside_surrogate_pairs() {
+        // U+1F600 と U+1F601 は上位サロゲートが同じ
+        let text = utf16("😀😁");
+        let low = [utf16("😁")[1]];
+        assert_eq!(find_next(&text, query(&low, true), 0), None);
+        let pattern = utf16("😁");
+        assert_eq!(
+            find_next(&text, query(&pattern, true), 0),
+            Some(found(2, 4, false))
+        );
+    }
+
+    #[test]
+    fn replace_all_at_once() {
+        let text = utf16("aaa-AAA");
+        let (replaced, count) = replace_all(&text, query(&utf16("aa"), false), &utf16("b"));
+        assert_eq!(String::from_utf16_lossy(&replaced), "ba-bA");
+        assert_eq!(count, 2);
+        assert!(is_match(&utf16("AA"), query(&utf16("aa"), false)));
+        assert!(!is_match(&utf16("aaa"), query(&utf16("aa"), false)));
+    }
+}
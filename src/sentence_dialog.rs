@@ -0,0 +1,176 @@
+use crate::dialog::{self, create_control};
+use crate::dpi::LogicalRect;
+use crate::settings;
+use crate::strings::{self, Msg};
+use anyhow::Result;
+use speech::sentence_export::DEFAULT_PATTERN;
+use std::cell::RefCell;
+use windows::{
+    core::{w, HSTRING, PCWSTR},
+    Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+        UI::{
+            Controls::BST_CHECKED,
+            Input::KeyboardAndMouse::SetFocus,
+            WindowsAndMessaging::{
+                DefWindowProcW, GetDlgItem, GetWindowTextLengthW, GetWindowTextW, SendMessageW,
+                BM_GETCHECK, BM_SETCHECK, BS_AUTOCHECKBOX, BS_DEFPUSHBUTTON, BS_PUSHBUTTON,
+                ES_AUTOHSCROLL, IDCANCEL, IDOK, WINDOW_STYLE, WM_CLOSE, WM_COMMAND, WM_CREATE,
+                WS_BORDER, WS_TABSTOP,
+            },
+        },
+    },
+};
+
+/// 文ごとに書き出すダイアログのクラス名
+const CLASS_NAME: PCWSTR = w!("speech_sentence_cls42");
+/// ファイル名のパターンのエディットの ID
+const ID_PATTERN: u16 = 100;
+/// ファイル名と文の一覧を書き出すチェックボックスの ID
+const ID_MANIFEST: u16 = 101;
+/// ダイアログのクライアント領域の大きさ (96 DPI 基準)
+const CLIENT_SIZE: (i32, i32) = (360, 150);
+/// パターンの説明のラベルの配置
+const LABEL_RECT: LogicalRect = LogicalRect::new(12, 12, 336, 20);
+/// パターンのエディットの配置
+const PATTERN_RECT: LogicalRect = LogicalRect::new(12, 34, 336, 24);
+/// 一覧を書き出すチェックボックスの配置
+const MANIFEST_RECT: LogicalRect = LogicalRect::new(12, 72, 336, 20);
+/// OK ボタンの配置
+const OK_RECT: LogicalRect = LogicalRect::new(172, 110, 84, 28);
+/// キャンセルボタンの配置
+const CANCEL_RECT: LogicalRect = LogicalRect::new(264, 110, 84, 28);
+
+thread_local! {
+    /// OK で閉じたときのパターン
+    static ACCEPTED: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// 文ごとに書き出すファイル名のパターンを入力するダイアログをモーダルで表示する
+///
+/// OK で閉じたらパターンと一覧の有無を設定に保存し、パターンを返す。キャンセルした場合は None を返す。
+pub fn show(owner: HWND) -> Result<Option<String>> {
+    ACCEPTED.take();
+    dialog::show_modal(
+        owner,
+        CLASS_NAME,
+        Some(wnd_proc),
+        Msg::SentenceExportTitle,
+        CLIENT_SIZE,
+    )?;
+    Ok(ACCEPTED.take())
+}
+
+fn create(hwnd: HWND) -> Result<()> {
+    let (pattern, write_manifest) = {
+        let settings = settings::get();
+        let pattern = settings
+            .sentence_pattern
+            .as_deref()
+            .unwrap_or(DEFAULT_PATTERN);
+        (HSTRING::from(pattern), settings.sentence_manifest)
+    };
+    create_control(
+        hwnd,
+        w!("STATIC"),
+        &strings::wide(Msg::LabelSentencePattern),
+        WINDOW_STYLE::default(),
+        LABEL_RECT,
+        0,
+    )?;
+    let edit = create_control(
+        hwnd,
+        w!("EDIT"),
+        &pattern,
+        WINDOW_STYLE(ES_AUTOHSCROLL as _) | WS_BORDER | WS_TABSTOP,
+        PATTERN_RECT,
+        ID_PATTERN,
+    )?;
+    let manifest = create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::SentenceManifest),
+        WINDOW_STYLE(BS_AUTOCHECKBOX as _) | WS_TABSTOP,
+        MANIFEST_RECT,
+        ID_MANIFEST,
+    )?;
+    if write_manifest {
+        unsafe { SendMessageW(manifest, BM_SETCHECK, WPARAM(BST_CHECKED.0 as _), None) };
+    }
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ButtonOk),
+        WINDOW_STYLE(BS_DEFPUSHBUTTON as _) | WS_TABSTOP,
+        OK_RECT,
+        IDOK.0 as _,
+    )?;
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ButtonCancel),
+        WINDOW_STYLE(BS_PUSHBUTTON as _) | WS_TABSTOP,
+        CANCEL_RECT,
+        IDCANCEL.0 as _,
+    )?;
+    _ = unsafe { SetFocus(edit) };
+    Ok(())
+}
+
+/// 入力されたパターンと一覧の有無を設定に保存して閉じる
+fn apply(hwnd: HWND) -> Result<()> {
+    let edit = unsafe { GetDlgItem(hwnd, ID_PATTERN as _)? };
+    let mut buf = vec![0; unsafe { GetWindowTextLengthW(edit) } as usize + 1];
+    let len = unsafe { GetWindowTextW(edit, &mut buf) } as usize;
+    let pattern = String::from_utf16_lossy(&buf[..len]).trim().to_string();
+    let pattern = if pattern.is_empty() {
+        DEFAULT_PATTERN.to_string()
+    } else {
+        pattern
+    };
+    let manifest = unsafe { GetDlgItem(hwnd, ID_MANIFEST as _)? };
+    let checked =
+        unsafe { SendMessageW(manifest, BM_GETCHECK, None, None) }.0 == BST_CHECKED.0 as isize;
+    settings::update(|s| {
+        s.sentence_pattern = Some(pattern.clone()).filter(|p| p != DEFAULT_PATTERN);
+        s.sentence_manifest = checked;
+    })?;
+    ACCEPTED.set(Some(pattern));
+    dialog::close(hwnd)
+}
+
+fn command(hwnd: HWND, wparam: WPARAM) -> Result<()> {
+    let id = crate::loword(wparam.0 as _);
+    if id == IDOK.0 as u16 {
+        apply(hwnd)?;
+    } else if id == IDCANCEL.0 as u16 {
+        dialog::close(hwnd)?;
+    }
+    Ok(())
+}
+
+/// ダイアログのウィンドウプロシージャ
+unsafe extern "system" fn wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_CREATE => {
+            if create(hwnd).is_err() {
+                return LRESULT(-1);
+            }
+        }
+        WM_COMMAND => {
+            if let Err(e) = command(hwnd, wparam) {
+                crate::report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+        WM_CLOSE => {
+            dialog::close(hwnd).ok();
+        }
+        _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+    LRESULT::default()
+}
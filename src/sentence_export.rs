@@ -0,0 +1,268 @@
+use crate::chunk;
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+
+/// ファイル名のパターンの既定値
+pub const DEFAULT_PATTERN: &str = "{index:03}_{first10chars}.wav";
+/// 拡張子を除いたファイル名の最大の文字数 (パスが長くなりすぎないように)
+const MAX_STEM_CHARS: usize = 100;
+/// 保存ダイアログで提案するファイル名の最大の文字数 (拡張子を除く)
+const SUGGESTED_NAME_CHARS: usize = 40;
+/// Windows でファイル名に使えない名前 (拡張子が付いていても使えない)
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// 文ごとに書き出す文
+pub struct Sentence {
+    /// 書き出すファイル名
+    pub file_name: String,
+    /// 読み上げる文 (前後の空白は除く)
+    pub text: String,
+}
+
+/// テキストを文に区切り、パターンからそれぞれのファイル名を決める
+///
+/// 空白だけの文は飛ばし、番号は書き出す文だけに 1 から振る。同じ名前になったら `_2` などを付けて区別する。
+pub fn plan(text: &[u16], pattern: &str) -> Result<Vec<Sentence>> {
+    let mut used = HashSet::new();
+    let mut sentences = vec![];
+    for sentence in chunk::sentences(text) {
+        let text = String::from_utf16_lossy(sentence).trim().to_string();
+        if text.is_empty() {
+            continue;
+        }
+        let name = expand(pattern, sentences.len() + 1, &text)?;
+        let file_name = unique_name(&name, &mut used);
+        sentences.push(Sentence { file_name, text });
+    }
+    Ok(sentences)
+}
+
+/// パターンの `{index}` `{index:03}` `{first10chars}` を置き換え、ファイル名に使えるようにする
+///
+/// 拡張子がなければ `.wav` を付ける。
+pub fn expand(pattern: &str, index: usize, text: &str) -> Result<String> {
+    let mut name = String::new();
+    let mut rest = pattern;
+    while let Some(open) = rest.find('{') {
+        name.push_str(&rest[..open]);
+        let close = rest[open..]
+            .find('}')
+            .with_context(|| format!("unterminated placeholder in the pattern: {pattern}"))?;
+        name.push_str(&placeholder(&rest[open + 1..open + close], index, text)?);
+        rest = &rest[open + close + 1..];
+    }
+    name.push_str(rest);
+    let name = sanitize_file_name(&name);
+    if name.to_ascii_lowercase().ends_with(".wav") {
+        Ok(name)
+    } else {
+        Ok(name + ".wav")
+    }
+}
+
+/// `{` と `}` の間のプレースホルダーを置き換える
+fn placeholder(key: &str, index: usize, text: &str) -> Result<String> {
+    if key == "index" {
+        return Ok(index.to_string());
+    }
+    if let Some(width) = key.strip_prefix("index:0") {
+        let width: usize = width
+            .parse()
+            .with_context(|| format!("invalid width: {key}"))?;
+        return Ok(format!("{index:0width$}"));
+    }
+    if let Some(count) = key
+        .strip_prefix("first")
+        .and_then(|rest| rest.strip_suffix("chars"))
+    {
+        let count: usize = count
+            .parse()
+            .with_context(|| format!("invalid length: {key}"))?;
+        return Ok(text.chars().take(count).collect());
+    }
+    bail!("unknown placeholder: {{{key}}}")
+}
+
+/// ファイル名に使えない文字を `_` に置き換える
+///
+/// 制御文字と `\ / : * ? " < > |` を置き換え、末尾の空白とピリオドを取り除き、
+/// 予約されたデバイス名には `_` を付ける。長すぎる名前は拡張子を残して切り詰める。
+pub fn sanitize_file_name(name: &str) -> String {
+    let replaced: String = name
+        .chars()
+        .map(|c| match c {
+            '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let trimmed = replaced
+        .trim_start()
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace());
+    let (stem, extension) = match trimmed.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+        _ => (trimmed, None),
+    };
+    let mut stem: String = stem.trim_end().chars().take(MAX_STEM_CHARS).collect();
+    if stem.is_empty() {
+        stem.push('_');
+    }
+    let device = stem.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(device))
+    {
+        stem.insert(0, '_');
+    }
+    match extension {
+        Some(extension) => format!("{stem}.{extension}"),
+        None => stem,
+    }
+}
+
+/// テキストの最初の空でない行から、保存するファイル名を提案する
+///
+/// ファイル名に使えない文字は空白にして、[SUGGESTED_NAME_CHARS] 文字までに切り詰める。
+/// 使える文字が残らない場合は None を返す。
+pub fn suggest_file_name(text: &str) -> Option<String> {
+    let line = text.lines().find(|line| !line.trim().is_empty())?;
+    let cleaned: String = line
+        .chars()
+        .map(|c| match c {
+            '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => ' ',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect();
+    let stem: String = cleaned
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(SUGGESTED_NAME_CHARS)
+        .collect();
+    let stem = stem.trim_end_matches(|c: char| c == '.' || c.is_whitespace());
+    if stem.trim_start_matches('.').is_empty() {
+        return None;
+    }
+    Some(sanitize_file_name(&format!("{stem}.wav")))
+}
+
+/// すでに使った名前と重ならないように、拡張子の前に `_2` などを付ける (大文字と小文字は区別しない)
+fn unique_name(name: &str, used: &mut HashSet<String>) -> String {
+    let (stem, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+    let mut candidate = name.to_string();
+    let mut n = 1;
+    while !used.insert(candidate.to_lowercase()) {
+        n += 1;
+        candidate = format!("{stem}_{n}.{extension}");
+    }
+    candidate
+}
+
+/// ファイル名と文の対応を CSV にする (1 行目は見出し)
+pub fn manifest(sentences: &[Sentence]) -> String {
+    let mut csv = String::from("file,text\r\n");
+    for sentence in sentences {
+        csv.push_str(&csv_field(&sentence.file_name));
+        csv.push(',');
+        csv.push_str(&csv_field(&sentence.text));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// カンマや引用符、改行を含む値を `"` で囲む
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn expand_the_default_pattern() {
+        assert_eq!(
+            expand(DEFAULT_PATTERN, 7, "今日はとても良い天気ですね。").unwrap(),
+            "007_今日はとても良い天気.wav"
+        );
+        assert_eq!(expand("{index}", 12, "x").unwrap(), "12.wav");
+        assert!(expand("{index", 1, "x").is_err());
+        assert!(expand("{name}", 1, "x").is_err());
+        assert!(expand("{index:0x}", 1, "x").is_err());
+    }
+
+    #[test]
+    fn sanitize_invalid_characters() {
+        assert_eq!(
+            expand("{index}_{first20chars}", 1, "a/b: \"c\"? <d>|e*\tf.").unwrap(),
+            "1_a_b_ _c__ _d__e__f.wav"
+        );
+        assert_eq!(sanitize_file_name("con.wav"), "_con.wav");
+        assert_eq!(sanitize_file_name("LPT1"), "_LPT1");
+        assert_eq!(sanitize_file_name(" .. "), "_");
+        assert_eq!(sanitize_file_name("name. . "), "name");
+        let long = "あ".repeat(300) + ".wav";
+        assert_eq!(
+            sanitize_file_name(&long),
+            "あ".repeat(MAX_STEM_CHARS) + ".wav"
+        );
+    }
+
+    #[test]
+    fn suggest_from_the_first_line() {
+        let text = "\r\n  今日の: 天気?\r\n二行目";
+        assert_eq!(suggest_file_name(text).as_deref(), Some("今日の 天気.wav"));
+        let long = "あ".repeat(SUGGESTED_NAME_CHARS + 5);
+        assert_eq!(
+            suggest_file_name(&long),
+            Some("あ".repeat(SUGGESTED_NAME_CHARS) + ".wav")
+        );
+        assert_eq!(suggest_file_name("con"), Some("_con.wav".into()));
+        assert_eq!(suggest_file_name(" \r\n"), None);
+        assert_eq!(suggest_file_name("???"), None);
+    }
+
+    #[test]
+    fn number_colliding_names() {
+        let sentences = plan(&wide("はい。はい。ハイ。  \r\n。はい。"), "{first2chars}").unwrap();
+        let names: Vec<_> = sentences.iter().map(|s| s.file_name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["はい.wav", "はい_2.wav", "ハイ.wav", "。.wav", "はい_3.wav"]
+        );
+        let names = plan(&wide("A. a. A."), "{first1chars}.WAV").unwrap();
+        let names: Vec<_> = names.iter().map(|s| s.file_name.as_str()).collect();
+        assert_eq!(names, ["A.WAV", "a_2.WAV", "A_3.WAV"]);
+    }
+
+    #[test]
+    fn quote_manifest_fields() {
+        let sentences = [
+            Sentence {
+                file_name: "001.wav".into(),
+                text: "Hello, \"world\".".into(),
+            },
+            Sentence {
+                file_name: "002.wav".into(),
+                text: "こんにちは。".into(),
+            },
+        ];
+        assert_eq!(
+            manifest(&sentences),
+            "file,text\r\n001.wav,\"Hello, \"\"world\"\".\"\r\n002.wav,こんにちは。\r\n"
+        );
+    }
+}
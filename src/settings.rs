@@ -0,0 +1,511 @@
+use crate::hotkey::{Action, Hotkey};
+use crate::strings::Lang;
+use anyhow::{Context, Result};
+use speech::lexicon::{self, Entry, Lexicon};
+use speech::normalize;
+use speech::rules::{self, Rule, RuleSet};
+use speech::synthesis;
+use speech::wav;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+
+/// 設定ファイルのファイル名
+const FILE_NAME: &str = "settings.ini";
+/// 再生や保存の前に確認する長いテキストの既定の文字数 (UTF-16 単位)
+const DEFAULT_LONG_TEXT: usize = 100_000;
+/// 最近開いたファイルを一行に並べるときの区切り文字 (ファイル名には使えない文字)
+const PATH_SEPARATOR: &str = "|";
+/// 読み込んだ設定を保持するグローバル変数
+static SETTINGS: OnceLock<Mutex<Settings>> = OnceLock::new();
+
+/// アプリケーションの設定
+#[derive(Clone, Default)]
+pub struct Settings {
+    /// 閉じるボタンでウィンドウを閉じずにタスクトレイに格納する
+    pub close_to_tray: bool,
+    /// ウィンドウを常に最前面に表示する
+    pub always_on_top: bool,
+    /// 前回終了時のウィンドウの位置と大きさ
+    pub window_rect: Option<WindowRect>,
+    /// 前回終了時にウィンドウが最大化されていたかどうか
+    pub maximized: bool,
+    /// 表示言語 (None の場合はユーザーの UI 言語に従う)
+    pub language: Option<Lang>,
+    /// 音声と読み上げ速度のパネルを折りたたんでいるかどうか
+    pub panel_collapsed: bool,
+    /// 最近開いたファイル (新しい順)
+    pub recent_files: Vec<PathBuf>,
+    /// 二重起動を許可する (false の場合は起動済みのウィンドウに引数を転送する)
+    pub allow_multiple_instances: bool,
+    /// 別のインスタンスから転送されたテキストをすぐに読み上げる
+    pub play_forwarded: bool,
+    /// 名前付きパイプでほかのプログラムからのコマンドを受け付ける
+    pub pipe_server: bool,
+    /// 起動時にクリップボードのテキストを読み込んで読み上げる
+    pub speak_clipboard_at_launch: bool,
+    /// 文ごとに書き出すファイル名のパターン (None の場合は既定のパターン)
+    pub sentence_pattern: Option<String>,
+    /// 文ごとに書き出すときに、ファイル名と文の一覧 (CSV) も書き出す
+    pub sentence_manifest: bool,
+    /// 再生や保存の前に確認する長いテキストの文字数 (None の場合は既定の文字数、0 で確認しない)
+    pub long_text: Option<usize>,
+    /// エディットコントロールのフォント (None の場合は既定のフォント)
+    pub edit_font: Option<EditFont>,
+    /// クイック入力モード (エディットコントロールで Enter キーを押すとすぐに読み上げる)
+    pub quick_input: bool,
+    /// 最後に WAV ファイルを保存したフォルダー (保存ダイアログの初期フォルダー)
+    pub last_save_dir: Option<PathBuf>,
+    /// エディットコントロールのテキストを自動保存しない (プライバシーのため)
+    pub autosave_disabled: bool,
+    /// 読み上げの履歴をファイルに保存して次回に復元する
+    pub save_history: bool,
+    /// WAV ファイルと一緒にスピーチマーク (単語と文のタイミング) を保存する
+    pub speech_marks: bool,
+    /// WAV ファイルを保存するときに、ピークが一定になるように音量を正規化する
+    pub normalize_volume: bool,
+    /// WAV ファイルを保存するときに、前後の無音を切り取る
+    pub trim_silence: bool,
+    /// 前後の無音を切り取るときに残す長さ (ミリ秒、None の場合は既定の長さ)
+    pub trim_margin_ms: Option<u64>,
+    /// 保存する WAV のサンプリングレート (None の場合は合成したまま)
+    pub output_sample_rate: Option<u32>,
+    /// 保存する WAV をモノラルにまとめる
+    pub output_mono: bool,
+    /// 読み上げ中は他のアプリケーションの音声を小さくする
+    pub duck_others: bool,
+    /// 合成する前に日付を読み方に直す
+    pub normalize_dates: bool,
+    /// 合成する前に数値の桁区切りを取り除く
+    pub normalize_numbers: bool,
+    /// 合成する前に URL をドメインごとに読むように直す
+    pub normalize_urls: bool,
+    /// 段落と段落の間に入れる無音の長さ (ミリ秒、None の場合は既定の長さ)
+    pub paragraph_pause_ms: Option<u64>,
+    /// 既定から変更されたショートカットキー
+    pub hotkeys: BTreeMap<Action, Hotkey>,
+    /// 音声の ID ごとに最後に使った読み上げ速度
+    pub voice_rates: BTreeMap<String, f64>,
+    /// 読み上げるテキストの言語を推定して、その言語の音声で読み上げる
+    pub auto_voice: bool,
+    /// 言語 (`ja` など主言語の部分) ごとに最後に選んだ音声の ID
+    pub language_voices: BTreeMap<String, String>,
+    /// 言語の混ざったテキストを、言語の区間ごとにその言語の音声で読み上げる
+    pub mixed_voices: bool,
+    /// 再生中はシステムのスリープを防ぐ
+    pub prevent_sleep: bool,
+    /// スリープを防いでいる間は画面も消さない
+    pub keep_display_on: bool,
+}
+
+/// ウィンドウの位置と大きさ (`left,top,right,bottom` 形式で保存する)
+#[derive(Clone, Copy)]
+pub struct WindowRect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl FromStr for WindowRect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let v = s
+            .split(',')
+            .map(|v| v.trim().parse())
+            .collect::<Result<Vec<i32>, _>>()?;
+        let [left, top, right, bottom] = v[..] else {
+            anyhow::bail!("invalid window rect.");
+        };
+        Ok(Self {
+            left,
+            top,
+            right,
+            bottom,
+        })
+    }
+}
+
+impl fmt::Display for WindowRect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.left, self.top, self.right, self.bottom
+        )
+    }
+}
+
+/// エディットコントロールのフォント (`大きさ,太さ,書体名` 形式で保存する)
+#[derive(Clone, PartialEq)]
+pub struct EditFont {
+    /// 書体名
+    pub face: String,
+    /// 大きさ (1/10 ポイント単位)
+    pub size: i32,
+    /// 太さ (400 が標準、700 が太字)
+    pub weight: i32,
+}
+
+impl Default for EditFont {
+    /// 長い文章を読みやすい既定のフォント (Yu Gothic UI の 11 ポイント)
+    fn default() -> Self {
+        Self {
+            face: "Yu Gothic UI".into(),
+            size: 110,
+            weight: 400,
+        }
+    }
+}
+
+impl FromStr for EditFont {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // 書体名にはカンマが含まれることがあるので最後に置く
+        let mut fields = s.splitn(3, ',');
+        let (Some(size), Some(weight), Some(face)) = (fields.next(), fields.next(), fields.next())
+        else {
+            anyhow::bail!("invalid font.");
+        };
+        anyhow::ensure!(!face.trim().is_empty(), "invalid font.");
+        Ok(Self {
+            face: face.trim().into(),
+            size: size.trim().parse()?,
+            weight: weight.trim().parse()?,
+        })
+    }
+}
+
+impl fmt::Display for EditFont {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{}", self.size, self.weight, self.face)
+    }
+}
+
+impl Settings {
+    /// `key=value` 形式のテキストから設定を読み込む。不明なキーや不正な値は無視する
+    fn parse(text: &str) -> Self {
+        let map = text
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .collect::<BTreeMap<_, _>>();
+        let mut settings = Self::default();
+        read(&map, "close_to_tray", &mut settings.close_to_tray);
+        read(&map, "always_on_top", &mut settings.always_on_top);
+        read_option(&map, "window_rect", &mut settings.window_rect);
+        read(&map, "maximized", &mut settings.maximized);
+        read_option(&map, "language", &mut settings.language);
+        read(&map, "panel_collapsed", &mut settings.panel_collapsed);
+        read(
+            &map,
+            "allow_multiple_instances",
+            &mut settings.allow_multiple_instances,
+        );
+        read(&map, "play_forwarded", &mut settings.play_forwarded);
+        read(&map, "pipe_server", &mut settings.pipe_server);
+        read(
+            &map,
+            "speak_clipboard_at_launch",
+            &mut settings.speak_clipboard_at_launch,
+        );
+        read_option(&map, "sentence_pattern", &mut settings.sentence_pattern);
+        read(&map, "sentence_manifest", &mut settings.sentence_manifest);
+        read_option(&map, "edit_font", &mut settings.edit_font);
+        read_option(&map, "long_text", &mut settings.long_text);
+        read(&map, "autosave_disabled", &mut settings.autosave_disabled);
+        read(&map, "save_history", &mut settings.save_history);
+        read(&map, "speech_marks", &mut settings.speech_marks);
+        read(&map, "normalize_volume", &mut settings.normalize_volume);
+        read(&map, "trim_silence", &mut settings.trim_silence);
+        read_option(&map, "trim_margin_ms", &mut settings.trim_margin_ms);
+        read_option(&map, "output_sample_rate", &mut settings.output_sample_rate);
+        read(&map, "output_mono", &mut settings.output_mono);
+        read(&map, "duck_others", &mut settings.duck_others);
+        read(&map, "normalize_dates", &mut settings.normalize_dates);
+        read(&map, "normalize_numbers", &mut settings.normalize_numbers);
+        read(&map, "normalize_urls", &mut settings.normalize_urls);
+        read_option(&map, "paragraph_pause_ms", &mut settings.paragraph_pause_ms);
+        read_option(&map, "last_save_dir", &mut settings.last_save_dir);
+        read(&map, "quick_input", &mut settings.quick_input);
+        read(&map, "auto_voice", &mut settings.auto_voice);
+        read(&map, "mixed_voices", &mut settings.mixed_voices);
+        read(&map, "prevent_sleep", &mut settings.prevent_sleep);
+        read(&map, "keep_display_on", &mut settings.keep_display_on);
+        for action in Action::ALL {
+            if let Some(hotkey) = map.get(action.setting_key()).and_then(|v| v.parse().ok()) {
+                settings.hotkeys.insert(action, hotkey);
+            }
+        }
+        if let Some(rates) = map.get("voice_rates") {
+            settings.voice_rates = rates
+                .split(PATH_SEPARATOR)
+                .filter_map(|entry| {
+                    let (rate, id) = entry.split_once(',')?;
+                    Some((id.to_string(), rate.parse().ok()?))
+                })
+                .collect();
+        }
+        if let Some(voices) = map.get("language_voices") {
+            settings.language_voices = voices
+                .split(PATH_SEPARATOR)
+                .filter_map(|entry| {
+                    let (language, id) = entry.split_once(',')?;
+                    Some((language.to_string(), id.to_string()))
+                })
+                .collect();
+        }
+        if let Some(files) = map.get("recent_files") {
+            settings.recent_files = files
+                .split(PATH_SEPARATOR)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                .collect();
+        }
+        settings
+    }
+
+    /// `key=value` 形式のテキストに変換する
+    fn serialize(&self) -> String {
+        let mut text = String::new();
+        _ = writeln!(text, "close_to_tray={}", self.close_to_tray);
+        _ = writeln!(text, "always_on_top={}", self.always_on_top);
+        if let Some(rect) = self.window_rect {
+            _ = writeln!(text, "window_rect={rect}");
+        }
+        _ = writeln!(text, "maximized={}", self.maximized);
+        if let Some(language) = self.language {
+            _ = writeln!(text, "language={language}");
+        }
+        _ = writeln!(text, "panel_collapsed={}", self.panel_collapsed);
+        _ = writeln!(
+            text,
+            "allow_multiple_instances={}",
+            self.allow_multiple_instances
+        );
+        _ = writeln!(text, "play_forwarded={}", self.play_forwarded);
+        _ = writeln!(text, "pipe_server={}", self.pipe_server);
+        _ = writeln!(
+            text,
+            "speak_clipboard_at_launch={}",
+            self.speak_clipboard_at_launch
+        );
+        if let Some(pattern) = &self.sentence_pattern {
+            _ = writeln!(text, "sentence_pattern={pattern}");
+        }
+        _ = writeln!(text, "sentence_manifest={}", self.sentence_manifest);
+        if let Some(font) = &self.edit_font {
+            _ = writeln!(text, "edit_font={font}");
+        }
+        if let Some(len) = self.long_text {
+            _ = writeln!(text, "long_text={len}");
+        }
+        _ = writeln!(text, "autosave_disabled={}", self.autosave_disabled);
+        _ = writeln!(text, "save_history={}", self.save_history);
+        _ = writeln!(text, "speech_marks={}", self.speech_marks);
+        _ = writeln!(text, "normalize_volume={}", self.normalize_volume);
+        _ = writeln!(text, "trim_silence={}", self.trim_silence);
+        if let Some(ms) = self.trim_margin_ms {
+            _ = writeln!(text, "trim_margin_ms={ms}");
+        }
+        if let Some(rate) = self.output_sample_rate {
+            _ = writeln!(text, "output_sample_rate={rate}");
+        }
+        _ = writeln!(text, "output_mono={}", self.output_mono);
+        _ = writeln!(text, "duck_others={}", self.duck_others);
+        _ = writeln!(text, "normalize_dates={}", self.normalize_dates);
+        _ = writeln!(text, "normalize_numbers={}", self.normalize_numbers);
+        _ = writeln!(text, "normalize_urls={}", self.normalize_urls);
+        if let Some(ms) = self.paragraph_pause_ms {
+            _ = writeln!(text, "paragraph_pause_ms={ms}");
+        }
+        if let Some(dir) = &self.last_save_dir {
+            _ = writeln!(text, "last_save_dir={}", dir.display());
+        }
+        _ = writeln!(text, "quick_input={}", self.quick_input);
+        _ = writeln!(text, "auto_voice={}", self.auto_voice);
+        _ = writeln!(text, "mixed_voices={}", self.mixed_voices);
+        _ = writeln!(text, "prevent_sleep={}", self.prevent_sleep);
+        _ = writeln!(text, "keep_display_on={}", self.keep_display_on);
+        for (action, hotkey) in &self.hotkeys {
+            _ = writeln!(text, "{}={hotkey}", action.setting_key());
+        }
+        if !self.voice_rates.is_empty() {
+            // ID にカンマがあっても分けられるように、速度を先に書いて最初のカンマで区切る
+            let rates = self
+                .voice_rates
+                .iter()
+                .map(|(id, rate)| format!("{rate},{id}"))
+                .collect::<Vec<_>>();
+            _ = writeln!(text, "voice_rates={}", rates.join(PATH_SEPARATOR));
+        }
+        if !self.language_voices.is_empty() {
+            let voices = self
+                .language_voices
+                .iter()
+                .map(|(language, id)| format!("{language},{id}"))
+                .collect::<Vec<_>>();
+            _ = writeln!(text, "language_voices={}", voices.join(PATH_SEPARATOR));
+        }
+        if !self.recent_files.is_empty() {
+            let files = self
+                .recent_files
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect::<Vec<_>>();
+            _ = writeln!(text, "recent_files={}", files.join(PATH_SEPARATOR));
+        }
+        text
+    }
+
+    /// 操作に割り当てられたショートカットキー
+    pub fn hotkey(&self, action: Action) -> Hotkey {
+        self.hotkeys
+            .get(&action)
+            .copied()
+            .unwrap_or_else(|| action.default_hotkey())
+    }
+
+    /// 段落と段落の間に入れる無音の長さ
+    pub fn paragraph_pause(&self) -> Duration {
+        self.paragraph_pause_ms
+            .map_or(synthesis::DEFAULT_PARAGRAPH_PAUSE, Duration::from_millis)
+    }
+
+    /// 前後の無音を切り取るときに残す長さ。切り取らない設定なら None
+    pub fn trim_margin(&self) -> Option<Duration> {
+        self.trim_silence.then(|| {
+            self.trim_margin_ms
+                .map_or(wav::DEFAULT_TRIM_MARGIN, Duration::from_millis)
+        })
+    }
+
+    /// 保存する WAV の形式
+    pub fn output_format(&self) -> wav::OutputFormat {
+        wav::OutputFormat {
+            sample_rate: self.output_sample_rate,
+            mono: self.output_mono,
+        }
+    }
+
+    /// テキストが再生や保存の前に確認するほど長いかどうか
+    pub fn is_long_text(&self, len: usize) -> bool {
+        let limit = self.long_text.unwrap_or(DEFAULT_LONG_TEXT);
+        limit > 0 && len > limit
+    }
+
+    /// 設定ファイルに保存する
+    pub fn save(&self) -> Result<()> {
+        let path = settings_path()?;
+        fs::create_dir_all(path.parent().context("no parent directory.")?)?;
+        fs::write(path, self.serialize())?;
+        Ok(())
+    }
+}
+
+/// キーに対応する値があり、正しく変換できる場合だけ設定値を上書きする
+fn read<T: FromStr>(map: &BTreeMap<&str, &str>, key: &str, value: &mut T) {
+    if let Some(v) = map.get(key).and_then(|v| v.parse().ok()) {
+        *value = v;
+    }
+}
+
+/// 省略可能な設定値を読み込む
+fn read_option<T: FromStr>(map: &BTreeMap<&str, &str>, key: &str, value: &mut Option<T>) {
+    if let Some(v) = map.get(key).and_then(|v| v.parse().ok()) {
+        *value = Some(v);
+    }
+}
+
+/// 設定ファイルなどを置くディレクトリ (%APPDATA%\speech)
+pub fn config_dir() -> Result<PathBuf> {
+    let app_data = std::env::var_os("APPDATA").context("no APPDATA.")?;
+    Ok(PathBuf::from(app_data).join("speech"))
+}
+
+fn settings_path() -> Result<PathBuf> {
+    Ok(config_dir()?.join(FILE_NAME))
+}
+
+fn load() -> Settings {
+    settings_path()
+        .and_then(|path| Ok(fs::read_to_string(path)?))
+        .map(|text| Settings::parse(&text))
+        .unwrap_or_default()
+}
+
+/// 保存した置換ルールを読み込む。ファイルがなければ空にする
+pub fn load_rules() -> Result<Vec<Rule>> {
+    let path = config_dir()?.join(rules::FILE_NAME);
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(rules::parse(&text)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 置換ルールを保存して、以降の合成に適用する
+pub fn save_rules(list: &[Rule]) -> Result<()> {
+    let active = RuleSet::new(list)?;
+    let path = config_dir()?.join(rules::FILE_NAME);
+    fs::create_dir_all(path.parent().context("no parent directory.")?)?;
+    fs::write(path, rules::serialize(list))?;
+    rules::set_active(active);
+    Ok(())
+}
+
+/// 保存した置換ルールと読み方の辞書、合成する前の整形と段落の間隔の設定を読み込んで、以降の合成に適用する
+pub fn activate_rules() -> Result<()> {
+    activate_normalizers();
+    synthesis::set_paragraph_pause(get().paragraph_pause());
+    rules::set_active(RuleSet::new(&load_rules()?)?);
+    lexicon::set_active(Lexicon::new(&load_lexicon()?));
+    Ok(())
+}
+
+/// 設定で有効にした整形を、以降の合成に適用する
+pub fn activate_normalizers() {
+    let settings = get();
+    normalize::set_active(normalize::Options {
+        dates: settings.normalize_dates,
+        numbers: settings.normalize_numbers,
+        urls: settings.normalize_urls,
+    });
+}
+
+/// 保存した読み方の辞書を読み込む。ファイルがなければ空にする
+pub fn load_lexicon() -> Result<Vec<Entry>> {
+    let path = config_dir()?.join(lexicon::FILE_NAME);
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(lexicon::parse(&text)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 読み方の辞書を保存して、以降の合成に適用する
+pub fn save_lexicon(entries: &[Entry]) -> Result<()> {
+    let path = config_dir()?.join(lexicon::FILE_NAME);
+    fs::create_dir_all(path.parent().context("no parent directory.")?)?;
+    fs::write(path, lexicon::serialize(entries))?;
+    lexicon::set_active(Lexicon::new(entries));
+    Ok(())
+}
+
+/// 現在の設定を取得する。初回呼び出し時に設定ファイルを読み込む
+pub fn get() -> MutexGuard<'static, Settings> {
+    SETTINGS.get_or_init(|| Mutex::new(load())).lock().unwrap()
+}
+
+/// 設定を変更して設定ファイルに保存する
+pub fn update(f: impl FnOnce(&mut Settings)) -> Result<()> {
+    let mut settings = get();
+    f(&mut settings);
+    settings.save()
+}
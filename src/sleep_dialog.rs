@@ -0,0 +1,156 @@
+use crate::dialog::{self, create_control};
+use crate::dpi::LogicalRect;
+use crate::strings::{self, Msg};
+use anyhow::Result;
+use std::time::Duration;
+use windows::{
+    core::{w, HSTRING, PCWSTR},
+    Win32::{
+        Foundation::{HWND, LPARAM, LRESULT, WPARAM},
+        UI::{
+            Controls::{
+                UDM_GETPOS32, UDM_SETBUDDY, UDM_SETPOS32, UDM_SETRANGE32, UDS_ALIGNRIGHT,
+                UDS_ARROWKEYS, UDS_NOTHOUSANDS, UDS_SETBUDDYINT, UPDOWN_CLASS,
+            },
+            Input::KeyboardAndMouse::SetFocus,
+            WindowsAndMessaging::{
+                DefWindowProcW, GetDlgItem, GetWindow, SendMessageW, BS_DEFPUSHBUTTON,
+                BS_PUSHBUTTON, ES_NUMBER, GW_OWNER, IDCANCEL, IDOK, WINDOW_STYLE, WM_CLOSE,
+                WM_COMMAND, WM_CREATE, WS_BORDER, WS_TABSTOP,
+            },
+        },
+    },
+};
+
+/// スリープタイマーの時間を入力するダイアログのクラス名
+const CLASS_NAME: PCWSTR = w!("speech_sleep_cls42");
+/// 分数を入力するエディットの ID
+const ID_MINUTES: u16 = 100;
+/// 分数のアップダウンコントロールの ID
+const ID_MINUTES_SPIN: u16 = 101;
+/// 最初に表示する分数
+const DEFAULT_MINUTES: u32 = 60;
+/// 入力できる最大の分数
+const MAX_MINUTES: u32 = 600;
+/// ダイアログのクライアント領域の大きさ (96 DPI 基準)
+const CLIENT_SIZE: (i32, i32) = (280, 96);
+/// 説明のラベルの配置
+const LABEL_RECT: LogicalRect = LogicalRect::new(12, 16, 170, 20);
+/// 分数のエディットの配置
+const MINUTES_RECT: LogicalRect = LogicalRect::new(190, 12, 78, 24);
+/// OK ボタンの配置
+const OK_RECT: LogicalRect = LogicalRect::new(92, 56, 84, 28);
+/// キャンセルボタンの配置
+const CANCEL_RECT: LogicalRect = LogicalRect::new(184, 56, 84, 28);
+
+/// スリープタイマーの時間を分単位で入力するダイアログをモーダルで表示する
+///
+/// OK を押すとメインウィンドウのスリープタイマーを設定する。ダイアログが閉じられるまで戻らない。
+pub fn show(owner: HWND) -> Result<()> {
+    dialog::show_modal(
+        owner,
+        CLASS_NAME,
+        Some(wnd_proc),
+        Msg::SleepTitle,
+        CLIENT_SIZE,
+    )
+}
+
+fn create(hwnd: HWND) -> Result<()> {
+    create_control(
+        hwnd,
+        w!("STATIC"),
+        &strings::wide(Msg::LabelSleepMinutes),
+        WINDOW_STYLE::default(),
+        LABEL_RECT,
+        0,
+    )?;
+    let minutes = create_control(
+        hwnd,
+        w!("EDIT"),
+        &HSTRING::new(),
+        WINDOW_STYLE(ES_NUMBER as _) | WS_BORDER | WS_TABSTOP,
+        MINUTES_RECT,
+        ID_MINUTES,
+    )?;
+    // バディに付けると、アップダウンコントロールはエディットの右端に配置される
+    let spin = create_control(
+        hwnd,
+        UPDOWN_CLASS,
+        &HSTRING::new(),
+        WINDOW_STYLE(UDS_SETBUDDYINT | UDS_ALIGNRIGHT | UDS_ARROWKEYS | UDS_NOTHOUSANDS),
+        LogicalRect::new(0, 0, 0, 0),
+        ID_MINUTES_SPIN,
+    )?;
+    unsafe {
+        SendMessageW(spin, UDM_SETBUDDY, WPARAM(minutes.0 as _), None);
+        SendMessageW(spin, UDM_SETRANGE32, WPARAM(1), LPARAM(MAX_MINUTES as _));
+        SendMessageW(spin, UDM_SETPOS32, None, LPARAM(DEFAULT_MINUTES as _));
+    }
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ButtonOk),
+        WINDOW_STYLE(BS_DEFPUSHBUTTON as _) | WS_TABSTOP,
+        OK_RECT,
+        IDOK.0 as _,
+    )?;
+    create_control(
+        hwnd,
+        w!("BUTTON"),
+        &strings::wide(Msg::ButtonCancel),
+        WINDOW_STYLE(BS_PUSHBUTTON as _) | WS_TABSTOP,
+        CANCEL_RECT,
+        IDCANCEL.0 as _,
+    )?;
+    _ = unsafe { SetFocus(minutes) };
+    Ok(())
+}
+
+/// 入力された時間でメインウィンドウのスリープタイマーを設定する
+fn apply(hwnd: HWND) -> Result<()> {
+    let spin = unsafe { GetDlgItem(hwnd, ID_MINUTES_SPIN as _)? };
+    let pos = unsafe { SendMessageW(spin, UDM_GETPOS32, None, None) }.0;
+    let minutes = u32::try_from(pos)
+        .unwrap_or(DEFAULT_MINUTES)
+        .clamp(1, MAX_MINUTES);
+    let owner = unsafe { GetWindow(hwnd, GW_OWNER)? };
+    crate::arm_sleep_timer(owner, Duration::from_secs(u64::from(minutes) * 60))?;
+    dialog::close(hwnd)
+}
+
+fn command(hwnd: HWND, wparam: WPARAM) -> Result<()> {
+    let id = crate::loword(wparam.0 as _);
+    if id == IDOK.0 as u16 {
+        apply(hwnd)?;
+    } else if id == IDCANCEL.0 as u16 {
+        dialog::close(hwnd)?;
+    }
+    Ok(())
+}
+
+/// ダイアログのウィンドウプロシージャ
+unsafe extern "system" fn wnd_proc(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match msg {
+        WM_CREATE => {
+            if create(hwnd).is_err() {
+                return LRESULT(-1);
+            }
+        }
+        WM_COMMAND => {
+            if let Err(e) = command(hwnd, wparam) {
+                crate::report_error(hwnd, Msg::ErrorCommand, &e);
+            }
+        }
+        WM_CLOSE => {
+            dialog::close(hwnd).ok();
+        }
+        _ => return DefWindowProcW(hwnd, msg, wparam, lparam),
+    }
+    LRESULT::default()
+}
@@ -0,0 +1,227 @@
+//! Amazon Polly 形式のスピーチマーク (単語と文のタイミング)
+//!
+//! 合成したストリームの単語と文の境界のキューから、一行に一つの JSON オブジェクトを並べたファイルを作る。
+//! 位置は合成する前に整形や置換をする前のテキストでの UTF-16 単位で、置き換えた語句はその語句全体の範囲にする。
+
+use crate::offsets::OffsetMap;
+use anyhow::Result;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use windows::{
+    core::Interface,
+    Media::{
+        Core::SpeechCue,
+        SpeechSynthesis::{SpeechSynthesisStream, SpeechSynthesizer},
+    },
+};
+
+/// WAV ファイルと一緒に保存するスピーチマークの拡張子
+pub const EXTENSION: &str = "marks.json";
+
+/// 境界の種類 (同じ時刻なら文を先に並べる)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Sentence,
+    Word,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Sentence => "sentence",
+            Self::Word => "word",
+        }
+    }
+}
+
+/// 単語か文が読み上げられ始める時刻と、テキストでの範囲
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mark {
+    pub time: Duration,
+    pub kind: Kind,
+    /// 開始位置 (UTF-16 単位)
+    pub start: usize,
+    /// 終了位置 (UTF-16 単位、この位置は含まない)
+    pub end: usize,
+    pub value: String,
+}
+
+impl Mark {
+    /// テキストの start の位置から始まり、time 後に再生するチャンクのマークを、全体での位置と時刻にずらす
+    pub fn shifted(self, start: usize, time: Duration) -> Self {
+        Self {
+            time: self.time + time,
+            start: self.start + start,
+            end: self.end + start,
+            ..self
+        }
+    }
+
+    /// 整形や置換をした後のテキストでの位置を、map で置き換える前のテキストの位置に戻す
+    pub fn mapped(self, map: &OffsetMap) -> Self {
+        Self {
+            start: map.start(self.start),
+            end: map.end(self.end),
+            ..self
+        }
+    }
+
+    /// `{"time":370,"type":"word","start":5,"end":9,"value":"mars"}` の形の JSON
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"time":{},"type":"{}","start":{},"end":{},"value":"{}"}}"#,
+            self.time.as_millis(),
+            self.kind.name(),
+            self.start,
+            self.end,
+            escape(&self.value)
+        )
+    }
+}
+
+/// マークを時刻の順に並べ、一行に一つずつ JSON にする
+pub fn to_json_lines(marks: &[Mark]) -> String {
+    let mut marks = marks.iter().collect::<Vec<_>>();
+    marks.sort_by_key(|mark| (mark.time, mark.kind));
+    marks.iter().map(|mark| mark.to_json() + "\n").collect()
+}
+
+/// WAV ファイルと一緒に保存するスピーチマークのパス (name.wav なら name.marks.json)
+pub fn path_for(wav: &Path) -> PathBuf {
+    wav.with_extension(EXTENSION)
+}
+
+/// スピーチマークをファイルに保存する
+pub fn write(marks: &[Mark], path: &Path) -> Result<()> {
+    fs::write(path, to_json_lines(marks))?;
+    Ok(())
+}
+
+/// 合成するストリームに単語の境界のキューを含めるかどうかを切り替える
+///
+/// 文の境界のキューは、再生中に前後の文に移るためにいつも含めている ([crate::synthesis::apply_options])。
+pub fn set_enabled(synth: &SpeechSynthesizer, enabled: bool) -> Result<()> {
+    synth.Options()?.SetIncludeWordBoundaryMetadata(enabled)?;
+    Ok(())
+}
+
+/// 合成したストリームのキューからマークを作る (キューを含めずに合成した場合は空)
+///
+/// 位置と時刻はそのストリームのテキストの先頭からになる。
+pub fn from_stream(stream: &SpeechSynthesisStream) -> Result<Vec<Mark>> {
+    let mut marks = vec![];
+    for track in stream.TimedMetadataTracks()? {
+        let kind = match track.Id()?.to_string().as_str() {
+            "SpeechWord" => Kind::Word,
+            "SpeechSentence" => Kind::Sentence,
+            _ => continue,
+        };
+        for cue in track.Cues()? {
+            let cue = cue.cast::<SpeechCue>()?;
+            let start = cue.StartPositionInInput()?.Value()?.max(0) as usize;
+            // 終了位置は最後の文字の位置なので、含まない位置にする
+            let end = cue.EndPositionInInput()?.Value()?.max(0) as usize + 1;
+            marks.push(Mark {
+                time: Duration::from_nanos(cue.StartTime()?.Duration.max(0) as u64 * 100),
+                kind,
+                start,
+                end: end.max(start),
+                value: cue.Text()?.to_string(),
+            });
+        }
+    }
+    Ok(marks)
+}
+
+/// JSON の文字列に入れられるようにエスケープする
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => _ = write!(escaped, "\\u{:04x}", c as u32),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{Rule, RuleSet};
+
+    fn mark(ms: u64, kind: Kind, start: usize, end: usize, value: &str) -> Mark {
+        Mark {
+            time: Duration::from_millis(ms),
+            kind,
+            start,
+            end,
+            value: value.into(),
+        }
+    }
+
+    #[test]
+    fn write_one_object_per_line() {
+        let marks = [
+            mark(370, Kind::Word, 5, 9, "mars"),
+            mark(6, Kind::Word, 0, 4, "Mars"),
+            mark(6, Kind::Sentence, 0, 10, "Mars mars."),
+        ];
+        assert_eq!(
+            to_json_lines(&marks),
+            concat!(
+                r#"{"time":6,"type":"sentence","start":0,"end":10,"value":"Mars mars."}"#,
+                "\n",
+                r#"{"time":6,"type":"word","start":0,"end":4,"value":"Mars"}"#,
+                "\n",
+                r#"{"time":370,"type":"word","start":5,"end":9,"value":"mars"}"#,
+                "\n",
+            )
+        );
+        assert_eq!(to_json_lines(&[]), "");
+    }
+
+    #[test]
+    fn escape_quotes_and_newlines() {
+        let value = "「\"引用\"」\r\n\\\t\u{1}";
+        assert_eq!(
+            mark(0, Kind::Sentence, 0, 9, value).to_json(),
+            r#"{"time":0,"type":"sentence","start":0,"end":9,"value":"「\"引用\"」\r\n\\\t\u0001"}"#
+        );
+    }
+
+    #[test]
+    fn point_into_the_text_before_the_rules() {
+        let rules = RuleSet::new(&[Rule {
+            pattern: r"v(\d+)".into(),
+            replacement: "バージョン$1".into(),
+            enabled: true,
+        }])
+        .unwrap();
+        let (replaced, map) = rules.apply_mapped("v2 の新機能");
+        assert_eq!(replaced, "バージョン2 の新機能");
+        // 置き換えた後ろの語句は、長くなった分だけ前に戻す
+        let word = mark(500, Kind::Word, 9, 12, "新機能").mapped(&map);
+        assert_eq!(word, mark(500, Kind::Word, 5, 8, "新機能"));
+        // 置き換えた語句の一部は、置き換える前の語句全体にする
+        let word = mark(0, Kind::Word, 0, 5, "バージョン").mapped(&map);
+        assert_eq!(word, mark(0, Kind::Word, 0, 2, "バージョン"));
+    }
+
+    #[test]
+    fn shift_to_the_whole_text() {
+        let shifted = mark(100, Kind::Word, 2, 4, "テスト").shifted(200, Duration::from_secs(3));
+        assert_eq!(shifted, mark(3100, Kind::Word, 202, 204, "テスト"));
+        assert_eq!(
+            path_for(Path::new("out/今日.wav")),
+            Path::new("out/今日.marks.json")
+        );
+    }
+}
@@ -0,0 +1,728 @@
+use crate::panel;
+use crate::playback::{AppEngine, AppSynth, Engine, Playback, Voice};
+use crate::schedule::Schedule;
+use anyhow::{bail, ensure, Context, Result};
+use speech::error::SpeechError;
+use speech::synthesis::{self, CancelHandle, SynthOptions};
+use speech::utf16;
+use std::cell::{Cell, RefCell};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use windows::{
+    core::HSTRING,
+    Win32::{
+        Foundation::{HWND, LPARAM, WPARAM},
+        UI::Controls::{TBM_GETPOS, UDM_GETPOS32},
+        UI::WindowsAndMessaging::{
+            GetDlgItem, GetWindowLongPtrW, GetWindowTextLengthW, GetWindowTextW, SendMessageW,
+            SetWindowLongPtrW, CB_GETCOUNT, CB_GETCURSEL, CB_GETITEMDATA, CB_SETCURSEL, EM_GETSEL,
+            GWLP_USERDATA,
+        },
+    },
+};
+
+/// メインウィンドウごとの状態
+///
+/// WM_CREATE で生成して GWLP_USERDATA に保持し、WM_NCDESTROY で破棄する。
+/// 子コントロールは作り直せるように [Cell] で持つ。
+#[derive(Default)]
+pub struct AppState {
+    /// 状態を持たせたメインウィンドウ (子コントロールは ID で探す)
+    window: Cell<HWND>,
+    /// コンボボックスに追加した音声 (項目データにこの位置を持たせる)
+    pub voices: RefCell<Vec<Voice>>,
+    /// 合成の完了ハンドラやメディアのイベントと共有する再生の状態
+    pub playback: Arc<Playback<AppEngine>>,
+    /// WAV ファイルに保存中の合成を取り消すハンドル
+    pub saving: RefCell<Option<CancelHandle>>,
+    /// 再生ボタンの二重押しを見分けるための最後の再生
+    pub play_guard: PlayGuard,
+    /// 停止した位置から続きを再生するための位置
+    pub resume: ResumePoint,
+    /// 再生を自動で停止するスリープタイマー
+    pub sleep_timer: SleepTimer,
+    /// 時刻を指定した再生の予約
+    pub schedule: RefCell<Option<Schedule>>,
+    /// 取り消した保存が終わるのを待ってから閉じるかどうか
+    pub closing: Cell<bool>,
+    /// 使い回す音声合成エンジン (音声や速度が変わったら設定を更新する)
+    synthesizer: RefCell<Option<AppSynth>>,
+    /// 設定パネルのコントロールの説明を表示するツールチップ (子ウィンドウではないので ID では探せない)
+    pub tooltip: Cell<HWND>,
+    /// 表示中の検索・置換ダイアログ (閉じていれば無効なハンドル)
+    pub find_dialog: Cell<HWND>,
+}
+
+/// parent の子コントロールを ID で探す (作り直しても同じ ID で見つかる)
+pub fn control(parent: HWND, id: u16) -> Result<HWND> {
+    Ok(unsafe { GetDlgItem(parent, id as _)? })
+}
+
+/// 同じテキストの再生をこれより短い間隔で始めようとしたら二重押しとみなす
+const DOUBLE_PLAY: Duration = Duration::from_millis(500);
+
+/// 再生ボタンのダブルクリックなどで、同じテキストを続けて再生し始めないようにする
+#[derive(Default)]
+pub struct PlayGuard {
+    /// 最後に再生を始めた時刻とテキストのハッシュ
+    last: Cell<Option<(Instant, u64)>>,
+}
+
+impl PlayGuard {
+    /// 直前に同じテキストの再生を始めていれば true を返す。そうでなければ now に始めたことを記録する
+    pub fn is_double_play(&self, text: &[u16], now: Instant) -> bool {
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        let hash = hasher.finish();
+        if let Some((started, last_hash)) = self.last.get() {
+            if last_hash == hash && now.saturating_duration_since(started) < DOUBLE_PLAY {
+                return true;
+            }
+        }
+        self.last.set(Some((now, hash)));
+        false
+    }
+
+    /// 停止や再生の終了で記録を消し、すぐに再生し直せるようにする
+    pub fn clear(&self) {
+        self.last.set(None);
+    }
+}
+
+/// 停止した位置から続きを再生するための位置
+///
+/// エディットのテキストを読み上げている間は範囲を持ち、途中で停止したら続きの位置を覚える。
+/// 最後まで読み上げた場合や、テキストが変わった場合は忘れる。
+#[derive(Default)]
+pub struct ResumePoint {
+    /// 再生中のスピーチが読み上げているエディットのテキストの範囲
+    spoken: Cell<Option<(usize, usize)>>,
+    /// 続きから再生するエディットのテキストの位置
+    offset: Cell<Option<usize>>,
+}
+
+impl ResumePoint {
+    /// スピーチを始めた。spoken はエディットのテキストのうち読み上げる範囲 (ほかのテキストなら None)
+    ///
+    /// ほかのテキストを読み上げても、エディットのテキストの続きの位置は忘れない。
+    pub fn start(&self, spoken: Option<(usize, usize)>) {
+        if spoken.is_some() {
+            self.offset.set(None);
+        }
+        self.spoken.set(spoken);
+    }
+
+    /// 途中で停止した。読み上げていた範囲の先頭から position 単位の位置を続きの位置として覚える
+    pub fn stopped(&self, position: Option<usize>) {
+        if let (Some((start, end)), Some(position)) = (self.spoken.take(), position) {
+            self.offset
+                .set(Some(start + position).filter(|&offset| offset < end));
+        }
+    }
+
+    /// スピーチが終わった。エディットのテキストを最後まで読み上げたら続きの位置を忘れる
+    pub fn finished(&self) {
+        if self.spoken.take().is_some() {
+            self.offset.set(None);
+        }
+    }
+
+    /// テキストが変わったので、位置をすべて忘れる
+    pub fn clear(&self) {
+        self.spoken.set(None);
+        self.offset.set(None);
+    }
+
+    /// 読み上げているエディットのテキストの範囲
+    pub fn spoken(&self) -> Option<(usize, usize)> {
+        self.spoken.get()
+    }
+
+    /// 続きから再生する位置
+    pub fn offset(&self) -> Option<usize> {
+        self.offset.get()
+    }
+}
+
+/// 決めた時間が経ったら再生を停止するスリープタイマー
+///
+/// 時間の間は続けて何度再生しても止めるまで残り、手動で停止したら解除する。
+#[derive(Default)]
+pub struct SleepTimer {
+    /// 設定した時刻と長さ
+    armed: Cell<Option<(Instant, Duration)>>,
+}
+
+impl SleepTimer {
+    /// now から duration 後に停止するように設定する (設定済みなら置き換える)
+    pub fn arm(&self, now: Instant, duration: Duration) {
+        self.armed.set(Some((now, duration)));
+    }
+
+    /// 解除する
+    pub fn cancel(&self) {
+        self.armed.set(None);
+    }
+
+    /// 設定されているかどうか
+    pub fn is_armed(&self) -> bool {
+        self.armed.get().is_some()
+    }
+
+    /// 設定した長さ (メニューでどの項目を選んだか示すため)
+    pub fn duration(&self) -> Option<Duration> {
+        self.armed.get().map(|(_, duration)| duration)
+    }
+
+    /// 停止するまでの残り時間 (設定されていなければ None)
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.armed
+            .get()
+            .map(|(armed, duration)| (armed + duration).saturating_duration_since(now))
+    }
+
+    /// 停止する時刻を過ぎていれば解除して true を返す
+    pub fn expire(&self, now: Instant) -> bool {
+        if self
+            .remaining(now)
+            .is_some_and(|remaining| remaining.is_zero())
+        {
+            self.cancel();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// 残り時間を「12:34」の形にする (1 時間以上なら「1:02:03」)
+pub fn format_remaining(remaining: Duration) -> String {
+    // 残り 0.5 秒を 0:00 と表示しないように切り上げる
+    let secs = remaining.as_millis().div_ceil(1000);
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{h}:{m:02}:{s:02}")
+    } else {
+        format!("{m}:{s:02}")
+    }
+}
+
+/// 数を 3 桁ごとにカンマで区切る (12345 を「12,345」にする)
+pub fn group_digits(n: usize) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+impl AppState {
+    /// 状態を生成してウィンドウに持たせる
+    pub fn attach(hwnd: HWND) {
+        let state = Box::<Self>::default();
+        state.window.set(hwnd);
+        let state = Box::into_raw(state);
+        unsafe { SetWindowLongPtrW(hwnd, GWLP_USERDATA, state as _) };
+    }
+
+    /// ウィンドウから状態を外して破棄する
+    ///
+    /// WM_NCDESTROY より後にはウィンドウへのメッセージが届かないので、参照は残らない。
+    /// 再生中のスピーチを停止し、使い回していた [MediaPlayer](windows::Media::Playback::MediaPlayer) も閉じておく。
+    pub fn detach(hwnd: HWND) {
+        let state = unsafe { SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0) } as *mut Self;
+        if !state.is_null() {
+            let state = unsafe { Box::from_raw(state) };
+            state.playback.close();
+        }
+    }
+
+    /// ウィンドウに持たせた状態
+    ///
+    /// WM_CREATE より前と WM_NCDESTROY より後は状態がないのでエラーを返す。
+    pub fn get<'a>(hwnd: HWND) -> Result<&'a Self> {
+        let state = unsafe { GetWindowLongPtrW(hwnd, GWLP_USERDATA) } as *const Self;
+        ensure!(!state.is_null(), "no app state.");
+        Ok(unsafe { &*state })
+    }
+
+    /// エディットコントロールの [HWND]
+    pub fn edit(&self) -> Result<HWND> {
+        control(self.window.get(), crate::ID_EDIT)
+    }
+
+    /// コンボボックスの [HWND]
+    pub fn combobox(&self) -> Result<HWND> {
+        control(self.panel()?, crate::ID_COMBO)
+    }
+
+    /// トラックバーの [HWND]
+    pub fn trackbar(&self) -> Result<HWND> {
+        control(self.panel()?, crate::ID_TRACKBAR)
+    }
+
+    /// 再生する回数のエディットの [HWND]
+    pub fn repeat_edit(&self) -> Result<HWND> {
+        control(self.panel()?, crate::ID_REPEAT)
+    }
+
+    /// 再生する回数 (1 ～ [crate::MAX_REPEATS])
+    ///
+    /// 設定には保存せず、起動している間だけ最後の値を使う。
+    pub fn repeat_count(&self) -> Result<u32> {
+        let spin = control(self.panel()?, crate::ID_REPEAT_SPIN)?;
+        let pos = unsafe { SendMessageW(spin, UDM_GETPOS32, None, None) }.0;
+        Ok(u32::try_from(pos).unwrap_or(1).clamp(1, crate::MAX_REPEATS))
+    }
+
+    /// コンボボックスとトラックバーを置いたパネルの [HWND]
+    fn panel(&self) -> Result<HWND> {
+        control(self.window.get(), panel::ID_PANEL)
+    }
+
+    /// コンボボックスで選択中の音声
+    ///
+    /// 表示名は長いものや重複するものがあるので、項目データに持たせた位置で探す。
+    /// 何も選択されていない場合は既定の音声を使い、コンボボックスでも選び直す。
+    pub fn selected_voice(&self) -> Result<Voice> {
+        let hwnd = self.combobox()?;
+        let cursel = unsafe { SendMessageW(hwnd, CB_GETCURSEL, None, None) }.0;
+        let item_data =
+            |index| unsafe { SendMessageW(hwnd, CB_GETITEMDATA, WPARAM(index), None) }.0;
+        let voices = self.voices.borrow();
+        if let Some(position) = selected_position(cursel, item_data, voices.len()) {
+            return Ok(voices[position].clone());
+        }
+        drop(voices);
+        let voice = self
+            .playback
+            .engine()
+            .default_voice()
+            .map_err(|_| SpeechError::NoVoiceSelected)?;
+        self.select_voice_id(&voice.id).ok();
+        Ok(voice)
+    }
+
+    /// 選択中の音声がまだ使えるか、最新の音声の一覧で確かめる
+    ///
+    /// 一覧を作ったあとに音声がアンインストールされることがある。
+    pub fn is_selected_voice_available(&self) -> Result<bool> {
+        let wanted = self.selected_voice()?.id;
+        let available = voice_ids(&self.playback.engine().voices()?);
+        Ok(matches!(
+            choose_voice(Some(&wanted), &available, None),
+            VoiceChoice::Found(_)
+        ))
+    }
+
+    /// 使える音声が一つでもあるかどうか
+    pub fn has_voices(&self) -> bool {
+        !self.voices.borrow().is_empty()
+    }
+
+    /// ID の音声をコンボボックスで選ぶ
+    pub fn select_voice_id(&self, id: &HSTRING) -> Result<()> {
+        let hwnd = self.combobox()?;
+        let count = unsafe { SendMessageW(hwnd, CB_GETCOUNT, None, None) }.0;
+        for index in 0..count.max(0) as usize {
+            if self.voice_of_item(hwnd, index)?.id == *id {
+                unsafe { SendMessageW(hwnd, CB_SETCURSEL, WPARAM(index), None) };
+                return Ok(());
+            }
+        }
+        bail!("no voice with id {id}.")
+    }
+
+    /// コンボボックスの index 番目の項目の音声
+    fn voice_of_item(&self, hwnd: HWND, index: usize) -> Result<Voice> {
+        let data = unsafe { SendMessageW(hwnd, CB_GETITEMDATA, WPARAM(index), None) };
+        voice_for_item(&self.voices.borrow(), data.0)
+            .cloned()
+            .context("no voice.")
+    }
+
+    /// トラックバーで設定している読み上げ速度
+    pub fn speaking_rate(&self) -> Result<f64> {
+        let hwnd = self.trackbar()?;
+        let pos = unsafe { SendMessageW(hwnd, TBM_GETPOS, None, None) }.0;
+        synthesis::rate_from_pos(pos)
+    }
+
+    /// コンボボックスとトラックバーで選択中の音声と読み上げ速度から合成の設定を作る
+    pub fn synth_options(&self) -> Result<SynthOptions> {
+        Ok(SynthOptions {
+            voice_id: Some(self.selected_voice()?.id.to_string()),
+            rate: self.speaking_rate()?,
+            ..Default::default()
+        })
+    }
+
+    /// コントロールで選択中の音声と読み上げ速度を音声合成エンジンに反映する
+    ///
+    /// 音声合成エンジンがまだなければ作る。
+    pub fn update_synthesizer(&self) -> Result<()> {
+        // 設定が変わったら、前の設定で合成した結果は使わない
+        self.playback.clear_cache();
+        let options = self.synth_options()?;
+        let engine = self.playback.engine();
+        let mut synthesizer = self.synthesizer.borrow_mut();
+        match synthesizer.as_ref() {
+            Some(synth) => engine.apply_options(synth, &options),
+            None => {
+                *synthesizer = Some(engine.create_synthesizer(&options)?);
+                Ok(())
+            }
+        }
+    }
+
+    /// 選択中の音声と読み上げ速度を設定した音声合成エンジン
+    ///
+    /// 設定を変えるのは UI スレッドだけなので、ロックせずに使い回す。
+    /// 長いテキストの続きは完了ハンドラのスレッドから同じエンジンで合成する。
+    pub fn synthesizer(&self) -> Result<AppSynth> {
+        if self.synthesizer.borrow().is_none() {
+            self.update_synthesizer()?;
+        }
+        self.synthesizer.borrow().clone().context("no synthesizer.")
+    }
+
+    /// エディットコントロールのテキスト (末尾の NUL は含まない)
+    ///
+    /// 選択範囲の切り出しやテキストの書き出しでも使えるように、対になっていないサロゲートは U+FFFD に置き換える。
+    pub fn edit_text(&self) -> Result<Vec<u16>> {
+        let hwnd = self.edit()?;
+        let len = unsafe { GetWindowTextLengthW(hwnd) };
+        let mut buf = vec![0; len as usize + 1];
+        let copied = unsafe { GetWindowTextW(hwnd, &mut buf) };
+        Ok(synthesis::replace_lone_surrogates(&trim_copied(
+            buf, copied,
+        )))
+    }
+
+    /// エディットコントロールのテキストを [String] で返す
+    pub fn edit_string(&self) -> Result<String> {
+        Ok(String::from_utf16_lossy(&self.edit_text()?))
+    }
+
+    /// エディットコントロールの選択範囲 (UTF-16 単位の開始位置と終了位置)
+    pub fn selection(&self) -> Result<(usize, usize)> {
+        let hwnd = self.edit()?;
+        let (mut start, mut end) = (0u32, 0u32);
+        unsafe {
+            SendMessageW(
+                hwnd,
+                EM_GETSEL,
+                WPARAM(&mut start as *mut _ as _),
+                LPARAM(&mut end as *mut _ as _),
+            )
+        };
+        Ok((start as usize, end as usize))
+    }
+
+    /// 選択されているテキスト。何も選択されていない場合は None を返す
+    ///
+    /// 選択範囲がサロゲートペアの途中にかかっていたら、文字の境界に合わせる。
+    pub fn selected_text(&self) -> Result<Option<Vec<u16>>> {
+        let (start, end) = self.selection()?;
+        if start == end {
+            return Ok(None);
+        }
+        let text = self.edit_text()?;
+        let selected = utf16::slice_at_char_boundary(&text, start, end);
+        Ok((!selected.is_empty()).then(|| selected.to_vec()))
+    }
+
+    /// 読み上げられるテキストがあるかどうか (空白だけの場合は false)
+    pub fn has_speakable_text(&self) -> Result<bool> {
+        Ok(synthesis::prepare_text(&self.edit_text()?).is_ok())
+    }
+
+    /// エディットコントロールが空かどうか
+    pub fn is_edit_empty(&self) -> Result<bool> {
+        Ok(unsafe { GetWindowTextLengthW(self.edit()?) } == 0)
+    }
+}
+
+/// GetWindowTextW で実際にコピーされた文字数に切り詰める
+///
+/// 長さは多めに返ることがあるので、バッファの残りの NUL を合成に渡さないようにする。
+fn trim_copied(mut buf: Vec<u16>, copied: i32) -> Vec<u16> {
+    buf.truncate(usize::try_from(copied).unwrap_or(0).min(buf.len()));
+    buf
+}
+
+/// コンボボックスの選択から音声の一覧の位置を決める
+///
+/// 選択がない (CB_ERR) 場合や、項目データが一覧の範囲外の場合は None を返す。
+fn selected_position(
+    cursel: isize,
+    item_data: impl FnOnce(usize) -> isize,
+    voice_count: usize,
+) -> Option<usize> {
+    let index = usize::try_from(cursel).ok()?;
+    usize::try_from(item_data(index))
+        .ok()
+        .filter(|&position| position < voice_count)
+}
+
+/// 音声の一覧から選ぶ音声
+#[derive(Debug, PartialEq)]
+pub enum VoiceChoice {
+    /// 選びたい音声が一覧のこの位置にある
+    Found(usize),
+    /// 選びたい音声が一覧にないので、代わりにこの位置の音声を選ぶ (一覧が空なら None)
+    Fallback(Option<usize>),
+}
+
+/// 選びたい音声の ID を、使える音声の ID の一覧から探す
+///
+/// 見つからなければ既定の音声を、それも一覧になければ先頭の音声を代わりに選ぶ。
+pub fn choose_voice<T: PartialEq>(
+    wanted: Option<&T>,
+    available: &[T],
+    default: Option<&T>,
+) -> VoiceChoice {
+    let position = |id: Option<&T>| id.and_then(|id| available.iter().position(|v| v == id));
+    match position(wanted) {
+        Some(index) => VoiceChoice::Found(index),
+        None => VoiceChoice::Fallback(position(default).or((!available.is_empty()).then_some(0))),
+    }
+}
+
+/// 音声の一覧の ID
+pub fn voice_ids(voices: &[Voice]) -> Vec<HSTRING> {
+    voices.iter().map(|voice| voice.id.clone()).collect()
+}
+
+/// コンボボックスの項目データから音声を探す (CB_ERR や範囲外なら None)
+fn voice_for_item<T>(voices: &[T], data: isize) -> Option<&T> {
+    usize::try_from(data).ok().and_then(|i| voices.get(i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Weak;
+    use windows::{
+        core::w,
+        Win32::{
+            Foundation::LRESULT,
+            UI::WindowsAndMessaging::{
+                CreateWindowExW, DefWindowProcW, DestroyWindow, RegisterClassW, HWND_MESSAGE,
+                WINDOW_EX_STYLE, WINDOW_STYLE, WM_CREATE, WM_NCDESTROY, WNDCLASSW,
+            },
+        },
+    };
+
+    /// メインウィンドウと同じ時点で状態を持たせて破棄するウィンドウプロシージャ
+    unsafe extern "system" fn wnd_proc(
+        hwnd: HWND,
+        msg: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> LRESULT {
+        match msg {
+            WM_CREATE => AppState::attach(hwnd),
+            WM_NCDESTROY => AppState::detach(hwnd),
+            _ => {}
+        }
+        DefWindowProcW(hwnd, msg, wparam, lparam)
+    }
+
+    #[test]
+    fn ignore_double_play() {
+        let guard = PlayGuard::default();
+        let text = "テスト".encode_utf16().collect::<Vec<_>>();
+        let other = "別のテキスト".encode_utf16().collect::<Vec<_>>();
+        let start = Instant::now();
+        assert!(!guard.is_double_play(&text, start));
+        assert!(guard.is_double_play(&text, start + Duration::from_millis(100)));
+        // 違うテキストや、間隔をあけた再生は受け付ける
+        assert!(!guard.is_double_play(&other, start + Duration::from_millis(200)));
+        assert!(!guard.is_double_play(&other, start + Duration::from_millis(700)));
+        // 停止したり終わったりした後は、すぐに再生し直せる
+        guard.clear();
+        assert!(!guard.is_double_play(&other, start + Duration::from_millis(800)));
+    }
+
+    #[test]
+    fn sleep_timer_expires_once() {
+        let timer = SleepTimer::default();
+        let start = Instant::now();
+        assert!(!timer.expire(start));
+        timer.arm(start, Duration::from_secs(300));
+        // 設定し直すと前の時間を置き換える
+        timer.arm(start, Duration::from_secs(900));
+        assert_eq!(timer.duration(), Some(Duration::from_secs(900)));
+        assert_eq!(
+            timer.remaining(start + Duration::from_secs(60)),
+            Some(Duration::from_secs(840))
+        );
+        assert!(!timer.expire(start + Duration::from_secs(600)));
+        assert!(timer.expire(start + Duration::from_secs(900)));
+        assert!(!timer.is_armed());
+        assert!(!timer.expire(start + Duration::from_secs(901)));
+    }
+
+    #[test]
+    fn group_digits_by_thousands() {
+        assert_eq!(group_digits(0), "0");
+        assert_eq!(group_digits(999), "999");
+        assert_eq!(group_digits(12345), "12,345");
+        assert_eq!(group_digits(1234567), "1,234,567");
+    }
+
+    #[test]
+    fn format_sleep_remaining() {
+        assert_eq!(format_remaining(Duration::from_secs(0)), "0:00");
+        assert_eq!(format_remaining(Duration::from_millis(500)), "0:01");
+        assert_eq!(format_remaining(Duration::from_secs(754)), "12:34");
+        assert_eq!(format_remaining(Duration::from_secs(3723)), "1:02:03");
+    }
+
+    #[test]
+    fn remember_where_playback_stopped() {
+        let resume = ResumePoint::default();
+        // 途中で停止したら、読み上げていた範囲の先頭からの位置を覚える
+        resume.start(Some((10, 100)));
+        resume.stopped(Some(30));
+        assert_eq!(resume.offset(), Some(40));
+        // 停止した後に届く終了の通知では忘れない
+        resume.finished();
+        assert_eq!(resume.offset(), Some(40));
+        // ほかのテキストを読み上げても忘れない
+        resume.start(None);
+        resume.stopped(Some(5));
+        resume.finished();
+        assert_eq!(resume.offset(), Some(40));
+        // 続きから読み上げて最後まで終わったら忘れる
+        resume.start(Some((40, 100)));
+        assert_eq!(resume.offset(), None);
+        resume.finished();
+        assert_eq!(resume.offset(), None);
+        // 範囲の最後で止めた場合は続きがない
+        resume.start(Some((0, 100)));
+        resume.stopped(Some(100));
+        assert_eq!(resume.offset(), None);
+        // テキストが変わったら忘れる
+        resume.start(Some((0, 100)));
+        resume.stopped(Some(50));
+        resume.clear();
+        assert_eq!(resume.offset(), None);
+        assert_eq!(resume.spoken(), None);
+    }
+
+    #[test]
+    fn window_owns_state_until_destroyed() {
+        let wnd_class = WNDCLASSW {
+            lpfnWndProc: Some(wnd_proc),
+            lpszClassName: w!("speech_state_test_cls"),
+            ..Default::default()
+        };
+        unsafe { RegisterClassW(&wnd_class) };
+        let hwnd = unsafe {
+            CreateWindowExW(
+                WINDOW_EX_STYLE::default(),
+                wnd_class.lpszClassName,
+                None,
+                WINDOW_STYLE::default(),
+                0,
+                0,
+                0,
+                0,
+                HWND_MESSAGE,
+                None,
+                None,
+                None,
+            )
+        }
+        .unwrap();
+
+        let state = AppState::get(hwnd).unwrap();
+        assert!(state.edit().is_err());
+        assert!(!state.playback.is_speaking());
+        let playback: Weak<Playback<AppEngine>> = Arc::downgrade(&state.playback);
+
+        unsafe { DestroyWindow(hwnd) }.unwrap();
+        // WM_NCDESTROY で破棄されていれば、再生の状態も残っていない
+        assert!(playback.upgrade().is_none());
+    }
+
+    #[test]
+    fn voice_for_item_ignores_names() {
+        let long =
+            "Microsoft Nanami Online (Natural) - Japanese (Japan) with a very long display name";
+        let voices = [long, "Microsoft Haruka", long];
+        // CBS_SORT で並べ替えられても、項目データから元の音声がわかる
+        let mut items = voices.iter().enumerate().collect::<Vec<_>>();
+        items.sort_by_key(|&(_, name)| *name);
+        for (data, name) in items {
+            let voice = voice_for_item(&voices, data as isize).unwrap();
+            assert_eq!(voice, name);
+            assert!(std::ptr::eq(voice, &voices[data]));
+        }
+        assert!(voice_for_item(&voices, -1).is_none());
+        assert!(voice_for_item(&voices, 3).is_none());
+    }
+
+    #[test]
+    fn trim_copied_drops_nul() {
+        let wide = |s: &str| s.encode_utf16().collect::<Vec<_>>();
+        // 空のテキストは NUL だけがコピーされる
+        assert!(trim_copied(vec![0], 0).is_empty());
+        // 報告された長さどおりにコピーされた場合
+        let mut buf = wide("テスト");
+        buf.push(0);
+        assert_eq!(trim_copied(buf, 3), wide("テスト"));
+        // 改行を含むテキストで、報告より少なくコピーされた場合
+        let mut buf = wide("a\r\nb");
+        buf.extend([0; 4]);
+        assert_eq!(trim_copied(buf, 4), wide("a\r\nb"));
+        assert!(trim_copied(vec![0; 4], -1).is_empty());
+    }
+
+    #[test]
+    fn choose_another_voice_when_it_disappears() {
+        let available = ["haruka", "ayumi", "zira"];
+        assert_eq!(
+            choose_voice(Some(&"ayumi"), &available, Some(&"zira")),
+            VoiceChoice::Found(1)
+        );
+        // アンインストールされた音声の代わりに既定の音声を選ぶ
+        assert_eq!(
+            choose_voice(Some(&"nanami"), &available, Some(&"zira")),
+            VoiceChoice::Fallback(Some(2))
+        );
+        // 既定の音声も一覧になければ先頭の音声を選ぶ
+        assert_eq!(
+            choose_voice(Some(&"nanami"), &available, Some(&"ichiro")),
+            VoiceChoice::Fallback(Some(0))
+        );
+        assert_eq!(
+            choose_voice(None, &available, None),
+            VoiceChoice::Fallback(Some(0))
+        );
+        assert_eq!(
+            choose_voice(Some(&"nanami"), &[], Some(&"zira")),
+            VoiceChoice::Fallback(None)
+        );
+    }
+
+    #[test]
+    fn fall_back_when_nothing_is_selected() {
+        // CB_GETCURSEL が CB_ERR なら項目データは読まない
+        let unread = |_| panic!("no item to read.");
+        assert_eq!(selected_position(-1, unread, 3), None);
+        // 選択中の項目の位置を返す
+        assert_eq!(selected_position(0, |index| index as isize + 2, 3), Some(2));
+        // 項目データが壊れている場合も既定の音声に戻す
+        assert_eq!(selected_position(1, |_| -1, 3), None);
+        assert_eq!(selected_position(1, |_| 3, 3), None);
+        assert_eq!(selected_position(0, |_| 0, 0), None);
+    }
+}
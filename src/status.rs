@@ -0,0 +1,220 @@
+use crate::dpi;
+use crate::state::control;
+use anyhow::Result;
+use std::cell::RefCell;
+use windows::Win32::{
+    Foundation::{COLORREF, HWND, LPARAM, RECT, WPARAM},
+    Graphics::Gdi::{
+        DrawTextW, SetBkMode, SetTextColor, DT_END_ELLIPSIS, DT_NOPREFIX, DT_SINGLELINE,
+        DT_VCENTER, TRANSPARENT,
+    },
+    UI::{
+        Controls::{
+            DRAWITEMSTRUCT, PBM_SETMARQUEE, PBS_MARQUEE, PROGRESS_CLASSW, SBARS_SIZEGRIP,
+            SBT_OWNERDRAW, SB_GETRECT, SB_SETPARTS, SB_SETTEXTW, STATUSCLASSNAMEW,
+        },
+        WindowsAndMessaging::{
+            CreateWindowExW, GetWindowRect, MoveWindow, SendMessageW, ShowWindow, HMENU, SW_HIDE,
+            SW_SHOW, WINDOW_EX_STYLE, WINDOW_STYLE, WM_SIZE, WS_CHILD, WS_VISIBLE,
+        },
+    },
+};
+
+/// ステータスバーの ID
+const ID_STATUS: u16 = 5900;
+/// 合成中に表示するプログレスバーの ID
+const ID_PROGRESS: u16 = 5944;
+/// 各区画の幅 (96 DPI 基準、最後の区画は残り全部)
+const PART_WIDTHS: [i32; 2] = [150, 250];
+/// 合成中に表示するプログレスバーの余白 (96 DPI 基準)
+const PROGRESS_MARGIN: i32 = 2;
+/// 警告を表示する文字色 (暗めの赤)
+const WARNING_COLOR: COLORREF = COLORREF(0x0000_00C0);
+/// 警告の文字の左の余白 (96 DPI 基準)
+const WARNING_MARGIN: i32 = 4;
+
+thread_local! {
+    /// 警告として赤で描く区画ごとのテキスト
+    static WARNINGS: RefCell<[Vec<u16>; 3]> = RefCell::default();
+}
+
+/// ステータスバーの区画
+#[derive(Clone, Copy)]
+pub enum Part {
+    /// 再生状態
+    State = 0,
+    /// 文字数など
+    Counts = 1,
+    /// その他
+    Misc = 2,
+}
+
+/// ステータスバーを生成する
+///
+/// ステータスバーとプログレスバーは ID で探すので、ほかの関数には親ウィンドウを渡す。
+pub fn create(hwnd: HWND) -> Result<()> {
+    unsafe {
+        CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            STATUSCLASSNAMEW,
+            None,
+            WS_CHILD | WS_VISIBLE | WINDOW_STYLE(SBARS_SIZEGRIP),
+            0,
+            0,
+            0,
+            0,
+            hwnd,
+            HMENU(ID_STATUS as _),
+            None,
+            None,
+        )?
+    };
+
+    // 合成中だけ「その他」の区画に重ねて表示する
+    unsafe {
+        CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            PROGRESS_CLASSW,
+            None,
+            WS_CHILD | WINDOW_STYLE(PBS_MARQUEE),
+            0,
+            0,
+            0,
+            0,
+            hwnd,
+            HMENU(ID_PROGRESS as _),
+            None,
+            None,
+        )?
+    };
+    Ok(())
+}
+
+/// 親ウィンドウの大きさと DPI に合わせてステータスバーを配置し直す
+pub fn resize(parent: HWND, dpi: u32) -> Result<()> {
+    let hwnd = control(parent, ID_STATUS)?;
+    unsafe { SendMessageW(hwnd, WM_SIZE, None, None) };
+    let mut right = 0;
+    let parts = PART_WIDTHS
+        .iter()
+        .map(|width| {
+            right += dpi::scale(*width, dpi);
+            right
+        })
+        .chain(Some(-1))
+        .collect::<Vec<_>>();
+    unsafe {
+        SendMessageW(
+            hwnd,
+            SB_SETPARTS,
+            WPARAM(parts.len()),
+            LPARAM(parts.as_ptr() as _),
+        )
+    };
+    place_progress(parent, dpi)
+}
+
+/// ステータスバーの高さ
+pub fn height(parent: HWND) -> Result<i32> {
+    let hwnd = control(parent, ID_STATUS)?;
+    let mut rc = RECT::default();
+    unsafe { GetWindowRect(hwnd, &mut rc)? };
+    Ok(rc.bottom - rc.top)
+}
+
+/// ステータスバーの指定した区画にテキストを表示する
+pub fn set_status(parent: HWND, part: Part, text: &str) -> Result<()> {
+    let hwnd = control(parent, ID_STATUS)?;
+    let text = text.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+    unsafe {
+        SendMessageW(
+            hwnd,
+            SB_SETTEXTW,
+            WPARAM(part as _),
+            LPARAM(text.as_ptr() as _),
+        )
+    };
+    Ok(())
+}
+
+/// ステータスバーの指定した区画にテキストを赤で表示する
+///
+/// ステータスバーは文字色を変えられないので、オーナードローで [draw_item] が描く。
+pub fn set_warning(parent: HWND, part: Part, text: &str) -> Result<()> {
+    let hwnd = control(parent, ID_STATUS)?;
+    WARNINGS.with_borrow_mut(|warnings| warnings[part as usize] = text.encode_utf16().collect());
+    unsafe {
+        SendMessageW(
+            hwnd,
+            SB_SETTEXTW,
+            WPARAM(part as usize | SBT_OWNERDRAW as usize),
+            LPARAM(part as _),
+        )
+    };
+    Ok(())
+}
+
+/// WM_DRAWITEM で警告のテキストを描く。ステータスバーの区画でなければ false を返す
+pub fn draw_item(item: &DRAWITEMSTRUCT) -> bool {
+    if item.CtlID != u32::from(ID_STATUS) {
+        return false;
+    }
+    let mut text = WARNINGS.with_borrow(|warnings| {
+        warnings
+            .get(item.itemID as usize)
+            .cloned()
+            .unwrap_or_default()
+    });
+    let mut rc = item.rcItem;
+    rc.left += dpi::scale(WARNING_MARGIN, dpi::dpi_for_window(item.hwndItem));
+    unsafe {
+        SetBkMode(item.hDC, TRANSPARENT);
+        SetTextColor(item.hDC, WARNING_COLOR);
+        DrawTextW(
+            item.hDC,
+            &mut text,
+            &mut rc,
+            DT_SINGLELINE | DT_VCENTER | DT_NOPREFIX | DT_END_ELLIPSIS,
+        );
+    }
+    true
+}
+
+/// 合成中であることを示すプログレスバーを表示する。busy が false なら隠す
+pub fn set_busy(parent: HWND, busy: bool) -> Result<()> {
+    let progress = control(parent, ID_PROGRESS)?;
+    if busy {
+        place_progress(parent, dpi::dpi_for_window(progress))?;
+    }
+    let (cmd, marquee) = if busy { (SW_SHOW, 1) } else { (SW_HIDE, 0) };
+    unsafe { SendMessageW(progress, PBM_SETMARQUEE, WPARAM(marquee), LPARAM(0)) };
+    _ = unsafe { ShowWindow(progress, cmd) };
+    Ok(())
+}
+
+/// プログレスバーを「その他」の区画に合わせて配置する
+fn place_progress(parent: HWND, dpi: u32) -> Result<()> {
+    let hwnd = control(parent, ID_STATUS)?;
+    let progress = control(parent, ID_PROGRESS)?;
+    let mut rc = RECT::default();
+    unsafe {
+        SendMessageW(
+            hwnd,
+            SB_GETRECT,
+            WPARAM(Part::Misc as _),
+            LPARAM(&mut rc as *mut _ as _),
+        )
+    };
+    let margin = dpi::scale(PROGRESS_MARGIN, dpi);
+    unsafe {
+        MoveWindow(
+            progress,
+            rc.left + margin,
+            rc.top + margin,
+            rc.right - rc.left - margin * 2,
+            rc.bottom - rc.top - margin * 2,
+            true,
+        )?
+    };
+    Ok(())
+}
@@ -0,0 +1,795 @@
+use crate::settings;
+use speech::error::SpeechError;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use windows::{core::HSTRING, Win32::Globalization::GetUserDefaultUILanguage};
+
+/// 日本語のプライマリ言語 ID
+const LANG_JAPANESE: u16 = 0x11;
+/// 起動時に決定した表示言語を保持するグローバル変数
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// 表示言語
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    Ja,
+    En,
+}
+
+impl FromStr for Lang {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "ja" => Ok(Self::Ja),
+            "en" => Ok(Self::En),
+            _ => anyhow::bail!("unknown language: {s}"),
+        }
+    }
+}
+
+impl fmt::Display for Lang {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ja => f.write_str("ja"),
+            Self::En => f.write_str("en"),
+        }
+    }
+}
+
+/// ユーザーに表示する文字列の ID
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Msg {
+    AppName,
+    LabelSlow,
+    LabelFast,
+    LabelRepeat,
+    PanelTitle,
+    TipPlay,
+    TipPause,
+    TipStop,
+    TipOpen,
+    TipSave,
+    TipSettings,
+    TipPrevious,
+    TipNext,
+    TipVoice,
+    TipRate,
+    TipRepeat,
+    TipPanelToggle,
+    AccText,
+    AccVoice,
+    AccRate,
+    AccToolbar,
+    AccRepeat,
+    AccRateValue,
+    StatusSynthesizing,
+    StatusPlaying,
+    StatusPlayingRound,
+    StatusPaused,
+    StatusStopped,
+    StatusPlayFailed,
+    MediaErrorAborted,
+    MediaErrorNetwork,
+    MediaErrorDecoding,
+    MediaErrorUnsupported,
+    MediaErrorUnknown,
+    StatusCharCount,
+    StatusCounts,
+    StatusCountsWords,
+    ConfirmLongText,
+    Saved,
+    SaveCancelled,
+    StatusQueued,
+    StatusQueueSkipped,
+    StatusExporting,
+    ExportFinished,
+    AboutTitle,
+    AboutBuildDate,
+    AboutDescription,
+    AboutLicenses,
+    ButtonCopy,
+    ButtonOk,
+    ButtonReset,
+    ButtonCancel,
+    LanguageChanged,
+    ConfirmExitPlaying,
+    ConfirmExitSaving,
+    ErrorCreate,
+    ErrorCommand,
+    ErrorPlay,
+    ErrorPlaybackStalled,
+    ErrorNoText,
+    ErrorNoVoice,
+    NoVoices,
+    VoiceMissing,
+    UserNoVoiceSelected,
+    UserVoiceUnavailable,
+    UserSynthesisFailed,
+    UserNetworkRequired,
+    UserAudioDeviceMissing,
+    UserFileWrite,
+    UserDialogCancelled,
+    ErrorPaint,
+    ErrorOpenFile,
+    ErrorHotkey,
+    ErrorPipe,
+    JumpListRecent,
+    ToastOpen,
+    FilterWave,
+    FilterText,
+    MenuFile,
+    MenuOpen,
+    MenuWatchFile,
+    MenuWatchFolder,
+    MenuExportSentences,
+    SentenceExportTitle,
+    LabelSentencePattern,
+    SentenceManifest,
+    ErrorSentencePattern,
+    StatusExportingSentence,
+    StatusVoicesReloaded,
+    SentencesExported,
+    SentenceExportCancelled,
+    StatusWatching,
+    StatusWatchStopped,
+    StatusWatchingFolder,
+    StatusWatchFolderStopped,
+    StatusFolderConverted,
+    StatusFolderSkipped,
+    MenuSave,
+    MenuExit,
+    MenuEdit,
+    MenuClear,
+    MenuSelectAll,
+    MenuQuickInput,
+    MenuFind,
+    MenuFindNext,
+    MenuReplace,
+    FindNotFound,
+    StatusReplaced,
+    NothingToSave,
+    MenuAutoVoice,
+    MenuMixedVoices,
+    MenuPreventSleep,
+    MenuKeepDisplayOn,
+    ResumeAfterSleep,
+    StatusNoVoiceForLanguage,
+    MenuUndo,
+    MenuCut,
+    MenuCopy,
+    MenuPaste,
+    MenuDelete,
+    MenuSpeakSelection,
+    MenuSpeakFromCursor,
+    MenuPasteAndSpeak,
+    MenuSaveSelection,
+    MenuFormatText,
+    MenuPlayback,
+    MenuPlay,
+    MenuResume,
+    MenuPause,
+    MenuStop,
+    MenuPreviousSentence,
+    MenuNextSentence,
+    MenuSleepTimer,
+    MenuSleepOff,
+    MenuSleep5,
+    MenuSleep15,
+    MenuSleep30,
+    MenuSleepCustom,
+    MenuHistory,
+    MenuHistoryClear,
+    MenuSaveHistory,
+    SleepTitle,
+    MenuSchedule,
+    MenuScheduleCancel,
+    ScheduleTitle,
+    LabelScheduleTime,
+    ScheduleDaily,
+    StatusScheduled,
+    ScheduleMissed,
+    LabelSleepMinutes,
+    StatusSleepTimer,
+    StatusSleepStopped,
+    MenuSettings,
+    MenuTopmost,
+    MenuCloseToTray,
+    MenuMultipleInstances,
+    MenuPlayForwarded,
+    MenuSpeakClipboardAtLaunch,
+    ClipboardTruncated,
+    MenuPipeServer,
+    MenuLanguage,
+    MenuLanguageAuto,
+    MenuLanguageJa,
+    MenuLanguageEn,
+    MenuHelp,
+    MenuAbout,
+    MenuOpenLogFolder,
+    Crashed,
+    CliHelp,
+    MenuHotkeys,
+    MenuRules,
+    MenuLexicon,
+    MenuReloadVoices,
+    MenuNormalize,
+    MenuNormalizeDates,
+    MenuNormalizeNumbers,
+    MenuNormalizeUrls,
+    MenuParagraphPause,
+    MenuParagraphPauseNone,
+    MenuParagraphPauseHalf,
+    MenuParagraphPause1,
+    MenuParagraphPause2,
+    MenuFont,
+    MenuAutosave,
+    MenuSpeechMarks,
+    MenuNormalizeVolume,
+    MenuTrimSilence,
+    MenuOutputFormat,
+    MenuOutputNative,
+    MenuOutput8000,
+    MenuOutput16000,
+    MenuOutput22050,
+    MenuOutput44100,
+    MenuOutputMono,
+    MenuDuckOthers,
+    ErrorRecentFileMissing,
+    RulesTitle,
+    LabelRulePattern,
+    LabelRuleReplacement,
+    LabelRuleSample,
+    LabelRulePreview,
+    RuleEnabled,
+    ButtonAdd,
+    ButtonUpdate,
+    ButtonDelete,
+    ButtonUp,
+    ButtonDown,
+    ErrorRulePattern,
+    LexiconTitle,
+    LabelLexiconPattern,
+    LabelLexiconReplacement,
+    LexiconWholeWord,
+    ErrorLexiconPattern,
+    HotkeyTitle,
+    HotkeySpeakClipboard,
+    HotkeyPlay,
+    HotkeyPause,
+    HotkeyStop,
+    TrayPlay,
+    TrayStop,
+    TrayShow,
+    TrayExit,
+}
+
+/// 文字列テーブル ([日本語, 英語])
+fn table(msg: Msg) -> [&'static str; 2] {
+    use Msg::*;
+    match msg {
+        AppName => ["speech", "speech"],
+        LabelSlow => ["読み上げ速度：遅", "Speed: Slow"],
+        LabelFast => ["速", "Fast"],
+        LabelRepeat => ["回数", "Times"],
+        PanelTitle => ["音声と読み上げ速度", "Voice and speed"],
+        TipPlay => [
+            "テキストを読み上げます。(Ctrl+Enter)",
+            "Reads the text aloud. (Ctrl+Enter)",
+        ],
+        TipPause => [
+            "読み上げを一時停止します。もう一度押すと再開します。",
+            "Pauses the speech. Press again to resume.",
+        ],
+        TipStop => ["読み上げを停止します。(Esc)", "Stops the speech. (Esc)"],
+        TipOpen => [
+            "テキストファイルを開きます。(Ctrl+O)",
+            "Opens a text file. (Ctrl+O)",
+        ],
+        TipSave => [
+            "読み上げた音声を WAV ファイルに保存します。(Ctrl+S)",
+            "Saves the speech to a WAV file. (Ctrl+S)",
+        ],
+        TipSettings => ["設定メニューを表示します。", "Shows the settings menu."],
+        TipPrevious => ["前の文に戻ります。", "Goes back to the previous sentence."],
+        TipNext => ["次の文に進みます。", "Skips to the next sentence."],
+        TipVoice => [
+            "読み上げに使う音声を選択します。",
+            "Selects the voice used for speech.",
+        ],
+        TipRate => [
+            "読み上げ速度を 0.5 倍 (遅) から 2.5 倍 (速) の間で調整します。",
+            "Adjusts the speaking rate between 0.5x (slow) and 2.5x (fast).",
+        ],
+        TipRepeat => [
+            "同じテキストを続けて再生する回数です。停止するとすべての回を止めます。",
+            "How many times to play the same text. Stop cancels all of them.",
+        ],
+        TipPanelToggle => [
+            "音声と読み上げ速度の設定を折りたたむ・展開します。",
+            "Collapses or expands the voice and speed settings.",
+        ],
+        AccText => ["テキスト", "Text"],
+        AccVoice => ["音声の選択", "Voice selection"],
+        AccRate => ["読み上げ速度", "Speaking rate"],
+        AccToolbar => ["ツールバー", "Toolbar"],
+        AccRepeat => ["再生する回数", "Repeat count"],
+        AccRateValue => ["{0} 倍", "{0}x"],
+        StatusSynthesizing => ["合成中...", "Synthesizing..."],
+        StatusPlaying => ["再生中", "Playing"],
+        StatusPlayingRound => ["再生中 ({0}/{1})", "Playing ({0}/{1})"],
+        StatusPaused => ["一時停止中", "Paused"],
+        StatusStopped => ["停止", "Stopped"],
+        StatusPlayFailed => ["再生に失敗しました: {0}", "Playback failed: {0}"],
+        MediaErrorAborted => ["再生が中断されました", "Playback was aborted"],
+        MediaErrorNetwork => ["ネットワークエラー", "Network error"],
+        MediaErrorDecoding => ["音声をデコードできませんでした", "Could not decode the audio"],
+        MediaErrorUnsupported => [
+            "音声の形式に対応していません",
+            "The audio format is not supported",
+        ],
+        MediaErrorUnknown => ["不明なエラー", "Unknown error"],
+        StatusCharCount => ["{0} 文字", "{0} characters"],
+        StatusCounts => ["{0} 文字 / 約 {1} 分", "{0} characters / about {1} min"],
+        StatusCountsWords => [
+            "{0} 文字 · {1} 語 / 約 {2} 分",
+            "{0} characters · {1} words / about {2} min",
+        ],
+        ConfirmLongText => [
+            "テキストが長いため ({0} 文字)、合成に時間がかかります (約 {1} 分)。続けますか?",
+            "The text is long ({0} characters), so synthesis will take a while (about {1} min). Continue?",
+        ],
+        Saved => ["{0} を保存しました。", "Saved {0}."],
+        SaveCancelled => ["保存を取り消しました。", "Saving was cancelled."],
+        StatusQueued => ["再生待ち {0} 件", "{0} queued"],
+        StatusQueueSkipped => [
+            "開けなかったファイルを飛ばしました: {0}",
+            "Skipped a file that could not be opened: {0}",
+        ],
+        StatusExporting => ["書き出し中 ({0}/{1}): {2}", "Exporting ({0}/{1}): {2}"],
+        ExportFinished => [
+            "{1} 件中 {0} 件を WAV ファイルに書き出しました。",
+            "Exported {0} of {1} files to WAV.",
+        ],
+        AboutTitle => ["バージョン情報", "About"],
+        AboutBuildDate => ["ビルド日: {0}", "Built on {0}"],
+        AboutDescription => [
+            "Windows の音声合成でテキストを読み上げるツールです。",
+            "Reads text aloud using Windows speech synthesis.",
+        ],
+        AboutLicenses => ["サードパーティ ライセンス:", "Third-party licenses:"],
+        ButtonCopy => ["コピー", "Copy"],
+        ButtonOk => ["OK", "OK"],
+        ButtonReset => ["既定に戻す(&R)", "&Reset to Defaults"],
+        ButtonCancel => ["キャンセル", "Cancel"],
+        LanguageChanged => [
+            "表示言語の変更は次回の起動時に反映されます。",
+            "The display language will change the next time the app starts.",
+        ],
+        ConfirmExitPlaying => [
+            "再生中です。終了しますか？",
+            "Speech is playing. Do you want to exit?",
+        ],
+        ConfirmExitSaving => [
+            "WAV ファイルを保存中です。保存を中止して終了しますか？",
+            "A WAV file is being saved. Do you want to stop saving and exit?",
+        ],
+        ErrorCreate => ["起動に失敗しました。", "Failed to start."],
+        ErrorCommand => ["操作に失敗しました。", "The operation failed."],
+        ErrorPlay => ["再生に失敗しました。", "Playback failed."],
+        ErrorPlaybackStalled => [
+            "再生が終わらないため停止しました。",
+            "Playback was stopped because it did not finish.",
+        ],
+        ErrorNoText => ["テキストを入力してください。", "Please enter some text."],
+        ErrorNoVoice => ["音声が選択されていません。", "No voice is selected."],
+        VoiceMissing => [
+            "選択した音声が見つかりません。音声一覧を更新します。",
+            "The selected voice was not found. The voice list will be refreshed.",
+        ],
+        UserNoVoiceSelected => [
+            "音声が選択されていません。音声を選んでから、もう一度お試しください。",
+            "No voice is selected. Choose a voice and try again.",
+        ],
+        UserVoiceUnavailable => [
+            "選択した音声を使えません。別の音声を選ぶか、Windows の音声の設定を確認してください。",
+            "The selected voice is unavailable. Choose another voice or check the speech settings in Windows.",
+        ],
+        UserSynthesisFailed => [
+            "音声を合成できませんでした。別の音声を選ぶか、テキストを短くしてお試しください。(エラー コード {0})",
+            "Could not synthesize the speech. Try another voice or shorter text. (error code {0})",
+        ],
+        UserNetworkRequired => [
+            "オンライン音声にはネットワーク接続が必要です。接続を確認するか、別の音声を選んでください。",
+            "Online voices require a network connection. Check the connection or choose another voice.",
+        ],
+        UserAudioDeviceMissing => [
+            "音声を再生するデバイスが見つかりません。スピーカーやヘッドホンの接続を確認してください。",
+            "No audio playback device was found. Check that speakers or headphones are connected.",
+        ],
+        UserFileWrite => [
+            "ファイルに書き込めませんでした。保存先や空き容量を確認してください。({0})",
+            "Could not write the file. Check the destination and the free space. ({0})",
+        ],
+        UserDialogCancelled => ["操作を取り消しました。", "The operation was cancelled."],
+        NoVoices => [
+            "読み上げに使える音声が見つかりません。\r\nWindows の設定の [時刻と言語] > [音声認識] で音声を追加してください。\r\n\r\n音声の設定を開きますか？",
+            "No voices are available for reading aloud.\r\nAdd a voice in Windows Settings under Time & language > Speech.\r\n\r\nDo you want to open the speech settings?",
+        ],
+        ErrorHotkey => [
+            "クリップボードを読み上げるホットキーを登録できませんでした。ほかのアプリケーションが使っている可能性があります。",
+            "Failed to register the hotkey for reading the clipboard. Another application may be using it.",
+        ],
+        ErrorPipe => [
+            "名前付きパイプを開始できませんでした。",
+            "Failed to start the named pipe server.",
+        ],
+        ErrorOpenFile => ["ファイルを開けませんでした。", "Failed to open the file."],
+        JumpListRecent => ["最近開いたファイル", "Recent files"],
+        ToastOpen => ["ファイルの場所を開く", "Open File Location"],
+        ErrorPaint => [
+            "ウィンドウの描画に失敗しました。",
+            "Failed to draw the window.",
+        ],
+        FilterWave => [
+            "WAVE ファイル (*.wav)\0*.wav\0\0",
+            "Wave File (*.wav)\0*.wav\0\0",
+        ],
+        FilterText => [
+            "テキスト ファイル (*.txt)\0*.txt\0すべてのファイル (*.*)\0*.*\0\0",
+            "Text File (*.txt)\0*.txt\0All Files (*.*)\0*.*\0\0",
+        ],
+        MenuFile => ["ファイル(&F)", "&File"],
+        MenuOpen => ["開く(&O)...\tCtrl+O", "&Open...\tCtrl+O"],
+        MenuWatchFile => ["ファイルを監視(&W)...", "&Watch File..."],
+        MenuWatchFolder => [
+            "フォルダーを監視して WAV に変換(&F)...",
+            "Convert Watched &Folder to WAV...",
+        ],
+        MenuExportSentences => ["文ごとに書き出す(&E)...", "&Export Each Sentence..."],
+        SentenceExportTitle => ["文ごとに書き出す", "Export Each Sentence"],
+        LabelSentencePattern => [
+            "ファイル名のパターン ({index:03}, {first10chars}):",
+            "File name pattern ({index:03}, {first10chars}):",
+        ],
+        SentenceManifest => [
+            "ファイル名と文の一覧 (manifest.csv) も書き出す(&M)",
+            "Also write a list of file names and sentences (manifest.csv)",
+        ],
+        ErrorSentencePattern => [
+            "ファイル名のパターンが正しくありません。",
+            "The file name pattern is invalid.",
+        ],
+        StatusExportingSentence => ["文ごとに書き出し中 ({0}/{1})", "Exporting sentences ({0}/{1})"],
+        StatusVoicesReloaded => ["{0} 個の音声を読み込みました", "Loaded {0} voices"],
+        SentencesExported => [
+            "{1} 文のうち {0} 文を書き出しました。",
+            "Exported {0} of {1} sentences.",
+        ],
+        SentenceExportCancelled => [
+            "文ごとの書き出しを取り消しました ({0} 文を書き出し済み)",
+            "Cancelled exporting sentences ({0} exported)",
+        ],
+        StatusWatching => ["監視中: {0}", "Watching: {0}"],
+        StatusWatchStopped => ["ファイルの監視を停止しました", "Stopped watching the file"],
+        StatusWatchingFolder => ["フォルダーを監視中: {0}", "Watching folder: {0}"],
+        StatusWatchFolderStopped => [
+            "フォルダーの監視を停止しました",
+            "Stopped watching the folder",
+        ],
+        StatusFolderConverted => ["{0} → {1} 変換完了", "{0} → {1} converted"],
+        StatusFolderSkipped => [
+            "{0} を変換できなかったため飛ばしました",
+            "Skipped {0} because it could not be converted",
+        ],
+        MenuSave => ["保存(&S)...\tCtrl+S", "&Save...\tCtrl+S"],
+        MenuExit => ["終了(&X)", "E&xit"],
+        MenuEdit => ["編集(&E)", "&Edit"],
+        MenuClear => ["クリア(&C)\tCtrl+L", "&Clear\tCtrl+L"],
+        MenuSelectAll => ["すべて選択(&A)", "Select &All"],
+        MenuFind => ["検索(&F)...\tCtrl+F", "&Find...\tCtrl+F"],
+        MenuFindNext => ["次を検索(&N)\tF3", "Find &Next\tF3"],
+        MenuReplace => ["置換(&R)...\tCtrl+H", "&Replace...\tCtrl+H"],
+        FindNotFound => ["「{0}」が見つかりません。", "Cannot find \"{0}\"."],
+        StatusReplaced => ["{0} 件を置換しました", "Replaced {0} occurrences"],
+        MenuQuickInput => [
+            "クイック入力モード (Enter で読み上げ)(&Q)",
+            "&Quick Input Mode (Speak on Enter)",
+        ],
+        MenuUndo => ["元に戻す(&U)", "&Undo"],
+        MenuCut => ["切り取り(&T)", "Cu&t"],
+        MenuCopy => ["コピー(&C)", "&Copy"],
+        MenuPaste => ["貼り付け(&P)", "&Paste"],
+        MenuDelete => ["削除(&D)", "&Delete"],
+        MenuSpeakSelection => ["選択範囲を読み上げ(&R)", "&Read Selection"],
+        MenuSpeakFromCursor => ["カーソル位置から読み上げ(&O)", "Read fr&om Cursor"],
+        MenuPasteAndSpeak => ["貼り付けて読み上げ(&E)", "Paste and R&ead"],
+        MenuSaveSelection => ["選択範囲を WAV に保存(&V)", "Sa&ve Selection as WAV"],
+        NothingToSave => [
+            "保存するテキストがありません。範囲を選択するか、保存したい文にカーソルを置いてください。",
+            "There is no text to save. Select some text or place the cursor in a sentence.",
+        ],
+        MenuAutoVoice => [
+            "テキストの言語に合わせて音声を選ぶ(&G)",
+            "Choose Voice by Lan&guage",
+        ],
+        MenuMixedVoices => [
+            "言語の混ざった文は言語ごとに音声を切り替える(&W)",
+            "S&witch Voices Within Mixed-Language Text",
+        ],
+        StatusNoVoiceForLanguage => [
+            "言語 {0} の音声がないため、選択中の音声で読み上げます",
+            "No voice for language \"{0}\"; using the selected voice",
+        ],
+        MenuFormatText => ["読み上げ用に整形(&F)", "&Format for Speech"],
+        MenuPlayback => ["再生(&P)", "&Playback"],
+        MenuPlay => ["再生(&P)\tCtrl+Enter", "&Play\tCtrl+Enter"],
+        MenuResume => ["続きから再生(&C)", "&Continue"],
+        MenuPause => ["一時停止(&U)", "Pa&use"],
+        MenuStop => ["停止(&S)\tEsc", "&Stop\tEsc"],
+        MenuPreviousSentence => ["前の文(&V)", "Pre&vious Sentence"],
+        MenuNextSentence => ["次の文(&N)", "&Next Sentence"],
+        MenuSleepTimer => ["スリープタイマー(&T)", "Sleep &Timer"],
+        MenuSleepOff => ["なし(&N)", "&Off"],
+        MenuSleep5 => ["5 分(&5)", "&5 Minutes"],
+        MenuSleep15 => ["15 分(&1)", "&15 Minutes"],
+        MenuSleep30 => ["30 分(&3)", "&30 Minutes"],
+        MenuSleepCustom => ["カスタム(&C)...", "&Custom..."],
+        MenuHistory => ["履歴(&H)", "&History"],
+        MenuHistoryClear => ["履歴をクリア(&L)", "C&lear History"],
+        MenuSaveHistory => ["履歴を保存して次回に復元する(&S)", "&Save History for Next Time"],
+        SleepTitle => ["スリープタイマー", "Sleep Timer"],
+        MenuSchedule => ["時刻を指定して再生(&A)...", "Speak &at a Time..."],
+        MenuScheduleCancel => ["時刻指定の再生を取り消す(&X)", "Cancel Scheduled Speech"],
+        ScheduleTitle => ["時刻を指定して再生", "Speak at a Time"],
+        LabelScheduleTime => ["再生する時刻:", "Time to speak:"],
+        ScheduleDaily => ["毎日繰り返す(&D)", "Repeat &daily"],
+        StatusScheduled => ["{0} に再生 (あと {1})", "Speaking at {0} (in {1})"],
+        ScheduleMissed => [
+            "スリープなどで再生する時刻 ({0}) を過ぎていたため、再生しませんでした。",
+            "The scheduled time ({0}) passed while the computer was asleep, so the text was not spoken.",
+        ],
+        LabelSleepMinutes => ["停止するまでの時間 (分):", "Stop after (minutes):"],
+        StatusSleepTimer => ["スリープまで {0}", "Sleep in {0}"],
+        StatusSleepStopped => [
+            "スリープタイマーで停止しました",
+            "Stopped by the sleep timer",
+        ],
+        MenuSettings => ["設定(&O)", "&Options"],
+        MenuTopmost => ["常に最前面に表示(&A)\tCtrl+T", "&Always on Top\tCtrl+T"],
+        MenuCloseToTray => ["閉じるときにタスクトレイに格納する(&T)", "Close to &Tray"],
+        MenuMultipleInstances => [
+            "複数のウィンドウを開けるようにする(&M)",
+            "Allow &Multiple Windows",
+        ],
+        MenuPlayForwarded => [
+            "渡されたテキストをすぐに読み上げる(&R)",
+            "&Read Forwarded Text Immediately",
+        ],
+        MenuSpeakClipboardAtLaunch => [
+            "起動時にクリップボードを読み上げる(&C)",
+            "Read &Clipboard at Startup",
+        ],
+        ClipboardTruncated => [
+            "クリップボードのテキストが長すぎるため、先頭の {0} 文字だけを読み込みました。",
+            "The clipboard text was too long, so only the first {0} characters were loaded.",
+        ],
+        MenuPipeServer => [
+            "名前付きパイプでコマンドを受け付ける(&N)",
+            "Accept Commands over a &Named Pipe",
+        ],
+        MenuLanguage => ["表示言語(&L)", "&Language"],
+        MenuLanguageAuto => ["自動(&A)", "&Automatic"],
+        MenuLanguageJa => ["日本語(&J)", "日本語(&J)"],
+        MenuLanguageEn => ["English(&E)", "&English"],
+        MenuHelp => ["ヘルプ(&H)", "&Help"],
+        MenuAbout => ["バージョン情報(&A)", "&About"],
+        MenuOpenLogFolder => ["ログ フォルダーを開く(&L)", "Open &Log Folder"],
+        Crashed => [
+            "予期しないエラーで終了します。詳細: {0}",
+            "The application will close because of an unexpected error. Details: {0}",
+        ],
+        CliHelp => [
+            "使い方:
+  speech.exe [ファイル... | テキスト] [--play]
+  speech.exe --speak-clipboard
+  speech.exe ファイル... --export-dir <フォルダー>
+  speech.exe (--text <テキスト> | --in <ファイル>) --out <WAV ファイル> [--voice <音声>] [--rate <速度>]
+  <コマンド> | speech.exe --out <WAV ファイル> [--voice <音声>] [--rate <速度>]
+
+--out を指定すると、ウィンドウを表示せずに WAV ファイルを作成します。
+--text と --in を省略した場合は、標準入力から UTF-8 のテキストを読み込みます。
+
+オプション:
+  --play            開いたテキストをすぐに読み上げる (ウィンドウを表示する場合)
+                    二つ目以降のファイルは再生待ちに追加し、順に読み上げる
+  --speak-clipboard クリップボードのテキストを読み込んで、すぐに読み上げる
+  --export-dir <フォルダー>
+                    ファイルを開かずに、選択中の音声でフォルダーに WAV を書き出す
+  --text <テキスト> 読み上げるテキスト
+  --in <ファイル>   読み上げるテキストファイル (文字コードは自動判定)
+  --out <ファイル>  保存する WAV ファイル
+  --voice <音声>    音声の表示名または ID (省略時は既定の音声)
+  --rate <速度>     読み上げ速度 0.5 ～ 2.5 (既定は 1.0)
+  --help            このヘルプを表示する
+
+失敗した場合はエラーを標準エラー出力に表示し、終了コード 1 を返します。",
+            "Usage:
+  speech.exe [FILE... | TEXT] [--play]
+  speech.exe --speak-clipboard
+  speech.exe FILE... --export-dir <FOLDER>
+  speech.exe (--text <TEXT> | --in <FILE>) --out <WAV FILE> [--voice <VOICE>] [--rate <RATE>]
+  <COMMAND> | speech.exe --out <WAV FILE> [--voice <VOICE>] [--rate <RATE>]
+
+With --out, the WAV file is created without showing a window.
+Without --text and --in, UTF-8 text is read from standard input.
+
+Options:
+  --play          Read the opened text aloud immediately (when showing the window)
+                  Additional files are queued and read in order
+  --speak-clipboard
+                  Load the clipboard text and read it aloud immediately
+  --export-dir <FOLDER>
+                  Export the files to WAV in the folder with the selected voice
+  --text <TEXT>   Text to read
+  --in <FILE>     Text file to read (the encoding is detected automatically)
+  --out <FILE>    WAV file to save
+  --voice <VOICE> Display name or ID of the voice (default: the system voice)
+  --rate <RATE>   Speaking rate from 0.5 to 2.5 (default: 1.0)
+  --help          Show this help
+
+On failure, the error is printed to standard error and the exit code is 1.",
+        ],
+        MenuHotkeys => ["ショートカットキー(&K)...", "Shortcut &Keys..."],
+        MenuRules => ["置換ルール(&R)...", "Replacement &Rules..."],
+        MenuLexicon => ["読み方の辞書(&L)...", "Pronunciation &Lexicon..."],
+        MenuReloadVoices => ["音声を再読み込み(&V)", "Reload &Voices"],
+        MenuNormalize => ["読み上げ前の整形(&N)", "&Normalize Before Speaking"],
+        MenuNormalizeDates => [
+            "日付を読み方に直す(&D) (2024/05/01 → 2024年5月1日)",
+            "Read &Dates (2024/05/01 → May 1st, 2024)",
+        ],
+        MenuNormalizeNumbers => [
+            "数値の桁区切りを取り除く(&N) (1,234 → 1234)",
+            "Remove Digit Group &Separators (1,234 → 1234)",
+        ],
+        MenuNormalizeUrls => ["URL をドメインごとに読む(&U)", "Read &URLs by Domain"],
+        MenuParagraphPause => ["段落間の間隔(&P)", "&Pause Between Paragraphs"],
+        MenuParagraphPauseNone => ["なし(&N)", "&None"],
+        MenuParagraphPauseHalf => ["0.5 秒(&5)", "0.&5 Seconds"],
+        MenuParagraphPause1 => ["1 秒(&1)", "&1 Second"],
+        MenuParagraphPause2 => ["2 秒(&2)", "&2 Seconds"],
+        MenuFont => ["フォント(&F)...", "&Font..."],
+        ErrorRecentFileMissing => [
+            "ファイルが見つからないため、最近開いたファイルから削除しました。\r\n{0}",
+            "The file was not found and has been removed from the recent files.\r\n{0}",
+        ],
+        MenuAutosave => [
+            "テキストを自動保存して次回に復元する(&S)",
+            "Auto&save Text and Restore It Next Time",
+        ],
+        MenuSpeechMarks => [
+            "WAV と一緒にスピーチマークを保存する(&K)",
+            "Save Speech Mar&ks with WAV",
+        ],
+        MenuNormalizeVolume => ["音量を正規化して保存する(&V)", "Normalize &Volume When Saving"],
+        MenuTrimSilence => ["前後の無音をカット(&E)", "Trim Silence at &Edges When Saving"],
+        MenuOutputFormat => ["保存する形式(&O)", "Saved WAV F&ormat"],
+        MenuOutputNative => ["合成したまま(&N)", "&Native Sample Rate"],
+        MenuOutput8000 => ["8 kHz(&8)", "&8 kHz"],
+        MenuOutput16000 => ["16 kHz(&1)", "&16 kHz"],
+        MenuOutput22050 => ["22.05 kHz(&2)", "&22.05 kHz"],
+        MenuOutput44100 => ["44.1 kHz(&4)", "&44.1 kHz"],
+        MenuOutputMono => ["モノラルにする(&M)", "&Mono"],
+        MenuDuckOthers => [
+            "読み上げ中は他の音声を小さくする(&D)",
+            "&Duck Other Audio While Speaking",
+        ],
+        MenuPreventSleep => ["再生中はスリープを防ぐ(&S)", "Prevent &Sleep While Playing"],
+        MenuKeepDisplayOn => [
+            "スリープを防ぐ間は画面も消さない(&Y)",
+            "Keep the Displa&y On While Preventing Sleep",
+        ],
+        ResumeAfterSleep => [
+            "読み上げ中にスリープしたため、再生を止めました。続きから読み上げますか?",
+            "Playback was stopped because the computer went to sleep. Continue reading from where it stopped?",
+        ],
+        RulesTitle => ["置換ルール", "Replacement Rules"],
+        LabelRulePattern => ["パターン:", "Pattern:"],
+        LabelRuleReplacement => ["置換後:", "Replace with:"],
+        LabelRuleSample => ["テスト:", "Test:"],
+        LabelRulePreview => ["結果:", "Result:"],
+        RuleEnabled => ["有効(&E)", "&Enabled"],
+        ButtonAdd => ["追加(&A)", "&Add"],
+        ButtonUpdate => ["変更(&C)", "&Change"],
+        ButtonDelete => ["削除(&D)", "&Delete"],
+        ButtonUp => ["上へ(&U)", "Move &Up"],
+        ButtonDown => ["下へ(&N)", "Move Dow&n"],
+        ErrorRulePattern => [
+            "正規表現のパターンが正しくありません。",
+            "The regular expression pattern is invalid.",
+        ],
+        LexiconTitle => ["読み方の辞書", "Pronunciation Lexicon"],
+        LabelLexiconPattern => ["語句:", "Word:"],
+        LabelLexiconReplacement => ["読み方:", "Say as:"],
+        LexiconWholeWord => ["単語単位(&W)", "&Whole word only"],
+        ErrorLexiconPattern => ["語句を入力してください。", "Enter a word."],
+        HotkeyTitle => ["ショートカットキー", "Shortcut Keys"],
+        HotkeySpeakClipboard => [
+            "クリップボードを読み上げる (全体)",
+            "Read clipboard (global)",
+        ],
+        HotkeyPlay => ["再生", "Play"],
+        HotkeyPause => ["一時停止 / 再開", "Pause / resume"],
+        HotkeyStop => ["停止", "Stop"],
+        TrayPlay => ["再生(&P)", "&Play"],
+        TrayStop => ["停止(&S)", "&Stop"],
+        TrayShow => ["表示(&V)", "&Show"],
+        TrayExit => ["終了(&X)", "E&xit"],
+    }
+}
+
+/// ユーザーの UI 言語から表示言語を決める
+fn system_lang() -> Lang {
+    let lang_id = unsafe { GetUserDefaultUILanguage() };
+    if lang_id & 0x3ff == LANG_JAPANESE {
+        Lang::Ja
+    } else {
+        Lang::En
+    }
+}
+
+/// 表示言語。設定で指定されていればそれを、なければユーザーの UI 言語を使う
+pub fn lang() -> Lang {
+    *LANG.get_or_init(|| settings::get().language.unwrap_or_else(system_lang))
+}
+
+/// 表示言語に応じた文字列を取得する
+pub fn tr(msg: Msg) -> &'static str {
+    tr_in(msg, lang())
+}
+
+/// 指定した言語の文字列を取得する
+fn tr_in(msg: Msg, lang: Lang) -> &'static str {
+    let [ja, en] = table(msg);
+    match lang {
+        Lang::Ja => ja,
+        Lang::En => en,
+    }
+}
+
+/// `{0}`, `{1}`, ... を引数で置き換えた文字列を取得する
+pub fn trf(msg: Msg, args: &[&str]) -> String {
+    format_args_in(tr(msg), args)
+}
+
+fn format_args_in(text: &str, args: &[&str]) -> String {
+    args.iter()
+        .enumerate()
+        .fold(text.to_string(), |text, (i, arg)| {
+            text.replace(&format!("{{{i}}}"), arg)
+        })
+}
+
+/// 失敗の原因と対処を利用者向けに説明する
+pub fn user_message(err: &SpeechError, lang: Lang) -> String {
+    let text = |msg| tr_in(msg, lang);
+    match err {
+        SpeechError::NoVoiceSelected => text(Msg::UserNoVoiceSelected).to_string(),
+        SpeechError::VoiceUnavailable => text(Msg::UserVoiceUnavailable).to_string(),
+        SpeechError::SynthesisFailed(code) => format_args_in(
+            text(Msg::UserSynthesisFailed),
+            &[&format!("0x{:08X}", code.0 as u32)],
+        ),
+        SpeechError::NetworkRequired => text(Msg::UserNetworkRequired).to_string(),
+        SpeechError::AudioDeviceMissing => text(Msg::UserAudioDeviceMissing).to_string(),
+        SpeechError::FileWrite(e) => format_args_in(text(Msg::UserFileWrite), &[&e.to_string()]),
+        SpeechError::DialogCancelled => text(Msg::UserDialogCancelled).to_string(),
+    }
+}
+
+/// Win32 API に渡すための文字列を取得する
+pub fn wide(msg: Msg) -> HSTRING {
+    HSTRING::from(tr(msg))
+}
@@ -0,0 +1,898 @@
+use crate::error::SpeechError;
+use crate::offsets::OffsetMap;
+use crate::speech_marks::{self, Mark};
+use crate::{chunk, com, lexicon, normalize, rules, unwind, utf16, wav, worker};
+use anyhow::{ensure, Context, Result};
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::mem;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::Duration;
+use windows::{
+    core::{Interface, RuntimeType, HSTRING},
+    Foundation::{AsyncOperationCompletedHandler, AsyncStatus, IAsyncOperation},
+    Media::SpeechSynthesis::{SpeechSynthesisStream, SpeechSynthesizer, VoiceInformation},
+};
+
+/// 読み上げ速度の最小値
+pub const MIN_RATE: f64 = 0.5;
+/// 読み上げ速度の最大値
+pub const MAX_RATE: f64 = 2.5;
+/// 声の高さの最大値 (最小値は 0)
+pub const MAX_PITCH: f64 = 2.0;
+/// 音量の最大値 (最小値は 0)
+pub const MAX_VOLUME: f64 = 1.0;
+/// 一度に合成するテキストの最大の長さ (UTF-16 単位)
+pub const MAX_CHUNK_LEN: usize = 2000;
+/// 最初に合成するチャンクの最大の長さ (UTF-16 単位)
+///
+/// 文一つか二つ分にとどめて、最初の音声が聞こえるまでの時間を短くする。
+pub const FIRST_CHUNK_LEN: usize = 200;
+/// 読み上げ速度 1.0 で 1 分間に読み上げるおおよその文字数 (所要時間の目安に使う)
+pub const CHARS_PER_MINUTE: f64 = 400.0;
+/// 読み上げ速度 1.0 あたりのトラックバーの目盛りの数
+const RATE_STEPS: f64 = 10.0;
+/// 段落と段落の間に入れる無音の長さの既定値
+pub const DEFAULT_PARAGRAPH_PAUSE: Duration = Duration::from_millis(500);
+/// 段落と段落の間に入れる無音の長さ
+static PARAGRAPH_PAUSE: RwLock<Duration> = RwLock::new(DEFAULT_PARAGRAPH_PAUSE);
+
+/// トラックバーの位置を読み上げ速度にする (位置 10 が 1.0)
+pub fn rate_from_pos(pos: isize) -> Result<f64> {
+    let rate = pos as f64 / RATE_STEPS;
+    ensure!(
+        (MIN_RATE..=MAX_RATE).contains(&rate),
+        "speaking rate position {pos} is out of range ({}..={}).",
+        pos_from_rate(MIN_RATE),
+        pos_from_rate(MAX_RATE)
+    );
+    Ok(rate)
+}
+
+/// 読み上げ速度をトラックバーの位置にする
+pub fn pos_from_rate(rate: f64) -> isize {
+    (rate * RATE_STEPS).round() as isize
+}
+
+/// 音声合成の設定
+#[derive(Clone, Debug, PartialEq)]
+pub struct SynthOptions {
+    /// 音声の ID または表示名 (None の場合は既定の音声)
+    pub voice_id: Option<String>,
+    /// 読み上げ速度 (1.0 が標準)
+    pub rate: f64,
+    /// 声の高さ (1.0 が標準)
+    pub pitch: f64,
+    /// 音量 (1.0 が最大)
+    pub volume: f64,
+}
+
+impl Default for SynthOptions {
+    fn default() -> Self {
+        Self {
+            voice_id: None,
+            rate: 1.0,
+            pitch: 1.0,
+            volume: 1.0,
+        }
+    }
+}
+
+impl SynthOptions {
+    /// 各値が範囲内か確かめる
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            (MIN_RATE..=MAX_RATE).contains(&self.rate),
+            "rate must be between {MIN_RATE} and {MAX_RATE}."
+        );
+        ensure!(
+            (0.0..=MAX_PITCH).contains(&self.pitch),
+            "pitch must be between 0 and {MAX_PITCH}."
+        );
+        ensure!(
+            (0.0..=MAX_VOLUME).contains(&self.volume),
+            "volume must be between 0 and {MAX_VOLUME}."
+        );
+        Ok(())
+    }
+}
+
+/// 音声を切り替えて読み上げるテキストの区間。text[start..end] を synth で合成する
+///
+/// 混ざった言語をそれぞれの言語の音声で読み上げるのに使う。区間は重ならないように順に並べる。
+/// 再生では音声合成エンジンを差し替えられるように、synth の型を変えられる。
+#[derive(Clone)]
+pub struct Voiced<S = SpeechSynthesizer> {
+    pub start: usize,
+    pub end: usize,
+    pub synth: S,
+}
+
+impl<S: Clone> Voiced<S> {
+    /// テキスト全体を一つの音声合成エンジンで合成する区間
+    pub fn whole(text: &[u16], synth: &S) -> Self {
+        Self {
+            start: 0,
+            end: text.len(),
+            synth: synth.clone(),
+        }
+    }
+}
+
+/// 合成を取り消したことを表すエラー
+///
+/// 利用者が停止した結果なので、受け取った側はエラーとして表示しない。
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("cancelled.")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// 順に合成しているチャンクの合成を取り消すためのハンドル
+#[derive(Clone, Default)]
+pub struct CancelHandle(Arc<Mutex<CancelState>>);
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: bool,
+    /// 合成中のチャンク
+    operation: Option<IAsyncOperation<SpeechSynthesisStream>>,
+    /// 終わったチャンクの数と全体のチャンクの数
+    progress: (usize, usize),
+}
+
+impl CancelHandle {
+    /// 合成中のチャンクを取り消し、残りのチャンクも合成しないようにする
+    pub fn cancel(&self) {
+        let mut state = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        state.cancelled = true;
+        if let Some(operation) = state.operation.take() {
+            _ = operation.Cancel();
+        }
+    }
+
+    /// 合成を始めたチャンクを登録する。すでに取り消されていれば取り消してエラーを返す
+    fn set(&self, operation: &IAsyncOperation<SpeechSynthesisStream>) -> Result<()> {
+        let mut state = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.cancelled {
+            _ = operation.Cancel();
+            return Err(Cancelled.into());
+        }
+        state.operation = Some(operation.clone());
+        Ok(())
+    }
+
+    /// 全体のどこまで終わったか (0.0 ～ 1.0)。チャンクの数で割ったおおよその値
+    pub fn progress(&self) -> f64 {
+        let (done, total) = self
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .progress;
+        if total == 0 {
+            return 0.0;
+        }
+        done as f64 / total as f64
+    }
+
+    fn set_progress(&self, done: usize, total: usize) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .progress = (done, total);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .cancelled
+    }
+}
+
+/// 文字数と読み上げ速度から、読み上げにかかるおおよその時間を求める
+pub fn estimate_duration(len: usize, rate: f64) -> Duration {
+    Duration::from_secs_f64(len as f64 * 60.0 / (CHARS_PER_MINUTE * rate.max(MIN_RATE)))
+}
+
+/// 段落と段落の間に入れる無音の長さを変える (再生と WAV の保存の両方に使う)
+pub fn set_paragraph_pause(pause: Duration) {
+    *PARAGRAPH_PAUSE
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = pause;
+}
+
+/// 段落と段落の間に入れる無音の長さ
+pub fn paragraph_pause() -> Duration {
+    *PARAGRAPH_PAUSE
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// テキストと合成の設定から、合成結果を使い回すためのキーを求める
+pub fn cache_key(text: &[u16], options: &SynthOptions) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    options.voice_id.hash(&mut hasher);
+    // 置換ルールや読み方の辞書を変えたら合成し直す
+    rules::generation().hash(&mut hasher);
+    lexicon::generation().hash(&mut hasher);
+    normalize::active().hash(&mut hasher);
+    for value in [options.rate, options.pitch, options.volume] {
+        value.to_bits().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// 区間ごとに音声を切り替えて合成する場合の [cache_key]。区間の位置とそれぞれの設定から求める
+pub fn voiced_cache_key(text: &[u16], parts: &[(usize, usize, SynthOptions)]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for (start, end, options) in parts {
+        (start, end).hash(&mut hasher);
+        let part = text.get(*start..*end).unwrap_or_default();
+        cache_key(part, options).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// 対になっていないサロゲートを U+FFFD に置き換える
+///
+/// 他のアプリからの貼り付けなどで壊れたテキストは HSTRING にできなかったり合成に失敗したりする。
+/// 1 単位を 1 単位に置き換えるので、選択範囲などの位置は変わらない。
+pub fn replace_lone_surrogates(text: &[u16]) -> Vec<u16> {
+    char::decode_utf16(text.iter().copied())
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .flat_map(|c| {
+            let mut buf = [0; 2];
+            c.encode_utf16(&mut buf).to_vec()
+        })
+        .collect()
+}
+
+/// 合成する前にテキストを整える。末尾の NUL を取り除き、空白だけの場合はエラーにする
+pub fn prepare_text(text: &[u16]) -> Result<&[u16]> {
+    let end = text.iter().rposition(|&c| c != 0).map_or(0, |i| i + 1);
+    let text = &text[..end];
+    let blank = char::decode_utf16(text.iter().copied()).all(|c| c.is_ok_and(char::is_whitespace));
+    ensure!(!blank, "no text to speak.");
+    Ok(text)
+}
+
+/// テキストを [MAX_CHUNK_LEN] 以下に区切る。空白だけの部分は読み上げないので取り除く
+///
+/// 段落の間に無音を入れられるように、段落の境目では必ず区切る。
+/// 最初のチャンクは [FIRST_CHUNK_LEN] 以下にして、続くチャンクを少しずつ長くする
+/// (最初のチャンクを再生している間に次のチャンクを合成できるように)。
+pub fn chunks(text: &[u16]) -> Result<Vec<Vec<u16>>> {
+    Ok(chunks_at(text)?
+        .into_iter()
+        .map(|(_, chunk)| chunk.to_vec())
+        .collect())
+}
+
+/// [chunks] と同じように区切ったチャンクと、テキストでのそれぞれの開始位置
+fn chunks_at(text: &[u16]) -> Result<Vec<(usize, &[u16])>> {
+    let mut start = 0;
+    let mut chunks = vec![];
+    for paragraph in chunk::paragraphs(prepare_text(text)?) {
+        // 二つ目からの段落は、前の段落を再生している間に合成できる
+        let first_len = if chunks.is_empty() {
+            FIRST_CHUNK_LEN
+        } else {
+            MAX_CHUNK_LEN
+        };
+        for chunk in chunk::split_growing(paragraph, first_len, MAX_CHUNK_LEN) {
+            if prepare_text(chunk).is_ok() {
+                chunks.push((start, chunk));
+            }
+            start += chunk.len();
+        }
+    }
+    Ok(chunks)
+}
+
+/// 区間 (開始位置, 終了位置) ごとに [chunks_at] と同じように区切ったチャンクと、
+/// テキストでのそれぞれの開始位置と、何番目の区間か
+///
+/// 空白だけの区間は読み上げないので取り除く。
+fn part_chunks_at<'a>(
+    text: &'a [u16],
+    parts: &[(usize, usize)],
+) -> Result<Vec<(usize, &'a [u16], usize)>> {
+    let mut chunks = vec![];
+    for (index, &(start, end)) in parts.iter().enumerate() {
+        let part = text.get(start..end).context("part out of range.")?;
+        if prepare_text(part).is_err() {
+            continue;
+        }
+        let part_chunks = chunks_at(part)?;
+        chunks.extend(
+            part_chunks
+                .into_iter()
+                .map(|(offset, chunk)| (start + offset, chunk, index)),
+        );
+    }
+    ensure!(!chunks.is_empty(), "no text to speak.");
+    Ok(chunks)
+}
+
+/// 区間ごとに [chunks] と同じように区切ったチャンクと、それを合成する音声合成エンジン
+pub fn voiced_chunks<S: Clone>(text: &[u16], parts: &[Voiced<S>]) -> Result<Vec<(S, Vec<u16>)>> {
+    Ok(part_chunks_at(text, &bounds(parts))?
+        .into_iter()
+        .map(|(_, chunk, index)| (parts[index].synth.clone(), chunk.to_vec()))
+        .collect())
+}
+
+fn bounds<S>(parts: &[Voiced<S>]) -> Vec<(usize, usize)> {
+    parts.iter().map(|part| (part.start, part.end)).collect()
+}
+
+/// [chunks_at] で区切ったそれぞれのチャンクの後に入れる無音の長さ (段落の終わりなら pause)
+fn pauses<'a>(chunks: impl IntoIterator<Item = &'a [u16]>, pause: Duration) -> Vec<Duration> {
+    chunks
+        .into_iter()
+        .map(|chunk| {
+            if chunk::ends_paragraph(chunk) {
+                pause
+            } else {
+                Duration::ZERO
+            }
+        })
+        .collect()
+}
+
+/// [chunks] の index 番目のチャンクを fraction (0.0 ～ 1.0) まで再生して止めたときに、続きを読み上げ始める位置
+///
+/// 再生した割合から文字数を見積もり、文の途中から始めないように文の先頭まで戻す。
+pub fn resume_offset(text: &[u16], index: usize, fraction: f64) -> usize {
+    let chunk = chunks_at(text)
+        .ok()
+        .and_then(|chunks| chunks.get(index).copied());
+    offset_in_chunk(text, chunk, fraction)
+}
+
+/// [voiced_chunks] の index 番目のチャンクを fraction まで再生して止めたときに、続きを読み上げ始める位置
+pub fn voiced_resume_offset<S>(
+    text: &[u16],
+    parts: &[Voiced<S>],
+    index: usize,
+    fraction: f64,
+) -> usize {
+    let chunk = part_chunks_at(text, &bounds(parts))
+        .ok()
+        .and_then(|chunks| chunks.get(index).map(|&(start, chunk, _)| (start, chunk)));
+    offset_in_chunk(text, chunk, fraction)
+}
+
+/// 開始位置 start のチャンクを fraction まで再生したときの、文の先頭まで戻した位置 (チャンクがなければ 0)
+fn offset_in_chunk(text: &[u16], chunk: Option<(usize, &[u16])>, fraction: f64) -> usize {
+    let Some((start, chunk)) = chunk else {
+        return 0;
+    };
+    let pos = start + (chunk.len() as f64 * fraction.clamp(0.0, 1.0)) as usize;
+    chunk::sentence_start(text, utf16::floor_char_boundary(text, pos))
+}
+
+/// 指定した設定でテキストを合成し、終わるまで待って WAV 形式のバイト列を返す
+///
+/// 長いテキストは区切って順に合成し、段落の間に無音を入れて一つの WAV につなげる。
+pub fn synthesize_wav(text: &[u16], options: &SynthOptions) -> Result<Vec<u8>> {
+    let synth = create_synthesizer(options)?;
+    let chunks = chunks_at(text)?;
+    let parts = chunks
+        .iter()
+        .map(|(_, chunk)| wav::stream_bytes(&start_with(&synth, chunk)?.get()?.cast()?))
+        .collect::<Result<Vec<_>>>()?;
+    let chunks = chunks.iter().map(|&(_, chunk)| chunk);
+    wav::concat_with_pauses(&parts, &pauses(chunks, paragraph_pause()))
+}
+
+/// 設定済みの [SpeechSynthesizer] でテキストを WAV 形式に合成し始める
+///
+/// 長いテキストは区切って順に合成し、すべて終わったら段落の間に無音を入れて一つの WAV につなげて f に渡す。
+/// 合成を始められなかった場合は、この関数の中でエラーを渡して f を呼ぶ。
+/// 返したハンドルで取り消すと、f には [Cancelled] のエラーが渡される。
+pub fn start_wav<F>(synth: &SpeechSynthesizer, text: &[u16], f: F) -> CancelHandle
+where
+    F: FnOnce(Result<Vec<u8>>) + Send + 'static,
+{
+    start_wav_with_marks(synth, text, |output| f(output.map(|(bytes, _)| bytes)))
+}
+
+/// [start_wav] と同じように合成し、WAV と一緒にテキスト全体でのスピーチマークを f に渡す
+///
+/// マークを作るには、先に [speech_marks::set_enabled] で境界のキューを含めるようにしておく。
+pub fn start_wav_with_marks<F>(synth: &SpeechSynthesizer, text: &[u16], f: F) -> CancelHandle
+where
+    F: FnOnce(Result<(Vec<u8>, Vec<Mark>)>) + Send + 'static,
+{
+    start_voiced_wav_with_marks(text, &[Voiced::whole(text, synth)], f)
+}
+
+/// [start_wav_with_marks] と同じように合成し、区間ごとにその区間の音声合成エンジンで合成する
+///
+/// 音声によってサンプリングレートが違う場合は、最初のチャンクの形式に合わせてつなげる。
+pub fn start_voiced_wav_with_marks<F>(text: &[u16], parts: &[Voiced], f: F) -> CancelHandle
+where
+    F: FnOnce(Result<(Vec<u8>, Vec<Mark>)>) + Send + 'static,
+{
+    let handle = CancelHandle::default();
+    let started = part_chunks_at(text, &bounds(parts)).and_then(|chunks| {
+        let pauses = pauses(chunks.iter().map(|&(_, chunk, _)| chunk), paragraph_pause());
+        let mut chunks = chunks
+            .into_iter()
+            .map(|(start, chunk, index)| (start, chunk.to_vec(), parts[index].synth.clone()))
+            .collect::<VecDeque<_>>();
+        handle.set_progress(0, chunks.len());
+        let (start, first, synth) = chunks.pop_front().context("no text to speak.")?;
+        let (operation, map) = start_mapped(&synth, &first)?;
+        handle.set(&operation)?;
+        Ok((operation, start, map, chunks, pauses))
+    });
+    match started {
+        Ok((operation, start, map, chunks, pauses)) => {
+            let job = WavJob {
+                handle: handle.clone(),
+                chunks,
+                start,
+                map,
+                pauses,
+                parts: vec![],
+                marks: vec![],
+                elapsed: Duration::ZERO,
+                f: Box::new(f),
+            };
+            _ = job.continue_after(operation);
+        }
+        Err(e) => f(Err(e)),
+    }
+    handle
+}
+
+/// text を合成済みのチャンクを、段落の間に無音を入れて一つの WAV につなげて f に渡す
+///
+/// 合成し直さずに保存するときに使う。UI スレッドを待たせないように、[worker] のスレッドで読み出す。
+/// 返したハンドルで取り消すと、f には [Cancelled] のエラーが渡される。
+pub fn wav_from_chunks<F>(text: &[u16], chunks: Vec<SpeechSynthesisStream>, f: F) -> CancelHandle
+where
+    F: FnOnce(Result<Vec<u8>>) + Send + 'static,
+{
+    let pauses = chunks_at(text)
+        .map(|chunks| pauses(chunks.iter().map(|&(_, chunk)| chunk), paragraph_pause()))
+        .unwrap_or_default();
+    concat_chunks(chunks, pauses, f)
+}
+
+/// [wav_from_chunks] と同じように、[voiced_chunks] で区切って合成済みのチャンクをつなげる
+pub fn wav_from_voiced_chunks<S, F>(
+    text: &[u16],
+    parts: &[Voiced<S>],
+    chunks: Vec<SpeechSynthesisStream>,
+    f: F,
+) -> CancelHandle
+where
+    F: FnOnce(Result<Vec<u8>>) + Send + 'static,
+{
+    let pauses = part_chunks_at(text, &bounds(parts))
+        .map(|chunks| pauses(chunks.iter().map(|&(_, chunk, _)| chunk), paragraph_pause()))
+        .unwrap_or_default();
+    concat_chunks(chunks, pauses, f)
+}
+
+/// 合成済みのチャンクを [worker] のスレッドで読み出し、pauses の無音を入れて一つの WAV につなげて f に渡す
+fn concat_chunks<F>(chunks: Vec<SpeechSynthesisStream>, pauses: Vec<Duration>, f: F) -> CancelHandle
+where
+    F: FnOnce(Result<Vec<u8>>) + Send + 'static,
+{
+    let handle = CancelHandle::default();
+    let cancel = handle.clone();
+    worker::spawn(move || {
+        let _com = com::MtaGuard::new();
+        let parts = chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                ensure!(!cancel.is_cancelled(), Cancelled);
+                cancel.set_progress(i, chunks.len());
+                // 取っておいたストリームを読み進めないように、複製して読む
+                wav::stream_bytes(&chunk.CloneStream()?)
+            })
+            .collect::<Result<Vec<_>>>();
+        f(parts.and_then(|parts| wav::concat_with_pauses(&parts, &pauses)));
+    });
+    handle
+}
+
+/// [start_wav] で順に合成しているテキスト
+struct WavJob {
+    handle: CancelHandle,
+    /// まだ合成を始めていないチャンクと、テキストでの開始位置と、合成する音声合成エンジン
+    chunks: VecDeque<(usize, Vec<u16>, SpeechSynthesizer)>,
+    /// 合成中のチャンクのテキストでの開始位置
+    start: usize,
+    /// 合成中のチャンクで、整形や置換をした後の位置をチャンクでの位置に戻す対応
+    map: OffsetMap,
+    /// それぞれのチャンクの後に入れる無音の長さ
+    pauses: Vec<Duration>,
+    /// 合成し終わったチャンクの WAV
+    parts: Vec<Vec<u8>>,
+    /// 合成し終わったチャンクのスピーチマーク (テキスト全体での位置と時刻)
+    marks: Vec<Mark>,
+    /// 合成し終わったチャンクの再生時間と、その後の無音の長さの合計
+    elapsed: Duration,
+    f: Box<dyn FnOnce(Result<(Vec<u8>, Vec<Mark>)>) + Send>,
+}
+
+impl WavJob {
+    /// 合成中のチャンクが終わったら次のチャンクを合成する
+    fn continue_after(mut self, operation: IAsyncOperation<SpeechSynthesisStream>) -> Result<()> {
+        on_completed(&operation, move |stream| {
+            let next = unwind::catch(|| {
+                let stream = stream?;
+                let bytes = wav::stream_bytes(&stream.cast()?)?;
+                let (start, elapsed) = (self.start, self.elapsed);
+                let marks = speech_marks::from_stream(&stream)?;
+                let map = &self.map;
+                self.marks.extend(
+                    marks
+                        .into_iter()
+                        .map(|mark| mark.mapped(map).shifted(start, elapsed)),
+                );
+                self.elapsed += Duration::from_secs_f64(wav::parse_header(&bytes)?.duration_secs());
+                self.elapsed += self
+                    .pauses
+                    .get(self.parts.len())
+                    .copied()
+                    .unwrap_or_default();
+                self.parts.push(bytes);
+                let done = self.parts.len();
+                self.handle.set_progress(done, done + self.chunks.len());
+                let Some((start, chunk, synth)) = self.chunks.pop_front() else {
+                    return Ok(None);
+                };
+                let (operation, map) = start_mapped(&synth, &chunk)?;
+                (self.start, self.map) = (start, map);
+                self.handle.set(&operation)?;
+                Ok(Some(operation))
+            });
+            match next {
+                // 完了ハンドラを登録できなければ f は呼ばれないが、SetCompleted はまず失敗しない
+                Ok(Some(operation)) => _ = self.continue_after(operation),
+                // 書き込みの途中で終了しないように、f は [worker] のスレッドで呼ぶ
+                Ok(None) => worker::spawn(move || {
+                    let marks = mem::take(&mut self.marks);
+                    let bytes = wav::concat_with_pauses(&self.parts, &self.pauses);
+                    (self.f)(bytes.map(|bytes| (bytes, marks)))
+                }),
+                Err(e) => (self.f)(Err(e)),
+            }
+        })
+    }
+}
+
+/// 指定した設定でテキストの合成を始める
+///
+/// 完了を待たずに返るので、結果は [on_completed] で受け取る。取り消す場合は Cancel を呼ぶ。
+pub fn start(
+    text: &[u16],
+    options: &SynthOptions,
+) -> Result<IAsyncOperation<SpeechSynthesisStream>> {
+    start_with(&create_synthesizer(options)?, text)
+}
+
+/// 設定済みの [SpeechSynthesizer] でテキストの合成を始める
+///
+/// 合成する前に音声の言語に合わせた整形 ([normalize::set_active])、置換ルール ([rules::set_active])、
+/// 読み方の辞書 ([lexicon::set_active]) をこの順に適用する。
+pub fn start_with(
+    synth: &SpeechSynthesizer,
+    text: &[u16],
+) -> Result<IAsyncOperation<SpeechSynthesisStream>> {
+    Ok(start_mapped(synth, text)?.0)
+}
+
+/// [start_with] と同じように合成を始め、整形や置換をした後の位置を text での位置に戻す対応も返す
+///
+/// 音声合成エンジンが返す位置 (スピーチマークのキューの位置など) は、置き換えた後のテキストでの位置になる。
+pub fn start_mapped(
+    synth: &SpeechSynthesizer,
+    text: &[u16],
+) -> Result<(IAsyncOperation<SpeechSynthesisStream>, OffsetMap)> {
+    let lang = synth.Voice()?.Language()?.to_string();
+    // クリップボードやパイプから受け取ったテキストも、エディットのテキストと同じように置き換える
+    let text = replace_lone_surrogates(prepare_text(text)?);
+    let (text, normalized) = normalize::apply_active(&text, &lang);
+    let (text, replaced) = rules::apply_active(&text);
+    let (text, looked_up) = lexicon::apply_active(&text);
+    let source = HSTRING::from_wide(prepare_text(&text)?)?;
+    let operation = synth
+        .SynthesizeTextToStreamAsync(&source)
+        .map_err(SpeechError::from)?;
+    Ok((operation, normalized.then(replaced).then(looked_up)))
+}
+
+/// 指定した設定の [SpeechSynthesizer] を作る
+pub fn create_synthesizer(options: &SynthOptions) -> Result<SpeechSynthesizer> {
+    let synth = SpeechSynthesizer::new()?;
+    apply_options(&synth, options)?;
+    Ok(synth)
+}
+
+/// [SpeechSynthesizer] の設定を変更する
+///
+/// 音声の切り替えは時間がかかることがあるので、変わった場合だけ設定し直す。
+pub fn apply_options(synth: &SpeechSynthesizer, options: &SynthOptions) -> Result<()> {
+    options.validate()?;
+    if let Some(voice_id) = &options.voice_id {
+        let voice = find_voice(voice_id)?;
+        if synth.Voice()?.Id()? != voice.Id()? {
+            synth.SetVoice(&voice)?;
+        }
+    }
+    let synth_options = synth.Options()?;
+    synth_options.SetSpeakingRate(options.rate)?;
+    synth_options.SetAudioPitch(options.pitch)?;
+    synth_options.SetAudioVolume(options.volume)?;
+    // 再生中に前後の文に移れるように、文の境界はいつも含める
+    synth_options.SetIncludeSentenceBoundaryMetadata(true)?;
+    Ok(())
+}
+
+/// 非同期処理が終わったときに結果を渡して f を呼び出す
+///
+/// f は処理を実行したスレッドで呼ばれる。すでに終わっている場合はこの関数の中で呼ばれる。
+pub fn on_completed<T, F>(operation: &IAsyncOperation<T>, f: F) -> Result<()>
+where
+    T: RuntimeType + 'static,
+    F: FnOnce(Result<T>) + Send + 'static,
+{
+    let mut f = Some(f);
+    let handler = AsyncOperationCompletedHandler::new(move |operation, status| {
+        let Some(f) = f.take() else {
+            return Ok(());
+        };
+        let operation = operation.context("no operation.");
+        f(operation.and_then(|operation| match status {
+            AsyncStatus::Completed => Ok(operation.GetResults()?),
+            AsyncStatus::Canceled => Err(Cancelled.into()),
+            _ => Err(SpeechError::from_hresult(operation.ErrorCode()?).into()),
+        }));
+        Ok(())
+    });
+    operation.SetCompleted(&handler)?;
+    Ok(())
+}
+
+/// 表示名または ID で音声を探す (大文字と小文字は区別しない)
+pub fn find_voice(name: &str) -> Result<VoiceInformation> {
+    SpeechSynthesizer::AllVoices()?
+        .into_iter()
+        .find(|v| {
+            [v.DisplayName(), v.Id()]
+                .into_iter()
+                .flatten()
+                .any(|s| s.to_string_lossy().eq_ignore_ascii_case(name))
+        })
+        .ok_or(SpeechError::VoiceUnavailable)
+        .with_context(|| format!("no voice named {name}."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_duration_from_rate() {
+        assert_eq!(estimate_duration(400, 1.0), Duration::from_secs(60));
+        assert_eq!(estimate_duration(400, 2.0), Duration::from_secs(30));
+        assert_eq!(estimate_duration(0, 1.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn cache_key_covers_text_and_options() {
+        let text = wide("こんにちは");
+        let options = SynthOptions::default();
+        let key = cache_key(&text, &options);
+        assert_eq!(key, cache_key(&text, &options.clone()));
+        assert_ne!(key, cache_key(&wide("こんばんは"), &options));
+        let changes = [
+            SynthOptions {
+                voice_id: Some("Microsoft Haruka".into()),
+                ..options.clone()
+            },
+            SynthOptions {
+                rate: 1.5,
+                ..options.clone()
+            },
+            SynthOptions {
+                pitch: 0.5,
+                ..options.clone()
+            },
+            SynthOptions {
+                volume: 0.5,
+                ..options.clone()
+            },
+        ];
+        for changed in changes {
+            assert_ne!(key, cache_key(&text, &changed), "{changed:?}");
+        }
+    }
+
+    #[test]
+    fn map_trackbar_positions_to_rates() {
+        assert_eq!(rate_from_pos(5).unwrap(), MIN_RATE);
+        assert_eq!(rate_from_pos(10).unwrap(), 1.0);
+        assert_eq!(rate_from_pos(25).unwrap(), MAX_RATE);
+        for pos in [4, 26, 0, -1, 1024] {
+            let e = rate_from_pos(pos).unwrap_err();
+            assert!(e.to_string().contains(&pos.to_string()), "{e}");
+        }
+        for pos in 5..=25 {
+            assert_eq!(pos_from_rate(rate_from_pos(pos).unwrap()), pos);
+        }
+    }
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn default_options_are_valid() {
+        assert!(SynthOptions::default().validate().is_ok());
+    }
+
+    #[test]
+    fn reject_out_of_range_options() {
+        let options = SynthOptions::default();
+        for invalid in [
+            SynthOptions {
+                rate: 0.4,
+                ..options.clone()
+            },
+            SynthOptions {
+                rate: 2.6,
+                ..options.clone()
+            },
+            SynthOptions {
+                pitch: -0.1,
+                ..options.clone()
+            },
+            SynthOptions {
+                pitch: 2.1,
+                ..options.clone()
+            },
+            SynthOptions {
+                volume: 1.1,
+                ..options.clone()
+            },
+            SynthOptions {
+                rate: f64::NAN,
+                ..options.clone()
+            },
+        ] {
+            assert!(invalid.validate().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn replace_only_lone_surrogates() {
+        const HIGH: u16 = 0xD83D;
+        const LOW: u16 = 0xDE00;
+        const REPLACED: u16 = 0xFFFD;
+        let a = 'a' as u16;
+        // 正しいサロゲートペアはそのまま残す
+        let valid = wide("😀あ😀");
+        assert_eq!(replace_lone_surrogates(&valid), valid);
+        // 先頭、末尾、途中の対になっていないサロゲートを置き換える
+        assert_eq!(replace_lone_surrogates(&[LOW, a]), [REPLACED, a]);
+        assert_eq!(replace_lone_surrogates(&[a, HIGH]), [a, REPLACED]);
+        assert_eq!(
+            replace_lone_surrogates(&[a, HIGH, a, LOW, HIGH, LOW, LOW]),
+            [a, REPLACED, a, REPLACED, HIGH, LOW, REPLACED]
+        );
+        assert_eq!(
+            replace_lone_surrogates(&[HIGH, HIGH, LOW]),
+            [REPLACED, HIGH, LOW]
+        );
+        assert!(replace_lone_surrogates(&[]).is_empty());
+    }
+
+    #[test]
+    fn resume_from_sentence_start() {
+        let sentence = "あ".repeat(FIRST_CHUNK_LEN / 2 - 1) + "。";
+        let text = wide(&sentence.repeat(3));
+        let len = sentence.encode_utf16().count();
+        // 二つ目のチャンクの途中で止めたら、その中の文の先頭から
+        assert_eq!(chunks_at(&text).unwrap()[1].0, 2 * len);
+        assert_eq!(resume_offset(&text, 1, 0.0), 2 * len);
+        assert_eq!(resume_offset(&text, 1, 0.9), 2 * len);
+        // 最初のチャンクの後半の文の途中なら、後半の文の先頭から
+        assert_eq!(resume_offset(&text, 0, 0.75), len);
+        assert_eq!(resume_offset(&text, 0, 0.25), 0);
+        // 範囲外のチャンクは最初から
+        assert_eq!(resume_offset(&text, 5, 0.5), 0);
+    }
+
+    #[test]
+    fn split_at_paragraphs() {
+        let text = wide("一段落目。\r\n\r\n二段落目。\r\n三行目。\r\n\r\n\r\n三段落目。");
+        let chunks = chunks_at(&text).unwrap();
+        let starts = chunks.iter().map(|(start, _)| *start).collect::<Vec<_>>();
+        assert_eq!(starts, [0, 9, 26]);
+        // 最後の段落の後には入れない
+        let pause = Duration::from_secs(1);
+        let chunks = chunks.iter().map(|&(_, chunk)| chunk);
+        assert_eq!(pauses(chunks, pause), [pause, pause, Duration::ZERO]);
+    }
+
+    #[test]
+    fn split_each_part_into_chunks() {
+        let text = wide("日本語の文。English words.\r\n\r\n次の段落。");
+        // 言語ごとの区間 (英語の区間は段落の終わりまで)
+        let parts = [(0, 6), (6, 24), (24, 29)];
+        let chunks = part_chunks_at(&text, &parts).unwrap();
+        let starts = chunks
+            .iter()
+            .map(|&(start, _, index)| (start, index))
+            .collect::<Vec<_>>();
+        assert_eq!(starts, [(0, 0), (6, 1), (24, 2)]);
+        // 区間の境目では間を空けず、段落の終わりでだけ空ける
+        let pause = Duration::from_secs(1);
+        let pauses = pauses(chunks.iter().map(|&(_, chunk, _)| chunk), pause);
+        assert_eq!(pauses, [Duration::ZERO, pause, Duration::ZERO]);
+        // 空白だけの区間は読み上げない
+        let chunks = part_chunks_at(&text, &[(0, 6), (20, 24)]).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert!(part_chunks_at(&text, &[(20, 24)]).is_err());
+        assert!(part_chunks_at(&text, &[(0, 100)]).is_err());
+    }
+
+    #[test]
+    fn voiced_cache_key_covers_parts() {
+        let text = wide("日本語 English");
+        let japanese = SynthOptions {
+            voice_id: Some("Haruka".into()),
+            ..Default::default()
+        };
+        let english = SynthOptions {
+            voice_id: Some("Zira".into()),
+            ..Default::default()
+        };
+        let key = voiced_cache_key(&text, &[(0, 4, japanese.clone()), (4, 11, english.clone())]);
+        assert_ne!(
+            key,
+            voiced_cache_key(&text, &[(0, 3, japanese.clone()), (3, 11, english.clone())])
+        );
+        assert_ne!(
+            key,
+            voiced_cache_key(&text, &[(0, 4, english.clone()), (4, 11, japanese.clone())])
+        );
+        assert_ne!(key, cache_key(&text, &japanese));
+    }
+
+    #[test]
+    fn prepare_text_trims_nul() {
+        assert_eq!(prepare_text(&wide("テスト\0\0")).unwrap(), wide("テスト"));
+        assert_eq!(prepare_text(&wide(" a ")).unwrap(), wide(" a "));
+    }
+
+    #[test]
+    fn prepare_text_rejects_blank() {
+        assert!(prepare_text(&[]).is_err());
+        assert!(prepare_text(&wide(" \r\n\t\0")).is_err());
+        // 全角スペースも空白として扱う
+        assert!(prepare_text(&wide("\u{3000} \u{3000}\r\n\0")).is_err());
+        // 末尾の NUL だけを取り除き、前後の空白は残す
+        assert_eq!(
+            prepare_text(&wide("\u{3000}あ \0\0")).unwrap(),
+            wide("\u{3000}あ ")
+        );
+    }
+}
@@ -0,0 +1,235 @@
+use anyhow::Result;
+use speech::text_file;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use windows::Win32::{
+    Foundation::{HWND, LPARAM, WPARAM},
+    UI::WindowsAndMessaging::PostMessageW,
+};
+
+/// ファイルが伸びたか確かめる間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// 一度に読み込む最大のバイト数 (大量に追記されても少しずつ読み上げる)
+const MAX_READ: u64 = 1024 * 1024;
+
+/// 監視スレッド
+static WATCHER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+/// 監視を終了しようとしているかどうか
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// ファイルに追記された行を読み出す
+///
+/// 読み終えた位置を覚えておき、最後の改行までを新しい行として返す。
+/// ファイルが短くなったり作り直されたりしたら、新しいファイルの先頭から読み直す。
+pub struct Tail {
+    path: PathBuf,
+    /// 次に読む位置
+    offset: u64,
+    /// ファイルの作成日時 (作り直されたことを見分けるため)
+    created: Option<SystemTime>,
+    /// 先頭の BOM (追記された部分の文字コードを判定するために付け直す)
+    bom: Vec<u8>,
+    /// 改行で終わっていない読みかけの行
+    partial: Vec<u8>,
+}
+
+impl Tail {
+    /// ファイルの現在の末尾から監視を始める
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut tail = Self {
+            path: path.to_path_buf(),
+            offset: 0,
+            created: None,
+            bom: vec![],
+            partial: vec![],
+        };
+        let mut file = File::open(path)?;
+        tail.reset(&mut file)?;
+        tail.offset = file.metadata()?.len().max(tail.offset);
+        Ok(tail)
+    }
+
+    /// 作り直されたファイルの先頭 (BOM の後) から読むようにする
+    fn reset(&mut self, file: &mut File) -> io::Result<()> {
+        let metadata = file.metadata()?;
+        self.created = metadata.created().ok();
+        let mut head = [0; 3];
+        let len = read_up_to(file, &mut head)?;
+        self.bom = bom(&head[..len]).to_vec();
+        self.offset = self.bom.len() as u64;
+        self.partial.clear();
+        Ok(())
+    }
+
+    /// 前回から追記された行を UTF-16 で返す。完全な行がなければ None を返す
+    ///
+    /// 複数の行が追記されていたら、まとめて一つのテキストにする。
+    pub fn read_lines(&mut self) -> Result<Option<Vec<u16>>> {
+        let mut file = File::open(&self.path)?;
+        let metadata = file.metadata()?;
+        let rotated = metadata.created().ok() != self.created;
+        if rotated || metadata.len() < self.offset {
+            self.reset(&mut file)?;
+        }
+        let len = metadata.len().min(self.offset + MAX_READ);
+        if len > self.offset {
+            file.seek(SeekFrom::Start(self.offset))?;
+            let start = self.partial.len();
+            self.partial.resize(start + (len - self.offset) as usize, 0);
+            let read = read_up_to(&mut file, &mut self.partial[start..])?;
+            self.partial.truncate(start + read);
+            self.offset += read as u64;
+        }
+        let Some(end) = line_end(&self.partial, &self.bom) else {
+            return Ok(None);
+        };
+        let lines: Vec<u8> = self.partial.drain(..end).collect();
+        let text = text_file::decode(&[self.bom.as_slice(), &lines].concat())?;
+        let text = text_file::normalize_newlines(&text);
+        if text
+            .iter()
+            .all(|&c| char::from_u32(c.into()).is_some_and(char::is_whitespace))
+        {
+            return Ok(None);
+        }
+        Ok(Some(text))
+    }
+}
+
+/// buf が埋まるかファイルの末尾まで読む
+fn read_up_to(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+/// 先頭の BOM
+fn bom(head: &[u8]) -> &[u8] {
+    for bom in [&[0xEF, 0xBB, 0xBF][..], &[0xFF, 0xFE], &[0xFE, 0xFF]] {
+        if head.starts_with(bom) {
+            return bom;
+        }
+    }
+    &[]
+}
+
+/// 最後の改行の直後の位置 (UTF-16 の場合は 2 バイト単位で探す)
+fn line_end(bytes: &[u8], bom: &[u8]) -> Option<usize> {
+    let newline: &[u8] = match bom {
+        [0xFF, 0xFE] => &[b'\n', 0],
+        [0xFE, 0xFF] => &[0, b'\n'],
+        _ => &[b'\n'],
+    };
+    bytes
+        .chunks_exact(newline.len())
+        .rposition(|unit| unit == newline)
+        .map(|i| (i + 1) * newline.len())
+}
+
+/// ファイルの監視を始める。追記された行は msg の LPARAM に `Box<Vec<u16>>` を入れて hwnd に送る
+///
+/// 監視中のファイルがあれば、その監視を終えてから始める。
+pub fn start(hwnd: HWND, path: &Path, msg: u32) -> Result<()> {
+    stop();
+    let mut tail = Tail::open(path)?;
+    STOPPING.store(false, Ordering::Relaxed);
+    // HWND はスレッドに送れないので、値として渡す
+    let handle = hwnd.0 as isize;
+    *WATCHER.lock().unwrap() = Some(thread::spawn(move || {
+        while !STOPPING.load(Ordering::Relaxed) {
+            thread::sleep(POLL_INTERVAL);
+            // ローテーションの途中などでファイルがない間は、次の確認まで待つ
+            let Ok(Some(text)) = tail.read_lines() else {
+                continue;
+            };
+            let text = Box::into_raw(Box::new(text));
+            let hwnd = HWND(handle as _);
+            if unsafe { PostMessageW(hwnd, msg, WPARAM(0), LPARAM(text as _)) }.is_err() {
+                drop(unsafe { Box::from_raw(text) });
+                break;
+            }
+        }
+    }));
+    Ok(())
+}
+
+/// 監視しているかどうか
+pub fn is_watching() -> bool {
+    WATCHER.lock().unwrap().is_some()
+}
+
+/// 監視を終了し、スレッドが終わるまで待つ
+pub fn stop() {
+    let Some(watcher) = WATCHER.lock().unwrap().take() else {
+        return;
+    };
+    STOPPING.store(true, Ordering::Relaxed);
+    _ = watcher.join();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    fn append(path: &Path, bytes: &[u8]) {
+        let mut file = fs::OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn read_appended_lines() {
+        let dir = std::env::temp_dir().join(format!("speech-tail-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("append.log");
+        fs::write(&path, "old line\n").unwrap();
+
+        // 監視を始める前の行は読まない
+        let mut tail = Tail::open(&path).unwrap();
+        assert_eq!(tail.read_lines().unwrap(), None);
+        // 改行で終わるまでは待つ
+        append(&path, b"first");
+        assert_eq!(tail.read_lines().unwrap(), None);
+        append(&path, b" line\nsecond line\nthi");
+        assert_eq!(
+            tail.read_lines().unwrap(),
+            Some(wide("first line\r\nsecond line\r\n"))
+        );
+        append(&path, b"rd\n");
+        assert_eq!(tail.read_lines().unwrap(), Some(wide("third\r\n")));
+
+        // 短くなったら先頭から読み直す
+        fs::write(&path, "new\n").unwrap();
+        assert_eq!(tail.read_lines().unwrap(), Some(wide("new\r\n")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_appended_utf16_lines() {
+        let dir = std::env::temp_dir().join(format!("speech-tail16-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("append.log");
+        let utf16 = |s: &str| -> Vec<u8> { s.encode_utf16().flat_map(u16::to_le_bytes).collect() };
+        fs::write(&path, [&[0xFF, 0xFE][..], &utf16("old\n")].concat()).unwrap();
+
+        let mut tail = Tail::open(&path).unwrap();
+        append(&path, &utf16("日本語\n"));
+        assert_eq!(tail.read_lines().unwrap(), Some(wide("日本語\r\n")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::Result;
+use std::cell::RefCell;
+use std::sync::OnceLock;
+use windows::{
+    core::w,
+    Win32::{
+        Foundation::HWND,
+        System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER},
+        UI::{
+            Shell::{
+                ITaskbarList3, TaskbarList, TBPFLAG, TBPF_ERROR, TBPF_INDETERMINATE,
+                TBPF_NOPROGRESS, TBPF_NORMAL, TBPF_PAUSED,
+            },
+            WindowsAndMessaging::RegisterWindowMessageW,
+        },
+    },
+};
+
+/// タスクバーのボタンに表示する進み具合の最大値
+const PROGRESS_MAX: u64 = 1000;
+/// タスクバーのボタンが作られたときに通知されるメッセージ
+static BUTTON_CREATED: OnceLock<u32> = OnceLock::new();
+
+thread_local! {
+    /// タスクバーのボタンが作られてから使えるようになる
+    static TASKBAR: RefCell<Option<ITaskbarList3>> = const { RefCell::new(None) };
+}
+
+/// タスクバーのボタンが作られたときに通知されるメッセージを取得する
+pub fn button_created_message() -> u32 {
+    *BUTTON_CREATED.get_or_init(|| unsafe { RegisterWindowMessageW(w!("TaskbarButtonCreated")) })
+}
+
+/// タスクバーのボタンが作られたら [ITaskbarList3] を用意する (エクスプローラーの再起動後にも呼ばれる)
+///
+/// COM の初期化後に UI スレッドから呼び出すこと。
+pub fn init() -> Result<()> {
+    let taskbar: ITaskbarList3 =
+        unsafe { CoCreateInstance(&TaskbarList, None, CLSCTX_INPROC_SERVER)? };
+    unsafe { taskbar.HrInit()? };
+    TASKBAR.with(|cell| cell.replace(Some(taskbar)));
+    Ok(())
+}
+
+/// 進み具合 (0.0 ～ 1.0) を表示する。None の場合は終わりがわからない表示にする
+pub fn set_progress(hwnd: HWND, fraction: Option<f64>, paused: bool) {
+    let Some(fraction) = fraction else {
+        return set_state(hwnd, TBPF_INDETERMINATE, None);
+    };
+    let state = if paused { TBPF_PAUSED } else { TBPF_NORMAL };
+    let value = (fraction.clamp(0.0, 1.0) * PROGRESS_MAX as f64) as u64;
+    set_state(hwnd, state, Some(value));
+}
+
+/// 失敗したことを赤い表示で知らせる
+pub fn set_error(hwnd: HWND) {
+    set_state(hwnd, TBPF_ERROR, Some(PROGRESS_MAX));
+}
+
+/// 進み具合の表示を消す
+pub fn clear(hwnd: HWND) {
+    set_state(hwnd, TBPF_NOPROGRESS, None);
+}
+
+/// ボタンがまだ作られていなければ何もしない
+fn set_state(hwnd: HWND, state: TBPFLAG, value: Option<u64>) {
+    TASKBAR.with(|cell| {
+        let Some(taskbar) = cell.borrow().clone() else {
+            return;
+        };
+        unsafe {
+            // 値を設定すると状態が TBPF_NORMAL に変わるので、状態は後から設定する
+            if let Some(value) = value {
+                _ = taskbar.SetProgressValue(hwnd, value, PROGRESS_MAX);
+            }
+            _ = taskbar.SetProgressState(hwnd, state);
+        }
+    });
+}
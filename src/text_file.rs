@@ -0,0 +1,101 @@
+use anyhow::{ensure, Result};
+use std::fs;
+use std::path::Path;
+use windows::Win32::Globalization::{MultiByteToWideChar, MULTI_BYTE_TO_WIDE_CHAR_FLAGS};
+
+/// Shift_JIS のコードページ
+const CP_SHIFT_JIS: u32 = 932;
+
+/// テキストファイルを読み込み、文字コードを判定して UTF-16 に変換する
+///
+/// BOM 付き UTF-8 / UTF-16 (LE, BE)、BOM なし UTF-8、Shift_JIS の順に判定する。
+/// 改行はエディットコントロールで扱えるように CRLF に揃える。
+pub fn read_text_file(path: &Path) -> Result<Vec<u16>> {
+    let bytes = fs::read(path)?;
+    let text = decode(&bytes)?;
+    Ok(normalize_newlines(&text))
+}
+
+/// バイト列の文字コードを判定して UTF-16 に変換する
+pub fn decode(bytes: &[u8]) -> Result<Vec<u16>> {
+    if let Some(rest) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return Ok(String::from_utf8_lossy(rest).encode_utf16().collect());
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        return Ok(rest
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect());
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        return Ok(rest
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect());
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Ok(text.encode_utf16().collect());
+    }
+    decode_shift_jis(bytes)
+}
+
+fn decode_shift_jis(bytes: &[u8]) -> Result<Vec<u16>> {
+    let flags = MULTI_BYTE_TO_WIDE_CHAR_FLAGS::default();
+    let len = unsafe { MultiByteToWideChar(CP_SHIFT_JIS, flags, bytes, None) };
+    ensure!(len > 0, "failed to decode text.");
+    let mut buf = vec![0; len as usize];
+    let len = unsafe { MultiByteToWideChar(CP_SHIFT_JIS, flags, bytes, Some(&mut buf)) };
+    ensure!(len > 0, "failed to decode text.");
+    buf.truncate(len as usize);
+    Ok(buf)
+}
+
+/// 改行コードを CRLF に揃える
+pub fn normalize_newlines(text: &[u16]) -> Vec<u16> {
+    const CR: u16 = b'\r' as u16;
+    const LF: u16 = b'\n' as u16;
+    let mut buf = Vec::with_capacity(text.len());
+    let mut iter = text.iter().copied().peekable();
+    while let Some(c) = iter.next() {
+        match c {
+            CR => {
+                if iter.peek() == Some(&LF) {
+                    iter.next();
+                }
+                buf.extend([CR, LF]);
+            }
+            LF => buf.extend([CR, LF]),
+            _ => buf.push(c),
+        }
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn decode_with_bom() {
+        assert_eq!(decode(b"\xEF\xBB\xBFabc").unwrap(), wide("abc"));
+        assert_eq!(decode(b"\xFF\xFEa\0b\0").unwrap(), wide("ab"));
+        assert_eq!(decode(b"\xFE\xFF\0a\0b").unwrap(), wide("ab"));
+    }
+
+    #[test]
+    fn decode_utf8_without_bom() {
+        assert_eq!(decode("日本語".as_bytes()).unwrap(), wide("日本語"));
+    }
+
+    #[test]
+    fn normalize_mixed_newlines() {
+        assert_eq!(
+            normalize_newlines(&wide("a\nb\r\nc\rd")),
+            wide("a\r\nb\r\nc\r\nd")
+        );
+    }
+}
@@ -0,0 +1,55 @@
+/// 読み上げやすいようにテキストを整形する
+///
+/// - 空行で区切られた段落の中の改行を取り除く (折り返し済みのテキスト向け)。
+///   前の行が全角文字で終わる場合はそのままつなげ、それ以外は空白を挟む。
+/// - 連続する空白やタブを一つの空白にまとめ、行頭と行末の空白を取り除く。
+/// - 段落の区切りは一つの空行にまとめる。
+///
+/// 改行は CRLF で出力する。
+pub fn format_for_speech(text: &str) -> String {
+    let mut paragraphs = vec![];
+    let mut paragraph = String::new();
+    for line in text.lines() {
+        let line = collapse_spaces(line);
+        if line.is_empty() {
+            if !paragraph.is_empty() {
+                paragraphs.push(std::mem::take(&mut paragraph));
+            }
+            continue;
+        }
+        if let Some(last) = paragraph.chars().last() {
+            if last.is_ascii() {
+                paragraph.push(' ');
+            }
+        }
+        paragraph.push_str(&line);
+    }
+    if !paragraph.is_empty() {
+        paragraphs.push(paragraph);
+    }
+    paragraphs.join("\r\n\r\n")
+}
+
+/// 連続する空白を一つにまとめ、前後の空白を取り除く
+fn collapse_spaces(line: &str) -> String {
+    line.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_wrapped_lines() {
+        assert_eq!(format_for_speech("hello\nworld"), "hello world");
+        assert_eq!(format_for_speech("日本\n語です"), "日本語です");
+    }
+
+    #[test]
+    fn collapse_spaces_and_paragraphs() {
+        assert_eq!(
+            format_for_speech("  a \t b  \r\n\r\n\r\n c\n"),
+            "a b\r\n\r\nc"
+        );
+    }
+}
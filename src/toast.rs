@@ -0,0 +1,98 @@
+use crate::strings::{tr, Msg};
+use anyhow::{ensure, Result};
+use std::collections::VecDeque;
+use std::os::windows::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Mutex, PoisonError};
+use windows::{
+    core::{IInspectable, Interface, HSTRING},
+    Data::Xml::Dom::XmlDocument,
+    Foundation::TypedEventHandler,
+    Win32::UI::Shell::SetCurrentProcessExplicitAppUserModelID,
+    UI::Notifications::{
+        NotificationSetting, ToastActivatedEventArgs, ToastNotification, ToastNotificationManager,
+    },
+};
+
+/// トースト通知やジャンプリストで使うアプリケーション ID
+const APP_ID: &str = "zxrs.speech";
+/// 「開く」ボタンを押したときに渡される引数
+const OPEN_ARGUMENT: &str = "open";
+/// 押されたことを受け取れるように残しておく通知の数
+const MAX_KEPT: usize = 10;
+/// 表示した通知 (手放すとボタンを押されても Activated が届かないことがある)
+static SHOWN: Mutex<VecDeque<ToastNotification>> = Mutex::new(VecDeque::new());
+
+/// プロセスにアプリケーション ID を設定する (ウィンドウを生成する前に呼び出すこと)
+pub fn init() -> Result<()> {
+    unsafe { SetCurrentProcessExplicitAppUserModelID(&HSTRING::from(APP_ID))? };
+    Ok(())
+}
+
+/// ファイルの保存が終わったことをトースト通知で知らせる
+///
+/// 「開く」を押すと、保存したファイルを選択した状態でエクスプローラーを開く。
+/// どのファイルを開くかは通知ごとに覚えているので、後から押しても正しいフォルダーが開く。
+/// 通知が無効になっている場合などはエラーを返すので、呼び出し側で別の方法で知らせること。
+pub fn show_saved(message: &str, path: &Path) -> Result<()> {
+    let notifier = ToastNotificationManager::CreateToastNotifierWithId(&HSTRING::from(APP_ID))?;
+    ensure!(
+        notifier.Setting()? == NotificationSetting::Enabled,
+        "toast notifications are disabled."
+    );
+    let xml = format!(
+        r#"<toast><visual><binding template="ToastGeneric"><text>{}</text><text>{}</text></binding></visual><actions><action content="{}" activationType="foreground" arguments="{OPEN_ARGUMENT}"/></actions></toast>"#,
+        escape(tr(Msg::AppName)),
+        escape(message),
+        escape(tr(Msg::ToastOpen)),
+    );
+    let doc = XmlDocument::new()?;
+    doc.LoadXml(&HSTRING::from(xml))?;
+    let toast = ToastNotification::CreateToastNotification(&doc)?;
+    let path = path.to_path_buf();
+    toast.Activated(&TypedEventHandler::new(
+        move |_, args: &Option<IInspectable>| {
+            let arguments = args
+                .as_ref()
+                .and_then(|args| args.cast::<ToastActivatedEventArgs>().ok())
+                .and_then(|args| args.Arguments().ok());
+            // 通知の本文を押した場合は何もしない
+            if arguments.is_some_and(|arguments| arguments == OPEN_ARGUMENT) {
+                _ = show_in_explorer(&path);
+            }
+            Ok(())
+        },
+    ))?;
+    notifier.Show(&toast)?;
+    let mut shown = SHOWN.lock().unwrap_or_else(PoisonError::into_inner);
+    shown.push_back(toast);
+    if shown.len() > MAX_KEPT {
+        shown.pop_front();
+    }
+    Ok(())
+}
+
+/// ファイルを選択した状態でエクスプローラーを開く
+fn show_in_explorer(path: &Path) -> Result<()> {
+    // エクスプローラーは /select,"パス" の形でしか受け付けないので、引用符を自分で付ける
+    Command::new("explorer.exe")
+        .raw_arg(format!("/select,\"{}\"", path.display()))
+        .spawn()?;
+    Ok(())
+}
+
+/// XML の特殊文字をエスケープする
+fn escape(text: &str) -> String {
+    text.chars().fold(String::new(), |mut s, c| {
+        match c {
+            '&' => s.push_str("&amp;"),
+            '<' => s.push_str("&lt;"),
+            '>' => s.push_str("&gt;"),
+            '"' => s.push_str("&quot;"),
+            '\'' => s.push_str("&apos;"),
+            _ => s.push(c),
+        }
+        s
+    })
+}
@@ -0,0 +1,204 @@
+use crate::state::control;
+use crate::strings::{tr, Msg};
+use crate::{dpi, icon};
+use anyhow::{ensure, Context, Result};
+use std::mem;
+use std::sync::OnceLock;
+use windows::Win32::{
+    Foundation::{HWND, LPARAM, POINT, RECT, WPARAM},
+    Graphics::Gdi::ClientToScreen,
+    UI::{
+        Controls::{
+            ImageList_Create, ImageList_Destroy, ImageList_ReplaceIcon, BTNS_BUTTON, CCS_NODIVIDER,
+            HIMAGELIST, ILC_COLOR32, ILC_MASK, NMTTDISPINFOW, TBBUTTON, TBSTATE_ENABLED,
+            TBSTYLE_FLAT, TBSTYLE_TOOLTIPS, TB_ADDBUTTONSW, TB_AUTOSIZE, TB_BUTTONSTRUCTSIZE,
+            TB_ENABLEBUTTON, TB_GETIMAGELIST, TB_GETRECT, TB_SETIMAGELIST, TOOLBARCLASSNAMEW,
+        },
+        WindowsAndMessaging::{
+            CreateWindowExW, DestroyIcon, GetWindowRect, SendMessageW, HMENU, WINDOW_EX_STYLE,
+            WINDOW_STYLE, WS_CHILD, WS_TABSTOP, WS_VISIBLE,
+        },
+    },
+};
+
+/// ツールバーの ID
+const ID_TOOLBAR: u16 = 5909;
+/// ボタンのアイコンの大きさ (96 DPI 基準)
+const ICON_SIZE: i32 = 16;
+/// ツールバーのボタンの一覧
+static BUTTONS: OnceLock<&'static [Button]> = OnceLock::new();
+
+/// ツールバーのボタン
+pub struct Button {
+    /// コマンド ID
+    pub id: u16,
+    /// アイコンのリソース ID
+    pub icon: u16,
+    /// ツールチップの説明
+    pub tip: Msg,
+}
+
+/// ツールバーを生成する
+///
+/// Tab キーでフォーカスを移すと、矢印キーでボタンを選んで Enter キーで押せる。
+/// ボタンが押されると、ボタンのコマンド ID で親ウィンドウに WM_COMMAND が送られる。
+pub fn create(hwnd: HWND, buttons: &'static [Button]) -> Result<()> {
+    let toolbar = unsafe {
+        CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            TOOLBARCLASSNAMEW,
+            None,
+            WS_CHILD
+                | WS_VISIBLE
+                | WS_TABSTOP
+                | WINDOW_STYLE(TBSTYLE_FLAT | TBSTYLE_TOOLTIPS | CCS_NODIVIDER),
+            0,
+            0,
+            0,
+            0,
+            hwnd,
+            HMENU(ID_TOOLBAR as _),
+            None,
+            None,
+        )?
+    };
+    BUTTONS.get_or_init(|| buttons);
+    unsafe {
+        SendMessageW(
+            toolbar,
+            TB_BUTTONSTRUCTSIZE,
+            WPARAM(mem::size_of::<TBBUTTON>()),
+            None,
+        )
+    };
+    set_image_list(toolbar, dpi::dpi_for_window(hwnd))?;
+
+    let tb_buttons = buttons
+        .iter()
+        .enumerate()
+        .map(|(i, button)| TBBUTTON {
+            iBitmap: i as _,
+            idCommand: button.id as _,
+            fsState: TBSTATE_ENABLED as _,
+            fsStyle: BTNS_BUTTON as _,
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    unsafe {
+        SendMessageW(
+            toolbar,
+            TB_ADDBUTTONSW,
+            WPARAM(tb_buttons.len()),
+            LPARAM(tb_buttons.as_ptr() as _),
+        )
+    };
+    unsafe { SendMessageW(toolbar, TB_AUTOSIZE, None, None) };
+    Ok(())
+}
+
+/// DPI に合わせた大きさのアイコンでイメージリストを作り直す
+fn set_image_list(toolbar: HWND, dpi: u32) -> Result<()> {
+    let buttons = BUTTONS.get().context("no buttons.")?;
+    let size = dpi::scale(ICON_SIZE, dpi);
+    let list =
+        unsafe { ImageList_Create(size, size, ILC_COLOR32 | ILC_MASK, buttons.len() as _, 0) };
+    ensure!(!list.is_invalid(), "failed to create image list.");
+    for button in buttons.iter() {
+        // イメージリストにはアイコンの複製が追加される
+        let icon = icon::load_sized(button.icon, size)?;
+        unsafe { ImageList_ReplaceIcon(list, -1, icon) };
+        _ = unsafe { DestroyIcon(icon) };
+    }
+    // 前のイメージリストが返されるので破棄する
+    let old_list = unsafe { SendMessageW(toolbar, TB_SETIMAGELIST, None, LPARAM(list.0 as _)) };
+    destroy_image_list(HIMAGELIST(old_list.0 as _));
+    Ok(())
+}
+
+/// ツールバーの [HWND]
+pub fn handle(parent: HWND) -> Result<HWND> {
+    control(parent, ID_TOOLBAR)
+}
+
+/// 親ウィンドウの大きさと DPI に合わせてツールバーを配置し直す
+pub fn resize(parent: HWND, dpi: u32) -> Result<()> {
+    let toolbar = handle(parent)?;
+    set_image_list(toolbar, dpi)?;
+    unsafe { SendMessageW(toolbar, TB_AUTOSIZE, None, None) };
+    Ok(())
+}
+
+/// ツールバーの高さ
+pub fn height(parent: HWND) -> Result<i32> {
+    let toolbar = handle(parent)?;
+    let mut rc = RECT::default();
+    unsafe { GetWindowRect(toolbar, &mut rc)? };
+    Ok(rc.bottom - rc.top)
+}
+
+/// ボタンの有効・無効を切り替える
+pub fn enable_button(parent: HWND, id: u16, enable: bool) -> Result<()> {
+    let toolbar = handle(parent)?;
+    unsafe {
+        SendMessageW(
+            toolbar,
+            TB_ENABLEBUTTON,
+            WPARAM(id as _),
+            LPARAM(enable as _),
+        )
+    };
+    Ok(())
+}
+
+/// ボタンの左下のスクリーン座標 (ボタンからメニューを表示する位置)
+pub fn menu_position(parent: HWND, id: u16) -> Result<POINT> {
+    let toolbar = handle(parent)?;
+    let mut rc = RECT::default();
+    let ret = unsafe {
+        SendMessageW(
+            toolbar,
+            TB_GETRECT,
+            WPARAM(id as _),
+            LPARAM(&mut rc as *mut _ as _),
+        )
+    };
+    ensure!(ret.0 != 0, "no such button.");
+    let mut pt = POINT {
+        x: rc.left,
+        y: rc.bottom,
+    };
+    _ = unsafe { ClientToScreen(toolbar, &mut pt) };
+    Ok(pt)
+}
+
+/// TTN_GETDISPINFOW で要求されたボタンのツールチップの説明を設定する
+pub fn set_tooltip_text(info: &mut NMTTDISPINFOW) {
+    let Some(button) = BUTTONS
+        .get()
+        .and_then(|buttons| buttons.iter().find(|b| b.id as usize == info.hdr.idFrom))
+    else {
+        return;
+    };
+    let text = tr(button.tip)
+        .encode_utf16()
+        .take(info.szText.len() - 1)
+        .chain(Some(0))
+        .collect::<Vec<_>>();
+    info.szText[..text.len()].copy_from_slice(&text);
+}
+
+/// ツールバーに設定したイメージリストを外して破棄する (ツールバーを破棄しても残るため)
+pub fn destroy(parent: HWND) {
+    let Ok(toolbar) = handle(parent) else {
+        return;
+    };
+    let list = unsafe { SendMessageW(toolbar, TB_GETIMAGELIST, None, None) };
+    unsafe { SendMessageW(toolbar, TB_SETIMAGELIST, None, LPARAM(0)) };
+    destroy_image_list(HIMAGELIST(list.0 as _));
+}
+
+fn destroy_image_list(list: HIMAGELIST) {
+    if !list.is_invalid() {
+        _ = unsafe { ImageList_Destroy(list) };
+    }
+}
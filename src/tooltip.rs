@@ -0,0 +1,70 @@
+use crate::dpi;
+use anyhow::Result;
+use std::ffi::c_void;
+use std::mem;
+use windows::{
+    core::PWSTR,
+    Win32::{
+        Foundation::{HWND, LPARAM, WPARAM},
+        UI::{
+            Controls::{
+                TOOLTIPS_CLASSW, TTF_IDISHWND, TTF_SUBCLASS, TTM_ADDTOOLW, TTM_SETMAXTIPWIDTH,
+                TTS_ALWAYSTIP, TTS_NOPREFIX, TTTOOLINFOW,
+            },
+            WindowsAndMessaging::{
+                CreateWindowExW, SendMessageW, CW_USEDEFAULT, WINDOW_STYLE, WS_EX_TOPMOST, WS_POPUP,
+            },
+        },
+    },
+};
+
+/// ツールチップの最大幅 (96 DPI 基準)
+const MAX_TIP_WIDTH: i32 = 300;
+
+/// ツールチップを生成する
+///
+/// コモンコントロールの初期化後に呼び出すこと。ツールチップは子ウィンドウではなく ID で探せないので、
+/// 返した [HWND] は呼び出し側で持っておく。
+pub fn create(hwnd: HWND) -> Result<HWND> {
+    let tooltip = unsafe {
+        CreateWindowExW(
+            WS_EX_TOPMOST,
+            TOOLTIPS_CLASSW,
+            None,
+            WS_POPUP | WINDOW_STYLE(TTS_ALWAYSTIP | TTS_NOPREFIX),
+            CW_USEDEFAULT,
+            CW_USEDEFAULT,
+            CW_USEDEFAULT,
+            CW_USEDEFAULT,
+            hwnd,
+            None,
+            None,
+            None,
+        )?
+    };
+    set_max_width(tooltip, dpi::dpi_for_window(hwnd))?;
+    Ok(tooltip)
+}
+
+/// コントロールにツールチップの説明を登録する
+pub fn add_tool(tooltip: HWND, parent: HWND, control: HWND, text: &str) -> Result<()> {
+    let mut text = text.encode_utf16().chain(Some(0)).collect::<Vec<_>>();
+    let info = TTTOOLINFOW {
+        // コモンコントロール v6 以前でも受け付けられるように lpReserved を含まない大きさを指定する
+        cbSize: (mem::size_of::<TTTOOLINFOW>() - mem::size_of::<*mut c_void>()) as _,
+        uFlags: TTF_IDISHWND | TTF_SUBCLASS,
+        hwnd: parent,
+        uId: control.0 as _,
+        lpszText: PWSTR::from_raw(text.as_mut_ptr()),
+        ..Default::default()
+    };
+    unsafe { SendMessageW(tooltip, TTM_ADDTOOLW, None, LPARAM(&info as *const _ as _)) };
+    Ok(())
+}
+
+/// DPI に合わせてツールチップの最大幅を設定し、長い説明を折り返して表示する
+pub fn set_max_width(tooltip: HWND, dpi: u32) -> Result<()> {
+    let width = dpi::scale(MAX_TIP_WIDTH, dpi);
+    unsafe { SendMessageW(tooltip, TTM_SETMAXTIPWIDTH, WPARAM(0), LPARAM(width as _)) };
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use crate::menu::{self, Item};
+use crate::{dpi, icon};
+use anyhow::Result;
+use std::mem;
+use std::sync::OnceLock;
+use windows::{
+    core::w,
+    Win32::{
+        Foundation::{HWND, LPARAM, POINT, WPARAM},
+        UI::{
+            Shell::{
+                Shell_NotifyIconW, NIF_ICON, NIF_INFO, NIF_MESSAGE, NIF_TIP, NIIF_INFO, NIM_ADD,
+                NIM_DELETE, NIM_MODIFY, NOTIFYICONDATAW,
+            },
+            WindowsAndMessaging::{
+                DestroyMenu, GetCursorPos, PostMessageW, RegisterWindowMessageW,
+                SetForegroundWindow, TrackPopupMenu, TPM_RIGHTBUTTON, WM_APP, WM_NULL,
+            },
+        },
+    },
+};
+
+/// タスクトレイのアイコンから通知されるメッセージ
+pub const WM_TRAYICON: u32 = WM_APP + 1;
+/// タスクトレイのアイコンの ID
+const TRAY_ICON_ID: u32 = 1;
+/// エクスプローラーの再起動時に通知されるメッセージ
+static TASKBAR_CREATED: OnceLock<u32> = OnceLock::new();
+
+/// エクスプローラーの再起動時に通知されるメッセージを取得する
+pub fn taskbar_created_message() -> u32 {
+    *TASKBAR_CREATED.get_or_init(|| unsafe { RegisterWindowMessageW(w!("TaskbarCreated")) })
+}
+
+fn notify_icon_data(hwnd: HWND) -> NOTIFYICONDATAW {
+    NOTIFYICONDATAW {
+        cbSize: mem::size_of::<NOTIFYICONDATAW>() as _,
+        hWnd: hwnd,
+        uID: TRAY_ICON_ID,
+        ..Default::default()
+    }
+}
+
+/// タスクトレイにアイコンを追加する
+pub fn add(hwnd: HWND, tip: &str) -> Result<()> {
+    let mut data = notify_icon_data(hwnd);
+    data.uFlags = NIF_ICON | NIF_MESSAGE | NIF_TIP;
+    data.uCallbackMessage = WM_TRAYICON;
+    data.hIcon = icon::load_small(dpi::dpi_for_window(hwnd))?;
+    copy_text(&mut data.szTip, tip);
+    unsafe { Shell_NotifyIconW(NIM_ADD, &data).ok()? };
+    Ok(())
+}
+
+/// タスクトレイのアイコンからバルーン通知を表示する
+pub fn show_balloon(hwnd: HWND, title: &str, text: &str) -> Result<()> {
+    let mut data = notify_icon_data(hwnd);
+    data.uFlags = NIF_INFO;
+    data.dwInfoFlags = NIIF_INFO;
+    copy_text(&mut data.szInfoTitle, title);
+    copy_text(&mut data.szInfo, text);
+    unsafe { Shell_NotifyIconW(NIM_MODIFY, &data).ok()? };
+    Ok(())
+}
+
+/// 固定長のバッファに収まる分だけ文字列をコピーする (末尾は NUL のまま残す)
+fn copy_text(buf: &mut [u16], text: &str) {
+    text.encode_utf16()
+        .take(buf.len() - 1)
+        .enumerate()
+        .for_each(|(i, c)| buf[i] = c);
+}
+
+/// タスクトレイからアイコンを削除する
+pub fn remove(hwnd: HWND) {
+    let data = notify_icon_data(hwnd);
+    _ = unsafe { Shell_NotifyIconW(NIM_DELETE, &data) };
+}
+
+/// カーソル位置にタスクトレイのコンテキストメニューを表示する
+pub fn show_menu(hwnd: HWND, items: &[Item]) -> Result<()> {
+    let popup = menu::create_popup(items)?;
+    let mut pt = POINT::default();
+    unsafe { GetCursorPos(&mut pt)? };
+    // メニューの外をクリックしたときに閉じるようにするため前面に出す
+    _ = unsafe { SetForegroundWindow(hwnd) };
+    _ = unsafe { TrackPopupMenu(popup, TPM_RIGHTBUTTON, pt.x, pt.y, 0, hwnd, None) };
+    unsafe { PostMessageW(hwnd, WM_NULL, WPARAM(0), LPARAM(0))? };
+    unsafe { DestroyMenu(popup)? };
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use crate::folder_watch::Converted;
+use crate::{ExportFinished, SaveFinished};
+use anyhow::Result;
+use windows::{
+    Media::SystemMediaTransportControlsButton,
+    Win32::{
+        Foundation::{HWND, LPARAM, WPARAM},
+        UI::WindowsAndMessaging::{PostMessageW, WM_APP},
+    },
+};
+
+/// [UiMessage] を届けるメッセージ (LPARAM は `Box<UiMessage>`)
+pub const WM_UI_MESSAGE: u32 = WM_APP + 2;
+
+/// 別スレッドから UI スレッドに届ける通知
+///
+/// 合成の完了ハンドラやメディアのイベントは UI スレッド以外で呼ばれるので、コントロールには触れずに
+/// [WM_UI_MESSAGE] でメインウィンドウに送り、UI スレッドで反映する。
+pub enum UiMessage {
+    /// 再生が始まった (再生の番号と、繰り返している場合は何回目か)
+    SpeechStarted(usize, u32),
+    /// 再生が終わった (再生の番号と結果)
+    SpeechFinished(usize, Result<()>),
+    /// WAV ファイルへの保存が終わった
+    SaveFinished(SaveFinished),
+    /// 書き出し待ちのファイルを一つ書き出し終えた
+    ExportFinished(ExportFinished),
+    /// 文ごとの書き出しで一つの文を書き出し終えた
+    SentenceExported(Result<()>),
+    /// 監視しているフォルダーのテキストファイルを WAV に変換し終えたか、変換をあきらめた
+    FolderConverted(Converted),
+    /// メディアキーかメディアのフライアウトのボタンが押された
+    MediaButton(SystemMediaTransportControlsButton),
+}
+
+impl UiMessage {
+    /// ウィンドウに送る。HWND はスレッドに送れないので値で受け取る
+    ///
+    /// ウィンドウがすでに閉じられていて送れない場合は捨てる。
+    pub fn post(self, handle: isize) {
+        let lparam = self.into_lparam();
+        if unsafe { PostMessageW(HWND(handle as _), WM_UI_MESSAGE, WPARAM(0), lparam) }.is_err() {
+            drop(unsafe { Self::from_lparam(lparam) });
+        }
+    }
+
+    fn into_lparam(self) -> LPARAM {
+        LPARAM(Box::into_raw(Box::new(self)) as _)
+    }
+
+    /// [WM_UI_MESSAGE] の LPARAM から取り出す
+    ///
+    /// # Safety
+    ///
+    /// lparam は [UiMessage::post] が送ったもので、一度だけ取り出せる。
+    pub unsafe fn from_lparam(lparam: LPARAM) -> Self {
+        *Box::from_raw(lparam.0 as *mut Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn round_trip_through_lparam() {
+        let lparam = UiMessage::SpeechFinished(3, Err(anyhow!("failed."))).into_lparam();
+        match unsafe { UiMessage::from_lparam(lparam) } {
+            UiMessage::SpeechFinished(id, Err(e)) => {
+                assert_eq!(id, 3);
+                assert_eq!(e.to_string(), "failed.");
+            }
+            _ => panic!("unexpected message."),
+        }
+        let lparam = UiMessage::SpeechStarted(7, 2).into_lparam();
+        assert!(matches!(
+            unsafe { UiMessage::from_lparam(lparam) },
+            UiMessage::SpeechStarted(7, 2)
+        ));
+    }
+}
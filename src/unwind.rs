@@ -0,0 +1,35 @@
+use anyhow::{anyhow, Result};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+/// f を呼び出し、パニックした場合はエラーにして返す
+///
+/// WinRT のイベントハンドラや完了ハンドラでパニックするとプロセスごと終了してしまうので、
+/// ハンドラの中身はこれで包み、失敗として普段のエラー表示に回す。
+pub fn catch<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|payload| Err(anyhow!("panicked: {}", message(payload.as_ref()))))
+}
+
+/// パニックに渡された値からメッセージを取り出す
+pub fn message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panics_become_errors() {
+        assert_eq!(catch(|| Ok(1)).unwrap(), 1);
+        let e = catch::<()>(|| panic!("broken {}.", "handler")).unwrap_err();
+        assert_eq!(e.to_string(), "panicked: broken handler.");
+        let e = catch::<()>(|| std::panic::panic_any(42)).unwrap_err();
+        assert_eq!(e.to_string(), "panicked: unknown panic.");
+    }
+}
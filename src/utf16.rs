@@ -0,0 +1,107 @@
+//! UTF-16 のテキストを位置で扱うための関数
+//!
+//! エディットコントロールのテキストは `Vec<u16>` のまま扱うので、選択範囲やチャンクの位置は UTF-16 単位になる。
+//! 絵文字などのサロゲートペアの途中で切ると HSTRING にできなくなるので、切る位置はここで文字の境界に合わせる。
+
+/// 上位サロゲート (サロゲートペアの前半)
+pub fn is_high_surrogate(c: u16) -> bool {
+    (0xD800..0xDC00).contains(&c)
+}
+
+/// 下位サロゲート (サロゲートペアの後半)
+pub fn is_low_surrogate(c: u16) -> bool {
+    (0xDC00..0xE000).contains(&c)
+}
+
+/// 対になっていないサロゲートを含むかどうか
+pub fn has_lone_surrogate(text: &[u16]) -> bool {
+    char::decode_utf16(text.iter().copied()).any(|c| c.is_err())
+}
+
+/// pos がサロゲートペアの途中なら、ペアの前に戻した位置 (テキストより後ろなら末尾)
+pub fn floor_char_boundary(text: &[u16], pos: usize) -> usize {
+    let pos = pos.min(text.len());
+    if pos > 0
+        && pos < text.len()
+        && is_high_surrogate(text[pos - 1])
+        && is_low_surrogate(text[pos])
+    {
+        pos - 1
+    } else {
+        pos
+    }
+}
+
+/// text[start..end] を文字の境界に合わせて切り出す
+///
+/// 範囲がテキストを超えていれば末尾までにする。
+pub fn slice_at_char_boundary(text: &[u16], start: usize, end: usize) -> &[u16] {
+    let end = floor_char_boundary(text, end);
+    let start = floor_char_boundary(text, start).min(end);
+    &text[start..end]
+}
+
+/// UTF-16 単位の位置を、その前にある文字数に変換する
+pub fn char_index(text: &[u16], pos: usize) -> usize {
+    let pos = floor_char_boundary(text, pos);
+    char::decode_utf16(text[..pos].iter().copied()).count()
+}
+
+/// 先頭から chars 文字目の UTF-16 単位の位置 (文字数が足りなければ末尾)
+pub fn unit_index(text: &[u16], chars: usize) -> usize {
+    char::decode_utf16(text.iter().copied())
+        .take(chars)
+        .map(|c| c.map_or(1, char::len_utf16))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().collect()
+    }
+
+    #[test]
+    fn never_slice_inside_surrogate_pairs() {
+        // 😀 は 2 単位
+        let text = wide("あ😀い");
+        assert_eq!(floor_char_boundary(&text, 2), 1);
+        assert_eq!(floor_char_boundary(&text, 3), 3);
+        assert_eq!(floor_char_boundary(&text, 10), 4);
+        assert_eq!(slice_at_char_boundary(&text, 0, 2), wide("あ"));
+        assert_eq!(slice_at_char_boundary(&text, 2, 4), wide("😀い"));
+        assert!(slice_at_char_boundary(&text, 3, 1).is_empty());
+    }
+
+    #[test]
+    fn convert_between_units_and_chars() {
+        // 国旗は二つの地域指示文字 (それぞれサロゲートペア) でできている
+        let text = wide("a🇯🇵b");
+        assert_eq!(text.len(), 6);
+        assert_eq!(char_index(&text, 0), 0);
+        assert_eq!(char_index(&text, 3), 2);
+        assert_eq!(char_index(&text, 4), 2);
+        assert_eq!(char_index(&text, 6), 4);
+        assert_eq!(unit_index(&text, 2), 3);
+        assert_eq!(unit_index(&text, 4), 6);
+        assert_eq!(unit_index(&text, 100), 6);
+        for chars in 0..=4 {
+            assert_eq!(char_index(&text, unit_index(&text, chars)), chars);
+        }
+    }
+
+    #[test]
+    fn detect_lone_surrogates() {
+        assert!(!has_lone_surrogate(&wide("😀🇯🇵")));
+        assert!(!has_lone_surrogate(&[]));
+        // 壊れたテキストの対になっていないサロゲートは 1 文字として数える
+        let broken = [0xD83D, 'a' as u16, 0xDE00];
+        assert!(has_lone_surrogate(&broken));
+        assert!(has_lone_surrogate(&broken[..1]));
+        assert_eq!(char_index(&broken, 3), 3);
+        assert_eq!(unit_index(&broken, 2), 2);
+        assert_eq!(floor_char_boundary(&broken, 1), 1);
+    }
+}
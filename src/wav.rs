@@ -0,0 +1,850 @@
+use crate::error::SpeechError;
+use crate::worker;
+use anyhow::{bail, ensure, Context, Result};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+use windows::Storage::Streams::{DataReader, IRandomAccessStream};
+
+/// ストリームから一度に読み出すバイト数
+const READ_CHUNK_LEN: usize = 64 * 1024;
+/// ファイルに一度に書き込むバイト数 (区切りごとに終了の期限を確かめる)
+const WRITE_CHUNK_LEN: usize = 1024 * 1024;
+/// fmt チャンクの形式の番号で、圧縮していない PCM を表す
+const WAVE_FORMAT_PCM: u16 = 1;
+/// 音量を正規化するときのピークの目標 (dBFS)
+pub const PEAK_TARGET_DBFS: f64 = -1.0;
+/// 前後の無音を切り取るときに、無音とみなすサンプルの大きさの上限 (dBFS)
+pub const SILENCE_THRESHOLD_DBFS: f64 = -50.0;
+/// 前後の無音を切り取るときに残す長さの既定値
+pub const DEFAULT_TRIM_MARGIN: Duration = Duration::from_millis(100);
+
+/// 保存する WAV の形式 (既定値は合成した形式のまま)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OutputFormat {
+    /// サンプリングレート (None の場合は合成したまま)
+    pub sample_rate: Option<u32>,
+    /// モノラルにまとめる
+    pub mono: bool,
+}
+
+/// WAV ファイルの形式
+#[derive(Debug, PartialEq)]
+pub struct WavInfo {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    /// 音声データのバイト数
+    pub data_len: u32,
+}
+
+impl WavInfo {
+    /// 再生時間 (秒)
+    pub fn duration_secs(&self) -> f64 {
+        let bytes_per_sec =
+            self.sample_rate as f64 * self.channels as f64 * (self.bits_per_sample / 8) as f64;
+        if bytes_per_sec == 0.0 {
+            return 0.0;
+        }
+        self.data_len as f64 / bytes_per_sec
+    }
+}
+
+/// WAV ファイルに埋め込むタグ (RIFF の LIST/INFO チャンク)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tags {
+    /// タイトル (INAM)
+    pub title: String,
+    /// アーティスト (IART)
+    pub artist: String,
+    /// コメント (ICMT)
+    pub comment: String,
+    /// 作成日 (ICRD、YYYY-MM-DD 形式)
+    pub date: String,
+}
+
+impl Tags {
+    /// LIST/INFO チャンクの中身。空のタグは含めない
+    fn info_chunk(&self) -> Vec<u8> {
+        let mut body = b"INFO".to_vec();
+        let fields = [
+            (b"INAM", &self.title),
+            (b"IART", &self.artist),
+            (b"ICMT", &self.comment),
+            (b"ICRD", &self.date),
+        ];
+        for (id, value) in fields {
+            if value.is_empty() {
+                continue;
+            }
+            // 文字列は NUL で終わり、チャンクは 2 バイト境界にそろえる
+            let mut value = value.as_bytes().to_vec();
+            value.push(0);
+            push_chunk(&mut body, id, &value);
+        }
+        body
+    }
+}
+
+/// ストリームから読み出したバイト列を順に受け取る
+pub trait Sink {
+    fn write_chunk(&mut self, bytes: &[u8]) -> Result<()>;
+}
+
+impl Sink for Vec<u8> {
+    fn write_chunk(&mut self, bytes: &[u8]) -> Result<()> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// 合成した音声のバイト列 (WAV 形式) を取り出す
+pub fn stream_bytes(stream: &IRandomAccessStream) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    read_stream(stream, &mut bytes, |_, _| {})?;
+    Ok(bytes)
+}
+
+/// 合成した音声を [READ_CHUNK_LEN] ずつ読み出して sink に渡す
+///
+/// 一度に読み込まないので、長い音声でも大きなバッファを確保しない。
+/// 読み出すたびに progress に読み終えたバイト数と全体のバイト数を渡す。
+pub fn read_stream(
+    stream: &IRandomAccessStream,
+    sink: &mut impl Sink,
+    progress: impl FnMut(u64, u64),
+) -> Result<()> {
+    let reader = DataReader::CreateDataReader(stream)?;
+    let read = |buf: &mut [u8]| -> Result<usize> {
+        let loaded = reader.LoadAsync(buf.len() as u32)?.get()? as usize;
+        reader.ReadBytes(&mut buf[..loaded])?;
+        Ok(loaded)
+    };
+    copy_chunks(stream.Size()?, read, sink, progress)
+}
+
+/// total バイトを read で少しずつ読み出して sink に渡す
+fn copy_chunks(
+    total: u64,
+    mut read: impl FnMut(&mut [u8]) -> Result<usize>,
+    sink: &mut impl Sink,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<()> {
+    let mut buf = vec![0; READ_CHUNK_LEN];
+    let mut done = 0;
+    while done < total {
+        let len = (total - done).min(READ_CHUNK_LEN as u64) as usize;
+        let read_len = read(&mut buf[..len])?;
+        ensure!(read_len > 0, "unexpected end of stream.");
+        sink.write_chunk(&buf[..read_len])?;
+        done += read_len as u64;
+        progress(done, total);
+    }
+    Ok(())
+}
+
+/// 合成した音声 (WAV 形式) をファイルに書き込む
+pub fn write(bytes: &[u8], path: &Path) -> Result<()> {
+    parse_header(bytes)?;
+    // 書き込み中に終了しても書きかけのファイルが残らないように、一時ファイルに書いてから置き換える
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let result = write_file(Path::new(&temp), bytes).and_then(|()| std::fs::rename(&temp, path));
+    if result.is_err() {
+        _ = std::fs::remove_file(&temp);
+    }
+    Ok(result.map_err(SpeechError::from)?)
+}
+
+/// バイト列をファイルに書き込む。終了の期限を過ぎたら途中でやめてエラーを返す
+fn write_file(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    for block in bytes.chunks(WRITE_CHUNK_LEN) {
+        if worker::past_deadline() {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "the app is closing.",
+            ));
+        }
+        file.write_all(block)?;
+    }
+    file.sync_all()
+}
+
+/// タグを埋め込んで WAV ファイルに書き込む。埋め込めない形式の場合はそのまま書き込む
+pub fn write_with_tags(bytes: &[u8], tags: &Tags, path: &Path) -> Result<()> {
+    match with_tags(bytes, tags) {
+        Ok(tagged) => write(&tagged, path),
+        Err(_) => write(bytes, path),
+    }
+}
+
+/// WAV のヘッダーを読んで形式を返す
+pub fn parse_header(bytes: &[u8]) -> Result<WavInfo> {
+    Ok(parse(bytes)?.0)
+}
+
+/// 同じ形式の WAV をつなげて一つの WAV にする
+///
+/// 先頭の fmt チャンクを使い、音声データだけをつなげてサイズを計算し直す。
+pub fn concat(parts: &[Vec<u8>]) -> Result<Vec<u8>> {
+    concat_with_pauses(parts, &[])
+}
+
+/// [concat] と同じようにつなげ、parts[i] の後に pauses[i] の長さの無音を入れる
+///
+/// 無音のサンプル数は先頭の WAV の形式 (サンプリングレート、チャンネル数、ビット数) から求める。
+/// 最後の WAV の後には入れない。pauses が parts より短ければ、足りない分は無音を入れない。
+/// 音声によってサンプリングレートが違うので、形式の違う 16 ビット PCM の WAV は先頭の WAV の形式に変換する。
+pub fn concat_with_pauses(parts: &[Vec<u8>], pauses: &[Duration]) -> Result<Vec<u8>> {
+    let [first, rest @ ..] = parts else {
+        bail!("no WAV to concatenate.");
+    };
+    if rest.is_empty() {
+        return Ok(first.clone());
+    }
+    let (info, fmt, _) = parse(first)?;
+    let target = OutputFormat {
+        sample_rate: Some(info.sample_rate),
+        mono: info.channels == 1,
+    };
+    let mut data = vec![];
+    for (i, part) in parts.iter().enumerate() {
+        let converted;
+        let (_, mut part_fmt, mut part_data) = parse(part)?;
+        if part_fmt != fmt {
+            converted = convert(part, target)?;
+            (_, part_fmt, part_data) = parse(&converted)?;
+        }
+        ensure!(part_fmt == fmt, "WAV formats do not match.");
+        data.extend_from_slice(part_data);
+        match pauses.get(i) {
+            Some(&pause) if i + 1 < parts.len() => push_silence(&mut data, &info, fmt, pause),
+            _ => (),
+        }
+    }
+    build(fmt, &data, None)
+}
+
+/// duration の長さの無音を音声データに追加する
+fn push_silence(data: &mut Vec<u8>, info: &WavInfo, fmt: &[u8], duration: Duration) {
+    let samples = (info.sample_rate as f64 * duration.as_secs_f64()).round() as usize;
+    // 8 ビットの PCM は符号なしなので、無音は 0x80 になる
+    let value = if info.bits_per_sample == 8 { 0x80 } else { 0 };
+    data.resize(data.len() + samples * block_align(info, fmt), value);
+}
+
+/// 1 サンプル分のバイト数 (全チャンネル分)。fmt チャンクの値がなければビット数から求める
+fn block_align(info: &WavInfo, fmt: &[u8]) -> usize {
+    fmt.get(12..14)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+        .filter(|&align| align > 0)
+        .unwrap_or(info.channels as usize * (info.bits_per_sample as usize / 8))
+}
+
+/// 16 ビット PCM でなければエラーを返す (音量を調べて書き換える処理はこの形式だけを扱う)
+fn ensure_pcm16(info: &WavInfo, fmt: &[u8]) -> Result<()> {
+    let format = fmt.get(..2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    ensure!(
+        format == Some(WAVE_FORMAT_PCM) && info.bits_per_sample == 16,
+        "not a 16-bit PCM WAV."
+    );
+    Ok(())
+}
+
+/// dBFS を 16 ビットのサンプルの大きさにする
+fn amplitude(dbfs: f64) -> f64 {
+    (i16::MAX as f64 * 10f64.powf(dbfs / 20.0)).round()
+}
+
+/// 16 ビット PCM の WAV の音量を、最も大きいサンプルが target_dbfs になるように変える
+///
+/// 倍率は全体で一つなので、音の形は変わらない。範囲を超えるサンプルは切り詰める。
+/// 無音や、すでにピークが目標どおりの WAV はそのまま返す。
+/// 16 ビット PCM 以外の形式はエラーを返すので、呼び出し側で元のバイト列を使う。
+pub fn normalize_peak(bytes: &[u8], target_dbfs: f64) -> Result<Vec<u8>> {
+    let (info, fmt, data) = parse(bytes)?;
+    ensure_pcm16(&info, fmt)?;
+    let samples = || {
+        data.chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+    };
+    let peak = samples().map(|s| (s as i32).abs()).max().unwrap_or(0);
+    let target = amplitude(target_dbfs);
+    if peak == 0 || peak as f64 == target {
+        return Ok(bytes.to_vec());
+    }
+    let gain = target / peak as f64;
+    let data = samples()
+        .flat_map(|s| {
+            let scaled = (s as f64 * gain).round();
+            (scaled.clamp(i16::MIN as f64, i16::MAX as f64) as i16).to_le_bytes()
+        })
+        .collect::<Vec<_>>();
+    build(fmt, &data, None)
+}
+
+/// 16 ビット PCM の WAV の前後の無音を切り取り、切り取った先頭の長さと一緒に返す
+///
+/// どれかのチャンネルで threshold_dbfs を超える最初と最後のサンプルを探し、その前後に margin の長さを残す。
+/// すべて無音の場合は、空の WAV にならないように先頭の margin の長さ (少なくとも 1 サンプル) を残す。
+/// 16 ビット PCM 以外の形式はエラーを返すので、呼び出し側で元のバイト列を使う。
+pub fn trim_silence(
+    bytes: &[u8],
+    threshold_dbfs: f64,
+    margin: Duration,
+) -> Result<(Vec<u8>, Duration)> {
+    let (info, fmt, data) = parse(bytes)?;
+    ensure_pcm16(&info, fmt)?;
+    let frame_len = block_align(&info, fmt);
+    ensure!(frame_len > 0 && info.sample_rate > 0, "broken fmt chunk.");
+    let threshold = amplitude(threshold_dbfs) as i32;
+    let loud = |frame: &&[u8]| {
+        frame
+            .chunks_exact(2)
+            .any(|s| (i16::from_le_bytes([s[0], s[1]]) as i32).abs() > threshold)
+    };
+    let frames = data.chunks_exact(frame_len).collect::<Vec<_>>();
+    let margin = (info.sample_rate as f64 * margin.as_secs_f64()).round() as usize;
+    let (start, end) = match (frames.iter().position(loud), frames.iter().rposition(loud)) {
+        (Some(first), Some(last)) => (
+            first.saturating_sub(margin),
+            (last + 1 + margin).min(frames.len()),
+        ),
+        _ => (0, margin.max(1).min(frames.len())),
+    };
+    if start == 0 && end == frames.len() {
+        return Ok((bytes.to_vec(), Duration::ZERO));
+    }
+    let lead = Duration::from_secs_f64(start as f64 / info.sample_rate as f64);
+    let trimmed = build(fmt, &data[start * frame_len..end * frame_len], None)?;
+    Ok((trimmed, lead))
+}
+
+/// 16 ビット PCM の WAV を format のサンプリングレートとチャンネル数に変換する
+///
+/// モノラルにするときは全チャンネルの平均を取り、サンプリングレートは線形補間で変える。
+/// 変換しない形式ならバイト列をそのまま返す。16 ビット PCM 以外の形式はエラーを返す。
+pub fn convert(bytes: &[u8], format: OutputFormat) -> Result<Vec<u8>> {
+    if format == OutputFormat::default() {
+        return Ok(bytes.to_vec());
+    }
+    let (info, fmt, data) = parse(bytes)?;
+    ensure_pcm16(&info, fmt)?;
+    ensure!(
+        info.channels > 0 && info.sample_rate > 0,
+        "broken fmt chunk."
+    );
+    let channels = if format.mono { 1 } else { info.channels };
+    let sample_rate = format.sample_rate.unwrap_or(info.sample_rate);
+    ensure!(sample_rate > 0, "invalid sample rate.");
+    if channels == info.channels && sample_rate == info.sample_rate {
+        return Ok(bytes.to_vec());
+    }
+    let mut frames = data
+        .chunks_exact(block_align(&info, fmt))
+        .map(|frame| {
+            frame
+                .chunks_exact(2)
+                .map(|s| i16::from_le_bytes([s[0], s[1]]) as f64)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    if channels != info.channels {
+        for frame in &mut frames {
+            *frame = vec![frame.iter().sum::<f64>() / frame.len() as f64];
+        }
+    }
+    if sample_rate != info.sample_rate {
+        frames = resample(&frames, info.sample_rate, sample_rate);
+    }
+    let mut data = Vec::with_capacity(frames.len() * channels as usize * 2);
+    for sample in frames.iter().flatten() {
+        let sample = sample.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+        data.extend_from_slice(&sample.to_le_bytes());
+    }
+    let block_align = channels * 2;
+    let mut fmt = fmt.to_vec();
+    fmt[2..4].copy_from_slice(&channels.to_le_bytes());
+    fmt[4..8].copy_from_slice(&sample_rate.to_le_bytes());
+    fmt[8..12].copy_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    fmt[12..14].copy_from_slice(&block_align.to_le_bytes());
+    build(&fmt, &data, None)
+}
+
+/// from のサンプリングレートのフレームを、線形補間で to のサンプリングレートにする
+fn resample(frames: &[Vec<f64>], from: u32, to: u32) -> Vec<Vec<f64>> {
+    let Some(last) = frames.len().checked_sub(1) else {
+        return vec![];
+    };
+    let len = (frames.len() as f64 * to as f64 / from as f64).round() as usize;
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 * from as f64 / to as f64;
+            let index = (pos as usize).min(last);
+            let next = (index + 1).min(last);
+            let t = pos - index as f64;
+            frames[index]
+                .iter()
+                .zip(&frames[next])
+                .map(|(a, b)| a + (b - a) * t)
+                .collect()
+        })
+        .collect()
+}
+
+/// WAV に LIST/INFO チャンクでタグを埋め込む
+///
+/// ヘッダーを読んで fmt と data のチャンクから組み立て直すので、RIFF のサイズも正しくなる。
+/// WAV として読めない場合 (圧縮形式など) はエラーを返すので、呼び出し側で元のバイト列を使う。
+pub fn with_tags(bytes: &[u8], tags: &Tags) -> Result<Vec<u8>> {
+    let (_, fmt, data) = parse(bytes)?;
+    build(fmt, data, Some(&tags.info_chunk()))
+}
+
+/// fmt と data のチャンクと、あれば LIST チャンクから WAV を組み立てる
+fn build(fmt: &[u8], data: &[u8], list: Option<&[u8]>) -> Result<Vec<u8>> {
+    ensure!(u32::try_from(data.len()).is_ok(), "WAV is too large.");
+    let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+    push_chunk(&mut bytes, b"fmt ", fmt);
+    push_chunk(&mut bytes, b"data", data);
+    if let Some(list) = list {
+        push_chunk(&mut bytes, b"LIST", list);
+    }
+    let riff_len = u32::try_from(bytes.len() - 8).context("WAV is too large.")?;
+    bytes[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    Ok(bytes)
+}
+
+/// チャンクを追加する。奇数長のチャンクには 1 バイトの詰め物を続ける
+fn push_chunk(bytes: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    bytes.extend_from_slice(id);
+    bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(body);
+    if body.len() & 1 == 1 {
+        bytes.push(0);
+    }
+}
+
+/// WAV を読んで、形式と fmt チャンクの中身と音声データを返す
+fn parse(bytes: &[u8]) -> Result<(WavInfo, &[u8], &[u8])> {
+    ensure!(
+        bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WAVE",
+        "not a WAV file."
+    );
+    let mut format = None;
+    let mut rest = &bytes[12..];
+    while rest.len() >= 8 {
+        let id = &rest[..4];
+        let len = u32::from_le_bytes(rest[4..8].try_into()?);
+        let body = &rest[8..];
+        match id {
+            b"fmt " => {
+                ensure!(body.len() >= 16, "broken fmt chunk.");
+                let u16_at = |i: usize| u16::from_le_bytes([body[i], body[i + 1]]);
+                let sample_rate = u32::from_le_bytes(body[4..8].try_into()?);
+                let fmt = &body[..(len as usize).min(body.len())];
+                format = Some((u16_at(2), sample_rate, u16_at(14), fmt));
+            }
+            b"data" => {
+                let (channels, sample_rate, bits_per_sample, fmt) =
+                    format.context("no fmt chunk before data.")?;
+                // ストリームに書き出した WAV はデータ長が実際と異なることがあるので、小さい方を使う
+                let data_len = len.min(body.len() as u32);
+                let info = WavInfo {
+                    channels,
+                    sample_rate,
+                    bits_per_sample,
+                    data_len,
+                };
+                return Ok((info, fmt, &body[..data_len as usize]));
+            }
+            _ => (),
+        }
+        // チャンクは 2 バイト境界にそろえられている
+        let next = 8 + len as usize + (len as usize & 1);
+        if next > rest.len() {
+            break;
+        }
+        rest = &rest[next..];
+    }
+    bail!("no data chunk.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 16 ビットモノラルの WAV を作る
+    fn wav(sample_rate: u32, data: &[u8], extra: &[u8]) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        bytes.extend_from_slice(extra);
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn write_replaces_the_file() {
+        let dir = std::env::temp_dir().join(format!("speech-wav-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.wav");
+        std::fs::write(&path, b"old").unwrap();
+        let bytes = wav(16000, &[0; 8], b"");
+        write(&bytes, &path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        // 不正なデータは書き込まず、元のファイルも一時ファイルも残さない
+        assert!(write(b"not a wav", &path).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_simple_header() {
+        let info = parse_header(&wav(16000, &[0; 32000], b"")).unwrap();
+        assert_eq!(
+            info,
+            WavInfo {
+                channels: 1,
+                sample_rate: 16000,
+                bits_per_sample: 16,
+                data_len: 32000,
+            }
+        );
+        assert_eq!(info.duration_secs(), 1.0);
+    }
+
+    #[test]
+    fn skip_unknown_chunks() {
+        // 奇数長のチャンクは 1 バイトの詰め物が続く
+        let info = parse_header(&wav(22050, &[0; 4], b"LIST\x03\0\0\0abc\0")).unwrap();
+        assert_eq!(info.sample_rate, 22050);
+        assert_eq!(info.data_len, 4);
+    }
+
+    #[test]
+    fn concat_data_chunks() {
+        let first = wav(16000, &[1, 2, 3, 4], b"LIST\x03\0\0\0abc\0");
+        let second = wav(16000, &[5, 6], b"");
+        let bytes = concat(&[first.clone(), second, wav(16000, &[7, 8], b"")]).unwrap();
+        assert_eq!(bytes[8..], wav(16000, &[1, 2, 3, 4, 5, 6, 7, 8], b"")[8..]);
+        let riff_len = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        assert_eq!(riff_len as usize, bytes.len() - 8);
+        let (_, _, data) = parse(&bytes).unwrap();
+        assert_eq!(data, [1, 2, 3, 4, 5, 6, 7, 8]);
+        // 一つだけならそのまま返す
+        assert_eq!(concat(&[first.clone()]).unwrap(), first);
+    }
+
+    #[test]
+    fn resample_parts_to_the_first_format() {
+        // 2 kHz の WAV を、先頭の 1 kHz に合わせてからつなげる
+        let parts = [
+            wav(1000, &pcm(&[10, 20]), b""),
+            wav(2000, &pcm(&[100, 100, 300, 300]), b""),
+        ];
+        let bytes = concat(&parts).unwrap();
+        assert_eq!(parse_header(&bytes).unwrap().sample_rate, 1000);
+        assert_eq!(samples(&bytes), [10, 20, 100, 300]);
+        // ステレオはモノラルにまとめる
+        let parts = [wav(1000, &pcm(&[1]), b""), stereo(1000, &[100, 300])];
+        assert_eq!(samples(&concat(&parts).unwrap()), [1, 200]);
+    }
+
+    #[test]
+    fn insert_silence_between_parts() {
+        let parts = [
+            wav(16000, &[1, 2], b""),
+            wav(16000, &[3, 4], b""),
+            wav(16000, &[5, 6], b""),
+        ];
+        // 16 kHz の 16 ビットモノラルで 1 ミリ秒は 16 サンプル (32 バイト)
+        let pauses = [
+            Duration::from_millis(1),
+            Duration::ZERO,
+            Duration::from_secs(1),
+        ];
+        let bytes = concat_with_pauses(&parts, &pauses).unwrap();
+        let (_, _, data) = parse(&bytes).unwrap();
+        let mut expected = vec![1, 2];
+        expected.extend_from_slice(&[0; 32]);
+        expected.extend_from_slice(&[3, 4, 5, 6]);
+        assert_eq!(data, expected);
+        // 無音を指定しなければ concat と同じ
+        assert_eq!(
+            concat_with_pauses(&parts, &[]).unwrap(),
+            concat(&parts).unwrap()
+        );
+    }
+
+    /// 16 ビットのサンプルを WAV の音声データにする
+    fn pcm(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+
+    fn samples(bytes: &[u8]) -> Vec<i16> {
+        let (_, _, data) = parse(bytes).unwrap();
+        data.chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn normalize_peak_to_target() {
+        // -1 dBFS は 32767 の約 0.891 倍
+        let target = 29204;
+        // 最大の振幅の正弦波は目標まで下げる
+        let sine = (0..64)
+            .map(|i| (32767.0 * (i as f64 * std::f64::consts::PI / 16.0).sin()).round() as i16)
+            .collect::<Vec<_>>();
+        let normalized = samples(&normalize_peak(&wav(16000, &pcm(&sine), b""), -1.0).unwrap());
+        assert_eq!(normalized.iter().map(|s| s.abs()).max(), Some(target));
+        assert_eq!(normalized[8], target);
+        assert_eq!(normalized[24], -target);
+        // 小さい音は目標まで上げる
+        let quiet =
+            samples(&normalize_peak(&wav(16000, &pcm(&[100, -200, 50]), b""), -1.0).unwrap());
+        assert_eq!(quiet, [14602, -target, 7301]);
+        // i16::MIN も範囲内に収める
+        let min = samples(&normalize_peak(&wav(16000, &pcm(&[i16::MIN, 0]), b""), -1.0).unwrap());
+        assert_eq!(min, [-target, 0]);
+    }
+
+    #[test]
+    fn normalize_peak_keeps_silence_and_target() {
+        let silence = wav(16000, &[0; 32], b"LIST\x03\0\0\0abc\0");
+        assert_eq!(normalize_peak(&silence, -1.0).unwrap(), silence);
+        let at_target = wav(16000, &pcm(&[29204, -1000, 3]), b"");
+        assert_eq!(normalize_peak(&at_target, -1.0).unwrap(), at_target);
+    }
+
+    #[test]
+    fn normalize_only_16_bit_pcm() {
+        let mut eight_bit = wav(16000, &[0x80, 0x90], b"");
+        // ビット数 (fmt チャンクの 14 バイト目) を 8 にする
+        let bits = 12 + 8 + 14;
+        eight_bit[bits..bits + 2].copy_from_slice(&8u16.to_le_bytes());
+        assert!(normalize_peak(&eight_bit, -1.0).is_err());
+        let mut float = wav(16000, &pcm(&[100]), b"");
+        float[12 + 8..12 + 10].copy_from_slice(&3u16.to_le_bytes());
+        assert!(normalize_peak(&float, -1.0).is_err());
+    }
+
+    /// 16 ビットステレオの WAV を作る (サンプルは左右の順に並べる)
+    fn stereo(sample_rate: u32, samples: &[i16]) -> Vec<u8> {
+        let mut bytes = wav(sample_rate, &pcm(samples), b"");
+        // fmt チャンクのチャンネル数、1 秒あたりのバイト数、1 サンプルのバイト数を書き換える
+        let fmt = 12 + 8;
+        bytes[fmt + 2..fmt + 4].copy_from_slice(&2u16.to_le_bytes());
+        bytes[fmt + 8..fmt + 12].copy_from_slice(&(sample_rate * 4).to_le_bytes());
+        bytes[fmt + 12..fmt + 14].copy_from_slice(&4u16.to_le_bytes());
+        bytes
+    }
+
+    /// 1 kHz で 10 ミリ秒 (10 サンプル) の余白を残して切り取る
+    fn trim(bytes: &[u8]) -> (Vec<i16>, Duration) {
+        let (trimmed, lead) =
+            trim_silence(bytes, SILENCE_THRESHOLD_DBFS, Duration::from_millis(10)).unwrap();
+        (samples(&trimmed), lead)
+    }
+
+    #[test]
+    fn trim_silence_on_both_sides() {
+        // 左は 25 サンプル目、右は 15 サンプル目だけが大きい。しきい値 (約 104) 以下の雑音は無音とみなす
+        let mut samples = vec![50i16; 80];
+        samples[15 * 2 + 1] = 5000;
+        samples[25 * 2] = -5000;
+        let (trimmed, lead) = trim(&stereo(1000, &samples));
+        assert_eq!(trimmed, samples[5 * 2..36 * 2]);
+        assert_eq!(lead, Duration::from_millis(5));
+    }
+
+    #[test]
+    fn trim_audio_that_starts_immediately() {
+        let mut samples = vec![0i16; 30];
+        samples[0] = 20000;
+        let (trimmed, lead) = trim(&wav(1000, &pcm(&samples), b""));
+        assert_eq!(trimmed, samples[..11]);
+        assert_eq!(lead, Duration::ZERO);
+        // 切り取る無音がなければそのまま返す
+        let loud = wav(1000, &pcm(&[1000, 0, -1000]), b"LIST\x03\0\0\0abc\0");
+        let (bytes, _) = trim_silence(&loud, SILENCE_THRESHOLD_DBFS, Duration::ZERO).unwrap();
+        assert_eq!(bytes, loud);
+    }
+
+    #[test]
+    fn keep_a_stub_of_silence() {
+        let (trimmed, lead) = trim(&wav(1000, &pcm(&[100; 50]), b""));
+        assert_eq!(trimmed, [100; 10]);
+        assert_eq!(lead, Duration::ZERO);
+        let (bytes, _) = trim_silence(
+            &wav(1000, &pcm(&[0; 5]), b""),
+            SILENCE_THRESHOLD_DBFS,
+            Duration::ZERO,
+        )
+        .unwrap();
+        assert_eq!(samples(&bytes), [0]);
+    }
+
+    #[test]
+    fn keep_native_format() {
+        let bytes = wav(1000, &pcm(&[1, 2, 3]), b"LIST\x03\0\0\0abc\0");
+        assert_eq!(convert(&bytes, OutputFormat::default()).unwrap(), bytes);
+        let same = OutputFormat {
+            sample_rate: Some(1000),
+            mono: true,
+        };
+        assert_eq!(convert(&bytes, same).unwrap(), bytes);
+    }
+
+    #[test]
+    fn downmix_and_resample() {
+        // 2 kHz のステレオを 1 kHz のモノラルにする
+        let input = stereo(2000, &[100, 300, -100, -300, 1000, 2000, 0, 0]);
+        let format = OutputFormat {
+            sample_rate: Some(1000),
+            mono: true,
+        };
+        let output = convert(&input, format).unwrap();
+        let info = parse_header(&output).unwrap();
+        assert_eq!(
+            info,
+            WavInfo {
+                channels: 1,
+                sample_rate: 1000,
+                bits_per_sample: 16,
+                data_len: 4,
+            }
+        );
+        // 1 秒あたりのバイト数と 1 サンプルのバイト数も書き換える
+        assert_eq!(&output[28..34], &[0xd0, 0x07, 0, 0, 2, 0]);
+        assert_eq!(samples(&output), [200, 1500]);
+    }
+
+    #[test]
+    fn upsample_with_interpolation() {
+        let format = OutputFormat {
+            sample_rate: Some(2000),
+            mono: false,
+        };
+        let output = convert(&stereo(1000, &[0, 100, 1000, -100]), format).unwrap();
+        let info = parse_header(&output).unwrap();
+        assert_eq!((info.channels, info.sample_rate), (2, 2000));
+        assert_eq!(samples(&output), [0, 100, 500, 0, 1000, -100, 1000, -100]);
+        // 16 ビット PCM 以外の形式は変換しない
+        let mut float = wav(1000, &pcm(&[1, 2]), b"");
+        float[20] = 3;
+        assert!(convert(&float, format).is_err());
+    }
+
+    #[test]
+    fn embed_tags_in_info_chunk() {
+        let tags = Tags {
+            title: "今日".into(),
+            artist: "Haruka".into(),
+            comment: String::new(),
+            date: "2024-05-01".into(),
+        };
+        // ストリームのままの WAV は RIFF のサイズが 0 のこともある
+        let bytes = with_tags(&wav(16000, &[1, 2, 3], b"junk\x01\0\0\0x\0"), &tags).unwrap();
+        let riff_len = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        assert_eq!(riff_len as usize, bytes.len() - 8);
+        let (info, _, data) = parse(&bytes).unwrap();
+        assert_eq!((info.sample_rate, data), (16000, &[1, 2, 3][..]));
+        // 奇数長の data の後に詰め物があり、その後に LIST チャンクが続く
+        let list = &bytes[12 + 8 + 16 + 8 + 4..];
+        let mut expected = b"LIST\x38\0\0\0INFO".to_vec();
+        expected.extend_from_slice(b"INAM\x07\0\0\0\xe4\xbb\x8a\xe6\x97\xa5\0\0");
+        expected.extend_from_slice(b"IART\x07\0\0\0Haruka\0\0");
+        expected.extend_from_slice(b"ICRD\x0b\0\0\02024-05-01\0\0");
+        assert_eq!(list, expected);
+        // 読めないデータにはタグを付けない
+        assert!(with_tags(b"not a wav", &tags).is_err());
+    }
+
+    #[test]
+    fn reject_mismatched_formats() {
+        assert!(concat(&[]).is_err());
+        // サンプリングレートの違いは変換するが、16 ビット PCM 以外は変換できない
+        let mut eight_bit = wav(22050, &[0x80, 0x80], b"");
+        let bits = 12 + 8 + 14;
+        eight_bit[bits..bits + 2].copy_from_slice(&8u16.to_le_bytes());
+        assert!(concat(&[wav(16000, &[0; 2], b""), eight_bit]).is_err());
+    }
+
+    /// 受け取ったチャンクの長さを記録する
+    #[derive(Default)]
+    struct RecordingSink {
+        bytes: Vec<u8>,
+        chunk_lens: Vec<usize>,
+    }
+
+    impl Sink for RecordingSink {
+        fn write_chunk(&mut self, bytes: &[u8]) -> Result<()> {
+            self.bytes.extend_from_slice(bytes);
+            self.chunk_lens.push(bytes.len());
+            Ok(())
+        }
+    }
+
+    /// reference を最大 max_read バイトずつ返す読み出し関数
+    fn reader(reference: &[u8], max_read: usize) -> impl FnMut(&mut [u8]) -> Result<usize> + '_ {
+        let mut pos = 0;
+        move |buf| {
+            let len = buf.len().min(max_read).min(reference.len() - pos);
+            buf[..len].copy_from_slice(&reference[pos..pos + len]);
+            pos += len;
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn copy_stream_in_bounded_chunks() {
+        let reference = (0..READ_CHUNK_LEN * 2 + 123)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        let total = reference.len() as u64;
+        let mut sink = RecordingSink::default();
+        let mut reported = vec![];
+        copy_chunks(
+            total,
+            reader(&reference, usize::MAX),
+            &mut sink,
+            |done, all| reported.push((done, all)),
+        )
+        .unwrap();
+        assert_eq!(sink.bytes, reference);
+        assert_eq!(sink.chunk_lens, [READ_CHUNK_LEN, READ_CHUNK_LEN, 123]);
+        assert_eq!(reported.last(), Some(&(total, total)));
+        assert!(reported.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn copy_stream_with_short_reads() {
+        let reference = (0..1000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let mut sink = vec![];
+        copy_chunks(1000, reader(&reference, 300), &mut sink, |_, _| {}).unwrap();
+        assert_eq!(sink, reference);
+        // 途中でストリームが終わった場合はエラーにする
+        let result = copy_chunks(1001, reader(&reference, 300), &mut vec![], |_, _| {});
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reject_broken_files() {
+        assert!(parse_header(b"").is_err());
+        assert!(parse_header(b"RIFF\0\0\0\0AVI ").is_err());
+        assert!(parse_header(b"RIFF\0\0\0\0WAVEdata\0\0\0\0").is_err());
+    }
+}
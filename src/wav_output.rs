@@ -0,0 +1,128 @@
+use crate::logging;
+use crate::playback::{Engine, WinRt};
+use crate::settings;
+use crate::strings::{tr, Msg};
+use anyhow::{Context, Result};
+use speech::synthesis::Voiced;
+use speech::wav;
+use std::path::Path;
+use std::time::Duration;
+use windows::Win32::System::SystemInformation::GetLocalTime;
+
+/// WAV に埋め込むタイトルの最大の文字数
+const MAX_TAG_CHARS: usize = 100;
+
+/// 保存する WAV に埋め込むタグ (テキストの最初の行、合成に使った音声の名前、読み上げ速度、日付)
+///
+/// 区間ごとに音声を切り替えた場合は、使った音声の名前を順につなげる。速度は最初の区間の音声合成エンジンのもの。
+/// 音声と速度は、区間の音声合成エンジンを作った engine に尋ねる。
+pub fn wav_tags<E: Engine>(
+    engine: &E,
+    text: &[u16],
+    parts: &[Voiced<E::Synth>],
+) -> Result<wav::Tags> {
+    let first = parts.first().context("no voice.")?;
+    let mut voices: Vec<String> = vec![];
+    for part in parts {
+        let name = engine.voice(&part.synth)?.name.to_string();
+        if !voices.contains(&name) {
+            voices.push(name);
+        }
+    }
+    let rate = engine.speaking_rate(&first.synth)?;
+    let text = String::from_utf16_lossy(text);
+    let title = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    let now = unsafe { GetLocalTime() };
+    Ok(wav::Tags {
+        title: title.chars().take(MAX_TAG_CHARS).collect(),
+        artist: voices.join(", "),
+        comment: format!("{} (rate {rate:.1})", tr(Msg::AppName)),
+        date: format!("{:04}-{:02}-{:02}", now.wYear, now.wMonth, now.wDay),
+    })
+}
+
+/// 保存する WAV に施す後処理と埋め込むタグ
+///
+/// 同じテキストならどの方法で保存しても同じファイルになるように、WAV を書き出すところではすべてこれを使う。
+pub struct WavOutput {
+    tags: wav::Tags,
+    trim: Option<Duration>,
+    format: wav::OutputFormat,
+    normalize: bool,
+}
+
+impl WavOutput {
+    /// 今の設定と、テキストと合成に使う音声に合わせたタグで作る
+    pub fn new(text: &[u16], parts: &[Voiced]) -> Result<Self> {
+        let tags = wav_tags(&WinRt, text, parts)?;
+        let settings = settings::get();
+        Ok(Self {
+            tags,
+            trim: settings.trim_margin(),
+            format: settings.output_format(),
+            normalize: settings.normalize_volume,
+        })
+    }
+
+    /// 前後の無音をカットし、形式を変えて音量を正規化してから path に書き込む
+    ///
+    /// 切り取った先頭の長さを返す (スピーチマークの時刻を合わせるのに使う)。
+    pub fn write(&self, bytes: Vec<u8>, path: &Path) -> Result<Duration> {
+        let (bytes, lead) = trim_silence(bytes, self.trim);
+        let bytes = convert_format(bytes, self.format);
+        wav::write_with_tags(&normalize_volume(bytes, self.normalize), &self.tags, path)?;
+        Ok(lead)
+    }
+}
+
+/// enabled なら保存する WAV の音量を正規化する
+///
+/// 16 ビット PCM 以外の形式は正規化せずに、ログに残してそのまま保存する。
+fn normalize_volume(bytes: Vec<u8>, enabled: bool) -> Vec<u8> {
+    if !enabled {
+        return bytes;
+    }
+    match wav::normalize_peak(&bytes, wav::PEAK_TARGET_DBFS) {
+        Ok(normalized) => normalized,
+        Err(e) => {
+            logging::error("skipped volume normalization", &e);
+            bytes
+        }
+    }
+}
+
+/// margin があれば保存する WAV の前後の無音を切り取り、切り取った先頭の長さと一緒に返す
+///
+/// 16 ビット PCM 以外の形式は切り取らずに、ログに残してそのまま保存する。
+fn trim_silence(bytes: Vec<u8>, margin: Option<Duration>) -> (Vec<u8>, Duration) {
+    let Some(margin) = margin else {
+        return (bytes, Duration::ZERO);
+    };
+    match wav::trim_silence(&bytes, wav::SILENCE_THRESHOLD_DBFS, margin) {
+        Ok(trimmed) => trimmed,
+        Err(e) => {
+            logging::error("skipped silence trimming", &e);
+            (bytes, Duration::ZERO)
+        }
+    }
+}
+
+/// 保存する WAV を format のサンプリングレートとチャンネル数に変換する
+///
+/// 16 ビット PCM 以外の形式は変換せずに、ログに残してそのまま保存する。
+fn convert_format(bytes: Vec<u8>, format: wav::OutputFormat) -> Vec<u8> {
+    if format == wav::OutputFormat::default() {
+        return bytes;
+    }
+    match wav::convert(&bytes, format) {
+        Ok(converted) => converted,
+        Err(e) => {
+            logging::error("skipped format conversion", &e);
+            bytes
+        }
+    }
+}
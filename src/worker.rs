@@ -0,0 +1,74 @@
+//! 終了するときに待つ作業スレッド
+//!
+//! 保存や再生のために作るスレッドは [spawn] で登録しておき、ウィンドウを閉じるときに [shutdown] で待つ。
+//! 切り離したままにすると、書き込みの途中でプロセスごと終わってしまうことがある。
+
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// 登録した作業スレッド
+static WORKERS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+/// 終了を始めていれば、作業をやめるまでの期限
+static DEADLINE: Mutex<Option<Instant>> = Mutex::new(None);
+/// 終了を始めたことを [sleep] で待っているスレッドに知らせる
+static SHUTDOWN: Condvar = Condvar::new();
+/// 期限を過ぎてから、書きかけのファイルを消すなどの後片付けを待つ時間
+const CLEANUP_TIME: Duration = Duration::from_millis(500);
+/// 作業スレッドが終わったかどうかを確かめる間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// 作業スレッドを作って登録する。終わったスレッドは登録から外す
+pub fn spawn(f: impl FnOnce() + Send + 'static) {
+    let mut workers = lock(&WORKERS);
+    workers.retain(|worker| !worker.is_finished());
+    workers.push(thread::spawn(f));
+}
+
+/// 終了を始めているかどうか
+pub fn is_shutting_down() -> bool {
+    lock(&DEADLINE).is_some()
+}
+
+/// 終了を始めていて、作業をやめる期限を過ぎたかどうか
+///
+/// 長い書き込みは区切りごとに確かめて、過ぎていれば書きかけのファイルを消してやめる。
+pub fn past_deadline() -> bool {
+    lock(&DEADLINE).is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+/// duration だけ待つ。途中で終了が始まったらすぐに戻り、false を返す
+pub fn sleep(duration: Duration) -> bool {
+    let deadline = lock(&DEADLINE);
+    let (deadline, _) = SHUTDOWN
+        .wait_timeout_while(deadline, duration, |deadline| deadline.is_none())
+        .unwrap_or_else(PoisonError::into_inner);
+    deadline.is_none()
+}
+
+/// 終了を始め、登録した作業スレッドが終わるまで待つ
+///
+/// timeout を過ぎたら作業をやめるように知らせ、後片付けの時間だけ待って戻る。
+/// すべて終わった場合は true を返す。
+pub fn shutdown(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    *lock(&DEADLINE) = Some(deadline);
+    SHUTDOWN.notify_all();
+    let workers = std::mem::take(&mut *lock(&WORKERS));
+    let limit = deadline + CLEANUP_TIME;
+    while !workers.iter().all(JoinHandle::is_finished) {
+        if Instant::now() >= limit {
+            return false;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    for worker in workers {
+        _ = worker.join();
+    }
+    true
+}
+
+/// パニックしたスレッドがあってもロックを取る
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}